@@ -0,0 +1,92 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{stdin, stdout};
+
+use clap::Args;
+
+use cozo::DbInstance;
+
+use crate::options::EngineOptionArgs;
+
+#[derive(Args, Debug)]
+pub(crate) struct DumpArgs {
+    /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
+    #[clap(short, long, default_value_t = String::from("mem"))]
+    engine: String,
+
+    /// Path to the directory to store the database
+    #[clap(short, long, default_value_t = String::from("cozo.db"))]
+    path: String,
+
+    /// Extra config in JSON format
+    #[clap(short, long, default_value_t = String::from("{}"))]
+    config: String,
+
+    #[command(flatten)]
+    engine_options: EngineOptionArgs,
+
+    /// File to write the dump to, `-` for the standard output
+    file: String,
+
+    /// Stored relations to dump, all of them if none is given
+    relations: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct LoadArgs {
+    /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
+    #[clap(short, long, default_value_t = String::from("mem"))]
+    engine: String,
+
+    /// Path to the directory to store the database
+    #[clap(short, long, default_value_t = String::from("cozo.db"))]
+    path: String,
+
+    /// Extra config in JSON format
+    #[clap(short, long, default_value_t = String::from("{}"))]
+    config: String,
+
+    #[command(flatten)]
+    engine_options: EngineOptionArgs,
+
+    /// File to read the dump from, `-` for the standard input
+    file: String,
+}
+
+pub(crate) fn dump_main(args: DumpArgs) -> Result<(), Box<dyn Error>> {
+    let config = args.engine_options.merge_into(&args.engine, &args.config)?;
+    let db = DbInstance::new(&args.engine, args.path, &config)?;
+    let summary = if args.file == "-" {
+        db.dump(stdout().lock(), &args.relations)?
+    } else {
+        db.dump(File::create(&args.file)?, &args.relations)?
+    };
+    eprintln!(
+        "Dumped {} relations with {} rows",
+        summary.relations, summary.rows
+    );
+    Ok(())
+}
+
+pub(crate) fn load_main(args: LoadArgs) -> Result<(), Box<dyn Error>> {
+    let config = args.engine_options.merge_into(&args.engine, &args.config)?;
+    let db = DbInstance::new(&args.engine, args.path, &config)?;
+    let summary = if args.file == "-" {
+        db.load(stdin().lock())?
+    } else {
+        db.load(File::open(&args.file)?)?
+    };
+    eprintln!(
+        "Loaded {} relations with {} rows",
+        summary.relations, summary.rows
+    );
+    Ok(())
+}
@@ -13,11 +13,15 @@ use std::process::exit;
 use clap::{Parser, Subcommand};
 use env_logger::Env;
 
+use crate::dump::{dump_main, load_main, DumpArgs, LoadArgs};
 use crate::repl::{repl_main, ReplArgs};
 use crate::server::{server_main, ServerArgs};
 
 mod client;
+mod dump;
+mod options;
 mod repl;
+mod replication;
 mod server;
 
 #[derive(Parser)]
@@ -32,6 +36,10 @@ struct AppArgs {
 enum Commands {
     Server(ServerArgs),
     Repl(ReplArgs),
+    /// Dump stored relations to a file that `load` reads back into any engine
+    Dump(DumpArgs),
+    /// Load a file written by `dump`
+    Load(LoadArgs),
 }
 
 fn main() {
@@ -50,6 +58,18 @@ fn main() {
                 exit(-1);
             }
         }
+        Commands::Dump(args) => {
+            if let Err(e) = dump_main(args) {
+                eprintln!("{e}");
+                exit(-1);
+            }
+        }
+        Commands::Load(args) => {
+            if let Err(e) = load_main(args) {
+                eprintln!("{e}");
+                exit(-1);
+            }
+        }
     };
 
     // if args.repl {
@@ -0,0 +1,196 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::path::PathBuf;
+
+use clap::Args;
+use serde_json::{json, Value};
+
+/// Engine options that can be given as flags instead of in the JSON config.
+#[derive(Args, Debug)]
+pub(crate) struct EngineOptionArgs {
+    /// All engines: file containing the key the database is encrypted with
+    #[clap(long)]
+    encryption_key_file: Option<PathBuf>,
+
+    /// All engines: maximum number of rows sorting the results of a query holds in memory
+    /// before spilling to disk
+    #[clap(long)]
+    sort_memory_budget: Option<usize>,
+
+    /// RocksDB and SQLite: open an existing database read-only
+    #[clap(long)]
+    read_only: bool,
+
+    /// RocksDB: size in bytes of the block cache
+    #[clap(long)]
+    rocksdb_block_cache_size: Option<usize>,
+
+    /// RocksDB: compression of all levels but the last, `none`, `snappy`, `lz4` or `zstd`
+    #[clap(long)]
+    rocksdb_compression: Option<String>,
+
+    /// RocksDB: compression of the last level, `none`, `snappy`, `lz4` or `zstd`
+    #[clap(long)]
+    rocksdb_bottommost_compression: Option<String>,
+
+    /// RocksDB: bits per key of the bloom filters, 0 to disable them
+    #[clap(long)]
+    rocksdb_bloom_filter_bits_per_key: Option<f64>,
+
+    /// RocksDB: fsync the write-ahead log on every commit
+    #[clap(long)]
+    rocksdb_sync_wal: bool,
+
+    /// RocksDB: open read-only as a secondary instance keeping its logs in this directory
+    #[clap(long)]
+    rocksdb_secondary_path: Option<String>,
+
+    /// SQLite: journal mode, `delete`, `truncate`, `persist`, `memory`, `wal` or `off`
+    #[clap(long)]
+    sqlite_journal_mode: Option<String>,
+
+    /// SQLite: synchronous setting, `off`, `normal`, `full` or `extra`
+    #[clap(long)]
+    sqlite_synchronous: Option<String>,
+
+    /// SQLite: page cache size, in pages if positive, in KiB if negative
+    #[clap(long, allow_hyphen_values = true)]
+    sqlite_cache_size: Option<i64>,
+
+    /// SQLite: maximum number of bytes of the database file to memory-map
+    #[clap(long)]
+    sqlite_mmap_size: Option<u64>,
+
+    /// Sled: size in bytes of the page cache
+    #[clap(long)]
+    sled_cache_capacity: Option<u64>,
+
+    /// redb: size in bytes of the page cache
+    #[clap(long)]
+    redb_cache_size: Option<usize>,
+}
+
+impl EngineOptionArgs {
+    /// Merge the flags that are set into the JSON `config` of `engine`.
+    pub(crate) fn merge_into(&self, engine: &str, config: &str) -> Result<String, String> {
+        let mut options = match serde_json::from_str(config) {
+            Ok(Value::Object(map)) => map,
+            Ok(_) => return Err("the config must be a JSON object".to_string()),
+            Err(err) => return Err(format!("the config is not valid JSON: {err}")),
+        };
+        let encryption_key = match &self.encryption_key_file {
+            None => None,
+            Some(path) => {
+                let key = std::fs::read_to_string(path)
+                    .map_err(|err| format!("cannot read {}: {err}", path.display()))?;
+                Some(json!(key.trim_end_matches(['\r', '\n'])))
+            }
+        };
+        let is_rocksdb = engine == "rocksdb" || engine == "newrocksdb";
+        let mut set = |flag_engine: &str, applies: bool, key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                if !applies {
+                    return Err(format!(
+                        "the {flag_engine} options cannot be used with the {engine} engine"
+                    ));
+                }
+                options.insert(key.to_string(), value);
+            }
+            Ok(())
+        };
+        set("", true, "encryption_key", encryption_key)?;
+        set(
+            "",
+            true,
+            "sort_memory_budget",
+            self.sort_memory_budget.map(|v| json!(v)),
+        )?;
+        set(
+            "RocksDB or SQLite",
+            is_rocksdb || engine == "sqlite",
+            "read_only",
+            self.read_only.then(|| json!(true)),
+        )?;
+        set(
+            "RocksDB",
+            is_rocksdb,
+            "block_cache_size",
+            self.rocksdb_block_cache_size.map(|v| json!(v)),
+        )?;
+        set(
+            "RocksDB",
+            is_rocksdb,
+            "compression",
+            self.rocksdb_compression.as_ref().map(|v| json!(v)),
+        )?;
+        set(
+            "RocksDB",
+            is_rocksdb,
+            "bottommost_compression",
+            self.rocksdb_bottommost_compression
+                .as_ref()
+                .map(|v| json!(v)),
+        )?;
+        set(
+            "RocksDB",
+            is_rocksdb,
+            "bloom_filter_bits_per_key",
+            self.rocksdb_bloom_filter_bits_per_key.map(|v| json!(v)),
+        )?;
+        set(
+            "RocksDB",
+            is_rocksdb,
+            "sync_wal",
+            self.rocksdb_sync_wal.then(|| json!(true)),
+        )?;
+        set(
+            "RocksDB",
+            is_rocksdb,
+            "secondary_path",
+            self.rocksdb_secondary_path.as_ref().map(|v| json!(v)),
+        )?;
+        set(
+            "SQLite",
+            engine == "sqlite",
+            "journal_mode",
+            self.sqlite_journal_mode.as_ref().map(|v| json!(v)),
+        )?;
+        set(
+            "SQLite",
+            engine == "sqlite",
+            "synchronous",
+            self.sqlite_synchronous.as_ref().map(|v| json!(v)),
+        )?;
+        set(
+            "SQLite",
+            engine == "sqlite",
+            "cache_size",
+            self.sqlite_cache_size.map(|v| json!(v)),
+        )?;
+        set(
+            "SQLite",
+            engine == "sqlite",
+            "mmap_size",
+            self.sqlite_mmap_size.map(|v| json!(v)),
+        )?;
+        set(
+            "sled",
+            engine == "sled",
+            "cache_capacity",
+            self.sled_cache_capacity.map(|v| json!(v)),
+        )?;
+        set(
+            "redb",
+            engine == "redb",
+            "cache_size",
+            self.redb_cache_size.map(|v| json!(v)),
+        )?;
+        Ok(Value::Object(options).to_string())
+    }
+}
@@ -22,6 +22,8 @@ use serde_json::{json, Value};
 
 use cozo::{evaluate_expressions, DataValue, DbInstance, NamedRows, ScriptMutability};
 
+use crate::options::EngineOptionArgs;
+
 struct Indented;
 
 impl rustyline::hint::Hinter for Indented {
@@ -75,10 +77,14 @@ pub(crate) struct ReplArgs {
     /// Extra config in JSON format
     #[clap(short, long, default_value_t = String::from("{}"))]
     config: String,
+
+    #[command(flatten)]
+    engine_options: EngineOptionArgs,
 }
 
 pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
-    let db = DbInstance::new(&args.engine, args.path, &args.config).unwrap();
+    let config = args.engine_options.merge_into(&args.engine, &args.config)?;
+    let db = DbInstance::new(&args.engine, args.path, &config).unwrap();
 
     let db_copy = db.clone();
     ctrlc::set_handler(move || {
@@ -246,6 +252,34 @@ fn process_line(
                 db.backup_db(path)?;
                 println!("Backup written successfully to {path}")
             }
+            "backup_dir" => {
+                let path = payload.trim();
+                if path.is_empty() {
+                    bail!("Backup requires a directory");
+                };
+                let manifest = db.backup_to_dir(path)?;
+                println!(
+                    "Backup {} written to {path}: {} new files, {} bytes",
+                    manifest.id, manifest.new_files, manifest.new_bytes
+                )
+            }
+            "restore_dir" => {
+                let mut args = payload.split_whitespace();
+                let path = match args.next() {
+                    Some(path) => path,
+                    None => bail!("Restore requires a directory"),
+                };
+                let id = match args.next() {
+                    Some(id) => Some(id.parse::<u64>().into_diagnostic()?),
+                    None => None,
+                };
+                let manifest = db.restore_from_dir(path, id)?;
+                println!("Backup {} successfully loaded from {path}", manifest.id)
+            }
+            "catch_up" => {
+                db.catch_up_with_primary()?;
+                println!("Caught up with the primary instance")
+            }
             "run" => {
                 let path = payload.trim();
                 if path.is_empty() {
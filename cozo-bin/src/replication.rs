@@ -0,0 +1,208 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use log::{info, warn};
+use rand::Rng;
+use serde_json::json;
+
+use cozo::{DbInstance, WriteSet};
+
+#[derive(Args, Debug)]
+pub(crate) struct ReplicationArgs {
+    /// Record the committed writes so that replicas can follow this server
+    #[clap(long, conflicts_with = "replica_of")]
+    enable_replication: bool,
+
+    /// Run as a read-only replica of the server at this URL, e.g. `http://127.0.0.1:9070`,
+    /// which must have been started with `--enable-replication`
+    #[clap(long)]
+    replica_of: Option<String>,
+
+    /// Milliseconds to wait before asking the primary for new writes once caught up
+    #[clap(long, default_value_t = 1000)]
+    replica_poll_ms: u64,
+
+    /// Auth token sent to the primary, if it is not bound to 127.0.0.1
+    #[clap(long)]
+    replica_auth: Option<String>,
+}
+
+/// Number of write sets asked for at once by replicas.
+const LOG_PAGE_SIZE: usize = 1000;
+
+/// Body of `GET /replication/log`, in MessagePack.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct LogPage {
+    pub(crate) last_seq: u64,
+    pub(crate) write_sets: Vec<WriteSet>,
+}
+
+/// What a replica knows of its progress, shown by `GET /replication/status`.
+pub(crate) struct ReplicaStatus {
+    primary: String,
+    applied_seq: u64,
+    primary_seq: Option<u64>,
+    last_contact: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl ReplicaStatus {
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        json!({
+            "ok": true,
+            "role": "replica",
+            "primary": self.primary,
+            "applied_seq": self.applied_seq,
+            "primary_seq": self.primary_seq,
+            "lag": self.primary_seq.map(|seq| seq.saturating_sub(self.applied_seq)),
+            "secs_since_contact": self.last_contact.map(|t| t.elapsed().as_secs_f64()),
+            "last_error": self.last_error,
+        })
+    }
+}
+
+impl ReplicationArgs {
+    /// Enable the write log of `db` if this server is a primary, and if it is a replica,
+    /// bootstrap `db` from the primary when it is new and start following it.
+    pub(crate) fn start(
+        &self,
+        db: &DbInstance,
+    ) -> Result<Option<Arc<Mutex<ReplicaStatus>>>, String> {
+        if self.enable_replication {
+            let seq = db.enable_write_log().map_err(|err| err.to_string())?;
+            info!("Replication enabled, last write set: {seq}");
+        }
+        let primary = match &self.replica_of {
+            None => return Ok(None),
+            Some(url) => url.trim_end_matches('/').to_string(),
+        };
+        let applied_seq = match db.write_log_last_seq() {
+            Some(seq) => seq,
+            None => self.bootstrap(db, &primary)?,
+        };
+        info!("Replicating {primary} from write set {applied_seq}");
+        let status = Arc::new(Mutex::new(ReplicaStatus {
+            primary,
+            applied_seq,
+            primary_seq: None,
+            last_contact: None,
+            last_error: None,
+        }));
+        let db = db.clone();
+        let auth = self.replica_auth.clone();
+        let poll = Duration::from_millis(self.replica_poll_ms);
+        let ret = status.clone();
+        thread::spawn(move || loop {
+            let caught_up = match follow(&db, &status, auth.as_deref()) {
+                Ok(caught_up) => caught_up,
+                Err(err) => {
+                    warn!("Replication failed: {err}");
+                    status.lock().unwrap().last_error = Some(err);
+                    true
+                }
+            };
+            if caught_up {
+                thread::sleep(poll);
+            }
+        });
+        Ok(Some(ret))
+    }
+
+    fn bootstrap(&self, db: &DbInstance, primary: &str) -> Result<u64, String> {
+        info!("Bootstrapping from a snapshot of {primary}");
+        let path = temp_path("replica");
+        let restored = download(
+            &format!("{primary}/replication/snapshot"),
+            self.replica_auth.as_deref(),
+            &path,
+        )
+        .and_then(|_| db.restore_backup(&path).map_err(|err| err.to_string()));
+        let _ = std::fs::remove_file(&path);
+        restored?;
+        db.write_log_last_seq()
+            .ok_or_else(|| "the snapshot of the primary has no write log".to_string())
+    }
+}
+
+/// Apply the next page of write sets of the primary, returns whether there were no more.
+fn follow(
+    db: &DbInstance,
+    status: &Mutex<ReplicaStatus>,
+    auth: Option<&str>,
+) -> Result<bool, String> {
+    let (primary, after) = {
+        let status = status.lock().unwrap();
+        (status.primary.clone(), status.applied_seq)
+    };
+    let resp = get(
+        &format!("{primary}/replication/log?after={after}&limit={LOG_PAGE_SIZE}"),
+        auth,
+    )?;
+    let page: LogPage = rmp_serde::from_slice(resp.as_bytes()).map_err(|err| err.to_string())?;
+    let n_write_sets = page.write_sets.len();
+    let applied_seq = db
+        .apply_write_sets(&page.write_sets)
+        .map_err(|err| format!("{err:?}"))?;
+    let mut status = status.lock().unwrap();
+    status.applied_seq = applied_seq;
+    status.primary_seq = Some(page.last_seq);
+    status.last_contact = Some(Instant::now());
+    status.last_error = None;
+    Ok(n_write_sets < LOG_PAGE_SIZE)
+}
+
+fn request(url: &str, auth: Option<&str>) -> minreq::Request {
+    let mut req = minreq::get(url);
+    if let Some(auth) = auth {
+        req = req.with_header("x-cozo-auth", auth);
+    }
+    req
+}
+
+fn get(url: &str, auth: Option<&str>) -> Result<minreq::Response, String> {
+    let resp = request(url, auth)
+        .send()
+        .map_err(|err| format!("{url}: {err}"))?;
+    if resp.status_code != 200 {
+        return Err(format!(
+            "{url}: {} {}",
+            resp.status_code,
+            resp.as_str().unwrap_or_default()
+        ));
+    }
+    Ok(resp)
+}
+
+/// Write the body of the response to the file as it is received.
+fn download(url: &str, auth: Option<&str>, path: &Path) -> Result<(), String> {
+    let mut resp = request(url, auth)
+        .send_lazy()
+        .map_err(|err| format!("{url}: {err}"))?;
+    if resp.status_code != 200 {
+        let mut message = String::new();
+        let _ = resp.read_to_string(&mut message);
+        return Err(format!("{url}: {} {message}", resp.status_code));
+    }
+    let mut file = File::create(path).map_err(|err| err.to_string())?;
+    io::copy(&mut resp, &mut file).map_err(|err| format!("{url}: {err}"))?;
+    Ok(())
+}
+
+/// A path in the temporary directory for a snapshot being transferred.
+pub(crate) fn temp_path(kind: &str) -> PathBuf {
+    let id: u64 = rand::thread_rng().gen();
+    std::env::temp_dir().join(format!("cozo-{kind}-snapshot-{id:016x}.db"))
+}
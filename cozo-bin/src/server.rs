@@ -13,9 +13,9 @@ use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU32, Ordering};
 
-use axum::body::Body;
+use axum::body::{Body, Bytes};
 use axum::extract::{DefaultBodyLimit, Path, Query, State};
-use axum::http::{header, HeaderName, Method, Request, Response, StatusCode};
+use axum::http::{header, HeaderMap, HeaderName, Method, Request, Response, StatusCode};
 use axum::response::sse::{Event, KeepAlive};
 use axum::response::{Html, Sse};
 use axum::routing::{get, post, put};
@@ -29,13 +29,16 @@ use miette::miette;
 // use miette::miette;
 use rand::Rng;
 use serde_json::json;
+use tokio::io::AsyncReadExt;
 use tokio::net::TcpListener;
 use tokio::task::spawn_blocking;
 use tower_http::auth::{AsyncAuthorizeRequest, AsyncRequireAuthorizationLayer};
 use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
 
-use cozo::{DataValue, DbInstance, format_error_as_json, MultiTransaction, NamedRows, ScriptMutability, SimpleFixedRule};
+use crate::options::EngineOptionArgs;
+use crate::replication::{temp_path, LogPage, ReplicaStatus, ReplicationArgs};
+use cozo::{ChangelogEntry, DataValue, DbInstance, format_error_as_json, MultiTransaction, NamedRows, PreparedQuery, ScriptMutability, SimpleFixedRule};
 
 #[derive(Args, Debug)]
 pub(crate) struct ServerArgs {
@@ -55,6 +58,9 @@ pub(crate) struct ServerArgs {
     #[clap(short, long, default_value_t = String::from("{}"))]
     config: String,
 
+    #[command(flatten)]
+    engine_options: EngineOptionArgs,
+
     /// Address to bind the service to
     #[clap(short, long, default_value_t = String::from("127.0.0.1"))]
     bind: String,
@@ -66,6 +72,9 @@ pub(crate) struct ServerArgs {
     /// When set, the content of the named table will be used as a token table
     #[clap(long)]
     token_table: Option<String>,
+
+    #[command(flatten)]
+    replication: ReplicationArgs,
 }
 
 #[derive(Clone)]
@@ -75,11 +84,15 @@ struct DbState {
     rule_counter: Arc<AtomicU32>,
     tx_counter: Arc<AtomicU32>,
     txs: Arc<Mutex<BTreeMap<u32, Arc<MultiTransaction>>>>,
+    prepared_counter: Arc<AtomicU32>,
+    prepared: Arc<Mutex<BTreeMap<u32, Arc<PreparedQuery>>>>,
+    replica: Option<Arc<Mutex<ReplicaStatus>>>,
 }
 
 #[derive(Clone)]
 struct MyAuth {
     skip_auth: bool,
+    read_only: bool,
     auth_guard: String,
     token_table: Option<Arc<(String, DbInstance)>>,
 }
@@ -92,11 +105,16 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
 
     fn authorize(&mut self, mut request: Request<Body>) -> Self::Future {
         let skip_auth = self.skip_auth;
+        let read_only = self.read_only;
         let auth_guard = self.auth_guard.clone();
         let token_table = self.token_table.clone();
         Box::pin(async move {
             if skip_auth {
-                request.extensions_mut().insert(ScriptMutability::Mutable);
+                request.extensions_mut().insert(if read_only {
+                    ScriptMutability::Immutable
+                } else {
+                    ScriptMutability::Mutable
+                });
                 return Ok(request);
             }
 
@@ -171,7 +189,11 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
                 },
             };
             if let Some(mutability) = mutability {
-                request.extensions_mut().insert(mutability);
+                request.extensions_mut().insert(if read_only {
+                    ScriptMutability::Immutable
+                } else {
+                    mutability
+                });
                 Ok(request)
             } else {
                 let unauthorized_response = Response::builder()
@@ -189,7 +211,14 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
 fn x() {}
 
 pub(crate) async fn server_main(args: ServerArgs) {
-    let db = DbInstance::new(&args.engine, &args.path, &args.config).unwrap();
+    let config = match args.engine_options.merge_into(&args.engine, &args.config) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            panic!()
+        }
+    };
+    let db = DbInstance::new(&args.engine, &args.path, &config).unwrap();
     if let Some(p) = &args.restore {
         if let Err(err) = db.restore_backup(p) {
             error!("{}", err);
@@ -197,6 +226,14 @@ pub(crate) async fn server_main(args: ServerArgs) {
             panic!()
         }
     }
+    let replica = match args.replication.start(&db) {
+        Ok(replica) => replica,
+        Err(err) => {
+            error!("{}", err);
+            error!("Starting replication failed, terminate");
+            panic!()
+        }
+    };
 
     let skip_auth = args.bind == "127.0.0.1";
 
@@ -224,6 +261,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
 
     let auth_obj = MyAuth {
         skip_auth,
+        read_only: replica.is_some(),
         auth_guard,
         token_table: args.token_table.map(|t| Arc::new((t, db.clone()))),
     };
@@ -234,6 +272,9 @@ pub(crate) async fn server_main(args: ServerArgs) {
         rule_counter: Default::default(),
         tx_counter: Default::default(),
         txs: Default::default(),
+        prepared_counter: Default::default(),
+        prepared: Default::default(),
+        replica,
     };
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
@@ -245,8 +286,10 @@ pub(crate) async fn server_main(args: ServerArgs) {
         .route("/export/:relations", get(export_relations))
         .route("/import", put(import_relations))
         .route("/backup", post(backup))
+        .route("/backup-dir", post(backup_to_dir))
         .route("/import-from-backup", post(import_from_backup))
         .route("/changes/:relation", get(observe_changes))
+        .route("/changelog/:relation", get(read_changelog))
         .route("/rules/:name", get(register_rule))
         .route(
             "/rule-result/:id",
@@ -254,6 +297,15 @@ pub(crate) async fn server_main(args: ServerArgs) {
         ) // +keep alive
         .route("/transact", post(start_transact))
         .route("/transact/:id", post(transact_query).put(finish_query))
+        .route("/prepare", post(prepare_query))
+        .route(
+            "/prepared/:id",
+            post(run_prepared_query).delete(close_prepared_query),
+        )
+        .route("/replication/status", get(replication_status))
+        .route("/replication/snapshot", get(replication_snapshot))
+        .route("/replication/log", get(replication_log))
+        .route("/replication/truncate", post(replication_truncate))
         .with_state(state)
         .layer(AsyncRequireAuthorizationLayer::new(auth_obj))
         .fallback(not_found)
@@ -291,6 +343,11 @@ async fn start_transact(
     State(st): State<DbState>,
     Query(payload): Query<StartTransactPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if payload.write {
+        if let Some(rejected) = reject_on_replica(&st, "write transactions") {
+            return rejected;
+        }
+    }
     let tx = st.db.multi_transaction(payload.write);
     let id = st.tx_counter.fetch_add(1, Ordering::SeqCst);
     st.txs.lock().unwrap().insert(id, Arc::new(tx));
@@ -394,6 +451,84 @@ async fn text_query(
     }
 }
 
+#[derive(serde_derive::Deserialize)]
+struct PreparePayload {
+    script: String,
+}
+
+async fn prepare_query(
+    State(st): State<DbState>,
+    Json(payload): Json<PreparePayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let db = st.db.clone();
+    let result = spawn_blocking(move || db.prepare_str(&payload.script)).await;
+    match result {
+        Ok(Ok(query)) => {
+            let id = st.prepared_counter.fetch_add(1, Ordering::SeqCst);
+            st.prepared.lock().unwrap().insert(id, Arc::new(query));
+            (StatusCode::OK, json!({"ok": true, "id": id}).into())
+        }
+        Ok(Err(err)) => (
+            StatusCode::BAD_REQUEST,
+            serde_json::from_str::<serde_json::Value>(&err).unwrap().into(),
+        ),
+        Err(err) => internal_error(err),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct PreparedQueryPayload {
+    params: BTreeMap<String, serde_json::Value>,
+    immutable: Option<bool>,
+}
+
+async fn run_prepared_query(
+    Extension(mutability): Extension<ScriptMutability>,
+    State(st): State<DbState>,
+    Path(id): Path<u32>,
+    Json(payload): Json<PreparedQueryPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let query = match st.prepared.lock().unwrap().get(&id) {
+        None => return (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+        Some(query) => query.clone(),
+    };
+    let params = payload
+        .params
+        .into_iter()
+        .map(|(k, v)| (k, DataValue::from(v)))
+        .collect();
+    let immutable = match mutability {
+        ScriptMutability::Mutable => payload.immutable.unwrap_or(false),
+        ScriptMutability::Immutable => true,
+    };
+    let result = spawn_blocking(move || {
+        st.db.run_prepared_fold_err(
+            &query,
+            params,
+            if immutable {
+                ScriptMutability::Immutable
+            } else {
+                ScriptMutability::Mutable
+            },
+        )
+    })
+        .await;
+    match result {
+        Ok(res) => wrap_json(res),
+        Err(err) => internal_error(err),
+    }
+}
+
+async fn close_prepared_query(
+    State(st): State<DbState>,
+    Path(id): Path<u32>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match st.prepared.lock().unwrap().remove(&id) {
+        None => (StatusCode::NOT_FOUND, json!({"ok": false}).into()),
+        Some(_) => (StatusCode::OK, json!({"ok": true}).into()),
+    }
+}
+
 async fn export_relations(
     State(st): State<DbState>,
     Path(relations): Path<String>,
@@ -427,6 +562,9 @@ async fn import_relations(
     State(st): State<DbState>,
     Json(payload): Json<serde_json::Value>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if let Some(rejected) = reject_on_replica(&st, "imports") {
+        return rejected;
+    }
     let payload = match payload.as_object() {
         None => {
             return (
@@ -487,6 +625,33 @@ async fn backup(
     }
 }
 
+async fn backup_to_dir(
+    State(st): State<DbState>,
+    Json(payload): Json<BackupPayload>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let result = spawn_blocking(move || st.db.backup_to_dir(payload.path)).await;
+
+    match result {
+        Ok(Ok(manifest)) => {
+            let ret = json!({
+                "ok": true,
+                "id": manifest.id,
+                "parent": manifest.parent,
+                "kind": manifest.kind,
+                "files": manifest.files.len(),
+                "new_files": manifest.new_files,
+                "new_bytes": manifest.new_bytes
+            });
+            (StatusCode::OK, ret.into())
+        }
+        Ok(Err(err)) => {
+            let ret = json!({"ok": false, "message": err.to_string()});
+            (StatusCode::BAD_REQUEST, ret.into())
+        }
+        Err(err) => internal_error(err),
+    }
+}
+
 #[derive(serde_derive::Deserialize)]
 struct BackupImportPayload {
     path: String,
@@ -497,6 +662,9 @@ async fn import_from_backup(
     State(st): State<DbState>,
     Json(payload): Json<BackupImportPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if let Some(rejected) = reject_on_replica(&st, "imports") {
+        return rejected;
+    }
     let result =
         spawn_blocking(move || st.db.import_from_backup(&payload.path, &payload.relations)).await;
 
@@ -626,10 +794,53 @@ async fn register_rule(
     Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
+#[derive(serde_derive::Deserialize)]
+struct ChangesOptions {
+    after: Option<u64>,
+    limit: Option<usize>,
+}
+
+fn changelog_entry_json(entry: ChangelogEntry) -> serde_json::Value {
+    json!({"seq": entry.seq, "op": entry.op.to_string(), "new_rows": entry.new.into_json(), "old_rows": entry.old.into_json()})
+}
+
+async fn read_changelog(
+    State(st): State<DbState>,
+    Path(relation): Path<String>,
+    Query(opts): Query<ChangesOptions>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let result = spawn_blocking(move || {
+        st.db
+            .read_changelog(&relation, opts.after.unwrap_or(0), opts.limit)
+    })
+        .await;
+    match result {
+        Ok(Ok(entries)) => {
+            let entries = entries.into_iter().map(changelog_entry_json).collect_vec();
+            (StatusCode::OK, json!({"ok": true, "entries": entries}).into())
+        }
+        Ok(Err(err)) => {
+            let ret = json!({"ok": false, "message": err.to_string()});
+            (StatusCode::BAD_REQUEST, ret.into())
+        }
+        Err(err) => internal_error(err),
+    }
+}
+
 async fn observe_changes(
     State(st): State<DbState>,
     Path(relation): Path<String>,
+    Query(opts): Query<ChangesOptions>,
+    headers: HeaderMap,
 ) -> Sse<impl Stream<Item=Result<Event, Infallible>>> {
+    // with a sequence number to resume from, events are read from the changelog
+    // and callbacks only signal that there are new entries
+    let after = opts.after.or_else(|| {
+        headers
+            .get("last-event-id")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+    });
     let (id, recv) = st.db.register_callback(&relation, None);
     let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
     struct Guard {
@@ -652,15 +863,171 @@ async fn observe_changes(
     });
     let stream = async_stream::stream! {
         info!("starting changes SSE {}: {}", relation, id);
-        let _guard = Guard {id, db: st.db, relation};
-        while let Some((op, new, old)) = receiver.recv().await {
-            let item = json!({"op": op.to_string(), "new_rows": new.into_json(), "old_rows": old.into_json()});
-            yield Ok(Event::default().json_data(item).unwrap());
+        let db = st.db.clone();
+        let _guard = Guard {id, db: st.db, relation: relation.clone()};
+        match after {
+            None => {
+                while let Some((op, new, old)) = receiver.recv().await {
+                    let item = json!({"op": op.to_string(), "new_rows": new.into_json(), "old_rows": old.into_json()});
+                    yield Ok(Event::default().json_data(item).unwrap());
+                }
+            }
+            Some(mut after) => loop {
+                let (db, relation) = (db.clone(), relation.clone());
+                match spawn_blocking(move || db.read_changelog(&relation, after, None)).await {
+                    Ok(Ok(entries)) => {
+                        for entry in entries {
+                            after = entry.seq;
+                            let event = Event::default().id(entry.seq.to_string());
+                            yield Ok(event.json_data(changelog_entry_json(entry)).unwrap());
+                        }
+                    }
+                    Ok(Err(err)) => {
+                        let item = json!({"type": "changelog-error", "error": err.to_string()});
+                        yield Ok(Event::default().json_data(item).unwrap());
+                        break;
+                    }
+                    Err(err) => {
+                        let item = json!({"type": "changelog-error", "error": err.to_string()});
+                        yield Ok(Event::default().json_data(item).unwrap());
+                        break;
+                    }
+                }
+                if receiver.recv().await.is_none() {
+                    break;
+                }
+            }
         }
     };
     Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
+async fn replication_status(
+    State(st): State<DbState>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let ret = match &st.replica {
+        Some(status) => status.lock().unwrap().to_json(),
+        None => match st.db.write_log_last_seq() {
+            Some(seq) => json!({"ok": true, "role": "primary", "last_seq": seq}),
+            None => json!({"ok": true, "role": "standalone"}),
+        },
+    };
+    (StatusCode::OK, ret.into())
+}
+
+/// Size of the pieces a snapshot is read and sent in.
+const SNAPSHOT_CHUNK_SIZE: usize = 1 << 16;
+
+async fn replication_snapshot(
+    State(st): State<DbState>,
+) -> Response<Body> {
+    let path = temp_path("primary");
+    let backup_path = path.clone();
+    let result = spawn_blocking(move || {
+        if st.db.write_log_last_seq().is_none() {
+            return Err(miette!("replication is not enabled, start the server with --enable-replication"));
+        }
+        st.db.backup_db(&backup_path)
+    })
+        .await;
+    let file = match result {
+        Ok(Ok(())) => tokio::fs::File::open(&path).await.map_err(|err| miette!(err)),
+        Ok(Err(err)) => Err(err),
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(err.to_string()))
+                .unwrap();
+        }
+    };
+    let mut file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(err.to_string()))
+                .unwrap();
+        }
+    };
+    // the backup is sent as it is read from the file, which is removed afterwards
+    let stream = async_stream::stream! {
+        let mut buf = vec![0; SNAPSHOT_CHUNK_SIZE];
+        loop {
+            match file.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => yield Ok(Bytes::copy_from_slice(&buf[..n])),
+                Err(err) => {
+                    yield Err(err);
+                    break;
+                }
+            }
+        }
+        drop(file);
+        let _ = tokio::fs::remove_file(&path).await;
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
+
+#[derive(serde_derive::Deserialize)]
+struct ReplicationLogOptions {
+    after: u64,
+    limit: Option<usize>,
+}
+
+async fn replication_log(
+    State(st): State<DbState>,
+    Query(opts): Query<ReplicationLogOptions>,
+) -> Response<Body> {
+    let result = spawn_blocking(move || -> miette::Result<Vec<u8>> {
+        let write_sets = st.db.read_write_log(opts.after, opts.limit)?;
+        let page = LogPage {
+            last_seq: st.db.write_log_last_seq().unwrap_or_default(),
+            write_sets,
+        };
+        rmp_serde::to_vec(&page).map_err(|err| miette!(err))
+    })
+        .await;
+    match result {
+        Ok(Ok(data)) => Response::builder()
+            .header(header::CONTENT_TYPE, "application/msgpack")
+            .body(Body::from(data))
+            .unwrap(),
+        Ok(Err(err)) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(err.to_string()))
+            .unwrap(),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(err.to_string()))
+            .unwrap(),
+    }
+}
+
+#[derive(serde_derive::Deserialize)]
+struct ReplicationTruncateOptions {
+    up_to: u64,
+}
+
+async fn replication_truncate(
+    State(st): State<DbState>,
+    Query(opts): Query<ReplicationTruncateOptions>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let result = spawn_blocking(move || st.db.truncate_write_log(opts.up_to)).await;
+    match result {
+        Ok(Ok(())) => (StatusCode::OK, json!({"ok": true}).into()),
+        Ok(Err(err)) => {
+            let ret = json!({"ok": false, "message": err.to_string()});
+            (StatusCode::BAD_REQUEST, ret.into())
+        }
+        Err(err) => internal_error(err),
+    }
+}
+
 async fn root() -> Html<&'static str> {
     Html(include_str!("./index.html"))
 }
@@ -675,6 +1042,15 @@ fn internal_error<E>(err: E) -> (StatusCode, Json<serde_json::Value>)
     )
 }
 
+fn reject_on_replica(st: &DbState, what: &str) -> Option<(StatusCode, Json<serde_json::Value>)> {
+    st.replica.as_ref().map(|_| {
+        (
+            StatusCode::FORBIDDEN,
+            json!({"ok": false, "message": format!("{what} are not allowed on a replica")}).into(),
+        )
+    })
+}
+
 fn wrap_json(json: serde_json::Value) -> (StatusCode, Json<serde_json::Value>) {
     let code = if let Some(serde_json::Value::Bool(true)) = json.get("ok") {
         StatusCode::OK
@@ -6,19 +6,30 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::any::Any;
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
 
-use miette::{bail, ensure, miette, Result};
+use crossbeam::channel::{bounded, Receiver, Sender};
+use lazy_static::lazy_static;
+use miette::{bail, ensure, miette, IntoDiagnostic, Result};
 use rand::prelude::*;
+use rust_decimal::Decimal;
+use twox_hash::XxHash64;
 
-use crate::data::value::DataValue;
+use crate::data::value::{DataValue, Num};
 
 pub struct Aggregation {
+    /// The name of the definition for built-in aggregations, e.g. `AGGR_SUM`,
+    /// and the registered name for custom ones.
     pub name: &'static str,
     pub is_meet: bool,
     pub meet_op: Option<Box<dyn MeetAggrObj>>,
     pub normal_op: Option<Box<dyn NormalAggrObj>>,
+    pub(crate) custom: Option<Arc<dyn CustomAggregation>>,
 }
 
 impl Clone for Aggregation {
@@ -28,6 +39,7 @@ impl Clone for Aggregation {
             is_meet: self.is_meet,
             meet_op: None,
             normal_op: None,
+            custom: self.custom.clone(),
         }
     }
 }
@@ -35,6 +47,23 @@ impl Clone for Aggregation {
 pub trait NormalAggrObj: Send + Sync {
     fn set(&mut self, value: &DataValue) -> Result<()>;
     fn get(&self) -> Result<DataValue>;
+    /// Merge in the state of another instance of the same aggregation, accumulated
+    /// over a different part of the values. Only supported by mergeable aggregations.
+    fn merge(&mut self, _other: &dyn NormalAggrObj) -> Result<()> {
+        bail!("aggregation does not support merging partial states")
+    }
+    /// The aggregation as [Any], so that `merge` can get at the state of the other instance.
+    /// Only needed by mergeable aggregations.
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+}
+
+fn downcast_aggr<'a, T: 'static>(name: &str, other: &'a dyn NormalAggrObj) -> Result<&'a T> {
+    other
+        .as_any()
+        .and_then(|other| other.downcast_ref::<T>())
+        .ok_or_else(|| miette!("cannot merge a different aggregation into '{}'", name))
 }
 
 pub trait MeetAggrObj: Send + Sync {
@@ -42,15 +71,154 @@ pub trait MeetAggrObj: Send + Sync {
     fn update(&self, left: &mut DataValue, right: &DataValue) -> Result<bool>;
 }
 
+/// A user-defined aggregation, registered with [crate::Db::register_aggregation].
+///
+/// The state of the aggregation is held as a [DataValue].
+/// For a normal aggregation, the state is created by `init` for each group,
+/// every value in the group is folded in by `update`,
+/// and the result is computed from the final state by `finalize`.
+///
+/// A meet aggregation can be used in recursive rules. For these the values
+/// aggregated and the state are the same thing: the result is computed
+/// by `merge`ing every value into the state created by `init`, and `update` and `finalize`
+/// are not used. For the result to be well-defined, `merge` must be idempotent,
+/// commutative and associative.
+pub trait CustomAggregation: Send + Sync {
+    /// Whether this is a meet aggregation.
+    fn is_meet(&self) -> bool {
+        false
+    }
+    /// Create the initial state, given the arguments passed to the aggregation,
+    /// e.g. `[10]` for `my_aggr(x, 10)`.
+    fn init(&self, args: &[DataValue]) -> Result<DataValue>;
+    /// Fold a value into the state.
+    fn update(&self, state: &mut DataValue, value: &DataValue) -> Result<()>;
+    /// Merge another state into the state, returning whether the state has changed.
+    fn merge(&self, state: &mut DataValue, other: &DataValue) -> Result<bool>;
+    /// Compute the result from the final state.
+    fn finalize(&self, state: &DataValue) -> Result<DataValue>;
+}
+
+/// An invocation of one of the operations of a [SimpleAggregation].
+#[derive(Debug, Clone)]
+pub enum AggregationCall {
+    /// Create the initial state from the arguments.
+    Init(Vec<DataValue>),
+    /// Fold the value (second) into the state (first), returning the new state.
+    Update(DataValue, DataValue),
+    /// Merge the other state (second) into the state (first), returning the new state.
+    Merge(DataValue, DataValue),
+    /// Compute the result from the state.
+    Finalize(DataValue),
+}
+
+/// Simple wrapper for custom aggregations implemented by a closure,
+/// where the state is passed by value.
+pub struct SimpleAggregation {
+    is_meet: bool,
+    aggr: Box<dyn Fn(AggregationCall) -> Result<DataValue> + Send + Sync + 'static>,
+}
+
+impl SimpleAggregation {
+    /// Construct a SimpleAggregation.
+    ///
+    /// * `is_meet`: Whether this is a meet aggregation.
+    /// * `aggr`: The aggregation implementation as a closure, which is called with each
+    ///   invocation and returns the new state, or the result for `Finalize`.
+    pub fn new<A>(is_meet: bool, aggr: A) -> Self
+    where
+        A: Fn(AggregationCall) -> Result<DataValue> + Send + Sync + 'static,
+    {
+        Self {
+            is_meet,
+            aggr: Box::new(aggr),
+        }
+    }
+    /// Construct a SimpleAggregation that uses channels for communication.
+    pub fn aggregation_with_channel(
+        is_meet: bool,
+    ) -> (Self, Receiver<(AggregationCall, Sender<Result<DataValue>>)>) {
+        let (db2app_sender, db2app_receiver) = bounded(0);
+        (
+            Self {
+                is_meet,
+                aggr: Box::new(move |call| -> Result<DataValue> {
+                    let (app2db_sender, app2db_receiver) = bounded(0);
+                    db2app_sender
+                        .send((call, app2db_sender))
+                        .into_diagnostic()?;
+                    app2db_receiver.recv().into_diagnostic()?
+                }),
+            },
+            db2app_receiver,
+        )
+    }
+}
+
+impl CustomAggregation for SimpleAggregation {
+    fn is_meet(&self) -> bool {
+        self.is_meet
+    }
+
+    fn init(&self, args: &[DataValue]) -> Result<DataValue> {
+        (self.aggr)(AggregationCall::Init(args.to_vec()))
+    }
+
+    fn update(&self, state: &mut DataValue, value: &DataValue) -> Result<()> {
+        let old = std::mem::replace(state, DataValue::Null);
+        *state = (self.aggr)(AggregationCall::Update(old, value.clone()))?;
+        Ok(())
+    }
+
+    fn merge(&self, state: &mut DataValue, other: &DataValue) -> Result<bool> {
+        let new = (self.aggr)(AggregationCall::Merge(state.clone(), other.clone()))?;
+        if new == *state {
+            Ok(false)
+        } else {
+            *state = new;
+            Ok(true)
+        }
+    }
+
+    fn finalize(&self, state: &DataValue) -> Result<DataValue> {
+        (self.aggr)(AggregationCall::Finalize(state.clone()))
+    }
+}
+
+struct CustomAggr {
+    aggr: Arc<dyn CustomAggregation>,
+    state: DataValue,
+}
+
+impl NormalAggrObj for CustomAggr {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.aggr.update(&mut self.state, value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        self.aggr.finalize(&self.state)
+    }
+}
+
+impl MeetAggrObj for CustomAggr {
+    fn init_val(&self) -> DataValue {
+        self.state.clone()
+    }
+
+    fn update(&self, left: &mut DataValue, right: &DataValue) -> Result<bool> {
+        self.aggr.merge(left, right)
+    }
+}
+
 impl PartialEq for Aggregation {
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
+        self.name() == other.name()
     }
 }
 
 impl Debug for Aggregation {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Aggr<{}>", self.name)
+        write!(f, "Aggr<{}>", self.name())
     }
 }
 
@@ -61,6 +229,7 @@ macro_rules! define_aggr {
             is_meet: $is_meet,
             meet_op: None,
             normal_op: None,
+            custom: None,
         };
     };
 }
@@ -496,12 +665,68 @@ impl NormalAggrObj for AggrStdDev {
     }
 }
 
+/// Exact running result over ints and decimals, kept alongside the float one.
+/// It is the result of the aggregation if some input was a decimal and none was a float.
+pub(crate) struct ExactAccum {
+    value: Decimal,
+    exact: bool,
+    seen_decimal: bool,
+}
+
+impl ExactAccum {
+    pub(crate) fn new(init: Decimal) -> Self {
+        Self {
+            value: init,
+            exact: true,
+            seen_decimal: false,
+        }
+    }
+    pub(crate) fn update(
+        &mut self,
+        n: &Num,
+        op: fn(Decimal, Decimal) -> Option<Decimal>,
+        name: &str,
+    ) -> Result<()> {
+        match n {
+            Num::Float(_) => self.exact = false,
+            Num::Decimal(_) => self.seen_decimal = true,
+            Num::Int(_) => {}
+        }
+        if self.exact {
+            match op(self.value, n.get_decimal().unwrap()) {
+                Some(v) => self.value = v,
+                None if self.seen_decimal => bail!("decimal overflow when computing '{}'", name),
+                // only ints so far: fall back to the float result, as before decimals existed
+                None => self.exact = false,
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn get(&self) -> Option<Decimal> {
+        if self.exact && self.seen_decimal {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+}
+
 define_aggr!(AGGR_MEAN, false);
 
-#[derive(Default)]
 pub(crate) struct AggrMean {
     count: i64,
     sum: f64,
+    exact: ExactAccum,
+}
+
+impl Default for AggrMean {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0.,
+            exact: ExactAccum::new(Decimal::ZERO),
+        }
+    }
 }
 
 impl NormalAggrObj for AggrMean {
@@ -509,6 +734,7 @@ impl NormalAggrObj for AggrMean {
         match value {
             DataValue::Num(n) => {
                 self.sum += n.get_float();
+                self.exact.update(n, Decimal::checked_add, "mean")?;
                 self.count += 1;
             }
             v => bail!("cannot compute 'mean': encountered value {:?}", v),
@@ -517,15 +743,29 @@ impl NormalAggrObj for AggrMean {
     }
 
     fn get(&self) -> Result<DataValue> {
+        if let Some(sum) = self.exact.get() {
+            if let Some(mean) = sum.checked_div(Decimal::from(self.count)) {
+                return Ok(DataValue::from(mean));
+            }
+        }
         Ok(DataValue::from(self.sum / (self.count as f64)))
     }
 }
 
 define_aggr!(AGGR_SUM, false);
 
-#[derive(Default)]
 pub(crate) struct AggrSum {
     sum: f64,
+    exact: ExactAccum,
+}
+
+impl Default for AggrSum {
+    fn default() -> Self {
+        Self {
+            sum: 0.,
+            exact: ExactAccum::new(Decimal::ZERO),
+        }
+    }
 }
 
 impl NormalAggrObj for AggrSum {
@@ -533,6 +773,7 @@ impl NormalAggrObj for AggrSum {
         match value {
             DataValue::Num(n) => {
                 self.sum += n.get_float();
+                self.exact.update(n, Decimal::checked_add, "sum")?;
             }
             v => bail!("cannot compute 'sum': encountered value {:?}", v),
         }
@@ -540,7 +781,10 @@ impl NormalAggrObj for AggrSum {
     }
 
     fn get(&self) -> Result<DataValue> {
-        Ok(DataValue::from(self.sum))
+        Ok(match self.exact.get() {
+            Some(sum) => DataValue::from(sum),
+            None => DataValue::from(self.sum),
+        })
     }
 }
 
@@ -548,11 +792,15 @@ define_aggr!(AGGR_PRODUCT, false);
 
 pub(crate) struct AggrProduct {
     product: f64,
+    exact: ExactAccum,
 }
 
 impl Default for AggrProduct {
     fn default() -> Self {
-        Self { product: 1.0 }
+        Self {
+            product: 1.0,
+            exact: ExactAccum::new(Decimal::ONE),
+        }
     }
 }
 
@@ -561,6 +809,7 @@ impl NormalAggrObj for AggrProduct {
         match value {
             DataValue::Num(n) => {
                 self.product *= n.get_float();
+                self.exact.update(n, Decimal::checked_mul, "product")?;
             }
             v => bail!("cannot compute 'product': encountered value {:?}", v),
         }
@@ -568,7 +817,18 @@ impl NormalAggrObj for AggrProduct {
     }
 
     fn get(&self) -> Result<DataValue> {
-        Ok(DataValue::from(self.product))
+        Ok(match self.exact.get() {
+            Some(product) => DataValue::from(product),
+            None => DataValue::from(self.product),
+        })
+    }
+}
+
+/// Compares numbers by value, exactly unless a float is involved.
+fn numeric_cmp(l: &DataValue, r: &DataValue, name: &str) -> Result<Option<Ordering>> {
+    match (l, r) {
+        (DataValue::Num(l), DataValue::Num(r)) => Ok(l.numeric_cmp(r)),
+        _ => bail!("'{}' applied to non-numerical values", name),
     }
 }
 
@@ -595,14 +855,7 @@ impl NormalAggrObj for AggrMin {
             self.found = value.clone();
             return Ok(());
         }
-        let f1 = self
-            .found
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-        let f2 = value
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-        if f1 > f2 {
+        if numeric_cmp(&self.found, value, "min")? == Some(Ordering::Greater) {
             self.found = value.clone();
         }
         Ok(())
@@ -628,19 +881,14 @@ impl MeetAggrObj for MeetAggrMin {
             *left = right.clone();
             return Ok(true);
         }
-        let f1 = left
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-        let f2 = right
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-
-        Ok(if f1 > f2 {
-            *left = right.clone();
-            true
-        } else {
-            false
-        })
+        Ok(
+            if numeric_cmp(left, right, "min")? == Some(Ordering::Greater) {
+                *left = right.clone();
+                true
+            } else {
+                false
+            },
+        )
     }
 }
 
@@ -667,14 +915,7 @@ impl NormalAggrObj for AggrMax {
             self.found = value.clone();
             return Ok(());
         }
-        let f1 = self
-            .found
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-        let f2 = value
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-        if f1 < f2 {
+        if numeric_cmp(&self.found, value, "max")? == Some(Ordering::Less) {
             self.found = value.clone();
         }
         Ok(())
@@ -700,19 +941,14 @@ impl MeetAggrObj for MeetAggrMax {
             *left = right.clone();
             return Ok(true);
         }
-        let f1 = left
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-        let f2 = right
-            .get_float()
-            .ok_or_else(|| miette!("'min' applied to non-numerical values"))?;
-
-        Ok(if f1 < f2 {
-            *left = right.clone();
-            true
-        } else {
-            false
-        })
+        Ok(
+            if numeric_cmp(left, right, "max")? == Some(Ordering::Less) {
+                *left = right.clone();
+                true
+            } else {
+                false
+            },
+        )
     }
 }
 
@@ -1155,6 +1391,420 @@ impl NormalAggrObj for AggrBitXor {
     }
 }
 
+fn hash_value(value: &DataValue) -> u64 {
+    // a fixed hash, so that sketches built separately are consistent with each other
+    let mut hasher = XxHash64::with_seed(0);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A HyperLogLog sketch for estimating the number of distinct values.
+pub(crate) struct HyperLogLog {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub(crate) fn new(precision: u32) -> Self {
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+    pub(crate) fn insert(&mut self, value: &DataValue) {
+        let hash = hash_value(value);
+        let idx = (hash >> (64 - self.precision)) as usize;
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[idx] {
+            self.registers[idx] = rank;
+        }
+    }
+    pub(crate) fn merge(&mut self, other: &Self) -> Result<()> {
+        ensure!(
+            self.precision == other.precision,
+            "cannot merge sketches of 'approx_count_distinct' with different precisions"
+        );
+        for (l, r) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if *r > *l {
+                *l = *r;
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1. + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            // linear counting is more accurate for small cardinalities
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+define_aggr!(AGGR_APPROX_COUNT_DISTINCT, false);
+
+pub(crate) struct AggrApproxCountDistinct {
+    sketch: HyperLogLog,
+}
+
+impl AggrApproxCountDistinct {
+    const DEFAULT_PRECISION: u32 = 14;
+
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let precision = match args.first() {
+            None => Self::DEFAULT_PRECISION,
+            Some(arg) => {
+                let p = arg.get_int().ok_or_else(|| {
+                    miette!(
+                        "the argument to 'approx_count_distinct' must be an integer, got {:?}",
+                        arg
+                    )
+                })?;
+                ensure!(
+                    (4..=18).contains(&p),
+                    "the precision of 'approx_count_distinct' must be between 4 and 18, got {}",
+                    p
+                );
+                p as u32
+            }
+        };
+        Ok(Self {
+            sketch: HyperLogLog::new(precision),
+        })
+    }
+}
+
+impl NormalAggrObj for AggrApproxCountDistinct {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.sketch.insert(value);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.sketch.estimate().round() as i64))
+    }
+
+    fn merge(&mut self, other: &dyn NormalAggrObj) -> Result<()> {
+        let other = downcast_aggr::<Self>("approx_count_distinct", other)?;
+        self.sketch.merge(&other.sketch)
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+/// A merging t-digest for estimating quantiles.
+#[derive(Clone)]
+pub(crate) struct TDigest {
+    compression: f64,
+    /// Centroids as `(mean, weight)`, sorted by mean.
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<(f64, f64)>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub(crate) fn new(compression: f64) -> Self {
+        Self {
+            compression,
+            centroids: vec![],
+            buffer: vec![],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+    pub(crate) fn insert(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.buffer.push((value, 1.));
+        if self.buffer.len() >= 10 * self.compression as usize {
+            self.compress();
+        }
+    }
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        self.compress();
+    }
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all = std::mem::take(&mut self.centroids);
+        all.append(&mut self.buffer);
+        all.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total: f64 = all.iter().map(|(_, w)| w).sum();
+
+        let mut merged = Vec::with_capacity(self.compression as usize);
+        let mut seen = 0.;
+        let mut cur = all[0];
+        for &(mean, weight) in &all[1..] {
+            let proposed = cur.1 + weight;
+            let q = (seen + proposed / 2.) / total;
+            let limit = 4. * total * q * (1. - q) / self.compression;
+            if proposed <= limit {
+                cur.0 += (mean - cur.0) * weight / proposed;
+                cur.1 = proposed;
+            } else {
+                seen += cur.1;
+                merged.push(cur);
+                cur = (mean, weight);
+            }
+        }
+        merged.push(cur);
+        self.centroids = merged;
+    }
+    pub(crate) fn quantile(&mut self, q: f64) -> Option<f64> {
+        self.compress();
+        let total: f64 = self.centroids.iter().map(|(_, w)| w).sum();
+        if total == 0. {
+            return None;
+        }
+        let target = q * total;
+        // each centroid is taken to sit at the middle of the weight it covers
+        let mut prev = (self.min, 0.);
+        let mut seen = 0.;
+        for &(mean, weight) in &self.centroids {
+            let pos = seen + weight / 2.;
+            if target < pos {
+                let frac = (target - prev.1) / (pos - prev.1);
+                return Some(prev.0 + (mean - prev.0) * frac);
+            }
+            prev = (mean, pos);
+            seen += weight;
+        }
+        if total > prev.1 {
+            let frac = (target - prev.1) / (total - prev.1);
+            Some(prev.0 + (self.max - prev.0) * frac)
+        } else {
+            Some(self.max)
+        }
+    }
+}
+
+define_aggr!(AGGR_APPROX_QUANTILE, false);
+
+pub(crate) struct AggrApproxQuantile {
+    name: &'static str,
+    q: f64,
+    digest: TDigest,
+}
+
+impl AggrApproxQuantile {
+    const COMPRESSION: f64 = 100.;
+
+    fn new(name: &'static str, q: f64) -> Result<Self> {
+        ensure!(
+            (0. ..=1.).contains(&q),
+            "the quantile given to '{}' must be between 0 and 1, got {}",
+            name,
+            q
+        );
+        Ok(Self {
+            name,
+            q,
+            digest: TDigest::new(Self::COMPRESSION),
+        })
+    }
+}
+
+impl NormalAggrObj for AggrApproxQuantile {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Num(n) => {
+                self.digest.insert(n.get_float());
+                Ok(())
+            }
+            v => bail!("cannot compute '{}': encountered value {:?}", self.name, v),
+        }
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let mut digest = self.digest.clone();
+        Ok(match digest.quantile(self.q) {
+            None => DataValue::Null,
+            Some(v) => DataValue::from(v),
+        })
+    }
+
+    fn merge(&mut self, other: &dyn NormalAggrObj) -> Result<()> {
+        let other = downcast_aggr::<Self>(self.name, other)?;
+        ensure!(
+            self.q == other.q,
+            "cannot merge states of '{}' for different quantiles",
+            self.name
+        );
+        self.digest.merge(&other.digest);
+        Ok(())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+define_aggr!(AGGR_APPROX_MEDIAN, false);
+
+/// A space-saving sketch for finding the most frequent values.
+pub(crate) struct SpaceSaving {
+    capacity: usize,
+    counts: BTreeMap<DataValue, u64>,
+    by_count: BTreeSet<(u64, DataValue)>,
+}
+
+impl SpaceSaving {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counts: BTreeMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+    fn add(&mut self, value: &DataValue, count: u64) {
+        if let Some(c) = self.counts.get_mut(value) {
+            self.by_count.remove(&(*c, value.clone()));
+            *c += count;
+            self.by_count.insert((*c, value.clone()));
+        } else if self.counts.len() < self.capacity {
+            self.counts.insert(value.clone(), count);
+            self.by_count.insert((count, value.clone()));
+        } else {
+            // the value evicted hands its count down as the error bound of the new one
+            let (min_count, evicted) = self.by_count.pop_first().unwrap();
+            self.counts.remove(&evicted);
+            self.counts.insert(value.clone(), min_count + count);
+            self.by_count.insert((min_count + count, value.clone()));
+        }
+    }
+    pub(crate) fn insert(&mut self, value: &DataValue) {
+        self.add(value, 1)
+    }
+    pub(crate) fn merge(&mut self, other: &Self) {
+        for (value, count) in &other.counts {
+            self.add(value, *count);
+        }
+    }
+    pub(crate) fn top(&self, k: usize) -> Vec<(DataValue, u64)> {
+        let mut ret = self
+            .counts
+            .iter()
+            .map(|(v, c)| (v.clone(), *c))
+            .collect::<Vec<_>>();
+        ret.sort_by(|(lv, lc), (rv, rc)| rc.cmp(lc).then_with(|| lv.cmp(rv)));
+        ret.truncate(k);
+        ret
+    }
+}
+
+define_aggr!(AGGR_TOP_K_FREQUENT, false);
+
+pub(crate) struct AggrTopKFrequent {
+    k: usize,
+    sketch: SpaceSaving,
+}
+
+impl AggrTopKFrequent {
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let arg = args
+            .first()
+            .ok_or_else(|| miette!("'top_k_frequent' requires the number of values to return"))?;
+        let k = arg.get_int().ok_or_else(|| {
+            miette!(
+                "the argument to 'top_k_frequent' must be an integer, got {:?}",
+                arg
+            )
+        })?;
+        ensure!(
+            k > 0,
+            "argument to 'top_k_frequent' must be positive, got {}",
+            k
+        );
+        let k = k as usize;
+        Ok(Self {
+            k,
+            sketch: SpaceSaving::new(k.saturating_mul(10).max(100)),
+        })
+    }
+}
+
+impl NormalAggrObj for AggrTopKFrequent {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.sketch.insert(value);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::List(
+            self.sketch
+                .top(self.k)
+                .into_iter()
+                .map(|(v, c)| DataValue::List(vec![v, DataValue::from(c as i64)]))
+                .collect(),
+        ))
+    }
+
+    fn merge(&mut self, other: &dyn NormalAggrObj) -> Result<()> {
+        let other = downcast_aggr::<Self>("top_k_frequent", other)?;
+        ensure!(
+            self.k == other.k,
+            "cannot merge states of 'top_k_frequent' for different numbers of values"
+        );
+        self.sketch.merge(&other.sketch);
+        Ok(())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+define_aggr!(AGGR_SCALAR_SUBQUERY, false);
+
+/// The aggregation giving the result of a scalar subquery with no aggregation in its head,
+/// which must have a single value for each row of the enclosing rule.
+/// It is not available in queries.
+pub(crate) fn scalar_subquery_aggr() -> Aggregation {
+    AGGR_SCALAR_SUBQUERY
+}
+
+#[derive(Default)]
+pub(crate) struct AggrScalarSubquery {
+    value: Option<DataValue>,
+}
+
+impl NormalAggrObj for AggrScalarSubquery {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match &self.value {
+            Some(v) if v != value => bail!(
+                "a subquery used as a value produced more than one row: {:?} and {:?}",
+                v,
+                value
+            ),
+            _ => self.value = Some(value.clone()),
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(self.value.clone().unwrap_or(DataValue::Null))
+    }
+}
+
 pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
     Some(match name {
         "and" => &AGGR_AND,
@@ -1182,12 +1832,82 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
         "latest_by" => &AGGR_LATEST_BY,
         "smallest_by" => &AGGR_SMALLEST_BY,
         "choice_rand" => &AGGR_CHOICE_RAND,
+        "approx_count_distinct" => &AGGR_APPROX_COUNT_DISTINCT,
+        "approx_quantile" => &AGGR_APPROX_QUANTILE,
+        "approx_median" => &AGGR_APPROX_MEDIAN,
+        "top_k_frequent" => &AGGR_TOP_K_FREQUENT,
         _ => return None,
     })
 }
 
+/// The registered names of custom aggregations are leaked once each,
+/// so that [Aggregation::name] can be `'static` for them as well.
+fn intern_aggr_name(name: &str) -> &'static str {
+    lazy_static! {
+        static ref NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    }
+    let mut names = NAMES.lock().unwrap();
+    match names.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.into());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
+/// Whether `name` is the name of a built-in aggregation.
+pub(crate) fn is_builtin_aggr(name: &str) -> bool {
+    parse_aggr(name).is_some()
+}
+
 impl Aggregation {
-    pub(crate) fn meet_init(&mut self, _args: &[DataValue]) -> Result<()> {
+    pub(crate) fn custom(name: &str, aggr: Arc<dyn CustomAggregation>) -> Self {
+        Self {
+            name: intern_aggr_name(name),
+            is_meet: aggr.is_meet(),
+            meet_op: None,
+            normal_op: None,
+            custom: Some(aggr),
+        }
+    }
+    /// The name of the aggregation, which for built-in aggregations is the name of its definition.
+    pub fn name(&self) -> &str {
+        self.name
+    }
+    /// The name of the aggregation as used in queries.
+    pub(crate) fn query_name(&self) -> String {
+        match &self.custom {
+            None => self
+                .name
+                .strip_prefix("AGGR_")
+                .unwrap()
+                .to_ascii_lowercase(),
+            Some(_) => self.name.to_string(),
+        }
+    }
+    /// Whether partial states of the normal aggregation can be combined with
+    /// [NormalAggrObj::merge], so that parts of the input can be aggregated in parallel.
+    pub(crate) fn is_mergeable(&self) -> bool {
+        self.custom.is_none()
+            && [
+                AGGR_APPROX_COUNT_DISTINCT.name,
+                AGGR_APPROX_QUANTILE.name,
+                AGGR_APPROX_MEDIAN.name,
+                AGGR_TOP_K_FREQUENT.name,
+            ]
+            .contains(&self.name)
+    }
+    pub(crate) fn meet_init(&mut self, args: &[DataValue]) -> Result<()> {
+        if let Some(aggr) = &self.custom {
+            let state = aggr.init(args)?;
+            self.meet_op.replace(Box::new(CustomAggr {
+                aggr: aggr.clone(),
+                state,
+            }));
+            return Ok(());
+        }
         self.meet_op.replace(match self.name {
             name if name == AGGR_AND.name => Box::new(MeetAggrAnd),
             name if name == AGGR_OR.name => Box::new(MeetAggrOr),
@@ -1205,6 +1925,14 @@ impl Aggregation {
         Ok(())
     }
     pub(crate) fn normal_init(&mut self, args: &[DataValue]) -> Result<()> {
+        if let Some(aggr) = &self.custom {
+            let state = aggr.init(args)?;
+            self.normal_op.replace(Box::new(CustomAggr {
+                aggr: aggr.clone(),
+                state,
+            }));
+            return Ok(());
+        }
         #[allow(clippy::box_default)]
         self.normal_op.replace(match self.name {
             name if name == AGGR_AND.name => Box::new(AggrAnd::default()),
@@ -1231,6 +1959,26 @@ impl Aggregation {
             name if name == AGGR_LATEST_BY.name => Box::new(AggrLatestBy::default()),
             name if name == AGGR_SMALLEST_BY.name => Box::new(AggrSmallestBy::default()),
             name if name == AGGR_CHOICE_RAND.name => Box::new(AggrChoiceRand::default()),
+            name if name == AGGR_APPROX_COUNT_DISTINCT.name => {
+                Box::new(AggrApproxCountDistinct::new(args)?)
+            }
+            name if name == AGGR_APPROX_QUANTILE.name => Box::new({
+                let arg = args
+                    .first()
+                    .ok_or_else(|| miette!("'approx_quantile' requires the quantile to compute"))?;
+                let q = arg.get_float().ok_or_else(|| {
+                    miette!(
+                        "the argument to 'approx_quantile' must be a number, got {:?}",
+                        arg
+                    )
+                })?;
+                AggrApproxQuantile::new("approx_quantile", q)?
+            }),
+            name if name == AGGR_APPROX_MEDIAN.name => {
+                Box::new(AggrApproxQuantile::new("approx_median", 0.5)?)
+            }
+            name if name == AGGR_TOP_K_FREQUENT.name => Box::new(AggrTopKFrequent::new(args)?),
+            name if name == AGGR_SCALAR_SUBQUERY.name => Box::new(AggrScalarSubquery::default()),
             name if name == AGGR_COLLECT.name => Box::new({
                 if args.is_empty() {
                     AggrCollect::default()
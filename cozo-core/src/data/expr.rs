@@ -10,9 +10,10 @@ use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::mem;
+use std::sync::Arc;
 
 use itertools::Itertools;
-use miette::{bail, miette, Diagnostic, Result};
+use miette::{bail, miette, Diagnostic, Report, Result};
 use serde::de::{Error, Visitor};
 use serde::{Deserializer, Serializer};
 use smartstring::{LazyCompact, SmartString};
@@ -45,6 +46,23 @@ pub enum Bytecode {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// pop n, push 1
+    CustomApply {
+        #[serde(
+            serialize_with = "serialize_custom_function",
+            deserialize_with = "deserialize_custom_function"
+        )]
+        func: Arc<CustomFunction>,
+        arity: usize,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// push 1, only present in queries compiled before their parameters are known
+    Param {
+        name: SmartString<LazyCompact>,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
     /// pop 1
     JumpIfFalse {
         jump_to: usize,
@@ -64,6 +82,24 @@ pub enum Bytecode {
 #[diagnostic(code(eval::unbound))]
 struct UnboundVariableError(String, #[label] SourceSpan);
 
+/// Raised when a parameter of a prepared query is used before it is bound.
+#[derive(Error, Diagnostic, Debug)]
+#[error("The parameter '${0}' is not bound")]
+#[diagnostic(code(eval::unbound_param))]
+#[diagnostic(help("Parameters used here must be known when the query is compiled"))]
+pub(crate) struct UnboundParamError(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+/// Whether the error, or one of the errors it is related to, is an [UnboundParamError].
+pub(crate) fn is_unbound_param_error(err: &Report) -> bool {
+    fn check(diag: &dyn Diagnostic) -> bool {
+        diag.code()
+            .is_some_and(|code| code.to_string() == "eval::unbound_param")
+            || diag.related().is_some_and(|mut related| related.any(check))
+            || diag.diagnostic_source().is_some_and(check)
+    }
+    err.downcast_ref::<UnboundParamError>().is_some() || check(err.as_ref())
+}
+
 #[derive(Error, Diagnostic, Debug)]
 #[error("The tuple bound by variable '{0}' is too short: index is {1}, length is {2}")]
 #[diagnostic(help("This is definitely a bug. Please report it."))]
@@ -126,6 +162,9 @@ pub fn eval_bytecode(
                 stack.push(val.clone());
                 pointer += 1;
             }
+            Bytecode::Param { name, span } => {
+                bail!(UnboundParamError(name.to_string(), *span))
+            }
             Bytecode::Apply { op, arity, span } => {
                 let frame_start = stack.len() - *arity;
                 let args_frame = &stack[frame_start..];
@@ -135,6 +174,15 @@ pub fn eval_bytecode(
                 stack.push(result);
                 pointer += 1;
             }
+            Bytecode::CustomApply { func, arity, span } => {
+                let frame_start = stack.len() - *arity;
+                let args_frame = &stack[frame_start..];
+                let result = (func.inner)(args_frame)
+                    .map_err(|err| EvalRaisedError(*span, err.to_string()))?;
+                stack.truncate(frame_start);
+                stack.push(result);
+                pointer += 1;
+            }
             Bytecode::JumpIfFalse { jump_to, span } => {
                 let val = stack.pop().unwrap();
                 let cond = val
@@ -154,6 +202,23 @@ pub fn eval_bytecode(
     Ok(stack.pop().unwrap())
 }
 
+/// Replace the parameters in the bytecode by their values.
+pub(crate) fn bind_params_in_bytecode(
+    bytecodes: &mut [Bytecode],
+    params: &BTreeMap<String, DataValue>,
+) -> Result<()> {
+    for code in bytecodes.iter_mut() {
+        if let Bytecode::Param { name, span } = code {
+            let val = params
+                .get(name.as_str())
+                .ok_or_else(|| ParamNotFoundError(name.to_string(), *span))?
+                .clone();
+            *code = Bytecode::Const { val, span: *span };
+        }
+    }
+    Ok(())
+}
+
 /// Expression can be evaluated to yield a DataValue
 #[derive(Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub enum Expr {
@@ -182,6 +247,20 @@ pub enum Expr {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// Application of a user-defined function
+    CustomApply {
+        /// The user-defined function to apply
+        #[serde(
+            serialize_with = "serialize_custom_function",
+            deserialize_with = "deserialize_custom_function"
+        )]
+        func: Arc<CustomFunction>,
+        /// Arguments to the application
+        args: Box<[Expr]>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
     /// Unbound function application
     UnboundApply {
         /// Op representing the function to apply
@@ -192,6 +271,14 @@ pub enum Expr {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// Parameter of a prepared query, to be replaced by its value before evaluation
+    Param {
+        /// The name of the parameter, without the leading `$`
+        name: SmartString<LazyCompact>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
     /// Conditional expressions
     Cond {
         /// Conditional clauses, the first expression in each tuple should evaluate to a boolean
@@ -217,15 +304,35 @@ impl Display for Expr {
             Expr::Const { val, .. } => {
                 write!(f, "{val}")
             }
+            Expr::Param { name, .. } => {
+                write!(f, "${name}")
+            }
             Expr::Apply { op, args, .. } => {
-                let mut writer =
-                    f.debug_tuple(op.name.strip_prefix("OP_").unwrap().to_lowercase().as_str());
+                let name = op.name.strip_prefix("OP_").unwrap().to_lowercase();
+                // `debug_tuple` would omit the parentheses, making it parse back as a variable
+                if args.is_empty() {
+                    return write!(f, "{name}()");
+                }
+                let mut writer = f.debug_tuple(name.as_str());
+                for arg in args.iter() {
+                    writer.field(arg);
+                }
+                writer.finish()
+            }
+            Expr::CustomApply { func, args, .. } => {
+                if args.is_empty() {
+                    return write!(f, "{}()", func.name);
+                }
+                let mut writer = f.debug_tuple(&func.name);
                 for arg in args.iter() {
                     writer.field(arg);
                 }
                 writer.finish()
             }
             Expr::UnboundApply { op, args, .. } => {
+                if args.is_empty() {
+                    return write!(f, "{op}()");
+                }
                 let mut writer = f.debug_tuple(op);
                 for arg in args.iter() {
                     writer.field(arg);
@@ -260,6 +367,11 @@ pub(crate) struct PredicateTypeError(#[label] pub(crate) SourceSpan, pub(crate)
 #[diagnostic(help("Entity ID should be an integer satisfying certain constraints"))]
 struct BadEntityId(DataValue, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Required parameter {0} not found")]
+#[diagnostic(code(parser::param_not_found))]
+pub(crate) struct ParamNotFoundError(pub(crate) String, #[label] pub(crate) SourceSpan);
+
 #[derive(Error, Diagnostic, Debug)]
 #[error("Evaluation of expression failed")]
 #[diagnostic(code(eval::throw))]
@@ -275,7 +387,9 @@ impl Expr {
         match self {
             Expr::Binding { var, .. } => var.span,
             Expr::Const { span, .. } | Expr::Apply { span, .. } | Expr::Cond { span, .. } => *span,
-            Expr::UnboundApply { span, .. } => *span,
+            Expr::CustomApply { span, .. }
+            | Expr::UnboundApply { span, .. }
+            | Expr::Param { span, .. } => *span,
         }
     }
     pub(crate) fn get_binding(&self) -> Option<&Symbol> {
@@ -342,8 +456,8 @@ impl Expr {
                     .ok_or_else(|| BadBindingError(var.to_string(), var.span))?;
                 *tuple_pos = Some(found_idx)
             }
-            Expr::Const { .. } => {}
-            Expr::Apply { args, .. } => {
+            Expr::Const { .. } | Expr::Param { .. } => {}
+            Expr::Apply { args, .. } | Expr::CustomApply { args, .. } => {
                 for arg in args.iter_mut() {
                     arg.fill_binding_indices(binding_map)?;
                 }
@@ -374,8 +488,8 @@ impl Expr {
                     coll.insert(*idx);
                 }
             }
-            Expr::Const { .. } => {}
-            Expr::Apply { args, .. } => {
+            Expr::Const { .. } | Expr::Param { .. } => {}
+            Expr::Apply { args, .. } | Expr::CustomApply { args, .. } => {
                 for arg in args.iter() {
                     arg.do_binding_indices(coll)?;
                 }
@@ -406,11 +520,58 @@ impl Expr {
         self.partial_eval()?;
         match self {
             Expr::Const { val, .. } => Ok(val),
-            _ => bail!(NotConstError),
+            _ => match self.find_param() {
+                Some((name, span)) => bail!(UnboundParamError(name.to_string(), span)),
+                None => bail!(NotConstError),
+            },
         }
     }
+    fn find_param(&self) -> Option<(&str, SourceSpan)> {
+        match self {
+            Expr::Param { name, span } => Some((name, *span)),
+            Expr::Binding { .. } | Expr::Const { .. } => None,
+            Expr::Apply { args, .. }
+            | Expr::CustomApply { args, .. }
+            | Expr::UnboundApply { args, .. } => args.iter().find_map(|arg| arg.find_param()),
+            Expr::Cond { clauses, .. } => clauses
+                .iter()
+                .find_map(|(cond, val)| cond.find_param().or_else(|| val.find_param())),
+        }
+    }
+    /// Replace the parameters in the expression by their values, and evaluate the parts
+    /// that become constant, so that filters such as `k > $p + 1` can bound range scans.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) -> Result<()> {
+        self.substitute_params(params)?;
+        self.partial_eval()
+    }
+    fn substitute_params(&mut self, params: &BTreeMap<String, DataValue>) -> Result<()> {
+        match self {
+            Expr::Param { name, span } => {
+                let val = params
+                    .get(name.as_str())
+                    .ok_or_else(|| ParamNotFoundError(name.to_string(), *span))?
+                    .clone();
+                *self = Expr::Const { val, span: *span };
+            }
+            Expr::Binding { .. } | Expr::Const { .. } => {}
+            Expr::Apply { args, .. }
+            | Expr::CustomApply { args, .. }
+            | Expr::UnboundApply { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.substitute_params(params)?;
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.substitute_params(params)?;
+                    val.substitute_params(params)?;
+                }
+            }
+        }
+        Ok(())
+    }
     pub(crate) fn partial_eval(&mut self) -> Result<()> {
-        if let Expr::Apply { args, span, .. } = self {
+        if let Expr::Apply { args, span, .. } | Expr::CustomApply { args, span, .. } = self {
             let span = *span;
             let mut all_evaluated = true;
             for arg in args.iter_mut() {
@@ -455,8 +616,8 @@ impl Expr {
             Expr::Binding { var, .. } => {
                 coll.insert(var.clone());
             }
-            Expr::Const { .. } => {}
-            Expr::Apply { args, .. } => {
+            Expr::Const { .. } | Expr::Param { .. } => {}
+            Expr::Apply { args, .. } | Expr::CustomApply { args, .. } => {
                 for arg in args.iter() {
                     arg.collect_bindings(coll)?;
                 }
@@ -493,6 +654,7 @@ impl Expr {
                     .clone()),
             },
             Expr::Const { val, .. } => Ok(val.clone()),
+            Expr::Param { name, span } => bail!(UnboundParamError(name.to_string(), *span)),
             Expr::Apply { op, args, .. } => {
                 let args: Box<[DataValue]> = args
                     .iter()
@@ -501,6 +663,14 @@ impl Expr {
                 Ok((op.inner)(&args)
                     .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?)
             }
+            Expr::CustomApply { func, args, .. } => {
+                let args: Box<[DataValue]> = args
+                    .iter()
+                    .map(|v| v.eval(bindings.as_ref()))
+                    .try_collect()?;
+                Ok((func.inner)(&args)
+                    .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?)
+            }
             Expr::Cond { clauses, .. } => {
                 for (cond, val) in clauses {
                     let cond_val = cond.eval(bindings.as_ref())?;
@@ -519,18 +689,21 @@ impl Expr {
             }
         }
     }
+    /// The range of `target` implied by the expression. Parameters give no bounds here:
+    /// the filters of prepared queries have their parameters bound before they run.
     pub(crate) fn extract_bound(&self, target: &Symbol) -> Result<ValueRange> {
         Ok(match self {
-            Expr::Binding { .. } | Expr::Const { .. } | Expr::Cond { .. } => ValueRange::default(),
+            Expr::Binding { .. }
+            | Expr::Const { .. }
+            | Expr::Cond { .. }
+            | Expr::CustomApply { .. }
+            | Expr::Param { .. } => ValueRange::default(),
             Expr::Apply { op, args, .. } => match op.name {
                 n if n == OP_GE.name || n == OP_GT.name => {
                     if let Some(symb) = args[0].get_binding() {
                         if let Some(val) = args[1].get_const() {
                             if target == symb {
-                                let tar_val = match val.get_int() {
-                                    Some(i) => DataValue::from(i),
-                                    None => val.clone(),
-                                };
+                                let tar_val = numeric_lower_bound(val);
                                 return Ok(ValueRange::lower_bound(tar_val));
                             }
                         }
@@ -564,10 +737,7 @@ impl Expr {
                     if let Some(symb) = args[1].get_binding() {
                         if let Some(val) = args[0].get_const() {
                             if target == symb {
-                                let tar_val = match val.get_int() {
-                                    Some(i) => DataValue::from(i),
-                                    None => val.clone(),
-                                };
+                                let tar_val = numeric_lower_bound(val);
 
                                 return Ok(ValueRange::lower_bound(tar_val));
                             }
@@ -575,6 +745,27 @@ impl Expr {
                     }
                     ValueRange::default()
                 }
+                n if n == OP_AFTER_CURSOR.name => {
+                    // only the leading key of a cursor can bound a scan, and inclusively
+                    if let (Expr::Apply { args: keys, .. }, Some(cursor), Some(descending)) =
+                        (&args[0], args[1].get_const(), args[2].get_const())
+                    {
+                        if let (Some(symb), Some(val), Some(desc)) = (
+                            keys.first().and_then(|k| k.get_binding()),
+                            cursor.get_slice().and_then(|c| c.first()),
+                            descending.get_slice().and_then(|d| d.first()),
+                        ) {
+                            if target == symb {
+                                return Ok(if *desc == DataValue::from(true) {
+                                    ValueRange::upper_bound(val.clone())
+                                } else {
+                                    ValueRange::lower_bound(val.clone())
+                                });
+                            }
+                        }
+                    }
+                    ValueRange::default()
+                }
                 n if n == OP_STARTS_WITH.name => {
                     if let Some(symb) = args[0].get_binding() {
                         if let Some(val) = args[1].get_const() {
@@ -617,8 +808,8 @@ impl Expr {
             Expr::Binding { var, .. } => {
                 coll.insert(var.to_string());
             }
-            Expr::Const { .. } => {}
-            Expr::Apply { args, .. } => {
+            Expr::Const { .. } | Expr::Param { .. } => {}
+            Expr::Apply { args, .. } | Expr::CustomApply { args, .. } => {
                 for arg in args.iter() {
                     arg.do_get_variables(coll)?;
                 }
@@ -657,6 +848,19 @@ impl Expr {
     }
 }
 
+/// Numbers sharing a float approximation sort ints first, then decimals, then floats.
+/// A non-integral lower bound therefore starts below the whole group, so that for
+/// example decimal keys equal to a float bound are not skipped.
+fn numeric_lower_bound(val: &DataValue) -> DataValue {
+    match val {
+        DataValue::Num(n) => match n.get_int() {
+            Some(i) => DataValue::from(i),
+            None => DataValue::from(n.get_float().next_down()),
+        },
+        v => v.clone(),
+    }
+}
+
 pub(crate) fn compute_bounds(
     filters: &[Expr],
     symbols: &[Symbol],
@@ -732,6 +936,60 @@ pub struct Op {
     pub(crate) inner: fn(&[DataValue]) -> Result<DataValue>,
 }
 
+/// A user-defined function, registered with [crate::Db::register_function].
+pub struct CustomFunction {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) min_arity: usize,
+    pub(crate) max_arity: Option<usize>,
+    pub(crate) inner: Box<dyn Fn(&[DataValue]) -> Result<DataValue> + Send + Sync>,
+}
+
+impl CustomFunction {
+    pub(crate) fn accepts_arity(&self, arity: usize) -> bool {
+        self.min_arity <= arity
+            && match self.max_arity {
+                None => true,
+                Some(max) => arity <= max,
+            }
+    }
+}
+
+impl PartialEq for CustomFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for CustomFunction {}
+
+impl Debug for CustomFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+fn serialize_custom_function<S>(
+    func: &Arc<CustomFunction>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&func.name)
+}
+
+fn deserialize_custom_function<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Arc<CustomFunction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name: String = serde::Deserialize::deserialize(deserializer)?;
+    Err(D::Error::custom(format!(
+        "user-defined function cannot be restored from serialized data: {name}"
+    )))
+}
+
 /// Used as `Arc<dyn CustomOp>`
 pub trait CustomOp {
     fn name(&self) -> &'static str;
@@ -813,6 +1071,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "floor" => &OP_FLOOR,
         "ceil" => &OP_CEIL,
         "round" => &OP_ROUND,
+        "round_dp" => &OP_ROUND_DP,
         "mod" => &OP_MOD,
         "max" => &OP_MAX,
         "min" => &OP_MIN,
@@ -864,6 +1123,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "is_int" => &OP_IS_INT,
         "is_float" => &OP_IS_FLOAT,
         "is_num" => &OP_IS_NUM,
+        "is_decimal" => &OP_IS_DECIMAL,
         "is_string" => &OP_IS_STRING,
         "is_list" => &OP_IS_LIST,
         "is_bytes" => &OP_IS_BYTES,
@@ -904,6 +1164,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "windows" => &OP_WINDOWS,
         "to_int" => &OP_TO_INT,
         "to_float" => &OP_TO_FLOAT,
+        "to_decimal" => &OP_TO_DECIMAL,
         "to_string" => &OP_TO_STRING,
         "l2_dist" => &OP_L2_DIST,
         "l2_normalize" => &OP_L2_NORMALIZE,
@@ -928,6 +1189,19 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "now" => &OP_NOW,
         "format_timestamp" => &OP_FORMAT_TIMESTAMP,
         "parse_timestamp" => &OP_PARSE_TIMESTAMP,
+        "to_date" => &OP_TO_DATE,
+        "to_time" => &OP_TO_TIME,
+        "to_timestamp" => &OP_TO_TIMESTAMP,
+        "to_duration" => &OP_TO_DURATION,
+        "current_timestamp" => &OP_CURRENT_TIMESTAMP,
+        "same_instant" => &OP_SAME_INSTANT,
+        "add_duration" => &OP_ADD_DURATION,
+        "date_trunc" => &OP_DATE_TRUNC,
+        "extract" => &OP_EXTRACT,
+        "is_date" => &OP_IS_DATE,
+        "is_time" => &OP_IS_TIME,
+        "is_timestamp" => &OP_IS_TIMESTAMP,
+        "is_duration" => &OP_IS_DURATION,
         "vec" => &OP_VEC,
         "rand_vec" => &OP_RAND_VEC,
         _ => return None,
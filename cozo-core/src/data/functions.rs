@@ -6,7 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::cmp::Reverse;
+use std::cmp::{Ordering, Reverse};
 use std::collections::BTreeSet;
 use std::mem;
 use std::ops::{Div, Rem};
@@ -15,13 +15,16 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{
+    DateTime, Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc,
+};
 use itertools::Itertools;
 #[cfg(target_arch = "wasm32")]
 use js_sys::Date;
 use miette::{bail, ensure, miette, IntoDiagnostic, Result};
 use num_traits::FloatConst;
 use rand::prelude::*;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde_json::{json, Value};
 use smartstring::SmartString;
 use unicode_normalization::UnicodeNormalization;
@@ -30,8 +33,12 @@ use uuid::v1::Timestamp;
 use crate::data::expr::Op;
 use crate::data::json::JsonValue;
 use crate::data::relation::VecElementType;
+use crate::data::temporal::{
+    DateData, DurationData, TimeData, TimestampData, Zone, MICROS_PER_DAY, MICROS_PER_SEC,
+};
 use crate::data::value::{
-    DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
+    decimal_to_f64, parse_decimal, DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity,
+    ValidityTs, Vector,
 };
 
 macro_rules! define_op {
@@ -57,6 +64,10 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
             | (Regex(_), Regex(_))
             | (List(_), List(_))
             | (Set(_), Set(_))
+            | (Date(_), Date(_))
+            | (Time(_), Time(_))
+            | (Timestamp(_), Timestamp(_))
+            | (Duration(_), Duration(_))
             | (Bot, Bot)
     ) {
         bail!(
@@ -212,6 +223,9 @@ fn to_json(d: &DataValue) -> JsonValue {
             Num::Float(f) => {
                 json!(f)
             }
+            Num::Decimal(d) => {
+                json!(d.to_string())
+            }
         },
         DataValue::Str(s) => {
             json!(s)
@@ -259,6 +273,18 @@ fn to_json(d: &DataValue) -> JsonValue {
         DataValue::Validity(vld) => {
             json!([vld.timestamp.0, vld.is_assert.0])
         }
+        DataValue::Date(d) => {
+            json!(d.to_string())
+        }
+        DataValue::Time(t) => {
+            json!(t.to_string())
+        }
+        DataValue::Timestamp(ts) => {
+            json!(ts.to_string())
+        }
+        DataValue::Duration(d) => {
+            json!(d.to_string())
+        }
         DataValue::Bot => {
             json!(null)
         }
@@ -299,6 +325,10 @@ pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 == *f,
+        (DataValue::Num(l @ Num::Decimal(_)), DataValue::Num(r))
+        | (DataValue::Num(l), DataValue::Num(r @ Num::Decimal(_))) => {
+            l.numeric_cmp(r) == Some(Ordering::Equal)
+        }
         (a, b) => a == b,
     }))
 }
@@ -335,6 +365,10 @@ pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 != *f,
+        (DataValue::Num(l @ Num::Decimal(_)), DataValue::Num(r))
+        | (DataValue::Num(l), DataValue::Num(r @ Num::Decimal(_))) => {
+            l.numeric_cmp(r) != Some(Ordering::Equal)
+        }
         (a, b) => a != b,
     }))
 }
@@ -345,6 +379,10 @@ pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l > *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r,
+        (DataValue::Num(l @ Num::Decimal(_)), DataValue::Num(r))
+        | (DataValue::Num(l), DataValue::Num(r @ Num::Decimal(_))) => {
+            matches!(l.numeric_cmp(r), Some(Ordering::Greater))
+        }
         (a, b) => a > b,
     }))
 }
@@ -355,6 +393,10 @@ pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l >= *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r,
+        (DataValue::Num(l @ Num::Decimal(_)), DataValue::Num(r))
+        | (DataValue::Num(l), DataValue::Num(r @ Num::Decimal(_))) => {
+            matches!(l.numeric_cmp(r), Some(Ordering::Greater | Ordering::Equal))
+        }
         (a, b) => a >= b,
     }))
 }
@@ -365,6 +407,10 @@ pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l < (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < *r,
+        (DataValue::Num(l @ Num::Decimal(_)), DataValue::Num(r))
+        | (DataValue::Num(l), DataValue::Num(r @ Num::Decimal(_))) => {
+            matches!(l.numeric_cmp(r), Some(Ordering::Less))
+        }
         (a, b) => a < b,
     }))
 }
@@ -375,19 +421,70 @@ pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l <= (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= *r,
+        (DataValue::Num(l @ Num::Decimal(_)), DataValue::Num(r))
+        | (DataValue::Num(l), DataValue::Num(r @ Num::Decimal(_))) => {
+            matches!(l.numeric_cmp(r), Some(Ordering::Less | Ordering::Equal))
+        }
         (a, b) => a <= b,
     }))
 }
 
+define_op!(OP_AFTER_CURSOR, 3, false);
+/// Internal to keyset pagination: whether the key list `args[0]` sorts strictly after the
+/// cursor list `args[1]`, with `args[2]` giving the direction of each position (`true` for descending).
+/// Unlike the comparison operators, this uses the total order of values used for sorting.
+pub(crate) fn op_after_cursor(args: &[DataValue]) -> Result<DataValue> {
+    let keys = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("'after_cursor' requires lists"))?;
+    let cursor = args[1]
+        .get_slice()
+        .ok_or_else(|| miette!("'after_cursor' requires lists"))?;
+    let descending = args[2]
+        .get_slice()
+        .ok_or_else(|| miette!("'after_cursor' requires lists"))?;
+    Ok(DataValue::from(sorts_after_cursor(
+        keys.iter()
+            .zip(cursor)
+            .zip(descending)
+            .map(|((k, c), d)| (k, c, *d == DataValue::from(true))),
+    )))
+}
+
+/// Compare `(key, cursor, descending)` triples lexicographically.
+pub(crate) fn sorts_after_cursor<'a>(
+    parts: impl Iterator<Item = (&'a DataValue, &'a DataValue, bool)>,
+) -> bool {
+    for (key, cursor, descending) in parts {
+        match key.cmp(cursor) {
+            Ordering::Equal => continue,
+            ord => return (ord == Ordering::Greater) != descending,
+        }
+    }
+    false
+}
+
 define_op!(OP_ADD, 0, true);
 pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(ds) = decimal_operands(args) {
+        return ds
+            .into_iter()
+            .try_fold(Decimal::ZERO, |accum, d| accum.checked_add(d))
+            .map(DataValue::from)
+            .ok_or_else(|| miette!("decimal overflow in addition"));
+    }
     let mut i_accum = 0i64;
     let mut f_accum = 0.0f64;
     for arg in args {
         match arg {
             DataValue::Num(Num::Int(i)) => i_accum += i,
             DataValue::Num(Num::Float(f)) => f_accum += f,
+            DataValue::Num(Num::Decimal(d)) => f_accum += decimal_to_f64(*d),
             DataValue::Vec(_) => return add_vecs(args),
+            DataValue::Date(_)
+            | DataValue::Time(_)
+            | DataValue::Timestamp(_)
+            | DataValue::Duration(_) => return add_temporals(args),
             _ => bail!("addition requires numbers"),
         }
     }
@@ -398,6 +495,23 @@ pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+/// Exact operands for arithmetic: `Some` if at least one argument is a decimal
+/// and all others are decimals or ints. Mixing in a float makes the result a float.
+fn decimal_operands(args: &[DataValue]) -> Option<Vec<Decimal>> {
+    if !args
+        .iter()
+        .any(|arg| matches!(arg, DataValue::Num(Num::Decimal(_))))
+    {
+        return None;
+    }
+    args.iter()
+        .map(|arg| match arg {
+            DataValue::Num(n) => n.get_decimal(),
+            _ => None,
+        })
+        .collect()
+}
+
 fn add_vecs(args: &[DataValue]) -> Result<DataValue> {
     if args.len() == 1 {
         return Ok(args[0].clone());
@@ -450,6 +564,45 @@ fn add_vecs(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+fn add_temporals(args: &[DataValue]) -> Result<DataValue> {
+    let (first, rest) = args.split_first().unwrap();
+    rest.iter()
+        .try_fold(first.clone(), |accum, nxt| add_temporal(&accum, nxt))
+}
+
+fn add_temporal(a: &DataValue, b: &DataValue) -> Result<DataValue> {
+    let out_of_range = || miette!("result of adding {} and {} is out of range", a, b);
+    Ok(match (a, b) {
+        (DataValue::Duration(x), DataValue::Duration(y)) => {
+            DataValue::Duration(x.checked_add(*y).ok_or_else(out_of_range)?)
+        }
+        (DataValue::Date(d), DataValue::Duration(dur))
+        | (DataValue::Duration(dur), DataValue::Date(d)) => {
+            if dur.micros == 0 {
+                DataValue::Date(d.add_duration(*dur).ok_or_else(out_of_range)?)
+            } else {
+                let ts = d.to_timestamp().and_then(|ts| ts.add_duration(*dur));
+                DataValue::Timestamp(ts.ok_or_else(out_of_range)?)
+            }
+        }
+        (DataValue::Date(d), DataValue::Num(Num::Int(n)))
+        | (DataValue::Num(Num::Int(n)), DataValue::Date(d)) => {
+            DataValue::Date(d.add_days(*n).ok_or_else(out_of_range)?)
+        }
+        (DataValue::Time(t), DataValue::Duration(dur))
+        | (DataValue::Duration(dur), DataValue::Time(t)) => {
+            DataValue::Time(t.add_duration(*dur).ok_or_else(|| {
+                miette!("only durations without months or days can be added to times")
+            })?)
+        }
+        (DataValue::Timestamp(ts), DataValue::Duration(dur))
+        | (DataValue::Duration(dur), DataValue::Timestamp(ts)) => {
+            DataValue::Timestamp(ts.add_duration(*dur).ok_or_else(out_of_range)?)
+        }
+        _ => bail!("cannot add {} and {}", a, b),
+    })
+}
+
 define_op!(OP_MAX, 1, true);
 pub(crate) fn op_max(args: &[DataValue]) -> Result<DataValue> {
     let res = args
@@ -482,6 +635,12 @@ pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_SUB, 2, false);
 pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(&[a, b]) = decimal_operands(args).as_deref() {
+        return Ok(DataValue::from(
+            a.checked_sub(b)
+                .ok_or_else(|| miette!("decimal overflow in subtraction"))?,
+        ));
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Int(*a - *b))
@@ -495,6 +654,9 @@ pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float(a - (*b as f64)))
         }
+        (DataValue::Num(a), DataValue::Num(b)) => {
+            DataValue::Num(Num::Float(a.get_float() - b.get_float()))
+        }
         (DataValue::Vec(a), DataValue::Vec(b)) => match (a, b) {
             (Vector::F32(a), Vector::F32(b)) => DataValue::Vec(Vector::F32(a - b)),
             (Vector::F64(a), Vector::F64(b)) => DataValue::Vec(Vector::F64(a - b)),
@@ -537,18 +699,59 @@ pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
                 }
             }
         }
+        (
+            DataValue::Date(_)
+            | DataValue::Time(_)
+            | DataValue::Timestamp(_)
+            | DataValue::Duration(_),
+            _,
+        ) => sub_temporal(&args[0], &args[1])?,
         _ => bail!("subtraction requires numbers"),
     })
 }
 
+fn sub_temporal(a: &DataValue, b: &DataValue) -> Result<DataValue> {
+    let out_of_range = || miette!("result of subtracting {} from {} is out of range", b, a);
+    Ok(match (a, b) {
+        (DataValue::Date(x), DataValue::Date(y)) => DataValue::Duration(DurationData {
+            months: 0,
+            days: x.0.checked_sub(y.0).ok_or_else(out_of_range)?,
+            micros: 0,
+        }),
+        (DataValue::Time(x), DataValue::Time(y)) => {
+            DataValue::Duration(DurationData::from_micros(x.0 - y.0))
+        }
+        (DataValue::Timestamp(x), DataValue::Timestamp(y)) => DataValue::Duration(
+            DurationData::from_micros(x.micros.checked_sub(y.micros).ok_or_else(out_of_range)?),
+        ),
+        (x, DataValue::Duration(dur)) => add_temporal(
+            x,
+            &DataValue::Duration(dur.checked_neg().ok_or_else(out_of_range)?),
+        )?,
+        (DataValue::Date(_), DataValue::Num(Num::Int(n))) => add_temporal(
+            a,
+            &DataValue::from(n.checked_neg().ok_or_else(out_of_range)?),
+        )?,
+        _ => bail!("cannot subtract {} from {}", b, a),
+    })
+}
+
 define_op!(OP_MUL, 0, true);
 pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(ds) = decimal_operands(args) {
+        return ds
+            .into_iter()
+            .try_fold(Decimal::ONE, |accum, d| accum.checked_mul(d))
+            .map(DataValue::from)
+            .ok_or_else(|| miette!("decimal overflow in multiplication"));
+    }
     let mut i_accum = 1i64;
     let mut f_accum = 1.0f64;
     for arg in args {
         match arg {
             DataValue::Num(Num::Int(i)) => i_accum *= i,
             DataValue::Num(Num::Float(f)) => f_accum *= f,
+            DataValue::Num(Num::Decimal(d)) => f_accum *= decimal_to_f64(*d),
             DataValue::Vec(_) => return mul_vecs(args),
             _ => bail!("multiplication requires numbers"),
         }
@@ -614,6 +817,13 @@ fn mul_vecs(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_DIV, 2, false);
 pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(&[a, b]) = decimal_operands(args).as_deref() {
+        ensure!(!b.is_zero(), "decimal division by zero");
+        return Ok(DataValue::from(
+            a.checked_div(b)
+                .ok_or_else(|| miette!("decimal overflow in division"))?,
+        ));
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float((*a as f64) / (*b as f64)))
@@ -627,6 +837,9 @@ pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float(a / (*b as f64)))
         }
+        (DataValue::Num(a), DataValue::Num(b)) => {
+            DataValue::Num(Num::Float(a.get_float() / b.get_float()))
+        }
         (DataValue::Vec(a), DataValue::Vec(b)) => match (a, b) {
             (Vector::F32(a), Vector::F32(b)) => DataValue::Vec(Vector::F32(a / b)),
             (Vector::F64(a), Vector::F64(b)) => DataValue::Vec(Vector::F64(a / b)),
@@ -672,8 +885,13 @@ pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(-(*i))),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(-(*f))),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(-(*d))),
         DataValue::Vec(Vector::F64(v)) => DataValue::Vec(Vector::F64(0. - v)),
         DataValue::Vec(Vector::F32(v)) => DataValue::Vec(Vector::F32(0. - v)),
+        DataValue::Duration(d) => DataValue::Duration(
+            d.checked_neg()
+                .ok_or_else(|| miette!("duration out of range"))?,
+        ),
         _ => bail!("minus can only be applied to numbers"),
     })
 }
@@ -683,6 +901,7 @@ pub(crate) fn op_abs(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(i.abs())),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.abs())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(d.abs())),
         DataValue::Vec(Vector::F64(v)) => DataValue::Vec(Vector::F64(v.mapv(|x| x.abs()))),
         DataValue::Vec(Vector::F32(v)) => DataValue::Vec(Vector::F32(v.mapv(|x| x.abs()))),
         _ => bail!("'abs' requires numbers"),
@@ -704,6 +923,13 @@ pub(crate) fn op_signum(args: &[DataValue]) -> Result<DataValue> {
                 DataValue::from(f64::NAN)
             }
         }
+        DataValue::Num(Num::Decimal(d)) => DataValue::from(if d.is_zero() {
+            0
+        } else if d.is_sign_negative() {
+            -1
+        } else {
+            1
+        }),
         _ => bail!("'signum' requires numbers"),
     })
 }
@@ -713,6 +939,7 @@ pub(crate) fn op_floor(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.floor())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(d.floor())),
         _ => bail!("'floor' requires numbers"),
     })
 }
@@ -722,6 +949,7 @@ pub(crate) fn op_ceil(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.ceil())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(d.ceil())),
         _ => bail!("'ceil' requires numbers"),
     })
 }
@@ -731,15 +959,59 @@ pub(crate) fn op_round(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.round())),
+        DataValue::Num(Num::Decimal(d)) => DataValue::Num(Num::Decimal(
+            d.round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero),
+        )),
         _ => bail!("'round' requires numbers"),
     })
 }
 
+define_op!(OP_ROUND_DP, 2, true);
+pub(crate) fn op_round_dp(args: &[DataValue]) -> Result<DataValue> {
+    let dp = args[1]
+        .get_non_neg_int()
+        .ok_or_else(|| miette!("'round_dp' requires a non-negative number of decimal places"))?;
+    let strategy = match args.get(2) {
+        None => RoundingStrategy::MidpointAwayFromZero,
+        Some(DataValue::Str(s)) => match s as &str {
+            "half_up" => RoundingStrategy::MidpointAwayFromZero,
+            "half_even" => RoundingStrategy::MidpointNearestEven,
+            "half_down" => RoundingStrategy::MidpointTowardZero,
+            "up" => RoundingStrategy::AwayFromZero,
+            "down" => RoundingStrategy::ToZero,
+            "ceil" => RoundingStrategy::ToPositiveInfinity,
+            "floor" => RoundingStrategy::ToNegativeInfinity,
+            s => bail!("unknown rounding mode for 'round_dp': {}", s),
+        },
+        Some(v) => bail!(
+            "'round_dp' requires a string for the rounding mode, got {}",
+            v
+        ),
+    };
+    let dp = u32::try_from(dp).unwrap_or(u32::MAX);
+    Ok(match &args[0] {
+        DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
+        DataValue::Num(Num::Decimal(d)) => {
+            DataValue::Num(Num::Decimal(d.round_dp_with_strategy(dp, strategy)))
+        }
+        DataValue::Num(Num::Float(f)) => {
+            if !f.is_finite() {
+                return Ok(args[0].clone());
+            }
+            let d = Decimal::try_from(*f)
+                .map_err(|_| miette!("'round_dp' cannot represent {} as a decimal", f))?;
+            DataValue::from(decimal_to_f64(d.round_dp_with_strategy(dp, strategy)))
+        }
+        _ => bail!("'round_dp' requires numbers"),
+    })
+}
+
 define_op!(OP_EXP, 1, false);
 pub(crate) fn op_exp(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.exp()))));
         }
@@ -756,6 +1028,7 @@ pub(crate) fn op_exp2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.exp2()))));
         }
@@ -772,6 +1045,7 @@ pub(crate) fn op_ln(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.ln()))));
         }
@@ -788,6 +1062,7 @@ pub(crate) fn op_log2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.log2()))));
         }
@@ -804,6 +1079,7 @@ pub(crate) fn op_log10(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.log10()))));
         }
@@ -820,6 +1096,7 @@ pub(crate) fn op_sin(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.sin()))));
         }
@@ -836,6 +1113,7 @@ pub(crate) fn op_cos(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.cos()))));
         }
@@ -852,6 +1130,7 @@ pub(crate) fn op_tan(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.tan()))));
         }
@@ -868,6 +1147,7 @@ pub(crate) fn op_asin(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.asin()))));
         }
@@ -884,6 +1164,7 @@ pub(crate) fn op_acos(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.acos()))));
         }
@@ -900,6 +1181,7 @@ pub(crate) fn op_atan(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.atan()))));
         }
@@ -916,11 +1198,13 @@ pub(crate) fn op_atan2(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         _ => bail!("'atan2' requires numbers"),
     };
     let b = match &args[1] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         _ => bail!("'atan2' requires numbers"),
     };
 
@@ -932,6 +1216,7 @@ pub(crate) fn op_sinh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.sinh()))));
         }
@@ -948,6 +1233,7 @@ pub(crate) fn op_cosh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.cosh()))));
         }
@@ -964,6 +1250,7 @@ pub(crate) fn op_tanh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.tanh()))));
         }
@@ -980,6 +1267,7 @@ pub(crate) fn op_asinh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.asinh()))));
         }
@@ -996,6 +1284,7 @@ pub(crate) fn op_acosh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.acosh()))));
         }
@@ -1012,6 +1301,7 @@ pub(crate) fn op_atanh(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.atanh()))));
         }
@@ -1028,6 +1318,7 @@ pub(crate) fn op_sqrt(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             return Ok(DataValue::Vec(Vector::F32(v.mapv(|x| x.sqrt()))));
         }
@@ -1044,6 +1335,7 @@ pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         DataValue::Vec(Vector::F32(v)) => {
             let b = args[1]
                 .get_float()
@@ -1061,6 +1353,7 @@ pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
     let b = match &args[1] {
         DataValue::Num(Num::Int(i)) => *i as f64,
         DataValue::Num(Num::Float(f)) => *f,
+        DataValue::Num(Num::Decimal(d)) => decimal_to_f64(*d),
         _ => bail!("'pow' requires numbers"),
     };
     Ok(DataValue::Num(Num::Float(a.powf(b))))
@@ -1068,6 +1361,13 @@ pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MOD, 2, false);
 pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(&[a, b]) = decimal_operands(args).as_deref() {
+        ensure!(!b.is_zero(), "'mod' requires non-zero divisor");
+        return Ok(DataValue::from(
+            a.checked_rem(b)
+                .ok_or_else(|| miette!("decimal overflow in 'mod'"))?,
+        ));
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             if *b == 0 {
@@ -1084,6 +1384,9 @@ pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float(a.rem(*b as f64)))
         }
+        (DataValue::Num(a), DataValue::Num(b)) => {
+            DataValue::Num(Num::Float(a.get_float().rem(b.get_float())))
+        }
         _ => bail!("'mod' requires numbers"),
     })
 }
@@ -1476,14 +1779,24 @@ define_op!(OP_IS_NUM, 1, false);
 pub(crate) fn op_is_num(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(
         args[0],
-        DataValue::Num(Num::Int(_)) | DataValue::Num(Num::Float(_))
+        DataValue::Num(Num::Int(_))
+            | DataValue::Num(Num::Float(_))
+            | DataValue::Num(Num::Decimal(_))
+    )))
+}
+
+define_op!(OP_IS_DECIMAL, 1, false);
+pub(crate) fn op_is_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(
+        args[0],
+        DataValue::Num(Num::Decimal(_))
     )))
 }
 
 define_op!(OP_IS_FINITE, 1, false);
 pub(crate) fn op_is_finite(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
-        DataValue::Num(Num::Int(_)) => true,
+        DataValue::Num(Num::Int(_)) | DataValue::Num(Num::Decimal(_)) => true,
         DataValue::Num(Num::Float(f)) => f.is_finite(),
         _ => false,
     }))
@@ -1930,6 +2243,8 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Set(s) => !s.is_empty(),
         DataValue::Vec(_) => true,
         DataValue::Validity(vld) => vld.is_assert.0,
+        DataValue::Date(_) | DataValue::Time(_) | DataValue::Timestamp(_) => true,
+        DataValue::Duration(d) => d.approx_micros() != 0,
         DataValue::Bot => false,
         DataValue::Json(json) => match &json.0 {
             Value::Null => false,
@@ -1956,6 +2271,8 @@ pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Set(s) => i64::from(!s.is_empty()),
         DataValue::Vec(_) => 1,
         DataValue::Validity(vld) => i64::from(vld.is_assert.0),
+        DataValue::Date(_) | DataValue::Time(_) | DataValue::Timestamp(_) => 1,
+        DataValue::Duration(d) => i64::from(d.approx_micros() != 0),
         DataValue::Bot => 0,
         DataValue::Json(json) => match &json.0 {
             Value::Null => 0,
@@ -2011,6 +2328,24 @@ pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_TO_DECIMAL, 1, false);
+pub(crate) fn op_to_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        DataValue::Num(Num::Float(f)) => DataValue::from(
+            Decimal::try_from(*f)
+                .map_err(|_| miette!("'to_decimal' cannot represent {} as a decimal", f))?,
+        ),
+        DataValue::Num(n) => DataValue::from(n.get_decimal().unwrap()),
+        DataValue::Null => DataValue::from(Decimal::ZERO),
+        DataValue::Bool(b) => DataValue::from(if *b { Decimal::ONE } else { Decimal::ZERO }),
+        DataValue::Str(s) => DataValue::from(
+            parse_decimal(s)
+                .ok_or_else(|| miette!("The string cannot be interpreted as decimal"))?,
+        ),
+        v => bail!("'to_decimal' does not recognize {:?}", v),
+    })
+}
+
 define_op!(OP_TO_STRING, 1, false);
 pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Str(val2str(&args[0]).into()))
@@ -2020,6 +2355,11 @@ fn val2str(arg: &DataValue) -> String {
     match arg {
         DataValue::Str(s) => s.to_string(),
         DataValue::Json(JsonData(JsonValue::String(s))) => s.clone(),
+        DataValue::Date(d) => d.to_string(),
+        DataValue::Time(t) => t.to_string(),
+        DataValue::Timestamp(ts) => ts.to_string(),
+        DataValue::Duration(d) => d.to_string(),
+        DataValue::Num(Num::Decimal(d)) => d.to_string(),
         v => {
             let jv = to_json(v);
             jv.to_string()
@@ -2473,9 +2813,16 @@ pub(crate) const TERMINAL_VALIDITY: Validity = Validity {
 
 define_op!(OP_FORMAT_TIMESTAMP, 1, true);
 pub(crate) fn op_format_timestamp(args: &[DataValue]) -> Result<DataValue> {
+    if let (DataValue::Timestamp(ts), None) = (&args[0], args.get(1)) {
+        let dt = ts
+            .to_datetime()
+            .ok_or_else(|| miette!("bad time: {}", &args[0]))?;
+        return Ok(DataValue::Str(SmartString::from(dt.to_rfc3339())));
+    }
     let dt = {
         let millis = match &args[0] {
             DataValue::Validity(vld) => vld.timestamp.0 .0 / 1000,
+            DataValue::Timestamp(ts) => ts.micros.div_euclid(1000),
             v => {
                 let f = v
                     .get_float()
@@ -2517,6 +2864,274 @@ pub(crate) fn op_parse_timestamp(args: &[DataValue]) -> Result<DataValue> {
     ))
 }
 
+define_op!(OP_IS_DATE, 1, false);
+pub(crate) fn op_is_date(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::Date(_))))
+}
+
+define_op!(OP_IS_TIME, 1, false);
+pub(crate) fn op_is_time(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::Time(_))))
+}
+
+define_op!(OP_IS_TIMESTAMP, 1, false);
+pub(crate) fn op_is_timestamp(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::Timestamp(_))))
+}
+
+define_op!(OP_IS_DURATION, 1, false);
+pub(crate) fn op_is_duration(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::Duration(_))))
+}
+
+fn local_datetime(ts: &TimestampData) -> Result<NaiveDateTime> {
+    ts.to_local()
+        .ok_or_else(|| miette!("timestamp out of range: {}", ts.micros))
+}
+
+fn naive_date(d: &DateData) -> Result<NaiveDate> {
+    d.to_naive()
+        .ok_or_else(|| miette!("date out of range: {}", d.0))
+}
+
+define_op!(OP_TO_DATE, 1, false);
+pub(crate) fn op_to_date(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        d @ DataValue::Date(_) => d.clone(),
+        DataValue::Str(s) => DataValue::Date(DateData::parse(s)?),
+        DataValue::Timestamp(ts) => {
+            DataValue::Date(DateData::from_naive(local_datetime(ts)?.date()))
+        }
+        _ => bail!("'to_date' requires a string or a timestamp"),
+    })
+}
+
+define_op!(OP_TO_TIME, 1, false);
+pub(crate) fn op_to_time(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        t @ DataValue::Time(_) => t.clone(),
+        DataValue::Str(s) => DataValue::Time(TimeData::parse(s)?),
+        DataValue::Timestamp(ts) => {
+            DataValue::Time(TimeData::from_naive(local_datetime(ts)?.time()))
+        }
+        _ => bail!("'to_time' requires a string or a timestamp"),
+    })
+}
+
+define_op!(OP_TO_TIMESTAMP, 1, true);
+pub(crate) fn op_to_timestamp(args: &[DataValue]) -> Result<DataValue> {
+    let zone = match args.get(1) {
+        None => None,
+        Some(DataValue::Str(s)) => Some(Zone::parse(s)?),
+        Some(_) => bail!("'to_timestamp' timezone specification requires a string"),
+    };
+    let out_of_range = || miette!("timestamp out of range: {}", &args[0]);
+    let ts = match &args[0] {
+        DataValue::Timestamp(ts) => *ts,
+        DataValue::Str(s) => TimestampData::parse(s, zone.as_ref())?,
+        DataValue::Date(d) => {
+            let midnight = naive_date(d)?.and_hms_opt(0, 0, 0).unwrap();
+            match &zone {
+                None => TimestampData::from_local(midnight, 0).ok_or_else(out_of_range)?,
+                Some(zone) => zone.localize(midnight)?,
+            }
+        }
+        DataValue::Num(n) => TimestampData::from_secs(n.get_float()).ok_or_else(out_of_range)?,
+        DataValue::Validity(vld) => TimestampData {
+            micros: vld.timestamp.0 .0,
+            offset: 0,
+        },
+        _ => bail!("'to_timestamp' requires a string, a number, a date or a timestamp"),
+    };
+    Ok(DataValue::Timestamp(match &zone {
+        None => ts,
+        Some(zone) => ts.in_zone(zone).ok_or_else(out_of_range)?,
+    }))
+}
+
+define_op!(OP_TO_DURATION, 1, false);
+pub(crate) fn op_to_duration(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        d @ DataValue::Duration(_) => d.clone(),
+        DataValue::Str(s) => DataValue::Duration(DurationData::parse(s)?),
+        DataValue::Num(n) => {
+            let micros = (n.get_float() * MICROS_PER_SEC as f64).round();
+            if !micros.is_finite() || micros.abs() >= i64::MAX as f64 {
+                bail!("duration out of range: {}", &args[0])
+            }
+            DataValue::Duration(DurationData::from_micros(micros as i64))
+        }
+        _ => bail!("'to_duration' requires a string or a number of seconds"),
+    })
+}
+
+define_op!(OP_CURRENT_TIMESTAMP, 0, false);
+pub(crate) fn op_current_timestamp(_args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Timestamp(TimestampData {
+        micros: current_validity().0 .0,
+        offset: 0,
+    }))
+}
+
+define_op!(OP_SAME_INSTANT, 2, false);
+pub(crate) fn op_same_instant(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Timestamp(a), DataValue::Timestamp(b)) => {
+            Ok(DataValue::from(a.micros == b.micros))
+        }
+        _ => bail!("'same_instant' requires two timestamps"),
+    }
+}
+
+define_op!(OP_ADD_DURATION, 2, false);
+pub(crate) fn op_add_duration(args: &[DataValue]) -> Result<DataValue> {
+    let dur = match &args[1] {
+        d @ DataValue::Duration(_) => d.clone(),
+        DataValue::Str(s) => DataValue::Duration(DurationData::parse(s)?),
+        _ => bail!("'add_duration' requires a duration as second argument"),
+    };
+    match &args[0] {
+        DataValue::Date(_)
+        | DataValue::Time(_)
+        | DataValue::Timestamp(_)
+        | DataValue::Duration(_) => add_temporal(&args[0], &dur),
+        _ => bail!("'add_duration' requires a date, time, timestamp or duration"),
+    }
+}
+
+/// Length of the units `date_trunc` truncates times of day to.
+fn time_unit_micros(unit: &str) -> Option<i64> {
+    Some(match unit {
+        "hour" => 3600 * MICROS_PER_SEC,
+        "minute" => 60 * MICROS_PER_SEC,
+        "second" => MICROS_PER_SEC,
+        "millisecond" => 1000,
+        "microsecond" => 1,
+        _ => return None,
+    })
+}
+
+fn trunc_date(date: NaiveDate, unit: &str) -> Result<NaiveDate> {
+    Ok(match unit {
+        "year" => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        "quarter" => NaiveDate::from_ymd_opt(date.year(), date.month0() / 3 * 3 + 1, 1).unwrap(),
+        "month" => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+        "week" => {
+            let since_monday = date.weekday().num_days_from_monday();
+            date.checked_sub_days(Days::new(since_monday as u64))
+                .ok_or_else(|| miette!("date out of range: {}", date))?
+        }
+        "day" => date,
+        _ => bail!("unknown unit for 'date_trunc': {}", unit),
+    })
+}
+
+define_op!(OP_DATE_TRUNC, 2, false);
+pub(crate) fn op_date_trunc(args: &[DataValue]) -> Result<DataValue> {
+    let unit = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'date_trunc' requires a string as unit"))?;
+    Ok(match &args[1] {
+        DataValue::Date(d) => match time_unit_micros(unit) {
+            Some(_) => args[1].clone(),
+            None => DataValue::Date(DateData::from_naive(trunc_date(naive_date(d)?, unit)?)),
+        },
+        DataValue::Time(t) => match time_unit_micros(unit) {
+            Some(len) => DataValue::Time(TimeData(t.0 - t.0 % len)),
+            None if unit == "day" => DataValue::Time(TimeData(0)),
+            None => bail!("unknown unit for 'date_trunc' of a time: {}", unit),
+        },
+        DataValue::Timestamp(ts) => {
+            let local = local_datetime(ts)?;
+            let local = match time_unit_micros(unit) {
+                Some(len) => {
+                    let micros = TimeData::from_naive(local.time()).0;
+                    local
+                        .date()
+                        .and_time(TimeData(micros - micros % len).to_naive())
+                }
+                None => trunc_date(local.date(), unit)?
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap(),
+            };
+            DataValue::Timestamp(
+                TimestampData::from_local(local, ts.offset)
+                    .ok_or_else(|| miette!("timestamp out of range: {}", &args[1]))?,
+            )
+        }
+        _ => bail!("'date_trunc' requires a date, time or timestamp"),
+    })
+}
+
+fn extract_date_field(date: NaiveDate, field: &str) -> Option<DataValue> {
+    Some(DataValue::from(match field {
+        "year" => date.year() as i64,
+        "quarter" => (date.month0() / 3 + 1) as i64,
+        "month" => date.month() as i64,
+        "week" => date.iso_week().week() as i64,
+        "day" => date.day() as i64,
+        "dow" => date.weekday().num_days_from_sunday() as i64,
+        "isodow" => date.weekday().number_from_monday() as i64,
+        "doy" => date.ordinal() as i64,
+        _ => return None,
+    }))
+}
+
+fn extract_time_field(time: NaiveTime, field: &str) -> Option<DataValue> {
+    Some(match field {
+        "hour" => DataValue::from(time.hour() as i64),
+        "minute" => DataValue::from(time.minute() as i64),
+        "second" => {
+            let micros = TimeData::from_naive(time).0 % (60 * MICROS_PER_SEC);
+            DataValue::from(micros as f64 / MICROS_PER_SEC as f64)
+        }
+        _ => return None,
+    })
+}
+
+define_op!(OP_EXTRACT, 2, false);
+pub(crate) fn op_extract(args: &[DataValue]) -> Result<DataValue> {
+    let field = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("'extract' requires a string as field"))?;
+    let secs = |micros: i128| DataValue::from(micros as f64 / MICROS_PER_SEC as f64);
+    let found = match &args[1] {
+        DataValue::Date(d) => match field {
+            "epoch" => Some(DataValue::from(
+                d.0 as i64 * (MICROS_PER_DAY / MICROS_PER_SEC),
+            )),
+            _ => extract_date_field(naive_date(d)?, field),
+        },
+        DataValue::Time(t) => match field {
+            "epoch" => Some(secs(t.0 as i128)),
+            _ => extract_time_field(t.to_naive(), field),
+        },
+        DataValue::Timestamp(ts) => match field {
+            "epoch" => Some(secs(ts.micros as i128)),
+            "timezone" => Some(DataValue::from(ts.offset as i64)),
+            _ => {
+                let local = local_datetime(ts)?;
+                extract_date_field(local.date(), field)
+                    .or_else(|| extract_time_field(local.time(), field))
+            }
+        },
+        DataValue::Duration(d) => match field {
+            "year" => Some(DataValue::from((d.months / 12) as i64)),
+            "month" => Some(DataValue::from((d.months % 12) as i64)),
+            "day" => Some(DataValue::from(d.days as i64)),
+            "hour" => Some(DataValue::from(d.micros / (3600 * MICROS_PER_SEC))),
+            "minute" => Some(DataValue::from(
+                d.micros % (3600 * MICROS_PER_SEC) / (60 * MICROS_PER_SEC),
+            )),
+            "second" => Some(secs((d.micros % (60 * MICROS_PER_SEC)) as i128)),
+            "epoch" => Some(secs(d.approx_micros())),
+            _ => None,
+        },
+        _ => bail!("'extract' requires a date, time, timestamp or duration"),
+    };
+    found.ok_or_else(|| miette!("cannot extract '{}' from {}", field, &args[1]))
+}
+
 pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
     let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {}", s))?;
     let st: SystemTime = dt.into();
@@ -58,6 +58,7 @@ impl From<DataValue> for JsonValue {
             DataValue::Null => JsonValue::Null,
             DataValue::Bool(b) => JsonValue::Bool(b),
             DataValue::Num(Num::Int(i)) => JsonValue::Number(i.into()),
+            DataValue::Num(Num::Decimal(d)) => JsonValue::String(d.to_string()),
             DataValue::Num(Num::Float(f)) => {
                 if f.is_finite() {
                     json!(f)
@@ -96,6 +97,10 @@ impl From<DataValue> for JsonValue {
                 json!([v.timestamp.0, v.is_assert])
             }
             DataValue::Json(j) => j.0,
+            DataValue::Date(d) => JsonValue::String(d.to_string()),
+            DataValue::Time(t) => JsonValue::String(t.to_string()),
+            DataValue::Timestamp(ts) => JsonValue::String(ts.to_string()),
+            DataValue::Duration(d) => JsonValue::String(d.to_string()),
         }
     }
 }
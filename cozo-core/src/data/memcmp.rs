@@ -13,13 +13,15 @@ use std::str::FromStr;
 
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use regex::Regex;
+use rust_decimal::Decimal;
 
+use crate::data::temporal::{DateData, DurationData, TimeData, TimestampData};
 use crate::data::value::{
     DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
 };
 
 const INIT_TAG: u8 = 0x00;
-const NULL_TAG: u8 = 0x01;
+pub(crate) const NULL_TAG: u8 = 0x01;
 const FALSE_TAG: u8 = 0x02;
 const TRUE_TAG: u8 = 0x03;
 const VEC_TAG: u8 = 0x04;
@@ -32,6 +34,10 @@ const LIST_TAG: u8 = 0x0A;
 const SET_TAG: u8 = 0x0B;
 const VLD_TAG: u8 = 0x0C;
 const JSON_TAG: u8 = 0x0D;
+const DATE_TAG: u8 = 0x0E;
+const TIME_TAG: u8 = 0x0F;
+const TIMESTAMP_TAG: u8 = 0x10;
+const DURATION_TAG: u8 = 0x11;
 const BOT_TAG: u8 = 0xFF;
 
 const VEC_F32: u8 = 0x01;
@@ -39,6 +45,7 @@ const VEC_F64: u8 = 0x02;
 
 const IS_FLOAT: u8 = 0b00010000;
 const IS_APPROX_INT: u8 = 0b00000100;
+const IS_DECIMAL: u8 = 0b00001000;
 const IS_EXACT_INT: u8 = 0b00000000;
 const EXACT_INT_BOUND: i64 = 0x20_0000_0000_0000;
 
@@ -121,6 +128,33 @@ pub(crate) trait MemCmpEncoder: Write {
                 self.write_u64::<BigEndian>(ts_flipped).unwrap();
                 self.write_u8(!vld.is_assert.0 as u8).unwrap();
             }
+            DataValue::Date(d) => {
+                self.write_u8(DATE_TAG).unwrap();
+                self.write_u32::<BigEndian>(order_encode_i32(d.0)).unwrap();
+            }
+            DataValue::Time(t) => {
+                self.write_u8(TIME_TAG).unwrap();
+                self.write_u64::<BigEndian>(order_encode_i64(t.0)).unwrap();
+            }
+            DataValue::Timestamp(ts) => {
+                self.write_u8(TIMESTAMP_TAG).unwrap();
+                self.write_u64::<BigEndian>(order_encode_i64(ts.micros))
+                    .unwrap();
+                self.write_u32::<BigEndian>(order_encode_i32(ts.offset))
+                    .unwrap();
+            }
+            DataValue::Duration(d) => {
+                // the approximate length comes first, for durations to sort by it
+                self.write_u8(DURATION_TAG).unwrap();
+                let approx = d.approx_micros() as u128 ^ (1 << 127);
+                self.write_u128::<BigEndian>(approx).unwrap();
+                self.write_u32::<BigEndian>(order_encode_i32(d.months))
+                    .unwrap();
+                self.write_u32::<BigEndian>(order_encode_i32(d.days))
+                    .unwrap();
+                self.write_u64::<BigEndian>(order_encode_i64(d.micros))
+                    .unwrap();
+            }
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
         }
     }
@@ -141,8 +175,35 @@ pub(crate) trait MemCmpEncoder: Write {
             Num::Float(_) => {
                 self.write_u8(IS_FLOAT).unwrap();
             }
+            Num::Decimal(d) => {
+                self.write_u8(IS_DECIMAL).unwrap();
+                self.encode_decimal(d);
+            }
         }
     }
+    /// Self-delimiting encoding of the exact value, used to order decimals
+    /// sharing the same float approximation: a sign byte, then the decimal
+    /// exponent and the significant digits, all inverted for negative numbers.
+    fn encode_decimal(&mut self, d: Decimal) {
+        let d = d.normalize();
+        if d.is_zero() {
+            self.write_u8(DECIMAL_ZERO).unwrap();
+            return;
+        }
+        let mantissa = d.mantissa().unsigned_abs().to_string();
+        let digits = mantissa.trim_end_matches('0');
+        // value = 0.<digits> * 10^exponent
+        let exponent = mantissa.len() as i32 - d.scale() as i32;
+        let negative = d.is_sign_negative();
+        let flip = |b: u8| if negative { !b } else { b };
+        self.write_u8(if negative { DECIMAL_NEG } else { DECIMAL_POS })
+            .unwrap();
+        self.write_u8(flip((exponent + 128) as u8)).unwrap();
+        for c in digits.bytes() {
+            self.write_u8(flip(c - b'0' + 1)).unwrap();
+        }
+        self.write_u8(flip(0)).unwrap();
+    }
 
     fn encode_bytes(&mut self, key: &[u8]) {
         let len = key.len();
@@ -201,6 +262,14 @@ fn order_decode_i64(u: u64) -> i64 {
     (u ^ SIGN_MARK) as i64
 }
 
+fn order_encode_i32(v: i32) -> u32 {
+    v as u32 ^ 0x80000000
+}
+
+fn order_decode_i32(u: u32) -> i32 {
+    (u ^ 0x80000000) as i32
+}
+
 fn order_encode_f64(v: f64) -> u64 {
     let u = v.to_bits();
     if v.is_sign_positive() {
@@ -219,6 +288,45 @@ fn order_decode_f64(u: u64) -> f64 {
     f64::from_bits(u)
 }
 
+const DECIMAL_NEG: u8 = 0x01;
+const DECIMAL_ZERO: u8 = 0x02;
+const DECIMAL_POS: u8 = 0x03;
+
+fn decode_decimal(bs: &[u8]) -> (Decimal, &[u8]) {
+    let (sign, remaining) = bs.split_first().unwrap();
+    if *sign == DECIMAL_ZERO {
+        return (Decimal::ZERO, remaining);
+    }
+    let negative = *sign == DECIMAL_NEG;
+    let flip = |b: u8| if negative { !b } else { b };
+    let (exponent, mut remaining) = remaining.split_first().unwrap();
+    let exponent = flip(*exponent) as i32 - 128;
+    let mut mantissa: i128 = 0;
+    let mut n_digits = 0;
+    loop {
+        let (b, rest) = remaining.split_first().unwrap();
+        remaining = rest;
+        let b = flip(*b);
+        if b == 0 {
+            break;
+        }
+        mantissa = mantissa * 10 + (b - 1) as i128;
+        n_digits += 1;
+    }
+    let mut scale = n_digits - exponent;
+    while scale < 0 {
+        mantissa *= 10;
+        scale += 1;
+    }
+    if negative {
+        mantissa = -mantissa;
+    }
+    (
+        Decimal::from_i128_with_scale(mantissa, scale as u32),
+        remaining,
+    )
+}
+
 const ENC_GROUP_SIZE: usize = 8;
 const ENC_MARKER: u8 = b'\xff';
 const ENC_ASC_PADDING: [u8; ENC_GROUP_SIZE] = [0; ENC_GROUP_SIZE];
@@ -238,6 +346,10 @@ impl Num {
                 let i = order_decode_i64(iu);
                 (Num::Int(i), remaining)
             }
+            IS_DECIMAL => {
+                let (d, remaining) = decode_decimal(remaining);
+                (Num::Decimal(d), remaining)
+            }
             _ => unreachable!(),
         }
         // if *tag == 0x80 {
@@ -334,6 +446,41 @@ impl DataValue {
                     rest,
                 )
             }
+            DATE_TAG => {
+                let (days, rest) = remaining.split_at(4);
+                let days = order_decode_i32(BigEndian::read_u32(days));
+                (DataValue::Date(DateData(days)), rest)
+            }
+            TIME_TAG => {
+                let (micros, rest) = remaining.split_at(8);
+                let micros = order_decode_i64(BigEndian::read_u64(micros));
+                (DataValue::Time(TimeData(micros)), rest)
+            }
+            TIMESTAMP_TAG => {
+                let (micros, rest) = remaining.split_at(8);
+                let (offset, rest) = rest.split_at(4);
+                (
+                    DataValue::Timestamp(TimestampData {
+                        micros: order_decode_i64(BigEndian::read_u64(micros)),
+                        offset: order_decode_i32(BigEndian::read_u32(offset)),
+                    }),
+                    rest,
+                )
+            }
+            DURATION_TAG => {
+                let (_approx, rest) = remaining.split_at(16);
+                let (months, rest) = rest.split_at(4);
+                let (days, rest) = rest.split_at(4);
+                let (micros, rest) = rest.split_at(8);
+                (
+                    DataValue::Duration(DurationData {
+                        months: order_decode_i32(BigEndian::read_u32(months)),
+                        days: order_decode_i32(BigEndian::read_u32(days)),
+                        micros: order_decode_i64(BigEndian::read_u64(micros)),
+                    }),
+                    rest,
+                )
+            }
             BOT_TAG => (DataValue::Bot, remaining),
             VEC_TAG => {
                 let (t_tag, remaining) = remaining.split_first().unwrap();
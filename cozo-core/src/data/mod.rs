@@ -14,6 +14,8 @@ pub(crate) mod memcmp;
 pub mod program;
 pub(crate) mod relation;
 pub mod symb;
+pub(crate) mod table_func;
+pub(crate) mod temporal;
 pub(crate) mod tuple;
 pub(crate) mod value;
 
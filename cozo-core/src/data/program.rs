@@ -11,6 +11,8 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
 
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use miette::{bail, ensure, miette, Diagnostic, Result};
 use smallvec::SmallVec;
 use smartstring::{LazyCompact, SmartString};
@@ -20,6 +22,8 @@ use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::table_func::TableFunction;
+use crate::data::tuple::Tuple;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::{FixedRule, FixedRuleHandle};
 use crate::fts::FtsIndexManifest;
@@ -50,6 +54,9 @@ pub(crate) enum ReturnMutation {
 pub struct QueryOutOptions {
     pub limit: Option<usize>,
     pub offset: Option<usize>,
+    /// Only return rows sorting after this row of a previous page, see [QueryOutOptions::cursor_keys].
+    /// An empty row starts from the first page.
+    pub after: Option<Tuple>,
     /// Terminate query with an error if it exceeds this many seconds.
     pub timeout: Option<f64>,
     /// Sleep after performing the query for this number of seconds. Ignored in WASM.
@@ -73,6 +80,9 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.offset {
             writeln!(f, ":offset {l};")?;
         }
+        if let Some(row) = &self.after {
+            writeln!(f, ":after {:?};", encode_cursor(row))?;
+        }
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
@@ -183,6 +193,45 @@ impl QueryOutOptions {
             (Some(i), Some(j)) => Some(i + j),
         }
     }
+    /// The `:limit` of a query paginated with `:after`, whose full pages come with a cursor.
+    pub(crate) fn page_limit(&self) -> Option<usize> {
+        match (&self.after, &self.store_relation) {
+            (Some(_), None) => self.limit,
+            _ => None,
+        }
+    }
+    /// The positions in `head` and directions (`true` for descending) that keyset cursors are ordered by.
+    /// With `:after`, the parser extends the sort keys with the rest of the row, so that the order is total.
+    pub(crate) fn cursor_keys(&self, head: &[Symbol]) -> Vec<(usize, bool)> {
+        self.sorters
+            .iter()
+            .filter_map(|(symb, dir)| {
+                head.iter()
+                    .position(|h| h == symb)
+                    .map(|i| (i, *dir == SortDir::Dsc))
+            })
+            .collect()
+    }
+}
+
+/// The cursor for the page after `rows`, if they fill the `page_limit`
+/// given by [QueryOutOptions::page_limit].
+pub(crate) fn page_cursor(page_limit: Option<usize>, rows: &[Tuple]) -> Option<String> {
+    match (page_limit, rows.last()) {
+        (Some(limit), Some(last)) if rows.len() == limit => Some(encode_cursor(last)),
+        _ => None,
+    }
+}
+
+/// Encode the last row of a page as an opaque cursor for `:after`.
+pub(crate) fn encode_cursor(row: &[DataValue]) -> String {
+    URL_SAFE_NO_PAD.encode(rmp_serde::to_vec(row).unwrap())
+}
+
+/// Decode a cursor produced by [encode_cursor].
+pub(crate) fn decode_cursor(cursor: &str) -> Option<Tuple> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    rmp_serde::from_slice(&bytes).ok()
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -279,6 +328,7 @@ impl Debug for FixedRuleApply {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct MagicFixedRuleApply {
     pub(crate) fixed_handle: FixedRuleHandle,
     pub(crate) rule_args: Vec<MagicFixedRuleRuleArg>,
@@ -419,7 +469,7 @@ impl Display for FixedRuleArg {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum MagicFixedRuleRuleArg {
     InMem {
         name: MagicSymbol,
@@ -489,7 +539,7 @@ impl Display for InputProgram {
                                 write!(f, ", ")?;
                             }
                             if let Some((aggr, aggr_args)) = a {
-                                write!(f, "{}({}", aggr.name, h)?;
+                                write!(f, "{}({}", aggr.name(), h)?;
                                 for aga in aggr_args {
                                     write!(f, ", {aga}")?;
                                 }
@@ -561,6 +611,71 @@ struct EntryHeadNotExplicitlyDefinedError(#[label] SourceSpan);
 pub(crate) struct NoEntryError;
 
 impl InputProgram {
+    /// The names of the stored relations the program reads from or writes to,
+    /// and whether any of them is read at a specific validity.
+    pub(crate) fn stored_relations(&self) -> (BTreeSet<SmartString<LazyCompact>>, bool) {
+        fn collect_atom(
+            atom: &InputAtom,
+            coll: &mut BTreeSet<SmartString<LazyCompact>>,
+            time_travel: &mut bool,
+        ) {
+            match atom {
+                InputAtom::Relation { inner } => {
+                    coll.insert(inner.name.name.clone());
+                    *time_travel |= inner.valid_at.is_some();
+                }
+                InputAtom::NamedFieldRelation { inner } => {
+                    coll.insert(inner.name.name.clone());
+                    *time_travel |= inner.valid_at.is_some();
+                }
+                InputAtom::Search { inner } => {
+                    coll.insert(inner.relation.name.clone());
+                }
+                InputAtom::Negation { inner, .. } | InputAtom::Optional { inner, .. } => {
+                    collect_atom(inner, coll, time_travel)
+                }
+                InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                    for atom in inner {
+                        collect_atom(atom, coll, time_travel);
+                    }
+                }
+                InputAtom::Rule { .. }
+                | InputAtom::Predicate { .. }
+                | InputAtom::Unification { .. }
+                | InputAtom::TableFunction { .. } => {}
+            }
+        }
+
+        let mut coll = BTreeSet::new();
+        let mut time_travel = false;
+        for rules_or_fixed in self.prog.values() {
+            match rules_or_fixed {
+                InputInlineRulesOrFixed::Rules { rules } => {
+                    for rule in rules {
+                        for atom in &rule.body {
+                            collect_atom(atom, &mut coll, &mut time_travel);
+                        }
+                    }
+                }
+                InputInlineRulesOrFixed::Fixed { fixed } => {
+                    for arg in &fixed.rule_args {
+                        match arg {
+                            FixedRuleArg::InMem { .. } => {}
+                            FixedRuleArg::Stored { name, valid_at, .. }
+                            | FixedRuleArg::NamedStored { name, valid_at, .. } => {
+                                coll.insert(name.name.clone());
+                                time_travel |= valid_at.is_some();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if let Some((h, _, _)) = &self.out_opts.store_relation {
+            coll.insert(h.name.name.clone());
+        }
+        (coll, time_travel)
+    }
     pub(crate) fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
         if let Some((h, _, _)) = &self.out_opts.store_relation {
             if !h.name.name.starts_with('_') {
@@ -604,14 +719,7 @@ impl InputProgram {
                     for (symb, aggr) in head.iter().zip(aggrs.iter()) {
                         if let Some((aggr, _)) = aggr {
                             ret.push(Symbol::new(
-                                format!(
-                                    "{}({})",
-                                    aggr.name
-                                        .strip_prefix("AGGR_")
-                                        .unwrap()
-                                        .to_ascii_lowercase(),
-                                    symb
-                                ),
+                                format!("{}({})", aggr.query_name(), symb),
                                 symb.span,
                             ))
                         } else {
@@ -946,6 +1054,13 @@ pub enum InputAtom {
         inner: Box<InputAtom>,
         span: SourceSpan,
     },
+    /// `optional { ... }`: binds the variables of the inner body to null when it has no match
+    Optional {
+        /// the inner body, a conjunction
+        inner: Box<InputAtom>,
+        /// span of the whole atom
+        span: SourceSpan,
+    },
     Conjunction {
         inner: Vec<InputAtom>,
         span: SourceSpan,
@@ -961,6 +1076,11 @@ pub enum InputAtom {
     Search {
         inner: SearchInput,
     },
+    /// `[a, b] in func(args..)`
+    TableFunction {
+        /// the function applied and the variables bound to its columns
+        inner: TableFunctionApply,
+    },
 }
 
 #[derive(Clone)]
@@ -1659,6 +1779,9 @@ impl Display for InputAtom {
             InputAtom::Negation { inner, .. } => {
                 write!(f, "not {inner}")?;
             }
+            InputAtom::Optional { inner, .. } => {
+                write!(f, "optional {{{inner}}}")?;
+            }
             InputAtom::Conjunction { inner, .. } => {
                 for (i, a) in inner.iter().enumerate() {
                     if i > 0 {
@@ -1692,6 +1815,9 @@ impl Display for InputAtom {
                 }
                 write!(f, "{expr}")?;
             }
+            InputAtom::TableFunction { inner } => {
+                write!(f, "{inner}")?;
+            }
         }
         Ok(())
     }
@@ -1711,6 +1837,7 @@ impl InputAtom {
     pub(crate) fn span(&self) -> SourceSpan {
         match self {
             InputAtom::Negation { span, .. }
+            | InputAtom::Optional { span, .. }
             | InputAtom::Conjunction { span, .. }
             | InputAtom::Disjunction { span, .. } => *span,
             InputAtom::Rule { inner, .. } => inner.span,
@@ -1719,6 +1846,7 @@ impl InputAtom {
             InputAtom::Predicate { inner, .. } => inner.span(),
             InputAtom::Unification { inner, .. } => inner.span,
             InputAtom::Search { inner, .. } => inner.span,
+            InputAtom::TableFunction { inner, .. } => inner.span,
         }
     }
 }
@@ -1734,6 +1862,8 @@ pub(crate) enum NormalFormAtom {
     HnswSearch(HnswSearch),
     FtsSearch(FtsSearch),
     LshSearch(LshSearch),
+    Optional(NormalFormOptional),
+    TableFunction(TableFunctionApply),
 }
 
 #[derive(Debug, Clone)]
@@ -1747,6 +1877,72 @@ pub(crate) enum MagicAtom {
     HnswSearch(HnswSearch),
     FtsSearch(FtsSearch),
     LshSearch(LshSearch),
+    Optional(MagicOptional),
+    TableFunction(TableFunctionApply),
+}
+
+/// The body of an `optional { ... }` atom, joined to the rest of the rule as a left outer join.
+#[derive(Debug, Clone)]
+pub(crate) struct NormalFormOptional {
+    pub(crate) body: Vec<NormalFormAtom>,
+    pub(crate) span: SourceSpan,
+}
+
+impl NormalFormOptional {
+    /// Variables bound by the inner body that are visible to the rest of the rule
+    pub(crate) fn bindings(&self) -> BTreeSet<Symbol> {
+        let mut ret = BTreeSet::new();
+        for atom in &self.body {
+            match atom {
+                NormalFormAtom::Rule(r) => ret.extend(r.args.iter().cloned()),
+                NormalFormAtom::Relation(v) => ret.extend(v.args.iter().cloned()),
+                NormalFormAtom::Unification(u) => {
+                    ret.insert(u.binding.clone());
+                }
+                NormalFormAtom::HnswSearch(s) => ret.extend(s.all_bindings().cloned()),
+                NormalFormAtom::FtsSearch(s) => ret.extend(s.all_bindings().cloned()),
+                NormalFormAtom::LshSearch(s) => ret.extend(s.all_bindings().cloned()),
+                NormalFormAtom::Optional(o) => ret.extend(o.bindings()),
+                NormalFormAtom::TableFunction(t) => ret.extend(t.bindings.iter().cloned()),
+                NormalFormAtom::NegatedRule(_)
+                | NormalFormAtom::NegatedRelation(_)
+                | NormalFormAtom::Predicate(_) => {}
+            }
+        }
+        ret.retain(|s| !s.is_internal_symbol());
+        ret
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct MagicOptional {
+    pub(crate) body: Vec<MagicAtom>,
+    pub(crate) span: SourceSpan,
+}
+
+impl MagicOptional {
+    pub(crate) fn bindings(&self) -> BTreeSet<Symbol> {
+        let mut ret = BTreeSet::new();
+        for atom in &self.body {
+            match atom {
+                MagicAtom::Rule(r) => ret.extend(r.args.iter().cloned()),
+                MagicAtom::Relation(v) => ret.extend(v.args.iter().cloned()),
+                MagicAtom::Unification(u) => {
+                    ret.insert(u.binding.clone());
+                }
+                MagicAtom::HnswSearch(s) => ret.extend(s.all_bindings().cloned()),
+                MagicAtom::FtsSearch(s) => ret.extend(s.all_bindings().cloned()),
+                MagicAtom::LshSearch(s) => ret.extend(s.all_bindings().cloned()),
+                MagicAtom::Optional(o) => ret.extend(o.bindings()),
+                MagicAtom::TableFunction(t) => ret.extend(t.bindings.iter().cloned()),
+                MagicAtom::NegatedRule(_)
+                | MagicAtom::NegatedRelation(_)
+                | MagicAtom::Predicate(_) => {}
+            }
+        }
+        ret.retain(|s| !s.is_internal_symbol());
+        ret
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1812,9 +2008,54 @@ pub struct Unification {
     pub span: SourceSpan,
 }
 
+/// Application of a table function, binding each row it produces to `bindings`
+#[derive(Clone)]
+pub struct TableFunctionApply {
+    pub(crate) func: Arc<TableFunction>,
+    pub(crate) args: Vec<Expr>,
+    pub(crate) bindings: Vec<Symbol>,
+    pub(crate) span: SourceSpan,
+}
+
+impl TableFunctionApply {
+    pub(crate) fn bindings_in_args(&self) -> Result<BTreeSet<Symbol>> {
+        let mut ret = BTreeSet::new();
+        for arg in &self.args {
+            ret.extend(arg.bindings()?);
+        }
+        Ok(ret)
+    }
+}
+
+impl Debug for TableFunctionApply {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl Display for TableFunctionApply {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, b) in self.bindings.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{b}")?;
+        }
+        write!(f, "] in {}(", self.func.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{arg}")?;
+        }
+        write!(f, ")")
+    }
+}
+
 impl Unification {
     pub(crate) fn is_const(&self) -> bool {
-        matches!(self.expr, Expr::Const { .. })
+        matches!(self.expr, Expr::Const { .. } | Expr::Param { .. })
     }
     pub(crate) fn bindings_in_expr(&self) -> Result<BTreeSet<Symbol>> {
         self.expr.bindings()
@@ -16,12 +16,16 @@ use base64::Engine;
 use chrono::DateTime;
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result};
+use rust_decimal::Decimal;
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::expr::Expr;
-use crate::data::value::{DataValue, JsonData, UuidWrapper, Validity, ValidityTs, Vector};
+use crate::data::temporal::{DateData, DurationData, TimeData, TimestampData};
+use crate::data::value::{
+    parse_decimal, DataValue, JsonData, UuidWrapper, Validity, ValidityTs, Vector,
+};
 use crate::Num;
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
@@ -72,6 +76,11 @@ impl Display for NullableColType {
             ColType::Json => {
                 f.write_str("Json")?;
             }
+            ColType::Date => f.write_str("Date")?,
+            ColType::Time => f.write_str("Time")?,
+            ColType::Timestamp => f.write_str("Timestamp")?,
+            ColType::Duration => f.write_str("Duration")?,
+            ColType::Decimal => f.write_str("Decimal")?,
         }
         if self.nullable {
             f.write_str("?")?;
@@ -100,6 +109,11 @@ pub enum ColType {
     Tuple(Vec<NullableColType>),
     Validity,
     Json,
+    Date,
+    Time,
+    Timestamp,
+    Duration,
+    Decimal,
 }
 
 #[derive(
@@ -401,6 +415,9 @@ impl NullableColType {
                     Num::Float(f) => {
                         json!(f)
                     }
+                    Num::Decimal(d) => {
+                        json!(d.to_string())
+                    }
                 },
                 DataValue::Str(s) => {
                     json!(s)
@@ -448,10 +465,53 @@ impl NullableColType {
                 DataValue::Validity(vld) => {
                     json!([vld.timestamp.0, vld.is_assert.0])
                 }
+                DataValue::Date(d) => {
+                    json!(d.to_string())
+                }
+                DataValue::Time(t) => {
+                    json!(t.to_string())
+                }
+                DataValue::Timestamp(ts) => {
+                    json!(ts.to_string())
+                }
+                DataValue::Duration(d) => {
+                    json!(d.to_string())
+                }
                 DataValue::Bot => {
                     json!(null)
                 }
             })),
+            ColType::Date => match data {
+                d @ DataValue::Date(_) => d,
+                DataValue::Str(s) => DataValue::Date(DateData::parse(&s)?),
+                _ => bail!(make_err()),
+            },
+            ColType::Time => match data {
+                t @ DataValue::Time(_) => t,
+                DataValue::Str(s) => DataValue::Time(TimeData::parse(&s)?),
+                _ => bail!(make_err()),
+            },
+            ColType::Timestamp => match data {
+                ts @ DataValue::Timestamp(_) => ts,
+                DataValue::Str(s) => DataValue::Timestamp(TimestampData::parse(&s, None)?),
+                DataValue::Num(n) => DataValue::Timestamp(
+                    TimestampData::from_secs(n.get_float()).ok_or_else(make_err)?,
+                ),
+                _ => bail!(make_err()),
+            },
+            ColType::Duration => match data {
+                d @ DataValue::Duration(_) => d,
+                DataValue::Str(s) => DataValue::Duration(DurationData::parse(&s)?),
+                _ => bail!(make_err()),
+            },
+            ColType::Decimal => match &data {
+                DataValue::Num(Num::Float(f)) => {
+                    DataValue::from(Decimal::try_from(*f).map_err(|_| make_err())?)
+                }
+                DataValue::Num(n) => DataValue::from(n.get_decimal().unwrap()),
+                DataValue::Str(s) => DataValue::from(parse_decimal(s).ok_or_else(make_err)?),
+                _ => bail!(make_err()),
+            },
         })
     }
 }
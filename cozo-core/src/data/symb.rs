@@ -91,6 +91,10 @@ impl Symbol {
     pub(crate) fn is_generated_ignored_symbol(&self) -> bool {
         self.name.starts_with('~')
     }
+    /// Ignored or temporary symbols introduced during normalization and compilation
+    pub(crate) fn is_internal_symbol(&self) -> bool {
+        self.name.starts_with('~') || self.name.starts_with('*')
+    }
     pub(crate) fn ensure_valid_field(&self) -> Result<()> {
         if self.name.contains('(') || self.name.contains(')') {
             #[derive(Debug, Error, Diagnostic)]
@@ -0,0 +1,206 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::iter;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use miette::{bail, ensure, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::functions::op_regex;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, JsonData, Num};
+
+/// The rows produced by a call to a table function, generated lazily.
+pub(crate) type TableFunctionRows = Box<dyn Iterator<Item = Tuple>>;
+
+/// A set-returning function, used in rule bodies as `[a, b] in name(args..)`.
+/// Custom ones are registered with [crate::Db::register_table_function].
+/// Each call produces any number of rows, each having exactly `columns` values.
+pub struct TableFunction {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) min_arity: usize,
+    pub(crate) max_arity: Option<usize>,
+    pub(crate) columns: usize,
+    pub(crate) inner: Box<dyn Fn(&[DataValue]) -> Result<TableFunctionRows> + Send + Sync>,
+}
+
+impl TableFunction {
+    pub(crate) fn new(
+        name: &str,
+        min_arity: usize,
+        max_arity: Option<usize>,
+        columns: usize,
+        inner: impl Fn(&[DataValue]) -> Result<TableFunctionRows> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: SmartString::from(name),
+            min_arity,
+            max_arity,
+            columns,
+            inner: Box::new(inner),
+        }
+    }
+    pub(crate) fn accepts_arity(&self, arity: usize) -> bool {
+        self.min_arity <= arity
+            && match self.max_arity {
+                None => true,
+                Some(max) => arity <= max,
+            }
+    }
+    /// Call the function, checking that every row has the declared number of columns.
+    pub(crate) fn call<'a>(
+        &'a self,
+        args: &[DataValue],
+    ) -> Result<impl Iterator<Item = Result<Tuple>> + 'a> {
+        let rows = (self.inner)(args)?;
+        Ok(rows.map(move |row| {
+            ensure!(
+                row.len() == self.columns,
+                "table function '{}' produced a row of {} columns, {} expected",
+                self.name,
+                row.len(),
+                self.columns
+            );
+            Ok(row)
+        }))
+    }
+}
+
+impl Debug for TableFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+lazy_static! {
+    pub(crate) static ref DEFAULT_TABLE_FUNCTIONS: BTreeMap<String, Arc<TableFunction>> = {
+        [
+            TableFunction::new("generate_series", 2, Some(3), 1, generate_series),
+            TableFunction::new("json_each", 1, Some(1), 2, json_each),
+            TableFunction::new("regex_matches_rows", 2, Some(2), 2, regex_matches_rows),
+            TableFunction::new("split_rows", 2, Some(2), 2, split_rows),
+        ]
+        .into_iter()
+        .map(|f| (f.name.to_string(), Arc::new(f)))
+        .collect()
+    };
+}
+
+/// `generate_series(start, stop, step = 1)`: the numbers from `start` to `stop` inclusive,
+/// as integers if all arguments are integers, as floats otherwise.
+fn generate_series(args: &[DataValue]) -> Result<TableFunctionRows> {
+    let step = args.get(2).cloned().unwrap_or(DataValue::from(1));
+    let as_int = |v: &DataValue| match v {
+        DataValue::Num(Num::Int(i)) => Some(*i),
+        _ => None,
+    };
+    if let (Some(start), Some(stop), Some(step)) =
+        (as_int(&args[0]), as_int(&args[1]), as_int(&step))
+    {
+        ensure!(step != 0, "'generate_series' requires a non-zero step");
+        let it = iter::successors(Some(start), move |cur| cur.checked_add(step))
+            .take_while(move |cur| if step > 0 { *cur <= stop } else { *cur >= stop })
+            .map(|cur| vec![DataValue::from(cur)]);
+        return Ok(Box::new(it));
+    }
+    match (args[0].get_float(), args[1].get_float(), step.get_float()) {
+        (Some(start), Some(stop), Some(step)) => {
+            ensure!(
+                step != 0. && step.is_finite(),
+                "'generate_series' requires a non-zero step"
+            );
+            let it = (0u64..)
+                .map(move |i| start + i as f64 * step)
+                .take_while(move |cur| {
+                    if step > 0. {
+                        *cur <= stop
+                    } else {
+                        *cur >= stop
+                    }
+                })
+                .map(|cur| vec![DataValue::from(cur)]);
+            Ok(Box::new(it))
+        }
+        _ => bail!("'generate_series' requires numbers"),
+    }
+}
+
+/// `json_each(value)`: a `[key, value]` row for each field of an object,
+/// or an `[index, element]` row for each element of an array or list.
+fn json_each(args: &[DataValue]) -> Result<TableFunctionRows> {
+    let rows: Vec<_> = match &args[0] {
+        DataValue::List(l) => l
+            .iter()
+            .enumerate()
+            .map(|(i, v)| vec![DataValue::from(i as i64), v.clone()])
+            .collect(),
+        DataValue::Json(JsonData(serde_json::Value::Object(o))) => o
+            .iter()
+            .map(|(k, v)| {
+                vec![
+                    DataValue::from(k.as_str()),
+                    DataValue::Json(JsonData(v.clone())),
+                ]
+            })
+            .collect(),
+        DataValue::Json(JsonData(serde_json::Value::Array(a))) => a
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                vec![
+                    DataValue::from(i as i64),
+                    DataValue::Json(JsonData(v.clone())),
+                ]
+            })
+            .collect(),
+        _ => bail!("'json_each' requires a JSON object or array, or a list"),
+    };
+    Ok(Box::new(rows.into_iter()))
+}
+
+/// `regex_matches_rows(string, regex)`: a `[match, groups]` row for each match,
+/// where `groups` lists the capture groups, null for those not taking part in the match.
+fn regex_matches_rows(args: &[DataValue]) -> Result<TableFunctionRows> {
+    let re = op_regex(&args[1..2])?;
+    let rows: Vec<_> = match (&args[0], &re) {
+        (DataValue::Str(s), DataValue::Regex(r)) => {
+            r.0.captures_iter(s)
+                .map(|caps| {
+                    let groups = caps
+                        .iter()
+                        .skip(1)
+                        .map(|g| g.map_or(DataValue::Null, |g| DataValue::from(g.as_str())))
+                        .collect();
+                    vec![
+                        DataValue::from(caps.get(0).unwrap().as_str()),
+                        DataValue::List(groups),
+                    ]
+                })
+                .collect()
+        }
+        _ => bail!("'regex_matches_rows' requires strings"),
+    };
+    Ok(Box::new(rows.into_iter()))
+}
+
+/// `split_rows(string, separator)`: an `[index, part]` row for each part of the string.
+fn split_rows(args: &[DataValue]) -> Result<TableFunctionRows> {
+    let rows: Vec<_> = match (&args[0], &args[1]) {
+        (DataValue::Str(s), DataValue::Str(sep)) => s
+            .split(sep as &str)
+            .enumerate()
+            .map(|(i, part)| vec![DataValue::from(i as i64), DataValue::from(part)])
+            .collect(),
+        _ => bail!("'split_rows' requires strings"),
+    };
+    Ok(Box::new(rows.into_iter()))
+}
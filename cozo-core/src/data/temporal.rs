@@ -0,0 +1,499 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Datelike, Days, FixedOffset, Months, NaiveDate, NaiveDateTime, NaiveTime,
+    SecondsFormat, TimeZone, Timelike, Utc,
+};
+use miette::{bail, miette, Result};
+
+pub(crate) const MICROS_PER_SEC: i64 = 1_000_000;
+pub(crate) const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SEC;
+/// Days from 0001-01-01 to 1970-01-01.
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+/// Calendar date, as days since 1970-01-01
+#[derive(
+    Copy,
+    Clone,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Debug,
+    serde_derive::Deserialize,
+    serde_derive::Serialize,
+)]
+pub struct DateData(pub i32);
+
+/// Time of day, as microseconds since midnight
+#[derive(
+    Copy,
+    Clone,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Debug,
+    serde_derive::Deserialize,
+    serde_derive::Serialize,
+)]
+pub struct TimeData(pub i64);
+
+/// Instant with the offset from UTC it was given in, sorted by instant, then by offset.
+/// The offset is part of the value: the same instant given at two offsets makes two
+/// distinct values, as keys of stored relations and in joins. Use `same_instant` to
+/// compare instants only, or `to_timestamp(ts, 'UTC')` to normalize before storing.
+#[derive(
+    Copy,
+    Clone,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Debug,
+    serde_derive::Deserialize,
+    serde_derive::Serialize,
+)]
+pub struct TimestampData {
+    /// Microseconds since 1970-01-01T00:00:00Z
+    pub micros: i64,
+    /// Offset from UTC in seconds, positive east of Greenwich
+    pub offset: i32,
+}
+
+/// Duration with its calendar parts kept apart, as the length of months and days
+/// depends on the date they are added to. Sorted by approximate length,
+/// counting 30 days in a month, then by parts.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, serde_derive::Deserialize, serde_derive::Serialize,
+)]
+pub struct DurationData {
+    /// Months, a year is twelve of them
+    pub months: i32,
+    /// Days
+    pub days: i32,
+    /// Microseconds
+    pub micros: i64,
+}
+
+impl DateData {
+    pub(crate) fn from_naive(date: NaiveDate) -> Self {
+        Self(date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE)
+    }
+    pub(crate) fn to_naive(self) -> Option<NaiveDate> {
+        NaiveDate::from_num_days_from_ce_opt(self.0.checked_add(UNIX_EPOCH_DAYS_FROM_CE)?)
+    }
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let date = NaiveDate::from_str(s.trim()).map_err(|_| miette!("bad date: {}", s))?;
+        Ok(Self::from_naive(date))
+    }
+    pub(crate) fn add_days(self, days: i64) -> Option<Self> {
+        let date = self.to_naive()?;
+        let date = if days >= 0 {
+            date.checked_add_days(Days::new(days as u64))?
+        } else {
+            date.checked_sub_days(Days::new(days.unsigned_abs()))?
+        };
+        Some(Self::from_naive(date))
+    }
+    /// The date with the calendar parts of `dur` added, which must not have an exact part.
+    pub(crate) fn add_duration(self, dur: DurationData) -> Option<Self> {
+        if dur.micros != 0 {
+            return None;
+        }
+        let date = add_months(self.to_naive()?, dur.months)?;
+        Self::from_naive(date).add_days(dur.days as i64)
+    }
+    /// Midnight of the date, in UTC.
+    pub(crate) fn to_timestamp(self) -> Option<TimestampData> {
+        let dt = self.to_naive()?.and_hms_opt(0, 0, 0)?.and_utc();
+        Some(TimestampData::from_datetime(&dt))
+    }
+}
+
+impl TimeData {
+    pub(crate) fn from_naive(time: NaiveTime) -> Self {
+        // leap seconds are folded into the last second of the minute
+        let sub_micros = (time.nanosecond() / 1000).min(999_999);
+        Self(time.num_seconds_from_midnight() as i64 * MICROS_PER_SEC + sub_micros as i64)
+    }
+    pub(crate) fn to_naive(self) -> NaiveTime {
+        let micros = self.0.rem_euclid(MICROS_PER_DAY);
+        NaiveTime::from_num_seconds_from_midnight_opt(
+            (micros / MICROS_PER_SEC) as u32,
+            (micros % MICROS_PER_SEC * 1000) as u32,
+        )
+        .unwrap()
+    }
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let time = NaiveTime::from_str(s)
+            .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+            .map_err(|_| miette!("bad time: {}", s))?;
+        Ok(Self::from_naive(time))
+    }
+    /// The time `dur` later, wrapping around midnight. `dur` must not have calendar parts.
+    pub(crate) fn add_duration(self, dur: DurationData) -> Option<Self> {
+        if dur.months != 0 || dur.days != 0 {
+            return None;
+        }
+        Some(Self(
+            (self.0 + dur.micros.rem_euclid(MICROS_PER_DAY)).rem_euclid(MICROS_PER_DAY),
+        ))
+    }
+}
+
+impl TimestampData {
+    pub(crate) fn from_datetime<Tz: TimeZone>(dt: &DateTime<Tz>) -> Self {
+        Self {
+            micros: dt.timestamp_micros(),
+            offset: dt.fixed_offset().offset().local_minus_utc(),
+        }
+    }
+    pub(crate) fn to_datetime(self) -> Option<DateTime<FixedOffset>> {
+        let offset = FixedOffset::east_opt(self.offset)?;
+        Some(DateTime::<Utc>::from_timestamp_micros(self.micros)?.with_timezone(&offset))
+    }
+    /// The date and time of day at the offset of the timestamp.
+    pub(crate) fn to_local(self) -> Option<NaiveDateTime> {
+        Some(self.to_datetime()?.naive_local())
+    }
+    /// Parse an RFC 3339 timestamp. If it has no offset, or is a date only,
+    /// it is taken to be in `zone`, or in UTC if not given.
+    pub(crate) fn parse(s: &str, zone: Option<&Zone>) -> Result<Self> {
+        let s = s.trim();
+        if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self::from_datetime(&dt));
+        }
+        let local = NaiveDateTime::from_str(s)
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M"))
+            .or_else(|_| NaiveDate::from_str(s).map(|d| d.and_hms_opt(0, 0, 0).unwrap()))
+            .map_err(|_| miette!("bad timestamp: {}", s))?;
+        match zone {
+            None => Ok(Self::from_datetime(&local.and_utc())),
+            Some(zone) => zone.localize(local),
+        }
+    }
+    /// The timestamp with `dur` added. The calendar parts are added to the date
+    /// at the offset of the timestamp, which is kept.
+    pub(crate) fn add_duration(self, dur: DurationData) -> Option<Self> {
+        let local = self.to_local()?;
+        let date = add_months(local.date(), dur.months)?;
+        let date = DateData::from_naive(date).add_days(dur.days as i64)?;
+        let local = date.to_naive()?.and_time(local.time());
+        let ret = Self {
+            micros: Self::from_local(local, self.offset)?
+                .micros
+                .checked_add(dur.micros)?,
+            offset: self.offset,
+        };
+        ret.to_datetime()?;
+        Some(ret)
+    }
+    /// The timestamp for a date and time of day at the given offset.
+    pub(crate) fn from_local(local: NaiveDateTime, offset: i32) -> Option<Self> {
+        Some(Self {
+            micros: local
+                .and_utc()
+                .timestamp_micros()
+                .checked_sub(offset as i64 * MICROS_PER_SEC)?,
+            offset,
+        })
+    }
+    /// The timestamp in UTC for seconds since the epoch, as given by `now()`.
+    pub(crate) fn from_secs(secs: f64) -> Option<Self> {
+        let micros = (secs * MICROS_PER_SEC as f64).round();
+        if !micros.is_finite() || micros.abs() >= i64::MAX as f64 {
+            return None;
+        }
+        Some(Self {
+            micros: micros as i64,
+            offset: 0,
+        })
+    }
+    /// The same instant at the offset `zone` has then.
+    pub(crate) fn in_zone(self, zone: &Zone) -> Option<Self> {
+        let dt = DateTime::<Utc>::from_timestamp_micros(self.micros)?;
+        Some(Self {
+            micros: self.micros,
+            offset: zone.offset_at(&dt),
+        })
+    }
+}
+
+impl DurationData {
+    pub(crate) fn from_micros(micros: i64) -> Self {
+        Self {
+            months: 0,
+            days: 0,
+            micros,
+        }
+    }
+    /// Length in microseconds, counting 30 days in a month and 24 hours in a day.
+    pub(crate) fn approx_micros(self) -> i128 {
+        (self.months as i128 * 30 + self.days as i128) * MICROS_PER_DAY as i128
+            + self.micros as i128
+    }
+    /// Parse an ISO 8601 duration such as `P1Y2M3DT4H5M6.5S` or `P2W`.
+    /// Parts may be negative, and a leading `-` negates all of them.
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let bad = || miette!("bad duration: {}", s);
+        let trimmed = s.trim();
+        let (negated, rest) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        let rest = rest
+            .strip_prefix('P')
+            .or_else(|| rest.strip_prefix('p'))
+            .ok_or_else(bad)?;
+        let mut months = 0i64;
+        let mut days = 0i64;
+        let mut micros = 0i64;
+        let mut in_time = false;
+        let mut has_part = false;
+        let mut num = String::new();
+        for c in rest.chars() {
+            match c.to_ascii_uppercase() {
+                'T' if !in_time && num.is_empty() => in_time = true,
+                c @ ('0'..='9' | '.' | ',' | '-' | '+') => num.push(c),
+                unit => {
+                    let added = if in_time && unit == 'S' {
+                        micros.checked_add(parse_seconds(&num).ok_or_else(bad)?)
+                    } else {
+                        let n = i64::from_str(&num).map_err(|_| bad())?;
+                        match (in_time, unit) {
+                            (false, 'Y') => n.checked_mul(12).and_then(|n| months.checked_add(n)),
+                            (false, 'M') => months.checked_add(n),
+                            (false, 'W') => n.checked_mul(7).and_then(|n| days.checked_add(n)),
+                            (false, 'D') => days.checked_add(n),
+                            (true, 'H') => n
+                                .checked_mul(3600 * MICROS_PER_SEC)
+                                .and_then(|n| micros.checked_add(n)),
+                            (true, 'M') => n
+                                .checked_mul(60 * MICROS_PER_SEC)
+                                .and_then(|n| micros.checked_add(n)),
+                            _ => bail!(bad()),
+                        }
+                    };
+                    let added = added.ok_or_else(bad)?;
+                    match (in_time, unit) {
+                        (false, 'Y' | 'M') => months = added,
+                        (false, _) => days = added,
+                        (true, _) => micros = added,
+                    }
+                    num.clear();
+                    has_part = true;
+                }
+            }
+        }
+        if !num.is_empty() || !has_part {
+            bail!(bad())
+        }
+        if negated {
+            months = -months;
+            days = -days;
+            micros = micros.checked_neg().ok_or_else(bad)?;
+        }
+        Ok(Self {
+            months: i32::try_from(months).map_err(|_| bad())?,
+            days: i32::try_from(days).map_err(|_| bad())?,
+            micros,
+        })
+    }
+    pub(crate) fn checked_add(self, other: Self) -> Option<Self> {
+        Some(Self {
+            months: self.months.checked_add(other.months)?,
+            days: self.days.checked_add(other.days)?,
+            micros: self.micros.checked_add(other.micros)?,
+        })
+    }
+    pub(crate) fn checked_neg(self) -> Option<Self> {
+        Some(Self {
+            months: self.months.checked_neg()?,
+            days: self.days.checked_neg()?,
+            micros: self.micros.checked_neg()?,
+        })
+    }
+}
+
+impl PartialOrd for DurationData {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DurationData {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.approx_micros()
+            .cmp(&other.approx_micros())
+            .then_with(|| self.months.cmp(&other.months))
+            .then_with(|| self.days.cmp(&other.days))
+            .then_with(|| self.micros.cmp(&other.micros))
+    }
+}
+
+/// Seconds with an optional fraction, to microseconds. Digits past microseconds are dropped.
+fn parse_seconds(s: &str) -> Option<i64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (int_part, frac_part) = match s.find(['.', ',']) {
+        Some(idx) => (&s[..idx], &s[idx + 1..]),
+        None => (s, ""),
+    };
+    if int_part.is_empty() && frac_part.is_empty()
+        || !int_part.bytes().all(|c| c.is_ascii_digit())
+        || !frac_part.bytes().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let secs = if int_part.is_empty() {
+        0
+    } else {
+        i64::from_str(int_part).ok()?
+    };
+    let mut frac = 0;
+    for (i, c) in frac_part.bytes().take(6).enumerate() {
+        frac += (c - b'0') as i64 * 10i64.pow(5 - i as u32);
+    }
+    let micros = secs.checked_mul(MICROS_PER_SEC)?.checked_add(frac)?;
+    Some(if negative { -micros } else { micros })
+}
+
+fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    if months >= 0 {
+        date.checked_add_months(Months::new(months as u32))
+    } else {
+        date.checked_sub_months(Months::new(months.unsigned_abs()))
+    }
+}
+
+/// A time zone given by name, e.g. `Europe/Paris`, or as a fixed offset, e.g. `+08:00`.
+pub(crate) enum Zone {
+    Fixed(FixedOffset),
+    Named(chrono_tz::Tz),
+}
+
+impl Zone {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        if s == "Z" || s == "UTC" {
+            return Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap()));
+        }
+        if let Ok(offset) = FixedOffset::from_str(s) {
+            return Ok(Zone::Fixed(offset));
+        }
+        chrono_tz::Tz::from_str(s)
+            .map(Zone::Named)
+            .map_err(|_| miette!("bad timezone specification: {}", s))
+    }
+    fn offset_at(&self, dt: &DateTime<Utc>) -> i32 {
+        match self {
+            Zone::Fixed(offset) => offset.local_minus_utc(),
+            Zone::Named(tz) => dt
+                .with_timezone(tz)
+                .fixed_offset()
+                .offset()
+                .local_minus_utc(),
+        }
+    }
+    /// The timestamp for a date and time of day in the zone. Times repeated when clocks
+    /// are turned back are taken at their first occurrence.
+    pub(crate) fn localize(&self, local: NaiveDateTime) -> Result<TimestampData> {
+        let found = match self {
+            Zone::Fixed(offset) => offset
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|dt| TimestampData::from_datetime(&dt)),
+            Zone::Named(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|dt| TimestampData::from_datetime(&dt)),
+        };
+        found.ok_or_else(|| miette!("{} does not exist in the timezone", local))
+    }
+}
+
+impl Display for DateData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.to_naive() {
+            Some(date) => write!(f, "{date}"),
+            None => write!(f, "{} days since epoch", self.0),
+        }
+    }
+}
+
+impl Display for TimeData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_naive())
+    }
+}
+
+impl Display for TimestampData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.to_datetime() {
+            Some(dt) => f.write_str(&dt.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            None => write!(f, "{} microseconds since epoch", self.micros),
+        }
+    }
+}
+
+impl Display for DurationData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.months == 0 && self.days == 0 && self.micros == 0 {
+            return f.write_str("PT0S");
+        }
+        f.write_str("P")?;
+        let (years, months) = (self.months / 12, self.months % 12);
+        if years != 0 {
+            write!(f, "{years}Y")?;
+        }
+        if months != 0 {
+            write!(f, "{months}M")?;
+        }
+        if self.days != 0 {
+            write!(f, "{}D", self.days)?;
+        }
+        if self.micros != 0 {
+            f.write_str("T")?;
+            let hours = self.micros / (3600 * MICROS_PER_SEC);
+            let minutes = self.micros % (3600 * MICROS_PER_SEC) / (60 * MICROS_PER_SEC);
+            let sec_micros = self.micros % (60 * MICROS_PER_SEC);
+            if hours != 0 {
+                write!(f, "{hours}H")?;
+            }
+            if minutes != 0 {
+                write!(f, "{minutes}M")?;
+            }
+            if sec_micros != 0 {
+                if sec_micros < 0 {
+                    f.write_str("-")?;
+                }
+                let sec_micros = sec_micros.abs();
+                write!(f, "{}", sec_micros / MICROS_PER_SEC)?;
+                let frac = sec_micros % MICROS_PER_SEC;
+                if frac != 0 {
+                    let frac = format!("{frac:06}");
+                    write!(f, ".{}", frac.trim_end_matches('0'))?;
+                }
+                f.write_str("S")?;
+            }
+        }
+        Ok(())
+    }
+}
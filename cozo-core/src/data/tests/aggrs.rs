@@ -6,10 +6,16 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::sync::Arc;
+
 use approx::AbsDiffEq;
 use itertools::Itertools;
+use miette::Result;
 
-use crate::data::aggr::parse_aggr;
+use crate::data::aggr::{
+    parse_aggr, Aggregation, AggregationCall, HyperLogLog, NormalAggrObj, SimpleAggregation,
+    SpaceSaving, TDigest,
+};
 use crate::data::value::DataValue;
 
 #[test]
@@ -572,3 +578,182 @@ fn test_bit_xor() {
     bit_xor_aggr.set(&DataValue::Bytes(vec![0b01011])).unwrap();
     assert_eq!(bit_xor_aggr.get().unwrap(), DataValue::Bytes(vec![0b10111]));
 }
+
+#[test]
+fn test_approx_count_distinct() {
+    let mut aggr = parse_aggr("approx_count_distinct").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut hll_aggr = aggr.normal_op.unwrap();
+    assert_eq!(hll_aggr.get().unwrap(), DataValue::from(0));
+    for i in 0..100000 {
+        hll_aggr.set(&DataValue::from(i % 20000)).unwrap();
+    }
+    let est = hll_aggr.get().unwrap().get_int().unwrap();
+    assert!((19000..21000).contains(&est), "{}", est);
+
+    let mut aggr = parse_aggr("approx_count_distinct").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(2)]).is_err());
+
+    let mut l = HyperLogLog::new(12);
+    let mut r = HyperLogLog::new(12);
+    for i in 0..1000 {
+        l.insert(&DataValue::from(i));
+        r.insert(&DataValue::from(i + 500));
+    }
+    l.merge(&r).unwrap();
+    assert!((l.estimate() - 1500.).abs() < 100.);
+    assert!(l.merge(&HyperLogLog::new(10)).is_err());
+}
+
+#[test]
+fn test_approx_quantile() {
+    let mut aggr = parse_aggr("approx_median").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut median_aggr = aggr.normal_op.unwrap();
+    assert_eq!(median_aggr.get().unwrap(), DataValue::Null);
+    for i in 1..=4 {
+        median_aggr.set(&DataValue::from(i)).unwrap();
+    }
+    assert_eq!(median_aggr.get().unwrap(), DataValue::from(2.5));
+
+    let mut aggr = parse_aggr("approx_quantile").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(0.9)]).unwrap();
+    let mut quantile_aggr = aggr.normal_op.unwrap();
+    for i in 0..100000 {
+        quantile_aggr
+            .set(&DataValue::from((i * 7919) % 100000))
+            .unwrap();
+    }
+    let est = quantile_aggr.get().unwrap().get_float().unwrap();
+    assert!((est - 90000.).abs() < 500., "{}", est);
+    assert!(quantile_aggr.set(&DataValue::from("a")).is_err());
+
+    let mut aggr = parse_aggr("approx_quantile").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(2)]).is_err());
+    assert!(aggr.normal_init(&[]).is_err());
+
+    let mut l = TDigest::new(100.);
+    let mut r = TDigest::new(100.);
+    for i in 0..10000 {
+        l.insert(i as f64);
+        r.insert((i + 10000) as f64);
+    }
+    l.merge(&r);
+    let median = l.quantile(0.5).unwrap();
+    assert!((median - 10000.).abs() < 100., "{}", median);
+    assert_eq!(l.quantile(0.).unwrap(), 0.);
+    assert_eq!(l.quantile(1.).unwrap(), 19999.);
+}
+
+#[test]
+fn test_top_k_frequent() {
+    let mut aggr = parse_aggr("top_k_frequent").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(2)]).unwrap();
+    let mut top_k_aggr = aggr.normal_op.unwrap();
+    for i in 0..1000 {
+        let v = match i % 10 {
+            0..=4 => DataValue::from("a"),
+            5..=7 => DataValue::from("b"),
+            _ => DataValue::from(i),
+        };
+        top_k_aggr.set(&v).unwrap();
+    }
+    assert_eq!(
+        top_k_aggr.get().unwrap(),
+        DataValue::List(vec![
+            DataValue::List(vec![DataValue::from("a"), DataValue::from(500)]),
+            DataValue::List(vec![DataValue::from("b"), DataValue::from(300)]),
+        ])
+    );
+
+    let mut aggr = parse_aggr("top_k_frequent").unwrap().clone();
+    assert!(aggr.normal_init(&[]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from(0)]).is_err());
+
+    let mut l = SpaceSaving::new(10);
+    let mut r = SpaceSaving::new(10);
+    for i in 0..100 {
+        l.insert(&DataValue::from(i % 3));
+        r.insert(&DataValue::from(i % 2));
+    }
+    l.merge(&r);
+    assert_eq!(
+        l.top(2),
+        vec![(DataValue::from(0), 84), (DataValue::from(1), 83)]
+    );
+}
+
+#[test]
+fn test_merge_partial_states() {
+    let partial = |name: &str, args: &[DataValue], vals: std::ops::Range<i64>| {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(args).unwrap();
+        let mut op = aggr.normal_op.unwrap();
+        for i in vals {
+            op.set(&DataValue::from(i % 5000)).unwrap();
+        }
+        op
+    };
+
+    let mut l = partial("approx_count_distinct", &[], 0..3000);
+    let r = partial("approx_count_distinct", &[], 2000..5000);
+    l.merge(r.as_ref()).unwrap();
+    let est = l.get().unwrap().get_int().unwrap();
+    assert!((4800..5200).contains(&est), "{}", est);
+
+    let mut l = partial("approx_median", &[], 0..3000);
+    let r = partial("approx_median", &[], 3000..4000);
+    l.merge(r.as_ref()).unwrap();
+    let est = l.get().unwrap().get_float().unwrap();
+    assert!((est - 2000.).abs() < 50., "{}", est);
+
+    let mut l = partial("top_k_frequent", &[DataValue::from(1)], 0..50);
+    let r = partial("top_k_frequent", &[DataValue::from(1)], 40..45);
+    l.merge(r.as_ref()).unwrap();
+    assert_eq!(
+        l.get().unwrap(),
+        DataValue::List(vec![DataValue::List(vec![
+            DataValue::from(40),
+            DataValue::from(2)
+        ])])
+    );
+
+    let r = partial("approx_count_distinct", &[], 0..10);
+    assert!(l.merge(r.as_ref()).is_err());
+    let mut l = partial("count", &[], 0..10);
+    let r = partial("count", &[], 0..10);
+    assert!(l.merge(r.as_ref()).is_err());
+
+    // implementations only providing `set` and `get` cannot be merged
+    struct Last(DataValue);
+    impl NormalAggrObj for Last {
+        fn set(&mut self, value: &DataValue) -> Result<()> {
+            self.0 = value.clone();
+            Ok(())
+        }
+        fn get(&self) -> Result<DataValue> {
+            Ok(self.0.clone())
+        }
+    }
+    let mut l = Last(DataValue::Null);
+    assert!(l.merge(&Last(DataValue::Null)).is_err());
+    assert!(l.as_any().is_none());
+    let mut r = partial("top_k_frequent", &[DataValue::from(1)], 0..10);
+    assert!(r.merge(&l).is_err());
+}
+
+#[test]
+fn custom_aggr_name() {
+    let aggr = Aggregation::custom(
+        "last",
+        Arc::new(SimpleAggregation::new(false, |call| match call {
+            AggregationCall::Init(_) => Ok(DataValue::Null),
+            AggregationCall::Update(_, v) => Ok(v),
+            AggregationCall::Merge(_, v) => Ok(v),
+            AggregationCall::Finalize(s) => Ok(s),
+        })),
+    );
+    assert_eq!(aggr.name, "last");
+    assert_eq!(aggr.clone().name(), "last");
+    assert_eq!(parse_aggr("sum").unwrap().name, "AGGR_SUM");
+}
@@ -0,0 +1,256 @@
+/*
+ *  Copyright 2023, The Cozo Project Authors.
+ *
+ *  This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ *  If a copy of the MPL was not distributed with this file,
+ *  You can obtain one at https://mozilla.org/MPL/2.0/.
+ *
+ */
+
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde_json::json;
+
+use crate::data::aggr::parse_aggr;
+use crate::data::functions::*;
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::value::DataValue;
+use crate::DbInstance;
+
+fn dec(s: &str) -> DataValue {
+    DataValue::from(Decimal::from_str(s).unwrap())
+}
+
+#[test]
+fn memcmp_order() {
+    let values = [
+        dec("-79228162514264337593543950335"),
+        dec("-1000"),
+        dec("-0.123"),
+        dec("-0.12"),
+        DataValue::from(-0.1),
+        dec("-0.1"),
+        dec("0"),
+        DataValue::from(0),
+        dec("0.0000000000000000000000000001"),
+        dec("0.1"),
+        dec("0.10000000000000000000000001"),
+        DataValue::from(1),
+        dec("1.00"),
+        DataValue::from(1.),
+        dec("1.5"),
+        dec("9007199254740993"),
+        DataValue::from(9007199254740993i64),
+        dec("9007199254740993.5"),
+        dec("79228162514264337593543950335"),
+    ];
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    let mut by_key = values.to_vec();
+    by_key.sort_by_cached_key(|v| {
+        let mut encoded = vec![];
+        encoded.encode_datavalue(v);
+        let (decoded, rest) = DataValue::decode_from_key(&encoded);
+        assert_eq!(&decoded, v);
+        assert!(rest.is_empty());
+        encoded
+    });
+    assert_eq!(sorted, by_key);
+    assert!(dec("0.1") < dec("0.10000000000000000000000001"));
+    assert_eq!(dec("1.0"), dec("1.00"));
+    assert_ne!(dec("1"), DataValue::from(1));
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(
+        op_add(&[dec("0.1"), dec("0.2"), DataValue::from(1)]).unwrap(),
+        dec("1.3")
+    );
+    assert_eq!(
+        op_add(&[dec("0.5"), DataValue::from(0.25)]).unwrap(),
+        DataValue::from(0.75)
+    );
+    assert_eq!(
+        op_sub(&[DataValue::from(1), dec("0.9")]).unwrap(),
+        dec("0.1")
+    );
+    assert_eq!(op_mul(&[dec("1.1"), dec("1.1")]).unwrap(), dec("1.21"));
+    assert_eq!(
+        op_div(&[dec("1"), DataValue::from(4)]).unwrap(),
+        dec("0.25")
+    );
+    assert!(op_div(&[dec("1"), DataValue::from(0)]).is_err());
+    assert!(op_mul(&[dec("79228162514264337593543950335"), dec("2")]).is_err());
+    assert_eq!(op_mod(&[dec("10.5"), dec("3")]).unwrap(), dec("1.5"));
+    assert_eq!(op_minus(&[dec("1.5")]).unwrap(), dec("-1.5"));
+    assert_eq!(op_abs(&[dec("-1.5")]).unwrap(), dec("1.5"));
+    assert_eq!(op_signum(&[dec("-0.01")]).unwrap(), DataValue::from(-1));
+    assert_eq!(op_floor(&[dec("-1.5")]).unwrap(), dec("-2"));
+    assert_eq!(op_ceil(&[dec("-1.5")]).unwrap(), dec("-1"));
+    assert_eq!(op_round(&[dec("2.5")]).unwrap(), dec("3"));
+
+    assert!(op_eq(&[dec("1.0"), DataValue::from(1)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(op_lt(&[
+        DataValue::from(9007199254740992i64),
+        dec("9007199254740992.5")
+    ])
+    .unwrap()
+    .get_bool()
+    .unwrap());
+    assert!(op_ge(&[dec("0.5"), DataValue::from(0.5)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+}
+
+#[test]
+fn conversions_and_rounding() {
+    assert_eq!(
+        op_to_decimal(&[DataValue::from("12.50")]).unwrap(),
+        dec("12.5")
+    );
+    assert_eq!(
+        op_to_decimal(&[DataValue::from("1.25e2")]).unwrap(),
+        dec("125")
+    );
+    assert_eq!(op_to_decimal(&[DataValue::from(0.1)]).unwrap(), dec("0.1"));
+    assert!(op_to_decimal(&[DataValue::from("ten")]).is_err());
+    assert_eq!(
+        op_to_string(&[dec("12.50")]).unwrap(),
+        DataValue::from("12.50")
+    );
+    assert_eq!(op_to_float(&[dec("0.5")]).unwrap(), DataValue::from(0.5));
+    assert_eq!(op_to_int(&[dec("7.00")]).unwrap(), DataValue::from(7));
+    assert!(op_is_decimal(&[dec("1")]).unwrap().get_bool().unwrap());
+    assert!(op_is_num(&[dec("1")]).unwrap().get_bool().unwrap());
+
+    let round = |v: DataValue, mode: Option<&str>| {
+        let mut args = vec![v, DataValue::from(1)];
+        if let Some(mode) = mode {
+            args.push(DataValue::from(mode));
+        }
+        op_round_dp(&args)
+    };
+    assert_eq!(round(dec("2.25"), None).unwrap(), dec("2.3"));
+    assert_eq!(round(dec("2.25"), Some("half_even")).unwrap(), dec("2.2"));
+    assert_eq!(round(dec("-2.21"), Some("floor")).unwrap(), dec("-2.3"));
+    assert_eq!(round(dec("-2.29"), Some("down")).unwrap(), dec("-2.2"));
+    assert_eq!(
+        round(DataValue::from(2.25), None).unwrap(),
+        DataValue::from(2.3)
+    );
+    assert!(round(dec("2.25"), Some("sideways")).is_err());
+}
+
+#[test]
+fn aggregations() {
+    let run = |name: &str, values: &[DataValue]| {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(&[]).unwrap();
+        let mut op = aggr.normal_op.unwrap();
+        for v in values {
+            op.set(v).unwrap();
+        }
+        op.get().unwrap()
+    };
+    let tenths = vec![dec("0.1"); 10];
+    assert_eq!(run("sum", &tenths), dec("1.0"));
+    assert_eq!(run("mean", &tenths), dec("0.1"));
+    assert_eq!(run("sum", &[dec("0.1"), DataValue::from(2)]), dec("2.1"));
+    assert_eq!(
+        run("sum", &[dec("0.5"), DataValue::from(0.25)]),
+        DataValue::from(0.75)
+    );
+    assert_eq!(
+        run("sum", &[DataValue::from(1), DataValue::from(2)]),
+        DataValue::from(3.)
+    );
+    assert_eq!(run("product", &[dec("1.1"), dec("1.1")]), dec("1.21"));
+    assert_eq!(
+        run(
+            "max",
+            &[
+                DataValue::from(9007199254740992i64),
+                dec("9007199254740992.5")
+            ]
+        ),
+        dec("9007199254740992.5")
+    );
+    assert_eq!(
+        run("min", &[dec("0.3"), DataValue::from(1), dec("0.2")]),
+        dec("0.2")
+    );
+}
+
+#[test]
+fn stored_columns() {
+    let db = DbInstance::default();
+    db.run_default(":create ledger {id: Int => amount: Decimal}")
+        .unwrap();
+    db.run_default(
+        r#"
+        ?[id, amount] <- [[1, '0.10'], [2, 0.2], [3, 5], [4, '-0.3e-1']]
+        :put ledger {id => amount}
+        "#,
+    )
+    .unwrap();
+    assert!(db
+        .run_default("?[id, amount] <- [[5, 'lots']] :put ledger {id => amount}")
+        .is_err());
+    let res = db
+        .run_default("?[sum(amount), max(amount)] := *ledger{amount}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["5.27", "5"]]));
+    let res = db
+        .run_default(
+            "?[id, doubled] := *ledger{id, amount}, amount < 1, doubled = round_dp(amount * 2, 1) :order id",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, "0.2"], [2, "0.4"], [4, "-0.1"]]));
+
+    db.run_default(":create prices {price: Decimal}").unwrap();
+    db.run_default("?[price] <- [['1.50'], [1.25], ['10'], ['-2']] :put prices {price}")
+        .unwrap();
+    let res = db
+        .run_default("?[price] := *prices{price}, price >= 1.25, price < 10")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["1.25"], ["1.5"]]));
+}
+
+#[test]
+fn window_aggregations() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[id, amount] <- [[1, '1.10'], [2, '2.205'], [3, '0.1']]
+        :create ledger {id: Int => amount: Decimal}
+        "#,
+    )
+    .unwrap();
+    let res = db
+        .run_default(
+            r"
+            ?[id, total = sum(amount) over (order by id),
+              avg = mean(amount) over (order by id),
+              last_two = sum(amount, 2) over (order by id)] := *ledger{id, amount}
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            [1, "1.10", "1.10", "1.10"],
+            [2, "3.305", "1.65250", "3.305"],
+            [3, "3.405", "1.135", "2.305"]
+        ])
+    );
+}
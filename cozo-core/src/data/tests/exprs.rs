@@ -6,6 +6,11 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
+
+use crate::data::expr::{compute_bounds, Expr};
+use crate::data::functions::{OP_ADD, OP_GT};
+use crate::data::symb::Symbol;
 use crate::{DataValue, DbInstance};
 
 #[test]
@@ -30,3 +35,42 @@ fn expression_eval() {
         .unwrap();
     assert_eq!(res.rows[0][0].get_bool().unwrap(), true);
 }
+
+#[test]
+fn bound_params_bound_ranges() {
+    let k = Symbol::new("k", Default::default());
+    // k > $p + 1
+    let mut filter = Expr::Apply {
+        op: &OP_GT,
+        args: [
+            Expr::Binding {
+                var: k.clone(),
+                tuple_pos: None,
+            },
+            Expr::Apply {
+                op: &OP_ADD,
+                args: [
+                    Expr::Param {
+                        name: "p".into(),
+                        span: Default::default(),
+                    },
+                    Expr::Const {
+                        val: DataValue::from(1),
+                        span: Default::default(),
+                    },
+                ]
+                .into(),
+                span: Default::default(),
+            },
+        ]
+        .into(),
+        span: Default::default(),
+    };
+    let (lower, _) = compute_bounds(&[filter.clone()], &[k.clone()]).unwrap();
+    assert_eq!(lower, vec![DataValue::Null]);
+    filter
+        .bind_params(&BTreeMap::from([("p".to_string(), DataValue::from(5))]))
+        .unwrap();
+    let (lower, _) = compute_bounds(&[filter], &[k]).unwrap();
+    assert_eq!(lower, vec![DataValue::from(6)]);
+}
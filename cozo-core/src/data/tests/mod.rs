@@ -7,9 +7,11 @@
  */
 
 mod aggrs;
+mod decimal;
 mod exprs;
 mod functions;
 mod json;
 mod memcmp;
+mod temporal;
 mod validity;
 mod values;
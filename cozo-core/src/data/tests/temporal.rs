@@ -0,0 +1,333 @@
+/*
+ *  Copyright 2023, The Cozo Project Authors.
+ *
+ *  This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ *  If a copy of the MPL was not distributed with this file,
+ *  You can obtain one at https://mozilla.org/MPL/2.0/.
+ *
+ */
+
+use serde_json::json;
+
+use crate::data::functions::*;
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::temporal::{DateData, DurationData, TimeData, TimestampData};
+use crate::data::value::DataValue;
+use crate::DbInstance;
+
+fn date(s: &str) -> DataValue {
+    DataValue::Date(DateData::parse(s).unwrap())
+}
+
+fn timestamp(s: &str) -> DataValue {
+    DataValue::Timestamp(TimestampData::parse(s, None).unwrap())
+}
+
+fn duration(s: &str) -> DataValue {
+    DataValue::Duration(DurationData::parse(s).unwrap())
+}
+
+#[test]
+fn parse_and_display() {
+    assert_eq!(DateData::parse("1970-01-02").unwrap(), DateData(1));
+    assert_eq!(DateData::parse("1969-12-31").unwrap(), DateData(-1));
+    assert!(DateData::parse("2023-02-29").is_err());
+    assert_eq!(TimeData::parse("00:00:01.5").unwrap(), TimeData(1_500_000));
+    assert_eq!(TimeData::parse("12:30").unwrap().to_string(), "12:30:00");
+    assert_eq!(
+        timestamp("2023-05-01T10:00:00+02:00").to_string(),
+        r#"to_timestamp("2023-05-01T10:00:00+02:00")"#
+    );
+    assert_eq!(
+        timestamp("2023-05-01 08:00:00.25").to_string(),
+        r#"to_timestamp("2023-05-01T08:00:00.250Z")"#
+    );
+    assert_eq!(timestamp("2023-05-01T10:00:00+02:00"), {
+        let ts = TimestampData::parse("2023-05-01T08:00:00Z", None).unwrap();
+        DataValue::Timestamp(TimestampData { offset: 7200, ..ts })
+    });
+    for s in [
+        "P1Y2M3DT4H5M6.5S",
+        "P2W",
+        "PT0S",
+        "-P1M",
+        "PT-0.5S",
+        "P1DT-1H",
+    ] {
+        let d = DurationData::parse(s).unwrap();
+        assert_eq!(DurationData::parse(&d.to_string()).unwrap(), d, "{s}");
+    }
+    assert_eq!(
+        DurationData::parse("P1Y2M3DT4H5M6.5S").unwrap(),
+        DurationData {
+            months: 14,
+            days: 3,
+            micros: (4 * 3600 + 5 * 60 + 6) * 1_000_000 + 500_000,
+        }
+    );
+    assert_eq!(DurationData::parse("P2W").unwrap().to_string(), "P14D");
+    assert!(DurationData::parse("P").is_err());
+    assert!(DurationData::parse("P1H").is_err());
+    assert!(DurationData::parse("PT1.5H").is_err());
+}
+
+#[test]
+fn memcmp_order() {
+    let values = [
+        date("1969-12-31"),
+        date("1970-01-01"),
+        date("2023-01-01"),
+        DataValue::Time(TimeData(0)),
+        DataValue::Time(TimeData(1)),
+        timestamp("1900-01-01T00:00:00Z"),
+        timestamp("2023-05-01T10:00:00+02:00"),
+        timestamp("2023-05-01T08:00:00Z"),
+        timestamp("2023-05-01T10:00:00+02:00"),
+        duration("-P1D"),
+        duration("PT0S"),
+        duration("PT23H"),
+        duration("P1D"),
+        duration("PT24H"),
+        duration("P1M"),
+        duration("P30D"),
+    ];
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    let mut by_key = values.to_vec();
+    by_key.sort_by_cached_key(|v| {
+        let mut encoded = vec![];
+        encoded.encode_datavalue(v);
+        let (decoded, rest) = DataValue::decode_from_key(&encoded);
+        assert_eq!(&decoded, v);
+        assert!(rest.is_empty());
+        encoded
+    });
+    assert_eq!(sorted, by_key);
+    assert!(duration("PT23H") < duration("P1D"));
+    assert!(duration("PT24H") < duration("P1M"));
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(
+        op_add(&[date("2023-01-31"), duration("P1M")]).unwrap(),
+        date("2023-02-28")
+    );
+    assert_eq!(
+        op_add(&[date("2023-01-31"), DataValue::from(1)]).unwrap(),
+        date("2023-02-01")
+    );
+    assert_eq!(
+        op_add(&[date("2023-01-31"), duration("PT12H")]).unwrap(),
+        timestamp("2023-01-31T12:00:00Z")
+    );
+    assert_eq!(
+        op_add(&[
+            timestamp("2023-03-31T23:00:00-05:00"),
+            duration("P1M"),
+            duration("PT2H")
+        ])
+        .unwrap(),
+        timestamp("2023-05-01T01:00:00-05:00")
+    );
+    assert_eq!(
+        op_add(&[
+            DataValue::Time(TimeData::parse("23:00").unwrap()),
+            duration("PT2H")
+        ])
+        .unwrap(),
+        DataValue::Time(TimeData::parse("01:00").unwrap())
+    );
+    assert!(op_add(&[DataValue::Time(TimeData(0)), duration("P1D")]).is_err());
+    assert!(op_add(&[date("2023-01-31"), DataValue::from("P1D")]).is_err());
+    assert_eq!(
+        op_sub(&[date("2023-03-01"), date("2023-02-01")]).unwrap(),
+        duration("P28D")
+    );
+    assert_eq!(
+        op_sub(&[
+            timestamp("2023-05-01T10:00:00+02:00"),
+            timestamp("2023-05-01T07:00:00Z")
+        ])
+        .unwrap(),
+        duration("PT1H")
+    );
+    assert_eq!(
+        op_sub(&[date("2023-03-01"), duration("P1M")]).unwrap(),
+        date("2023-02-01")
+    );
+    assert_eq!(
+        op_sub(&[duration("P1D"), duration("PT1H")]).unwrap(),
+        duration("P1DT-1H")
+    );
+    assert_eq!(op_minus(&[duration("P1D")]).unwrap(), duration("-P1D"));
+    assert!(op_lt(&[date("2023-01-01"), date("2023-01-02")])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+}
+
+#[test]
+fn conversions() {
+    assert_eq!(
+        op_to_timestamp(&[
+            DataValue::from("2023-05-01T10:00:00"),
+            DataValue::from("Europe/Paris")
+        ])
+        .unwrap()
+        .to_string(),
+        r#"to_timestamp("2023-05-01T10:00:00+02:00")"#
+    );
+    assert_eq!(
+        op_to_timestamp(&[timestamp("2023-05-01T10:00:00Z"), DataValue::from("+08:00")])
+            .unwrap()
+            .to_string(),
+        r#"to_timestamp("2023-05-01T18:00:00+08:00")"#
+    );
+    assert_eq!(
+        op_to_timestamp(&[DataValue::from(1.5)]).unwrap(),
+        timestamp("1970-01-01T00:00:01.5Z")
+    );
+    assert_eq!(
+        op_to_date(&[timestamp("2023-05-01T23:00:00-05:00")]).unwrap(),
+        date("2023-05-01")
+    );
+    assert_eq!(
+        op_to_time(&[timestamp("2023-05-01T23:00:00-05:00")]).unwrap(),
+        DataValue::Time(TimeData::parse("23:00").unwrap())
+    );
+    // the offset is part of the value, but not of the instant
+    let utc = timestamp("2020-01-01T00:00:00Z");
+    let paris = timestamp("2020-01-01T01:00:00+01:00");
+    assert_ne!(utc, paris);
+    assert_eq!(
+        op_same_instant(&[utc.clone(), paris.clone()]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_same_instant(&[utc.clone(), timestamp("2020-01-01T01:00:00Z")]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_to_timestamp(&[paris, DataValue::from("UTC")]).unwrap(),
+        utc
+    );
+    assert!(op_same_instant(&[utc, date("2020-01-01")]).is_err());
+    assert_eq!(
+        op_to_duration(&[DataValue::from(90)]).unwrap(),
+        duration("PT1M30S")
+    );
+    assert!(op_to_date(&[DataValue::from("tomorrow")]).is_err());
+    assert!(matches!(
+        op_current_timestamp(&[]).unwrap(),
+        DataValue::Timestamp(_)
+    ));
+    assert_eq!(
+        op_format_timestamp(&[timestamp("2023-05-01T10:00:00+02:00")]).unwrap(),
+        DataValue::from("2023-05-01T10:00:00+02:00")
+    );
+    assert_eq!(
+        op_to_string(&[duration("P1D")]).unwrap(),
+        DataValue::from("P1D")
+    );
+}
+
+#[test]
+fn trunc_and_extract() {
+    let ts = timestamp("2023-05-17T10:42:13.5+02:00");
+    let trunc = |unit: &str, v: &DataValue| op_date_trunc(&[DataValue::from(unit), v.clone()]);
+    assert_eq!(
+        trunc("month", &ts).unwrap(),
+        timestamp("2023-05-01T00:00:00+02:00")
+    );
+    assert_eq!(
+        trunc("quarter", &ts).unwrap(),
+        timestamp("2023-04-01T00:00:00+02:00")
+    );
+    assert_eq!(
+        trunc("week", &ts).unwrap(),
+        timestamp("2023-05-15T00:00:00+02:00")
+    );
+    assert_eq!(
+        trunc("hour", &ts).unwrap(),
+        timestamp("2023-05-17T10:00:00+02:00")
+    );
+    assert_eq!(
+        trunc("year", &date("2023-05-17")).unwrap(),
+        date("2023-01-01")
+    );
+    assert!(trunc("fortnight", &ts).is_err());
+    assert!(trunc("year", &DataValue::Time(TimeData(0))).is_err());
+
+    let extract = |field: &str, v: &DataValue| op_extract(&[DataValue::from(field), v.clone()]);
+    assert_eq!(extract("year", &ts).unwrap(), DataValue::from(2023));
+    assert_eq!(extract("hour", &ts).unwrap(), DataValue::from(10));
+    assert_eq!(extract("second", &ts).unwrap(), DataValue::from(13.5));
+    assert_eq!(extract("isodow", &ts).unwrap(), DataValue::from(3));
+    assert_eq!(extract("timezone", &ts).unwrap(), DataValue::from(7200));
+    assert_eq!(
+        extract("epoch", &date("1970-01-02")).unwrap(),
+        DataValue::from(86400)
+    );
+    assert_eq!(
+        extract("month", &duration("P1Y2M")).unwrap(),
+        DataValue::from(2)
+    );
+    assert!(extract("hour", &date("1970-01-02")).is_err());
+}
+
+#[test]
+fn stored_columns() {
+    let db = DbInstance::default();
+    db.run_default(
+        ":create events {day: Date, at: Timestamp => start: Time?, length: Duration default to_duration('PT1H')}",
+    )
+    .unwrap();
+    db.run_default(
+        r#"
+        ?[day, at, start] <- [
+            ['2023-05-02', '2023-05-02T09:00:00+02:00', '09:00'],
+            ['2023-05-01', '2023-05-01T09:00:00Z', null],
+            ['2023-05-03', 1683104400, '11:00:00.5'],
+        ]
+        :put events {day, at => start}
+        "#,
+    )
+    .unwrap();
+    assert!(db
+        .run_default("?[day, at] <- [['May 1st', '2023-05-01T09:00:00Z']] :put events {day, at}")
+        .is_err());
+    let res = db
+        .run_default(
+            r#"
+            ?[day, at, start, end] := *events{day, at, start, length},
+                                      day >= to_date('2023-05-02'),
+                                      end = at + length
+            :order day
+            "#,
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            [
+                "2023-05-02",
+                "2023-05-02T09:00:00+02:00",
+                "09:00:00",
+                "2023-05-02T10:00:00+02:00"
+            ],
+            [
+                "2023-05-03",
+                "2023-05-03T09:00:00Z",
+                "11:00:00.500",
+                "2023-05-03T10:00:00Z"
+            ]
+        ])
+    );
+    let res = db
+        .run_default("?[n] := *events{day}, n = extract('day', day) :order n")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1], [2], [3]]));
+}
@@ -17,8 +17,10 @@ use std::ops::Deref;
 
 use crate::data::json::JsonValue;
 use crate::data::relation::VecElementType;
+use crate::data::temporal::{DateData, DurationData, TimeData, TimestampData};
 use ordered_float::OrderedFloat;
 use regex::Regex;
+use rust_decimal::Decimal;
 use serde::de::{SeqAccess, Visitor};
 use serde::ser::SerializeTuple;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -169,6 +171,14 @@ pub enum DataValue {
     Json(JsonData),
     /// validity,
     Validity(Validity),
+    /// calendar date
+    Date(DateData),
+    /// time of day
+    Time(TimeData),
+    /// timestamp with a UTC offset
+    Timestamp(TimestampData),
+    /// duration
+    Duration(DurationData),
     /// bottom type, used internally only
     Bot,
 }
@@ -462,6 +472,12 @@ impl From<f64> for DataValue {
     }
 }
 
+impl From<Decimal> for DataValue {
+    fn from(v: Decimal) -> Self {
+        DataValue::Num(Num::Decimal(v))
+    }
+}
+
 impl From<&str> for DataValue {
     fn from(v: &str) -> Self {
         DataValue::Str(SmartString::from(v))
@@ -496,6 +512,8 @@ pub enum Num {
     Int(i64),
     /// float number
     Float(f64),
+    /// exact decimal number
+    Decimal(Decimal),
 }
 
 impl Hash for Num {
@@ -503,6 +521,7 @@ impl Hash for Num {
         match self {
             Num::Int(i) => i.hash(state),
             Num::Float(f) => OrderedFloat(*f).hash(state),
+            Num::Decimal(d) => d.normalize().hash(state),
         }
     }
 }
@@ -518,16 +537,67 @@ impl Num {
                     None
                 }
             }
+            Num::Decimal(d) => {
+                if d.fract().is_zero() {
+                    i64::try_from(*d).ok()
+                } else {
+                    None
+                }
+            }
         }
     }
     pub(crate) fn get_float(&self) -> f64 {
         match self {
             Num::Int(i) => *i as f64,
             Num::Float(f) => *f,
+            Num::Decimal(d) => decimal_to_f64(*d),
+        }
+    }
+    /// Exact decimal value for ints and decimals, `None` for floats
+    pub(crate) fn get_decimal(&self) -> Option<Decimal> {
+        match self {
+            Num::Int(i) => Some(Decimal::from(*i)),
+            Num::Float(_) => None,
+            Num::Decimal(d) => Some(*d),
+        }
+    }
+    fn kind_rank(&self) -> u8 {
+        match self {
+            Num::Int(_) => 0,
+            Num::Decimal(_) => 1,
+            Num::Float(_) => 2,
+        }
+    }
+    /// Compares by numeric value: exact when no float is involved, in which
+    /// case `Int(1)` equals `Decimal(1.0)`, unlike under `Ord`.
+    pub(crate) fn numeric_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Num::Int(l), Num::Int(r)) => Some(l.cmp(r)),
+            (Num::Float(_), _) | (_, Num::Float(_)) => {
+                self.get_float().partial_cmp(&other.get_float())
+            }
+            (l, r) => Some(l.get_decimal().unwrap().cmp(&r.get_decimal().unwrap())),
         }
     }
 }
 
+/// Correctly rounded conversion, so that the result is monotone in the decimal value:
+/// the memcmp encoding of numbers relies on this.
+pub(crate) fn decimal_to_f64(d: Decimal) -> f64 {
+    if d.is_zero() {
+        return 0.;
+    }
+    d.to_string().parse().unwrap()
+}
+
+/// Parses plain (`12.50`) as well as scientific (`1.25e1`) notation.
+pub(crate) fn parse_decimal(s: &str) -> Option<Decimal> {
+    let s = s.trim();
+    Decimal::from_str_exact(s)
+        .or_else(|_| Decimal::from_scientific(s))
+        .ok()
+}
+
 impl PartialEq for Num {
     fn eq(&self, other: &Self) -> bool {
         self.cmp(other) == Ordering::Equal
@@ -553,6 +623,7 @@ impl Display for Num {
                     write!(f, "{n}")
                 }
             }
+            Num::Decimal(d) => write!(f, r#"to_decimal("{d}")"#),
         }
     }
 }
@@ -562,6 +633,7 @@ impl Debug for Num {
         match self {
             Num::Int(i) => write!(f, "{i}"),
             Num::Float(n) => write!(f, "{n}"),
+            Num::Decimal(d) => write!(f, "{d}"),
         }
     }
 }
@@ -593,6 +665,11 @@ impl Ord for Num {
             }
             (Num::Int(l), Num::Int(r)) => l.cmp(r),
             (Num::Float(l), Num::Float(r)) => l.total_cmp(r),
+            (Num::Decimal(l), Num::Decimal(r)) => l.cmp(r),
+            (l, r) => l
+                .get_float()
+                .total_cmp(&r.get_float())
+                .then_with(|| l.kind_rank().cmp(&r.kind_rank())),
         }
     }
 }
@@ -644,6 +721,10 @@ impl Display for DataValue {
                     write!(f, "json({})", j.0)
                 }
             }
+            DataValue::Date(d) => write!(f, "to_date({:?})", d.to_string()),
+            DataValue::Time(t) => write!(f, "to_time({:?})", t.to_string()),
+            DataValue::Timestamp(ts) => write!(f, "to_timestamp({:?})", ts.to_string()),
+            DataValue::Duration(d) => write!(f, "to_duration({:?})", d.to_string()),
         }
     }
 }
@@ -10,8 +10,10 @@ pub(crate) mod constant;
 pub(crate) mod csv;
 pub(crate) mod jlines;
 pub(crate) mod reorder_sort;
+pub(crate) mod window;
 
 pub(crate) use self::csv::CsvReader;
 pub(crate) use constant::Constant;
 pub(crate) use jlines::JsonReader;
 pub(crate) use reorder_sort::ReorderSort;
+pub(crate) use window::Window;
@@ -0,0 +1,472 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use miette::{ensure, Diagnostic, Result};
+use rust_decimal::Decimal;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::aggr::ExactAccum;
+use crate::data::expr::Expr;
+use crate::data::program::SortDir;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num};
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Functions that can be applied over ordered partitions in rule heads.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag,
+    Lead,
+    Sum,
+    Mean,
+    Min,
+    Max,
+    Count,
+}
+
+impl WindowFunction {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "row_number" => WindowFunction::RowNumber,
+            "rank" => WindowFunction::Rank,
+            "dense_rank" => WindowFunction::DenseRank,
+            "lag" => WindowFunction::Lag,
+            "lead" => WindowFunction::Lead,
+            "sum" => WindowFunction::Sum,
+            "mean" => WindowFunction::Mean,
+            "min" => WindowFunction::Min,
+            "max" => WindowFunction::Max,
+            "count" => WindowFunction::Count,
+            _ => return None,
+        })
+    }
+    fn name(&self) -> &'static str {
+        match self {
+            WindowFunction::RowNumber => "row_number",
+            WindowFunction::Rank => "rank",
+            WindowFunction::DenseRank => "dense_rank",
+            WindowFunction::Lag => "lag",
+            WindowFunction::Lead => "lead",
+            WindowFunction::Sum => "sum",
+            WindowFunction::Mean => "mean",
+            WindowFunction::Min => "min",
+            WindowFunction::Max => "max",
+            WindowFunction::Count => "count",
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Wrong arguments for window function '{0}'")]
+#[diagnostic(code(parser::bad_window_args))]
+#[diagnostic(help("{1}"))]
+struct BadWindowArgs(&'static str, &'static str, #[label] SourceSpan);
+
+/// A window function applied in a rule head, e.g.
+/// `rn = row_number() over (partition by dept order by -salary)`.
+///
+/// The aggregating functions (`sum`, `mean`, `min`, `max`, `count`) take an optional
+/// frame size `n`, in which case the frame is the `n` rows up to and including the
+/// current one. Without it the frame runs from the start of the partition to the last
+/// peer of the current row, i.e. rows tying with it on the ordering are included,
+/// as with the default `RANGE` framing in SQL. Without an ordering all rows of the
+/// partition are peers.
+#[derive(Debug, Clone)]
+pub(crate) struct WindowApply {
+    pub(crate) func: WindowFunction,
+    pub(crate) arg: Option<Symbol>,
+    pub(crate) params: Vec<DataValue>,
+    pub(crate) partition_by: Vec<Symbol>,
+    pub(crate) order_by: Vec<(Symbol, SortDir)>,
+    pub(crate) span: SourceSpan,
+}
+
+impl WindowApply {
+    pub(crate) fn new(
+        func: WindowFunction,
+        arg: Option<Symbol>,
+        params: Vec<DataValue>,
+        partition_by: Vec<Symbol>,
+        order_by: Vec<(Symbol, SortDir)>,
+        span: SourceSpan,
+    ) -> Result<Self> {
+        let name = func.name();
+        match func {
+            WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => {
+                ensure!(
+                    arg.is_none() && params.is_empty(),
+                    BadWindowArgs(name, "This function takes no arguments", span)
+                );
+            }
+            WindowFunction::Lag | WindowFunction::Lead => {
+                ensure!(
+                    arg.is_some() && params.len() <= 2,
+                    BadWindowArgs(
+                        name,
+                        "Expected a variable, an optional non-negative offset and an optional default value",
+                        span
+                    )
+                );
+                if let Some(offset) = params.first() {
+                    ensure!(
+                        offset.get_non_neg_int().is_some(),
+                        BadWindowArgs(name, "The offset must be a non-negative integer", span)
+                    );
+                }
+            }
+            WindowFunction::Sum
+            | WindowFunction::Mean
+            | WindowFunction::Min
+            | WindowFunction::Max
+            | WindowFunction::Count => {
+                ensure!(
+                    arg.is_some() && params.len() <= 1,
+                    BadWindowArgs(
+                        name,
+                        "Expected a variable and an optional frame size, \
+                        which is the number of rows up to and including the current one",
+                        span
+                    )
+                );
+                if let Some(frame) = params.first() {
+                    ensure!(
+                        frame.get_non_neg_int().is_some_and(|n| n > 0),
+                        BadWindowArgs(name, "The frame size must be a positive integer", span)
+                    );
+                }
+            }
+        }
+        Ok(Self {
+            func,
+            arg,
+            params,
+            partition_by,
+            order_by,
+            span,
+        })
+    }
+}
+
+/// A column in the output of [Window].
+pub(crate) enum WindowColumn {
+    /// Copied from the input at the given position
+    Binding(usize),
+    /// Computed by a window function, with all positions referring to the input
+    Apply {
+        func: WindowFunction,
+        arg: Option<usize>,
+        params: Vec<DataValue>,
+        partition_by: Vec<usize>,
+        order_by: Vec<(usize, SortDir)>,
+        span: SourceSpan,
+    },
+}
+
+/// Computes window functions over the complete input relation.
+/// Produced by the parser for rules with window functions in their heads,
+/// and never registered by name.
+pub(crate) struct Window {
+    pub(crate) columns: Vec<WindowColumn>,
+}
+
+impl FixedRule for Window {
+    #[allow(clippy::mutable_key_type)]
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let in_rel = payload.get_input(0)?;
+        let mut rows = vec![];
+        for tuple in in_rel.iter()? {
+            rows.push(tuple?);
+            poison.check()?;
+        }
+
+        let mut computed = BTreeMap::new();
+        for (i, col) in self.columns.iter().enumerate() {
+            if let WindowColumn::Apply {
+                func,
+                arg,
+                params,
+                partition_by,
+                order_by,
+                span,
+            } = col
+            {
+                let mut partitions: BTreeMap<Vec<&DataValue>, Vec<usize>> = BTreeMap::new();
+                for (row_idx, row) in rows.iter().enumerate() {
+                    let key = partition_by.iter().map(|i| &row[*i]).collect();
+                    partitions.entry(key).or_default().push(row_idx);
+                }
+                let mut results = vec![DataValue::Null; rows.len()];
+                for mut members in partitions.into_values() {
+                    members.sort_by(|a, b| compare_rows(&rows[*a], &rows[*b], order_by));
+                    apply_window(
+                        *func,
+                        *arg,
+                        params,
+                        order_by,
+                        &rows,
+                        &members,
+                        &mut results,
+                        *span,
+                    )?;
+                    poison.check()?;
+                }
+                computed.insert(i, results);
+            }
+        }
+
+        for (row_idx, row) in rows.iter().enumerate() {
+            let tuple = self
+                .columns
+                .iter()
+                .enumerate()
+                .map(|(i, col)| match col {
+                    WindowColumn::Binding(j) => row[*j].clone(),
+                    WindowColumn::Apply { .. } => computed[&i][row_idx].clone(),
+                })
+                .collect();
+            out.put(tuple);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(self.columns.len())
+    }
+}
+
+fn compare_rows(a: &Tuple, b: &Tuple, order_by: &[(usize, SortDir)]) -> Ordering {
+    for (idx, dir) in order_by {
+        match a[*idx].cmp(&b[*idx]) {
+            Ordering::Equal => {}
+            o => {
+                return match dir {
+                    SortDir::Asc => o,
+                    SortDir::Dsc => o.reverse(),
+                }
+            }
+        }
+    }
+    a.cmp(b)
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Window function '{0}' requires numbers, got {1:?}")]
+#[diagnostic(code(eval::window_requires_number))]
+struct WindowRequiresNumber(&'static str, DataValue, #[label] SourceSpan);
+
+/// Computes the window function for the `members` of a partition, given in order,
+/// and writes the results to their positions in `results`.
+#[allow(clippy::too_many_arguments)]
+fn apply_window(
+    func: WindowFunction,
+    arg: Option<usize>,
+    params: &[DataValue],
+    order_by: &[(usize, SortDir)],
+    rows: &[Tuple],
+    members: &[usize],
+    results: &mut [DataValue],
+    span: SourceSpan,
+) -> Result<()> {
+    let same_order_key = |a: usize, b: usize| {
+        order_by
+            .iter()
+            .all(|(idx, _)| rows[a][*idx] == rows[b][*idx])
+    };
+    match func {
+        WindowFunction::RowNumber => {
+            for (i, m) in members.iter().enumerate() {
+                results[*m] = DataValue::from(i as i64 + 1);
+            }
+        }
+        WindowFunction::Rank | WindowFunction::DenseRank => {
+            let mut rank = 0;
+            for (i, m) in members.iter().enumerate() {
+                if i == 0 || !same_order_key(members[i - 1], *m) {
+                    rank = if func == WindowFunction::Rank {
+                        i + 1
+                    } else {
+                        rank + 1
+                    };
+                }
+                results[*m] = DataValue::from(rank as i64);
+            }
+        }
+        WindowFunction::Lag | WindowFunction::Lead => {
+            let arg = arg.unwrap();
+            let offset = params
+                .first()
+                .and_then(|v| v.get_non_neg_int())
+                .unwrap_or(1) as usize;
+            let default = params.get(1).cloned().unwrap_or(DataValue::Null);
+            for (i, m) in members.iter().enumerate() {
+                let target = if func == WindowFunction::Lag {
+                    i.checked_sub(offset)
+                } else {
+                    i.checked_add(offset).filter(|j| *j < members.len())
+                };
+                results[*m] = match target {
+                    Some(j) => rows[members[j]][arg].clone(),
+                    None => default.clone(),
+                };
+            }
+        }
+        WindowFunction::Sum
+        | WindowFunction::Mean
+        | WindowFunction::Min
+        | WindowFunction::Max
+        | WindowFunction::Count => {
+            let arg = arg.unwrap();
+            let frame = params
+                .first()
+                .and_then(|v| v.get_non_neg_int())
+                .map(|n| n as usize);
+            let values: Vec<&DataValue> = members.iter().map(|m| &rows[*m][arg]).collect();
+            if matches!(func, WindowFunction::Sum | WindowFunction::Mean) {
+                for v in &values {
+                    ensure!(
+                        matches!(v, DataValue::Num(_) | DataValue::Null),
+                        WindowRequiresNumber(func.name(), (*v).clone(), span)
+                    );
+                }
+            }
+            match frame {
+                Some(n) => {
+                    for (i, m) in members.iter().enumerate() {
+                        let start = (i + 1).saturating_sub(n);
+                        results[*m] = aggregate_frame(func, values[start..=i].iter().copied())?;
+                    }
+                }
+                None => {
+                    let mut acc = FrameAccumulator::default();
+                    let mut peers_start = 0;
+                    for (i, v) in values.iter().enumerate() {
+                        acc.add(v)?;
+                        let has_next_peer = members
+                            .get(i + 1)
+                            .is_some_and(|next| same_order_key(members[i], *next));
+                        if !has_next_peer {
+                            let result = acc.get(func);
+                            for m in &members[peers_start..=i] {
+                                results[*m] = result.clone();
+                            }
+                            peers_start = i + 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Aggregates the values in a frame, ignoring nulls.
+fn aggregate_frame<'a>(
+    func: WindowFunction,
+    frame: impl Iterator<Item = &'a DataValue>,
+) -> Result<DataValue> {
+    let mut acc = FrameAccumulator::default();
+    for v in frame {
+        acc.add(v)?;
+    }
+    Ok(acc.get(func))
+}
+
+/// Running state for the aggregating window functions. Nulls are ignored.
+struct FrameAccumulator<'a> {
+    count: usize,
+    int_sum: Option<i64>,
+    float_sum: f64,
+    /// Sum over decimals, as computed by the `sum` and `mean` aggregations
+    exact_sum: ExactAccum,
+    min: Option<&'a DataValue>,
+    max: Option<&'a DataValue>,
+}
+
+impl Default for FrameAccumulator<'_> {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            int_sum: Some(0),
+            float_sum: 0.,
+            exact_sum: ExactAccum::new(Decimal::ZERO),
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl<'a> FrameAccumulator<'a> {
+    fn add(&mut self, v: &'a DataValue) -> Result<()> {
+        if *v == DataValue::Null {
+            return Ok(());
+        }
+        self.count += 1;
+        if let DataValue::Num(n) = v {
+            self.int_sum = match (self.int_sum, n) {
+                (Some(s), Num::Int(i)) => s.checked_add(*i),
+                _ => None,
+            };
+            self.float_sum += n.get_float();
+            self.exact_sum.update(n, Decimal::checked_add, "sum")?;
+        }
+        if !matches!(self.min, Some(m) if m <= v) {
+            self.min = Some(v);
+        }
+        if !matches!(self.max, Some(m) if m >= v) {
+            self.max = Some(v);
+        }
+        Ok(())
+    }
+    fn get(&self, func: WindowFunction) -> DataValue {
+        match func {
+            WindowFunction::Count => DataValue::from(self.count as i64),
+            WindowFunction::Min => self.min.cloned().unwrap_or(DataValue::Null),
+            WindowFunction::Max => self.max.cloned().unwrap_or(DataValue::Null),
+            WindowFunction::Sum => match (self.int_sum, self.exact_sum.get()) {
+                (Some(s), _) => DataValue::from(s),
+                (None, Some(s)) => DataValue::from(s),
+                (None, None) => DataValue::from(self.float_sum),
+            },
+            WindowFunction::Mean => {
+                if self.count == 0 {
+                    return DataValue::Null;
+                }
+                let exact_mean = self
+                    .exact_sum
+                    .get()
+                    .and_then(|s| s.checked_div(Decimal::from(self.count)));
+                match exact_mean {
+                    Some(m) => DataValue::from(m),
+                    None => DataValue::from(self.float_sum / self.count as f64),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
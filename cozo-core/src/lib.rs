@@ -32,6 +32,8 @@
 #![allow(clippy::too_many_arguments)]
 
 use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::RangeBounds;
 use std::path::Path;
 use std::sync::Arc;
 #[allow(unused_imports)]
@@ -41,42 +43,64 @@ use crossbeam::channel::{bounded, Receiver, Sender};
 use data::functions::current_validity;
 use lazy_static::lazy_static;
 pub use miette::Error;
+pub use rust_decimal::Decimal;
 use miette::Report;
 #[allow(unused_imports)]
 use miette::{
     bail, miette, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic, JSONReportHandler,
     Result, ThemeCharacters, ThemeStyles,
 };
-use parse::parse_script;
-use parse::CozoScript;
+use parse::{parse_script_with_registries, CozoScript, Registries};
 use serde_json::json;
 
 pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
+pub use runtime::backup::{
+    list_backups, restore_checkpoint_backup, verify_backup, BackupFile, BackupKind,
+    BackupManifest,
+};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
+pub use runtime::dump::DumpSummary;
+pub use runtime::prepared::PreparedQuery;
 pub use runtime::relation::decode_tuple_from_kv;
 pub use runtime::temp_store::RegularTempStore;
-pub use storage::mem::{new_cozo_mem, MemStorage};
+pub use storage::mem::{new_cozo_mem, new_cozo_mem_durable, MemStorage};
+#[cfg(feature = "storage-redb")]
+pub use storage::redb::{new_cozo_redb, new_cozo_redb_with_options, RedbStorage};
 #[cfg(feature = "storage-rocksdb")]
-pub use storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
+pub use storage::rocks::{new_cozo_rocksdb, new_cozo_rocksdb_with_options, RocksDbStorage};
 #[cfg(feature = "storage-new-rocksdb")]
-pub use storage::newrocks::{new_cozo_newrocksdb, NewRocksDbStorage};
+pub use storage::newrocks::{
+    new_cozo_newrocksdb, new_cozo_newrocksdb_with_options, NewRocksDbStorage,
+};
 #[cfg(feature = "storage-sled")]
-pub use storage::sled::{new_cozo_sled, SledStorage};
+pub use storage::sled::{new_cozo_sled, new_cozo_sled_with_options, SledStorage};
 #[cfg(feature = "storage-sqlite")]
-pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
+pub use storage::sqlite::{new_cozo_sqlite, new_cozo_sqlite_with_options, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
+pub use storage::options::{
+    RedbOptions, RocksDbCompression, RocksDbOptions, SledOptions, SqliteJournalMode,
+    SqliteOptions, SqliteSynchronous,
+};
+pub use storage::wal::WalOptions;
+pub use storage::write_log::{WriteOp, WriteSet};
 pub use storage::{Storage, StoreTx};
 
-pub use crate::data::expr::Expr;
+pub use crate::data::aggr::{AggregationCall, CustomAggregation, SimpleAggregation};
+pub use crate::data::expr::{CustomFunction, Expr};
 use crate::data::json::JsonValue;
+use crate::storage::encrypted::take_encryption_key;
+use crate::storage::options::{parse_engine_options, take_sort_memory_budget};
 pub use crate::data::symb::Symbol;
+pub use crate::data::table_func::TableFunction;
+pub use crate::data::temporal::{DateData, DurationData, TimeData, TimestampData};
 pub use crate::data::value::{JsonData, Vector};
 pub use crate::fixed_rule::SimpleFixedRule;
 pub use crate::parse::SourceSpan;
 pub use crate::runtime::callback::CallbackOp;
+pub use crate::runtime::changelog::ChangelogEntry;
 pub use crate::runtime::db::evaluate_expressions;
 pub use crate::runtime::db::get_variables;
 pub use crate::runtime::db::Payload;
@@ -109,6 +133,9 @@ pub enum DbInstance {
     #[cfg(feature = "storage-sqlite")]
     /// Sqlite storage
     Sqlite(Db<SqliteStorage>),
+    #[cfg(feature = "storage-redb")]
+    /// redb storage
+    Redb(Db<RedbStorage>),
     #[cfg(feature = "storage-rocksdb")]
     /// RocksDB storage
     RocksDb(Db<RocksDbStorage>),
@@ -135,6 +162,7 @@ impl DbInstance {
     ///
     /// * `mem`
     /// * `sqlite`
+    /// * `redb`
     /// * `rocksdb`
     /// * `newrocksdb`
     /// * `sled`
@@ -143,36 +171,104 @@ impl DbInstance {
     /// assuming all features are enabled during compilation. Otherwise only
     /// some of the engines are available. The `mem` engine is always available.
     ///
-    /// `path` is ignored for `mem` and `tikv` engines.
-    /// `options` is ignored for every engine except `tikv`.
+    /// `path` is ignored for the `tikv` engine, and for the `mem` engine unless it is durable.
+    /// `options` is a JSON object whose fields depend on the engine, and unknown fields are errors:
+    ///
+    /// * `mem`: `{"wal": true}` makes the database durable in the directory `path`,
+    ///   see [new_cozo_mem_durable]; `"sync"` and `"checkpoint_size"` set the fields of [WalOptions].
+    /// * `rocksdb` and `newrocksdb`: the fields of [RocksDbOptions].
+    ///   `{"read_only": true}` or `{"secondary_path": "..."}` open the database read-only.
+    /// * `sqlite`: the fields of [SqliteOptions]. `{"read_only": true}` opens the database read-only.
+    /// * `redb`: the fields of [RedbOptions].
+    /// * `sled`: the fields of [SledOptions].
+    /// * `tikv`: `"end_points"` and `"optimistic"`.
+    ///
+    /// With all engines, `{"encryption_key": "..."}` encrypts the values stored in a new database
+    /// with the key, or gives the key an existing database was encrypted with,
+    /// see [Db::enable_encryption]. It should be a long random string rather than a password.
+    /// `{"sort_memory_budget": ...}` sets the number of rows a sort holds in memory,
+    /// see [Db::set_sort_memory_budget].
     #[allow(unused_variables)]
     pub fn new(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self> {
         let options = if options.is_empty() { "{}" } else { options };
-        Ok(match engine {
-            "mem" => Self::Mem(new_cozo_mem()?),
+        let (options, encryption_key) = take_encryption_key(options)?;
+        let (options, sort_memory_budget) = take_sort_memory_budget(&options)?;
+        let options = options.as_str();
+        let ret = match engine {
+            "mem" => {
+                #[derive(serde_derive::Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct MemOpts {
+                    #[serde(default)]
+                    wal: bool,
+                    sync: Option<bool>,
+                    checkpoint_size: Option<u64>,
+                }
+                let opts: MemOpts = parse_engine_options("mem", options)?;
+                if opts.wal {
+                    if path.as_ref().as_os_str().is_empty() {
+                        bail!("a path is required for the durable mem engine")
+                    }
+                    let mut wal_opts = WalOptions::default();
+                    if let Some(sync) = opts.sync {
+                        wal_opts.sync = sync;
+                    }
+                    if let Some(size) = opts.checkpoint_size {
+                        wal_opts.checkpoint_size = size;
+                    }
+                    Self::Mem(new_cozo_mem_durable(path, wal_opts)?)
+                } else {
+                    Self::Mem(new_cozo_mem()?)
+                }
+            }
             #[cfg(feature = "storage-sqlite")]
-            "sqlite" => Self::Sqlite(new_cozo_sqlite(path)?),
+            "sqlite" => Self::Sqlite(new_cozo_sqlite_with_options(
+                path,
+                parse_engine_options("sqlite", options)?,
+            )?),
+            #[cfg(feature = "storage-redb")]
+            "redb" => Self::Redb(new_cozo_redb_with_options(
+                path,
+                parse_engine_options("redb", options)?,
+            )?),
             #[cfg(feature = "storage-rocksdb")]
-            "rocksdb" => Self::RocksDb(new_cozo_rocksdb(path)?),
+            "rocksdb" => Self::RocksDb(new_cozo_rocksdb_with_options(
+                path,
+                parse_engine_options("rocksdb", options)?,
+            )?),
             #[cfg(feature = "storage-new-rocksdb")]
-            "newrocksdb" => Self::NewRocksDb(new_cozo_newrocksdb(path)?),
+            "newrocksdb" => Self::NewRocksDb(new_cozo_newrocksdb_with_options(
+                path,
+                parse_engine_options("newrocksdb", options)?,
+            )?),
             #[cfg(feature = "storage-sled")]
-            "sled" => Self::Sled(new_cozo_sled(path)?),
+            "sled" => Self::Sled(new_cozo_sled_with_options(
+                path,
+                parse_engine_options("sled", options)?,
+            )?),
             #[cfg(feature = "storage-tikv")]
             "tikv" => {
                 #[derive(serde_derive::Deserialize)]
+                #[serde(deny_unknown_fields)]
                 struct TiKvOpts {
                     end_points: Vec<String>,
                     optimistic: bool,
                 }
-                let opts: TiKvOpts = serde_json::from_str(options).into_diagnostic()?;
+                let opts: TiKvOpts = parse_engine_options("tikv", options)?;
                 Self::TiKv(new_cozo_tikv(opts.end_points.clone(), opts.optimistic)?)
             }
             k => bail!(
                 "database engine '{}' not supported (maybe not compiled in)",
                 k
             ),
-        })
+        };
+        if let Some(key) = encryption_key {
+            ret.enable_encryption(key.as_bytes())?;
+        }
+        if let Some(rows) = sort_memory_budget {
+            ret.set_sort_memory_budget(rows);
+        }
+        Ok(ret)
     }
     /// Same as [Self::new], but inputs and error messages are all in strings
     pub fn new_with_str(
@@ -189,6 +285,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.get_fixed_rules(),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.get_fixed_rules(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.get_fixed_rules(),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.get_fixed_rules(),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -199,6 +297,60 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.get_fixed_rules(),
         }
     }
+    /// Dispatcher method. See [crate::Db::get_functions].
+    pub fn get_functions(&self) -> BTreeMap<String, Arc<CustomFunction>> {
+        match self {
+            DbInstance::Mem(db) => db.get_functions(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.get_functions(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.get_functions(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.get_functions(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.get_functions(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.get_functions(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.get_functions(),
+        }
+    }
+    /// Dispatcher method.  See [crate::Db::get_aggregations].
+    pub fn get_aggregations(&self) -> BTreeMap<String, Arc<dyn CustomAggregation>> {
+        match self {
+            DbInstance::Mem(db) => db.get_aggregations(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.get_aggregations(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.get_aggregations(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.get_aggregations(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.get_aggregations(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.get_aggregations(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.get_aggregations(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::get_table_functions].
+    pub fn get_table_functions(&self) -> BTreeMap<String, Arc<TableFunction>> {
+        match self {
+            DbInstance::Mem(db) => db.get_table_functions(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.get_table_functions(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.get_table_functions(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.get_table_functions(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.get_table_functions(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.get_table_functions(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.get_table_functions(),
+        }
+    }
     /// Dispatcher method. See [crate::Db::run_script].
     pub fn run_script(
         &self,
@@ -208,11 +360,44 @@ impl DbInstance {
     ) -> Result<NamedRows> {
         let cur_vld = current_validity();
         self.run_script_ast(
-            parse_script(payload, &params, &self.get_fixed_rules(), cur_vld)?,
+            parse_script_with_registries(
+                payload,
+                &params,
+                &Registries {
+                    functions: &self.get_functions(),
+                    fixed_rules: &self.get_fixed_rules(),
+                    aggregations: &self.get_aggregations(),
+                    table_functions: &self.get_table_functions(),
+                },
+                cur_vld,
+            )?,
             cur_vld,
             mutability,
         )
     }
+    /// Dispatcher method. See [crate::Db::run_script_page].
+    pub fn run_script_page(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<(NamedRows, Option<String>)> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_page(payload, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_page(payload, params, mutability),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.run_script_page(payload, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_page(payload, params, mutability),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.run_script_page(payload, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_page(payload, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_page(payload, params, mutability),
+        }
+    }
     /// `run_script` with mutable script and no parameters
     pub fn run_default(&self, payload: &str) -> Result<NamedRows> {
         self.run_script(payload, BTreeMap::new(), ScriptMutability::Mutable)
@@ -228,6 +413,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.run_script_ast(payload, cur_vld, mutability),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.run_script_ast(payload, cur_vld, mutability),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.run_script_ast(payload, cur_vld, mutability),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.run_script_ast(payload, cur_vld, mutability),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -239,24 +426,34 @@ impl DbInstance {
         }
     }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
-    /// Fold any error into the return JSON itself.
-    /// See [crate::Db::run_script].
+    /// Fold any error into the return JSON itself, which also holds the `cursor`
+    /// for the next page if there is one.
+    /// See [crate::Db::run_script_page].
     pub fn run_script_fold_err(
         &self,
         payload: &str,
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
+    ) -> JsonValue {
+        Self::fold_err(payload, || self.run_script_page(payload, params, mutability))
+    }
+    fn fold_err(
+        payload: &str,
+        run: impl FnOnce() -> Result<(NamedRows, Option<String>)>,
     ) -> JsonValue {
         #[cfg(not(target_arch = "wasm32"))]
         let start = Instant::now();
 
-        match self.run_script(payload, params, mutability) {
-            Ok(named_rows) => {
+        match run() {
+            Ok((named_rows, cursor)) => {
                 let mut j_val = named_rows.into_json();
                 #[cfg(not(target_arch = "wasm32"))]
                 let took = start.elapsed().as_secs_f64();
                 let map = j_val.as_object_mut().unwrap();
                 map.insert("ok".to_string(), json!(true));
+                if let Some(cursor) = cursor {
+                    map.insert("cursor".to_string(), json!(cursor));
+                }
                 #[cfg(not(target_arch = "wasm32"))]
                 map.insert("took".to_string(), json!(took));
 
@@ -268,18 +465,11 @@ impl DbInstance {
     /// Run the CozoScript passed in. The `params` argument is a map of parameters formatted as JSON.
     /// See [crate::Db::run_script].
     pub fn run_script_str(&self, payload: &str, params: &str, immutable: bool) -> String {
-        let params_json = if params.is_empty() {
-            BTreeMap::default()
-        } else {
-            match serde_json::from_str::<BTreeMap<String, JsonValue>>(params) {
-                Ok(map) => map
-                    .into_iter()
-                    .map(|(k, v)| (k, DataValue::from(v)))
-                    .collect(),
-                Err(_) => {
-                    return json!({"ok": false, "message": "params argument is not a JSON map"})
-                        .to_string();
-                }
+        let params_json = match params_from_str(params) {
+            Some(params) => params,
+            None => {
+                return json!({"ok": false, "message": "params argument is not a JSON map"})
+                    .to_string();
             }
         };
         self.run_script_fold_err(
@@ -293,6 +483,117 @@ impl DbInstance {
         )
         .to_string()
     }
+    /// Run a read-only query and iterate over its rows, with params formatted as JSON.
+    /// Returns the JSON-encoded headers, or the JSON-encoded error if the query failed
+    /// to start, together with the iterator. See [DbInstance::run_script_iter].
+    pub fn run_script_iter_str(&self, payload: &str, params: &str) -> (String, Option<RowIter>) {
+        let params_json = match params_from_str(params) {
+            Some(params) => params,
+            None => {
+                return (
+                    json!({"ok": false, "message": "params argument is not a JSON map"})
+                        .to_string(),
+                    None,
+                );
+            }
+        };
+        match self.run_script_iter(payload, params_json) {
+            Ok(iter) => {
+                let mut ret = json!({"ok": true, "headers": iter.headers});
+                if let Some(cursor) = &iter.cursor {
+                    ret["cursor"] = json!(cursor);
+                }
+                (ret.to_string(), Some(iter))
+            }
+            Err(err) => (format_error_as_json(err, Some(payload)).to_string(), None),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::prepare].
+    pub fn prepare(&self, script: &str) -> Result<PreparedQuery> {
+        match self {
+            DbInstance::Mem(db) => db.prepare(script),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.prepare(script),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.prepare(script),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.prepare(script),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.prepare(script),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.prepare(script),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.prepare(script),
+        }
+    }
+    /// Prepare a query, returning the error formatted as JSON if there is one.
+    /// See [crate::Db::prepare].
+    pub fn prepare_str(&self, script: &str) -> Result<PreparedQuery, String> {
+        self.prepare(script)
+            .map_err(|err| format_error_as_json(err, Some(script)).to_string())
+    }
+    /// Dispatcher method. See [crate::Db::run_prepared].
+    pub fn run_prepared(
+        &self,
+        query: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_prepared(query, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_prepared(query, params, mutability),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.run_prepared(query, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_prepared(query, params, mutability),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.run_prepared(query, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_prepared(query, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_prepared(query, params, mutability),
+        }
+    }
+    /// Run a prepared query. Fold any error into the return JSON itself.
+    /// See [crate::Db::run_prepared].
+    pub fn run_prepared_fold_err(
+        &self,
+        query: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> JsonValue {
+        Self::fold_err(query.script(), || {
+            self.run_prepared(query, params, mutability)
+                .map(|res| (res, None))
+        })
+    }
+    /// Run a prepared query, with params formatted as JSON.
+    /// See [crate::Db::run_prepared].
+    pub fn run_prepared_str(
+        &self,
+        query: &PreparedQuery,
+        params: &str,
+        immutable: bool,
+    ) -> String {
+        let params_json = match params_from_str(params) {
+            Some(params) => params,
+            None => {
+                return json!({"ok": false, "message": "params argument is not a JSON map"})
+                    .to_string();
+            }
+        };
+        self.run_prepared_fold_err(
+            query,
+            params_json,
+            if immutable {
+                ScriptMutability::Immutable
+            } else {
+                ScriptMutability::Mutable
+            },
+        )
+        .to_string()
+    }
     /// Dispatcher method. See [crate::Db::export_relations].
     pub fn export_relations<I, T>(&self, relations: I) -> Result<BTreeMap<String, NamedRows>>
     where
@@ -303,6 +604,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.export_relations(relations),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.export_relations(relations),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.export_relations(relations),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.export_relations(relations),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -345,6 +648,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.import_relations(data),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.import_relations(data),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.import_relations(data),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.import_relations(data),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -382,12 +687,50 @@ impl DbInstance {
             .collect::<Result<_>>()?;
         self.import_relations(mapping)
     }
+    /// Dispatcher method. See [crate::Db::is_read_only].
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.is_read_only(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.is_read_only(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.is_read_only(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.is_read_only(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.is_read_only(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.is_read_only(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.is_read_only(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::catch_up_with_primary].
+    pub fn catch_up_with_primary(&self) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.catch_up_with_primary(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.catch_up_with_primary(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.catch_up_with_primary(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.catch_up_with_primary(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.catch_up_with_primary(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.catch_up_with_primary(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.catch_up_with_primary(),
+        }
+    }
     /// Dispatcher method. See [crate::Db::backup_db].
     pub fn backup_db(&self, out_file: impl AsRef<Path>) -> Result<()> {
         match self {
             DbInstance::Mem(db) => db.backup_db(out_file),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.backup_db(out_file),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.backup_db(out_file),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.backup_db(out_file),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -412,6 +755,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.restore_backup(in_file),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.restore_backup(in_file),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.restore_backup(in_file),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.restore_backup(in_file),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -430,6 +775,86 @@ impl DbInstance {
             Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
         }
     }
+    /// Dispatcher method. See [crate::Db::backup_to_dir].
+    pub fn backup_to_dir(&self, dir: impl AsRef<Path>) -> Result<BackupManifest> {
+        match self {
+            DbInstance::Mem(db) => db.backup_to_dir(dir),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.backup_to_dir(dir),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.backup_to_dir(dir),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.backup_to_dir(dir),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.backup_to_dir(dir),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.backup_to_dir(dir),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.backup_to_dir(dir),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::restore_from_dir].
+    pub fn restore_from_dir(
+        &self,
+        dir: impl AsRef<Path>,
+        id: Option<u64>,
+    ) -> Result<BackupManifest> {
+        match self {
+            DbInstance::Mem(db) => db.restore_from_dir(dir, id),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_from_dir(dir, id),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.restore_from_dir(dir, id),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_from_dir(dir, id),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.restore_from_dir(dir, id),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_from_dir(dir, id),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_from_dir(dir, id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::dump].
+    pub fn dump<I, T>(&self, writer: impl Write, relations: I) -> Result<DumpSummary>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>,
+    {
+        match self {
+            DbInstance::Mem(db) => db.dump(writer, relations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.dump(writer, relations),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.dump(writer, relations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.dump(writer, relations),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.dump(writer, relations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.dump(writer, relations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.dump(writer, relations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::load].
+    pub fn load(&self, reader: impl Read) -> Result<DumpSummary> {
+        match self {
+            DbInstance::Mem(db) => db.load(reader),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.load(reader),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.load(reader),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.load(reader),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.load(reader),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.load(reader),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.load(reader),
+        }
+    }
     /// Dispatcher method. See [crate::Db::import_from_backup].
     pub fn import_from_backup(
         &self,
@@ -440,6 +865,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.import_from_backup(in_file, relations),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.import_from_backup(in_file, relations),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.import_from_backup(in_file, relations),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.import_from_backup(in_file, relations),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -480,6 +907,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.register_callback(relation, capacity),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.register_callback(relation, capacity),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.register_callback(relation, capacity),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.register_callback(relation, capacity),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -498,6 +927,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.unregister_callback(id),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.unregister_callback(id),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.unregister_callback(id),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.unregister_callback(id),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -508,6 +939,173 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.unregister_callback(id),
         }
     }
+    /// Dispatcher method. See [crate::Db::read_changelog].
+    pub fn read_changelog(
+        &self,
+        relation: &str,
+        after: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<ChangelogEntry>> {
+        match self {
+            DbInstance::Mem(db) => db.read_changelog(relation, after, limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.read_changelog(relation, after, limit),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.read_changelog(relation, after, limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.read_changelog(relation, after, limit),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.read_changelog(relation, after, limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.read_changelog(relation, after, limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.read_changelog(relation, after, limit),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::enable_write_log].
+    pub fn enable_write_log(&self) -> Result<u64> {
+        match self {
+            DbInstance::Mem(db) => db.enable_write_log(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.enable_write_log(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.enable_write_log(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.enable_write_log(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.enable_write_log(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.enable_write_log(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.enable_write_log(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::write_log_last_seq].
+    pub fn write_log_last_seq(&self) -> Option<u64> {
+        match self {
+            DbInstance::Mem(db) => db.write_log_last_seq(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.write_log_last_seq(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.write_log_last_seq(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.write_log_last_seq(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.write_log_last_seq(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.write_log_last_seq(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.write_log_last_seq(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::read_write_log].
+    pub fn read_write_log(&self, after: u64, limit: Option<usize>) -> Result<Vec<WriteSet>> {
+        match self {
+            DbInstance::Mem(db) => db.read_write_log(after, limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.read_write_log(after, limit),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.read_write_log(after, limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.read_write_log(after, limit),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.read_write_log(after, limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.read_write_log(after, limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.read_write_log(after, limit),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::truncate_write_log].
+    pub fn truncate_write_log(&self, up_to: u64) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.truncate_write_log(up_to),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.truncate_write_log(up_to),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.truncate_write_log(up_to),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.truncate_write_log(up_to),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.truncate_write_log(up_to),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.truncate_write_log(up_to),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.truncate_write_log(up_to),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::apply_write_sets].
+    pub fn apply_write_sets(&self, write_sets: &[WriteSet]) -> Result<u64> {
+        match self {
+            DbInstance::Mem(db) => db.apply_write_sets(write_sets),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.apply_write_sets(write_sets),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.apply_write_sets(write_sets),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.apply_write_sets(write_sets),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.apply_write_sets(write_sets),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.apply_write_sets(write_sets),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.apply_write_sets(write_sets),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::enable_encryption].
+    pub fn enable_encryption(&self, key: &[u8]) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.enable_encryption(key),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.enable_encryption(key),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.enable_encryption(key),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.enable_encryption(key),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.enable_encryption(key),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.enable_encryption(key),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.enable_encryption(key),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::is_encrypted].
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.is_encrypted(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.is_encrypted(),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.is_encrypted(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.is_encrypted(),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.is_encrypted(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.is_encrypted(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.is_encrypted(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::rotate_encryption_key].
+    pub fn rotate_encryption_key(&self, new_key: &[u8], new_data_key: bool) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.rotate_encryption_key(new_key, new_data_key),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.rotate_encryption_key(new_key, new_data_key),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.rotate_encryption_key(new_key, new_data_key),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.rotate_encryption_key(new_key, new_data_key),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.rotate_encryption_key(new_key, new_data_key),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.rotate_encryption_key(new_key, new_data_key),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.rotate_encryption_key(new_key, new_data_key),
+        }
+    }
     /// Dispatcher method. See [crate::Db::register_fixed_rule].
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
     where
@@ -517,6 +1115,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.register_fixed_rule(name, rule_impl),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.register_fixed_rule(name, rule_impl),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.register_fixed_rule(name, rule_impl),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.register_fixed_rule(name, rule_impl),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -533,6 +1133,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.unregister_fixed_rule(name),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.unregister_fixed_rule(name),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.unregister_fixed_rule(name),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.unregister_fixed_rule(name),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -544,6 +1146,174 @@ impl DbInstance {
         }
     }
 
+    /// Dispatcher method. See [crate::Db::register_function].
+    pub fn register_function<F>(
+        &self,
+        name: String,
+        arity: impl RangeBounds<usize>,
+        func: F,
+    ) -> Result<()>
+    where
+        F: Fn(&[DataValue]) -> Result<DataValue> + Send + Sync + 'static, {
+        match self {
+            DbInstance::Mem(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_function(name, arity, func),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_function(name, arity, func),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unregister_function].
+    pub fn unregister_function(&self, name: &str) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_function(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_function(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::register_table_function].
+    pub fn register_table_function<F, I>(
+        &self,
+        name: String,
+        arity: impl RangeBounds<usize>,
+        columns: usize,
+        func: F,
+    ) -> Result<()>
+    where
+        F: Fn(&[DataValue]) -> Result<I> + Send + Sync + 'static,
+        I: IntoIterator<Item = Vec<DataValue>>,
+        I::IntoIter: 'static,
+    {
+        match self {
+            DbInstance::Mem(db) => db.register_table_function(name, arity, columns, func),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_table_function(name, arity, columns, func),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.register_table_function(name, arity, columns, func),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_table_function(name, arity, columns, func),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.register_table_function(name, arity, columns, func),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_table_function(name, arity, columns, func),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_table_function(name, arity, columns, func),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unregister_table_function].
+    pub fn unregister_table_function(&self, name: &str) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.unregister_table_function(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_table_function(name),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.unregister_table_function(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_table_function(name),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.unregister_table_function(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_table_function(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_table_function(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::register_aggregation].
+    pub fn register_aggregation<A>(&self, name: String, aggr_impl: A) -> Result<()>
+    where
+        A: CustomAggregation + 'static,
+    {
+        match self {
+            DbInstance::Mem(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_aggregation(name, aggr_impl),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_aggregation(name, aggr_impl),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unregister_aggregation]
+    pub fn unregister_aggregation(&self, name: &str) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_aggregation(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_aggregation(name),
+        }
+    }
+
+    /// Dispatcher method. See [crate::Db::set_sort_memory_budget]
+    pub fn set_sort_memory_budget(&self, rows: usize) {
+        match self {
+            DbInstance::Mem(db) => db.set_sort_memory_budget(rows),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_sort_memory_budget(rows),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.set_sort_memory_budget(rows),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_sort_memory_budget(rows),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_sort_memory_budget(rows),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_sort_memory_budget(rows),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_sort_memory_budget(rows),
+        }
+    }
+
+    /// Dispatcher method. See [crate::Db::set_hash_join_memory_budget]
+    pub fn set_hash_join_memory_budget(&self, rows: usize) {
+        match self {
+            DbInstance::Mem(db) => db.set_hash_join_memory_budget(rows),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_hash_join_memory_budget(rows),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.set_hash_join_memory_budget(rows),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_hash_join_memory_budget(rows),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.set_hash_join_memory_budget(rows),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_hash_join_memory_budget(rows),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_hash_join_memory_budget(rows),
+        }
+    }
+
     /// Dispatcher method. See [crate::Db::run_multi_transaction]
     pub fn run_multi_transaction(
         &self,
@@ -555,6 +1325,8 @@ impl DbInstance {
             DbInstance::Mem(db) => db.run_multi_transaction(write, payloads, results),
             #[cfg(feature = "storage-sqlite")]
             DbInstance::Sqlite(db) => db.run_multi_transaction(write, payloads, results),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.run_multi_transaction(write, payloads, results),
             #[cfg(feature = "storage-rocksdb")]
             DbInstance::RocksDb(db) => db.run_multi_transaction(write, payloads, results),
             #[cfg(feature = "storage-new-rocksdb")]
@@ -565,6 +1337,60 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
         }
     }
+    /// Dispatcher method. See [crate::Db::run_script_iter]
+    pub fn run_script_iter_to_channels(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        headers: Sender<Result<(Vec<String>, Option<String>)>>,
+        rows: Sender<Result<Vec<DataValue>>>,
+    ) {
+        match self {
+            DbInstance::Mem(db) => db.run_script_iter(payload, params, headers, rows),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_iter(payload, params, headers, rows),
+            #[cfg(feature = "storage-redb")]
+            DbInstance::Redb(db) => db.run_script_iter(payload, params, headers, rows),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_iter(payload, params, headers, rows),
+            #[cfg(feature = "storage-new-rocksdb")]
+            DbInstance::NewRocksDb(db) => db.run_script_iter(payload, params, headers, rows),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_iter(payload, params, headers, rows),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_iter(payload, params, headers, rows),
+        }
+    }
+    /// A higher-level wrapper for [crate::Db::run_script_iter]. Runs a read-only query on a
+    /// dedicated thread and returns an iterator over its rows. Rows are computed as they
+    /// are pulled from the iterator, as far as the query allows.
+    pub fn run_script_iter(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<RowIter> {
+        let (headers_send, headers_recv) = bounded(1);
+        let (rows_send, rows_recv) = bounded(1);
+        let db = self.clone();
+        let payload = payload.to_string();
+        #[cfg(target_arch = "wasm32")]
+        std::thread::spawn(move || {
+            db.run_script_iter_to_channels(&payload, params, headers_send, rows_send)
+        });
+        #[cfg(not(target_arch = "wasm32"))]
+        rayon::spawn(move || {
+            db.run_script_iter_to_channels(&payload, params, headers_send, rows_send)
+        });
+        let (headers, cursor) = match headers_recv.recv() {
+            Ok(r) => r?,
+            Err(err) => bail!(err),
+        };
+        Ok(RowIter {
+            headers,
+            cursor,
+            receiver: rows_recv,
+        })
+    }
     /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
     /// Write transactions _may_ block other reads, but we guarantee that this does not happen for the RocksDB backend.
     pub fn multi_transaction(&self, write: bool) -> MultiTransaction {
@@ -582,6 +1408,51 @@ impl DbInstance {
     }
 }
 
+/// A lazy iterator over the rows of a query, obtained by [DbInstance::run_script_iter].
+/// The read transaction of the query is held until the iterator is exhausted or dropped.
+pub struct RowIter {
+    /// The headers of the result
+    pub headers: Vec<String>,
+    /// For queries with `:after` that fill their `:limit`, the cursor to pass to `:after`
+    /// to get the next page
+    pub cursor: Option<String>,
+    receiver: Receiver<Result<Vec<DataValue>>>,
+}
+
+impl RowIter {
+    /// Get the next row formatted as JSON, or `None` if the iterator is exhausted.
+    pub fn next_str(&mut self) -> Option<String> {
+        let res = match self.next()? {
+            Ok(row) => {
+                let row = row.into_iter().map(JsonValue::from).collect::<JsonValue>();
+                json!({"ok": true, "row": row})
+            }
+            Err(err) => format_error_as_json(err, None),
+        };
+        Some(res.to_string())
+    }
+}
+
+impl Iterator for RowIter {
+    type Item = Result<Vec<DataValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+fn params_from_str(params: &str) -> Option<BTreeMap<String, DataValue>> {
+    if params.is_empty() {
+        return Some(BTreeMap::default());
+    }
+    let map = serde_json::from_str::<BTreeMap<String, JsonValue>>(params).ok()?;
+    Some(
+        map.into_iter()
+            .map(|(k, v)| (k, DataValue::from(v)))
+            .collect(),
+    )
+}
+
 /// A multi-transaction handle.
 /// You should use either the fields directly, or the associated functions.
 pub struct MultiTransaction {
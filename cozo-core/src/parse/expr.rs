@@ -7,6 +7,7 @@
  */
 
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use itertools::Itertools;
 use lazy_static::lazy_static;
@@ -15,7 +16,9 @@ use pest::pratt_parser::{Op, PrattParser};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{get_op, Bytecode, Expr, NoImplementationError};
+use crate::data::expr::{
+    get_op, Bytecode, CustomFunction, Expr, NoImplementationError, ParamNotFoundError,
+};
 use crate::data::functions::{
     OP_ADD, OP_AND, OP_COALESCE, OP_CONCAT, OP_DIV, OP_EQ, OP_GE, OP_GT, OP_JSON_OBJECT, OP_LE,
     OP_LIST, OP_LT, OP_MAYBE_GET, OP_MINUS, OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_OR, OP_POW,
@@ -23,7 +26,7 @@ use crate::data::functions::{
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
-use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, Pair, ParamPool, Rule, SourceSpan};
 
 lazy_static! {
     static ref PRATT_PARSER: PrattParser<Rule> = {
@@ -55,6 +58,11 @@ lazy_static! {
 #[diagnostic(code(parser::invalid_expression))]
 pub(crate) struct InvalidExpression(#[label] pub(crate) SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Wrong number of arguments for function '{0}'")]
+#[diagnostic(code(parser::func_wrong_num_args))]
+struct WrongNumArgsError(String, #[label] SourceSpan, #[help] String);
+
 pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) -> Result<()> {
     match expr {
         Expr::Binding { var, tuple_pos } => collector.push(Bytecode::Binding {
@@ -65,6 +73,10 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) -> Resul
             val: val.clone(),
             span: *span,
         }),
+        Expr::Param { name, span } => collector.push(Bytecode::Param {
+            name: name.clone(),
+            span: *span,
+        }),
         Expr::Apply { op, args, span } => {
             let arity = args.len();
             for arg in args.iter() {
@@ -76,6 +88,17 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) -> Resul
                 span: *span,
             })
         }
+        Expr::CustomApply { func, args, span } => {
+            let arity = args.len();
+            for arg in args.iter() {
+                expr2bytecode(arg, collector)?;
+            }
+            collector.push(Bytecode::CustomApply {
+                func: func.clone(),
+                arity,
+                span: *span,
+            })
+        }
         Expr::Cond { clauses, span } => {
             let mut return_jump_pos = vec![];
             for (cond, val) in clauses {
@@ -114,14 +137,18 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) -> Resul
     Ok(())
 }
 
-pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+pub(crate) fn build_expr(
+    pair: Pair<'_>,
+    param_pool: &ParamPool<'_>,
+    functions: &BTreeMap<String, Arc<CustomFunction>>,
+) -> Result<Expr> {
     ensure!(
         pair.as_rule() == Rule::expr,
         InvalidExpression(pair.extract_span())
     );
 
     PRATT_PARSER
-        .map_primary(|v| build_term(v, param_pool))
+        .map_primary(|v| build_term(v, param_pool, functions))
         .map_infix(build_expr_infix)
         .map_prefix(|op, rhs| {
             let rhs = rhs?;
@@ -175,7 +202,22 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
     })
 }
 
-fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+/// The variable standing for the result of the subquery at `span` in the enclosing rule,
+/// bound by the auxiliary rule the subquery is desugared into.
+pub(crate) fn subquery_binding(span: SourceSpan) -> Symbol {
+    Symbol::new(format!("_subquery@{}", span.0), span)
+}
+
+/// Whether the variable stands for the result of a subquery, see [subquery_binding].
+pub(crate) fn is_subquery_binding(symb: &Symbol) -> bool {
+    symb.name.starts_with("_subquery@")
+}
+
+fn build_term(
+    pair: Pair<'_>,
+    param_pool: &ParamPool<'_>,
+    functions: &BTreeMap<String, Arc<CustomFunction>>,
+) -> Result<Expr> {
     let span = pair.extract_span();
     let op = pair.as_rule();
     Ok(match op {
@@ -184,18 +226,17 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             tuple_pos: None,
         },
         Rule::param => {
-            #[derive(Error, Diagnostic, Debug)]
-            #[error("Required parameter {0} not found")]
-            #[diagnostic(code(parser::param_not_found))]
-            struct ParamNotFoundError(String, #[label] SourceSpan);
-
             let param_str = pair.as_str().strip_prefix('$').unwrap();
-            Expr::Const {
-                val: param_pool
-                    .get(param_str)
-                    .ok_or_else(|| ParamNotFoundError(param_str.to_string(), span))?
-                    .clone(),
-                span,
+            match param_pool.get(param_str) {
+                Some(val) => Expr::Const {
+                    val: val.clone(),
+                    span,
+                },
+                None if param_pool.deferred => Expr::Param {
+                    name: SmartString::from(param_str),
+                    span,
+                },
+                None => bail!(ParamNotFoundError(param_str.to_string(), span)),
             }
         }
         Rule::pos_int => {
@@ -269,7 +310,7 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
         Rule::list => {
             let mut collected = vec![];
             for p in pair.into_inner() {
-                collected.push(build_expr(p, param_pool)?)
+                collected.push(build_expr(p, param_pool, functions)?)
             }
             Expr::Apply {
                 op: &OP_LIST,
@@ -283,8 +324,8 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 let mut p = p.into_inner();
                 let k = p.next().unwrap();
                 let v = p.next().unwrap();
-                let k = build_expr(k, param_pool)?;
-                let v = build_expr(v, param_pool)?;
+                let k = build_expr(k, param_pool, functions)?;
+                let v = build_expr(v, param_pool, functions)?;
                 args.push(k);
                 args.push(v);
             }
@@ -302,7 +343,7 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|v| build_expr(v, param_pool))
+                .map(|v| build_expr(v, param_pool, functions))
                 .try_collect()?;
             #[derive(Error, Diagnostic, Debug)]
             #[error("Named function '{0}' not found")]
@@ -379,17 +420,40 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                     Expr::Cond { clauses, span }
                 }
                 _ => match get_op(ident) {
-                    None => Expr::UnboundApply {
-                        op: ident.into(),
-                        args: args.into(),
-                        span,
+                    None => match functions.get(ident) {
+                        None => Expr::UnboundApply {
+                            op: ident.into(),
+                            args: args.into(),
+                            span,
+                        },
+                        Some(func) => {
+                            ensure!(
+                                func.accepts_arity(args.len()),
+                                WrongNumArgsError(
+                                    ident.to_string(),
+                                    span,
+                                    match func.max_arity {
+                                        None =>
+                                            format!("Need at least {} argument(s)", func.min_arity),
+                                        Some(max) if max == func.min_arity => {
+                                            format!("Need exactly {max} argument(s)")
+                                        }
+                                        Some(max) => format!(
+                                            "Need between {} and {} arguments",
+                                            func.min_arity, max
+                                        ),
+                                    }
+                                )
+                            );
+                            Expr::CustomApply {
+                                func: func.clone(),
+                                args: args.into(),
+                                span,
+                            }
+                        }
                     },
                     Some(op) => {
                         op.post_process_args(&mut args);
-                        #[derive(Error, Diagnostic, Debug)]
-                        #[error("Wrong number of arguments for function '{0}'")]
-                        #[diagnostic(code(parser::func_wrong_num_args))]
-                        struct WrongNumArgsError(String, #[label] SourceSpan, #[help] String);
 
                         if op.vararg {
                             ensure!(
@@ -419,7 +483,26 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 },
             }
         }
-        Rule::grouping => build_expr(pair.into_inner().next().unwrap(), param_pool)?,
+        Rule::grouping => build_expr(pair.into_inner().next().unwrap(), param_pool, functions)?,
+        Rule::exists_subquery => Expr::Apply {
+            op: &OP_COALESCE,
+            args: [
+                Expr::Binding {
+                    var: subquery_binding(span),
+                    tuple_pos: None,
+                },
+                Expr::Const {
+                    val: DataValue::from(false),
+                    span,
+                },
+            ]
+            .into(),
+            span,
+        },
+        Rule::scalar_subquery => Expr::Binding {
+            var: subquery_binding(span),
+            tuple_pos: None,
+        },
         r => unreachable!("Encountered unknown op {:?}", r),
     })
 }
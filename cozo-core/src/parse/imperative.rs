@@ -7,9 +7,6 @@
  *
  */
 
-use std::collections::BTreeMap;
-use std::sync::Arc;
-
 use either::{Left, Right};
 use itertools::Itertools;
 use miette::{Diagnostic, Result};
@@ -20,14 +17,14 @@ use crate::parse::query::parse_query;
 use crate::parse::sys::parse_sys;
 use crate::parse::{
     ExtractSpan, ImperativeProgram, ImperativeStmt, ImperativeStmtClause, ImperativeSysop, Pair,
-    Rule, SourceSpan,
+    ParamPool, Registries, Rule, SourceSpan,
 };
-use crate::{DataValue, FixedRule, ValidityTs};
+use crate::ValidityTs;
 
 pub(crate) fn parse_imperative_block(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
 ) -> Result<ImperativeProgram> {
     let mut collected = vec![];
@@ -37,10 +34,7 @@ pub(crate) fn parse_imperative_block(
             break;
         }
         collected.push(parse_imperative_stmt(
-            pair,
-            param_pool,
-            fixed_rules,
-            cur_vld,
+            pair, param_pool, registries, cur_vld,
         )?);
     }
 
@@ -59,8 +53,8 @@ struct DuplicateMarker(#[label] SourceSpan);
 
 fn parse_imperative_stmt(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
 ) -> Result<ImperativeStmt> {
     Ok(match pair.as_rule() {
@@ -94,7 +88,7 @@ fn parse_imperative_stmt(
                         let prog = parse_query(
                             src.next().unwrap().into_inner(),
                             param_pool,
-                            fixed_rules,
+                            registries,
                             cur_vld,
                         )?;
                         let store_as = src.next().map(|p| SmartString::from(p.as_str().trim()));
@@ -116,7 +110,7 @@ fn parse_imperative_stmt(
                     let prog = parse_query(
                         src.next().unwrap().into_inner(),
                         param_pool,
-                        fixed_rules,
+                        registries,
                         cur_vld,
                     )?;
                     let store_as = src.next().map(|p| SmartString::from(p.as_str().trim()));
@@ -128,13 +122,13 @@ fn parse_imperative_stmt(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|p| parse_imperative_stmt(p, param_pool, fixed_rules, cur_vld))
+                .map(|p| parse_imperative_stmt(p, param_pool, registries, cur_vld))
                 .try_collect()?;
             let else_body = match inner.next() {
                 None => vec![],
                 Some(rest) => rest
                     .into_inner()
-                    .map(|p| parse_imperative_stmt(p, param_pool, fixed_rules, cur_vld))
+                    .map(|p| parse_imperative_stmt(p, param_pool, registries, cur_vld))
                     .try_collect()?,
             };
             ImperativeStmt::If {
@@ -152,7 +146,7 @@ fn parse_imperative_stmt(
                 mark = Some(SmartString::from(nxt.as_str()));
                 nxt = inner.next().unwrap();
             }
-            let body = parse_imperative_block(nxt, param_pool, fixed_rules, cur_vld)?;
+            let body = parse_imperative_block(nxt, param_pool, registries, cur_vld)?;
             ImperativeStmt::Loop { label: mark, body }
         }
         Rule::temp_swap => {
@@ -182,7 +176,7 @@ fn parse_imperative_stmt(
             let sysop = parse_sys(
                 src.next().unwrap().into_inner(),
                 param_pool,
-                fixed_rules,
+                registries,
                 cur_vld,
             )?;
             let store_as = src.next().map(|p| SmartString::from(p.as_str().trim()));
@@ -195,7 +189,7 @@ fn parse_imperative_stmt(
             let prog = parse_query(
                 src.next().unwrap().into_inner(),
                 param_pool,
-                fixed_rules,
+                registries,
                 cur_vld,
             )?;
             let store_as = src.next().map(|p| SmartString::from(p.as_str().trim()));
@@ -209,7 +203,7 @@ fn parse_imperative_stmt(
             let prog = parse_query(
                 src.next().unwrap().into_inner(),
                 param_pool,
-                fixed_rules,
+                registries,
                 cur_vld,
             )?;
             let store_as = src.next().map(|p| SmartString::from(p.as_str().trim()));
@@ -21,15 +21,17 @@ use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::is_unbound_param_error;
 use crate::data::program::InputProgram;
 use crate::data::relation::NullableColType;
+use crate::data::table_func::DEFAULT_TABLE_FUNCTIONS;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::expr::build_expr;
 use crate::parse::imperative::parse_imperative_block;
 use crate::parse::query::parse_query;
 use crate::parse::schema::parse_nullable_type;
 use crate::parse::sys::{parse_sys, SysOp};
-use crate::{Expr, FixedRule};
+use crate::{CustomAggregation, CustomFunction, Expr, FixedRule, TableFunction};
 
 pub(crate) mod expr;
 pub(crate) mod fts;
@@ -198,6 +200,9 @@ impl ImperativeStmt {
                         m.base_relation, m.index_name
                     )));
                 }
+                SysOp::Analyze(rel) => {
+                    collector.insert(rel.name.clone());
+                }
                 SysOp::RemoveIndex(rel, idx) => {
                     collector.insert(SmartString::from(format!("{}:{}", rel.name, idx.name)));
                 }
@@ -274,9 +279,87 @@ pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     parse_nullable_type(parsed.into_inner().next().unwrap())
 }
 
+/// The parameters a script is parsed with.
+#[derive(Default)]
+pub(crate) struct ParamPool<'a> {
+    params: Option<&'a BTreeMap<String, DataValue>>,
+    /// If set, parameters not found are left in the syntax tree, to be bound later.
+    pub(crate) deferred: bool,
+}
+
+impl<'a> ParamPool<'a> {
+    pub(crate) fn new(params: &'a BTreeMap<String, DataValue>) -> Self {
+        Self {
+            params: Some(params),
+            deferred: false,
+        }
+    }
+    pub(crate) fn get(&self, name: &str) -> Option<&DataValue> {
+        self.params.and_then(|params| params.get(name))
+    }
+}
+
+/// Functions giving different results each time they are called.
+/// Calls to them with constant arguments are evaluated when the query is compiled.
+const VOLATILE_FUNCTIONS: &[&str] = &[
+    "now",
+    "rand_float",
+    "rand_bernoulli",
+    "rand_int",
+    "rand_choose",
+    "rand_vec",
+    "rand_uuid_v1",
+    "rand_uuid_v4",
+];
+
+/// Parse a script consisting of a single query, leaving its parameters unbound,
+/// so that the query can be compiled once and run with different parameters.
+///
+/// Returns `None` if this is not possible: if the script is not a single query, if it
+/// uses parameters where constants are required, or if it calls volatile functions
+/// or user-defined functions, whose results must not be fixed at compile time.
+pub(crate) fn parse_query_template(
+    src: &str,
+    registries: &Registries<'_>,
+    cur_vld: ValidityTs,
+) -> Result<Option<InputProgram>> {
+    let parsed = CozoScriptParser::parse(Rule::script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+    if parsed.as_rule() != Rule::query_script {
+        return Ok(None);
+    }
+    let calls_volatile = parsed.clone().into_inner().flatten().any(|pair| {
+        pair.as_rule() == Rule::apply && {
+            let name = pair.into_inner().next().unwrap().as_str();
+            VOLATILE_FUNCTIONS.contains(&name) || registries.functions.contains_key(name)
+        }
+    });
+    if calls_volatile {
+        return Ok(None);
+    }
+    let param_pool = ParamPool {
+        params: None,
+        deferred: true,
+    };
+    match parse_query(parsed.into_inner(), &param_pool, registries, cur_vld) {
+        Ok(p) => Ok(Some(p)),
+        Err(err) if is_unbound_param_error(&err) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
 pub(crate) fn parse_expressions(
     src: &str,
     param_pool: &BTreeMap<String, DataValue>,
+    functions: &BTreeMap<String, Arc<CustomFunction>>,
 ) -> Result<Expr> {
     let parsed = CozoScriptParser::parse(Rule::expression_script, src)
         .map_err(|err| {
@@ -289,7 +372,24 @@ pub(crate) fn parse_expressions(
         .next()
         .unwrap();
 
-    build_expr(parsed.into_inner().next().unwrap(), param_pool)
+    build_expr(
+        parsed.into_inner().next().unwrap(),
+        &ParamPool::new(param_pool),
+        functions,
+    )
+}
+
+/// The user-defined extensions registered with a database, which scripts are parsed against.
+#[derive(Clone, Copy)]
+pub struct Registries<'a> {
+    /// Custom functions callable in expressions
+    pub functions: &'a BTreeMap<String, Arc<CustomFunction>>,
+    /// Fixed rules, substituted into the syntax tree during parsing
+    pub fixed_rules: &'a BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    /// Custom aggregations usable in rule heads
+    pub aggregations: &'a BTreeMap<String, Arc<dyn CustomAggregation>>,
+    /// Table functions usable as atoms in rule bodies
+    pub table_functions: &'a BTreeMap<String, Arc<TableFunction>>,
 }
 
 /// This parses a text script into the AST used by Cozo.
@@ -303,11 +403,38 @@ pub(crate) fn parse_expressions(
 /// * `fixed_rules` - a mapping of fixed rule names to their implementations. These are substituted into the syntax tree during parsing.
 ///
 /// * `cur_vld` - the current timestamp, substituted into expressions where validity is relevant.
+///
+/// Only the built-in functions, aggregations and table functions are available,
+/// use [parse_script_with_registries] for custom ones.
 pub fn parse_script(
     src: &str,
     param_pool: &BTreeMap<String, DataValue>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
+) -> Result<CozoScript> {
+    parse_script_with_registries(
+        src,
+        param_pool,
+        &Registries {
+            functions: &BTreeMap::new(),
+            fixed_rules,
+            aggregations: &BTreeMap::new(),
+            table_functions: &DEFAULT_TABLE_FUNCTIONS,
+        },
+        cur_vld,
+    )
+}
+
+/// This parses a text script into the AST used by Cozo, as [parse_script] does.
+///
+/// Note! This is an unstable interface, the signature may change between releases. Depend on it at your own risk.
+///
+/// * `registries` - the custom functions, fixed rules, aggregations and table functions the script can refer to.
+pub fn parse_script_with_registries(
+    src: &str,
+    param_pool: &BTreeMap<String, DataValue>,
+    registries: &Registries<'_>,
+    cur_vld: ValidityTs,
 ) -> Result<CozoScript> {
     let parsed = CozoScriptParser::parse(Rule::script, src)
         .map_err(|err| {
@@ -319,20 +446,21 @@ pub fn parse_script(
         })?
         .next()
         .unwrap();
+    let param_pool = &ParamPool::new(param_pool);
     Ok(match parsed.as_rule() {
         Rule::query_script => {
-            let q = parse_query(parsed.into_inner(), param_pool, fixed_rules, cur_vld)?;
+            let q = parse_query(parsed.into_inner(), param_pool, registries, cur_vld)?;
             CozoScript::Single(q)
         }
         Rule::imperative_script => {
-            let p = parse_imperative_block(parsed, param_pool, fixed_rules, cur_vld)?;
+            let p = parse_imperative_block(parsed, param_pool, registries, cur_vld)?;
             CozoScript::Imperative(p)
         }
 
         Rule::sys_script => CozoScript::Sys(parse_sys(
             parsed.into_inner(),
             param_pool,
-            fixed_rules,
+            registries,
             cur_vld,
         )?),
         _ => unreachable!(),
@@ -20,22 +20,27 @@ use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::aggr::{parse_aggr, Aggregation};
-use crate::data::expr::Expr;
-use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
+use crate::data::aggr::{parse_aggr, scalar_subquery_aggr, Aggregation};
+use crate::data::expr::{CustomFunction, Expr};
+use crate::data::functions::{str2vld, MAX_VALIDITY_TS, OP_AFTER_CURSOR, OP_COALESCE, OP_LIST};
 use crate::data::program::{
-    FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
-    InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
-    QueryAssertion, QueryOutOptions, RelationOp, ReturnMutation, SearchInput, SortDir, Unification,
+    decode_cursor, FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule,
+    InputInlineRulesOrFixed, InputNamedFieldRelationApplyAtom, InputProgram,
+    InputRelationApplyAtom, InputRuleApplyAtom, QueryAssertion, QueryOutOptions, RelationOp,
+    ReturnMutation, SearchInput, SortDir, TableFunctionApply, Unification,
 };
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
+use crate::fixed_rule::utilities::window::{WindowApply, WindowColumn, WindowFunction};
+use crate::fixed_rule::utilities::Window;
 use crate::fixed_rule::{FixedRuleHandle, FixedRuleNotFoundError};
-use crate::parse::expr::build_expr;
+use crate::parse::expr::{build_expr, is_subquery_binding, subquery_binding};
 use crate::parse::schema::parse_schema;
-use crate::parse::{CozoScriptParser, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
+use crate::parse::{
+    CozoScriptParser, ExtractSpan, Pair, Pairs, ParamPool, Registries, Rule, SourceSpan,
+};
 use crate::runtime::relation::InputRelationHandle;
 use crate::FixedRule;
 
@@ -59,9 +64,26 @@ struct OptionNotPosIntError(&'static str, #[label] SourceSpan);
 #[diagnostic(code(parser::option_not_bool))]
 struct OptionNotBoolError(&'static str, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Invalid pagination cursor")]
+#[diagnostic(code(parser::invalid_cursor))]
+#[diagnostic(help(
+    "The cursor must be null for the first page, or a cursor returned by the same query"
+))]
+struct InvalidCursorError(#[label] SourceSpan);
+
 #[derive(Debug)]
 struct MultipleRuleDefinitionError(String, Vec<SourceSpan>);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Rule {0} has window functions in its head and cannot have multiple definitions")]
+#[diagnostic(code(parser::window_rule_multiple_clauses))]
+#[diagnostic(help(
+    "Collect the rows in an auxiliary rule with as many definitions as needed, \
+    and apply the window functions in a single rule over it"
+))]
+struct WindowRuleMultipleClausesError(String, #[label] SourceSpan, #[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Multiple query output assertions defined")]
 #[diagnostic(code(parser::multiple_out_assert))]
@@ -105,13 +127,15 @@ fn merge_spans(symbs: &[Symbol]) -> SourceSpan {
 
 pub(crate) fn parse_query(
     src: Pairs<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
 ) -> Result<InputProgram> {
     let mut progs: BTreeMap<Symbol, InputInlineRulesOrFixed> = Default::default();
+    let mut window_rules: BTreeSet<Symbol> = Default::default();
     let mut out_opts: QueryOutOptions = Default::default();
     let mut disable_magic_rewrite = false;
+    let mut after_span = SourceSpan::default();
 
     let mut stored_relation = None;
     let mut returning_mutation = ReturnMutation::NotReturning;
@@ -119,7 +143,42 @@ pub(crate) fn parse_query(
     for pair in src {
         match pair.as_rule() {
             Rule::rule => {
-                let (name, rule) = parse_rule(pair, param_pool, cur_vld)?;
+                let mut aux_rules = vec![];
+                let (name, rule, windows) =
+                    parse_rule(pair, param_pool, registries, cur_vld, &mut aux_rules)?;
+                for (aux_name, aux_rule) in aux_rules {
+                    progs.insert(
+                        aux_name,
+                        InputInlineRulesOrFixed::Rules {
+                            rules: vec![aux_rule],
+                        },
+                    );
+                }
+
+                if windows.iter().any(|w| w.is_some()) {
+                    if let Some(found) = progs.get(&name) {
+                        let found_span = match found {
+                            InputInlineRulesOrFixed::Rules { rules } => rules[0].span,
+                            InputInlineRulesOrFixed::Fixed { fixed } => fixed.span,
+                        };
+                        bail!(WindowRuleMultipleClausesError(
+                            name.name.to_string(),
+                            found_span,
+                            rule.span
+                        ));
+                    }
+                    window_rules.insert(name.clone());
+                    let (inner_name, inner_rule, fixed) =
+                        rewrite_window_rule(&name, rule, windows)?;
+                    progs.insert(
+                        inner_name,
+                        InputInlineRulesOrFixed::Rules {
+                            rules: vec![inner_rule],
+                        },
+                    );
+                    progs.insert(name, InputInlineRulesOrFixed::Fixed { fixed });
+                    continue;
+                }
 
                 match progs.entry(name) {
                     Entry::Vacant(e) => {
@@ -152,6 +211,14 @@ pub(crate) fn parse_query(
                             }
                             InputInlineRulesOrFixed::Fixed { fixed } => {
                                 let fixed_span = fixed.span;
+                                ensure!(
+                                    !window_rules.contains(e.key()),
+                                    WindowRuleMultipleClausesError(
+                                        e.key().name.to_string(),
+                                        fixed_span,
+                                        rule.span
+                                    )
+                                );
                                 bail!(MultipleRuleDefinitionError(
                                     e.key().name.to_string(),
                                     vec![rule.span, fixed_span]
@@ -163,7 +230,7 @@ pub(crate) fn parse_query(
             }
             Rule::fixed_rule => {
                 let rule_span = pair.extract_span();
-                let (name, apply) = parse_fixed_rule(pair, param_pool, fixed_rules, cur_vld)?;
+                let (name, apply) = parse_fixed_rule(pair, param_pool, registries, cur_vld)?;
 
                 match progs.entry(name) {
                     Entry::Vacant(e) => {
@@ -185,7 +252,9 @@ pub(crate) fn parse_query(
             Rule::const_rule => {
                 let span = pair.extract_span();
                 let mut src = pair.into_inner();
-                let (name, mut head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
+                let (name, mut head, aggr, windows) =
+                    parse_rule_head(src.next().unwrap(), param_pool, registries)?;
+                ensure_no_window(&windows)?;
 
                 if let Some(found) = progs.get(&name) {
                     let mut found_span = match found {
@@ -213,7 +282,7 @@ pub(crate) fn parse_query(
                 }
                 let data_part = src.next().unwrap();
                 let data_part_str = data_part.as_str();
-                let data = build_expr(data_part.clone(), param_pool)?;
+                let data = build_expr(data_part.clone(), param_pool, registries.functions)?;
                 let mut options = BTreeMap::new();
                 options.insert(SmartString::from("data"), data);
                 let handle = FixedRuleHandle {
@@ -260,7 +329,7 @@ pub(crate) fn parse_query(
             Rule::timeout_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let timeout = build_expr(pair, param_pool)?
+                let timeout = build_expr(pair, param_pool, registries.functions)?
                     .eval_to_const()
                     .map_err(|err| OptionNotConstantError("timeout", span, [err]))?
                     .get_float()
@@ -279,7 +348,7 @@ pub(crate) fn parse_query(
                 {
                     let pair = pair.into_inner().next().unwrap();
                     let span = pair.extract_span();
-                    let sleep = build_expr(pair, param_pool)?
+                    let sleep = build_expr(pair, param_pool, registries.functions)?
                         .eval_to_const()
                         .map_err(|err| OptionNotConstantError("sleep", span, [err]))?
                         .get_float()
@@ -291,7 +360,7 @@ pub(crate) fn parse_query(
             Rule::limit_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let limit = build_expr(pair, param_pool)?
+                let limit = build_expr(pair, param_pool, registries.functions)?
                     .eval_to_const()
                     .map_err(|err| OptionNotConstantError("limit", span, [err]))?
                     .get_non_neg_int()
@@ -301,13 +370,29 @@ pub(crate) fn parse_query(
             Rule::offset_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let offset = build_expr(pair, param_pool)?
+                let offset = build_expr(pair, param_pool, registries.functions)?
                     .eval_to_const()
                     .map_err(|err| OptionNotConstantError("offset", span, [err]))?
                     .get_non_neg_int()
                     .ok_or(OptionNotNonNegIntError("offset", span))?;
                 out_opts.offset = Some(offset as usize);
             }
+            Rule::after_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let cursor = build_expr(pair, param_pool, registries.functions)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("after", span, [err]))?;
+                out_opts.after = Some(match cursor {
+                    DataValue::Null => vec![],
+                    cursor => cursor
+                        .get_str()
+                        .and_then(decode_cursor)
+                        .filter(|row| !row.is_empty())
+                        .ok_or(InvalidCursorError(span))?,
+                });
+                after_span = span;
+            }
             Rule::sort_option => {
                 for part in pair.into_inner() {
                     let mut var = "";
@@ -352,7 +437,7 @@ pub(crate) fn parse_query(
                     None => stored_relation = Some(Left((name, span, op))),
                     Some(schema_p) => {
                         let (mut metadata, mut key_bindings, mut dep_bindings) =
-                            parse_schema(schema_p)?;
+                            parse_schema(schema_p, registries.functions)?;
                         if !matches!(op, RelationOp::Create | RelationOp::Replace) {
                             key_bindings.extend(dep_bindings);
                             dep_bindings = vec![];
@@ -389,7 +474,7 @@ pub(crate) fn parse_query(
             Rule::disable_magic_rewrite_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let val = build_expr(pair, param_pool)?
+                let val = build_expr(pair, param_pool, registries.functions)?
                     .eval_to_const()
                     .map_err(|err| OptionNotConstantError("disable_magic_rewrite", span, [err]))?
                     .get_bool()
@@ -485,6 +570,64 @@ pub(crate) fn parse_query(
         }
     }
 
+    if let Some(after) = prog.out_opts.after.clone() {
+        let head = prog.get_entry_out_head()?;
+        ensure!(
+            after.is_empty() || after.len() == head.len(),
+            InvalidCursorError(after_span)
+        );
+        // a total order, so that pages neither overlap nor skip rows
+        for symb in &head {
+            if !prog.out_opts.sorters.iter().any(|(s, _)| s == symb) {
+                prog.out_opts.sorters.push((symb.clone(), SortDir::Asc));
+            }
+        }
+        let keys = prog.out_opts.cursor_keys(&head);
+        // rows before the cursor are filtered out again when the output is sorted,
+        // this makes it possible to start range scans at the cursor instead
+        if let Some(InputInlineRulesOrFixed::Rules { rules }) = prog
+            .prog
+            .get_mut(&Symbol::new(PROG_ENTRY, SourceSpan::default()))
+        {
+            if !after.is_empty() && rules.iter().all(|r| r.aggr.iter().all(|a| a.is_none())) {
+                let cursor = DataValue::List(keys.iter().map(|(i, _)| after[*i].clone()).collect());
+                let descending =
+                    DataValue::List(keys.iter().map(|(_, d)| DataValue::from(*d)).collect());
+                for rule in rules {
+                    let key_list = Expr::Apply {
+                        op: &OP_LIST,
+                        args: keys
+                            .iter()
+                            .map(|(i, _)| Expr::Binding {
+                                var: rule.head[*i].clone(),
+                                tuple_pos: None,
+                            })
+                            .collect(),
+                        span: after_span,
+                    };
+                    rule.body.push(InputAtom::Predicate {
+                        inner: Expr::Apply {
+                            op: &OP_AFTER_CURSOR,
+                            args: [
+                                key_list,
+                                Expr::Const {
+                                    val: cursor.clone(),
+                                    span: after_span,
+                                },
+                                Expr::Const {
+                                    val: descending.clone(),
+                                    span: after_span,
+                                },
+                            ]
+                            .into(),
+                            span: after_span,
+                        },
+                    });
+                }
+            }
+        }
+    }
+
     #[derive(Debug, Error, Diagnostic)]
     #[error("Input relation '{0}' has no keys")]
     #[diagnostic(code(parser::relation_has_no_keys))]
@@ -533,14 +676,16 @@ pub(crate) fn parse_query(
 
 fn parse_rule(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
-) -> Result<(Symbol, InputInlineRule)> {
+    aux_rules: &mut Vec<(Symbol, InputInlineRule)>,
+) -> Result<(Symbol, InputInlineRule, Vec<Option<WindowApply>>)> {
     let span = src.extract_span();
     let mut src = src.into_inner();
     let head = src.next().unwrap();
     let head_span = head.extract_span();
-    let (name, head, aggr) = parse_rule_head(head, param_pool)?;
+    let (name, head, aggr, windows) = parse_rule_head(head, param_pool, registries)?;
 
     #[derive(Debug, Error, Diagnostic)]
     #[error("Horn-clause rule cannot have empty rule head")]
@@ -548,32 +693,416 @@ fn parse_rule(
     struct EmptyRuleHead(#[label] SourceSpan);
 
     ensure!(!head.is_empty(), EmptyRuleHead(head_span));
-    let body = src.next().unwrap();
+    let body = parse_rule_body(
+        src.next().unwrap(),
+        &name,
+        &head,
+        param_pool,
+        registries,
+        cur_vld,
+        aux_rules,
+    )?;
+
+    Ok((
+        name,
+        InputInlineRule {
+            head,
+            aggr,
+            body,
+            span,
+        },
+        windows,
+    ))
+}
+
+/// Parses the body of a rule. Each `exists { ... }` and `{?[...] := ...}` subquery in it
+/// is desugared into an auxiliary rule, pushed to `aux_rules`, whose head consists of the
+/// variables the subquery shares with the enclosing rule followed by the result. The
+/// enclosing body then applies the auxiliary rule in an optional atom. When the subquery
+/// has no match, the result is false for `exists`, the value of the aggregation over
+/// no rows if the head has one (such as 0 for `count`), and null otherwise.
+/// Without an aggregation, the subquery must have at most one result for each row.
+///
+/// Shared variables that the subquery only uses in filters are bound by another
+/// auxiliary rule, holding their values in the atoms of the enclosing body
+/// not depending on subqueries.
+#[allow(clippy::too_many_arguments)]
+fn parse_rule_body(
+    body: Pair<'_>,
+    rule_name: &Symbol,
+    head: &[Symbol],
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
+    cur_vld: ValidityTs,
+    aux_rules: &mut Vec<(Symbol, InputInlineRule)>,
+) -> Result<Vec<InputAtom>> {
+    // subqueries nested in other subqueries are taken care of when parsing the latter
+    let mut subqueries: Vec<Pair<'_>> = vec![];
+    for p in body.clone().into_inner().flatten() {
+        if matches!(p.as_rule(), Rule::exists_subquery | Rule::scalar_subquery)
+            && !subqueries.iter().any(|sq| {
+                sq.as_span().start() <= p.as_span().start()
+                    && p.as_span().end() <= sq.as_span().end()
+            })
+        {
+            subqueries.push(p);
+        }
+    }
+
     let mut body_clauses = vec![];
     let mut ignored_counter = 0;
     for atom_src in body.into_inner() {
         body_clauses.push(parse_disjunction(
             atom_src,
             param_pool,
+            registries,
             cur_vld,
             &mut ignored_counter,
         )?)
     }
+    if subqueries.is_empty() {
+        return Ok(body_clauses);
+    }
 
-    Ok((
-        name,
-        InputInlineRule {
-            head,
-            aggr,
-            body: body_clauses,
+    let mut outer_vars: BTreeSet<Symbol> = head.iter().cloned().collect();
+    let mut independent_atoms = vec![];
+    for atom in &body_clauses {
+        let mut vars = BTreeSet::new();
+        collect_atom_vars(atom, &mut vars)?;
+        if !vars.iter().any(is_subquery_binding) {
+            independent_atoms.push(atom.clone());
+        }
+        outer_vars.extend(vars);
+    }
+    for subquery in subqueries {
+        let span = subquery.extract_span();
+        let result = subquery_binding(span);
+        let aux_name = Symbol::new(format!("{}*subquery@{}", rule_name.name, span.0), span);
+        let is_exists = subquery.as_rule() == Rule::exists_subquery;
+        let mut src = subquery.into_inner();
+        src.next().unwrap();
+        let (result_arg, result_aggr) = if is_exists {
+            (result.clone(), None)
+        } else {
+            let (arg, aggr, window) =
+                parse_rule_head_arg(src.next().unwrap(), param_pool, registries)?;
+            ensure_no_window(&[window])?;
+            (arg, Some(aggr.unwrap_or((scalar_subquery_aggr(), vec![]))))
+        };
+        // the value of the aggregation over no rows, for when the subquery has no match
+        let empty_result = match &result_aggr {
+            None => DataValue::Null,
+            Some((aggr, args)) => {
+                let mut aggr = aggr.clone();
+                aggr.normal_init(args)?;
+                aggr.normal_op.unwrap().get()?
+            }
+        };
+        let mut sub_body = parse_rule_body(
+            src.next().unwrap(),
+            &aux_name,
+            std::slice::from_ref(&result_arg),
+            param_pool,
+            registries,
+            cur_vld,
+            aux_rules,
+        )?;
+        if is_exists {
+            sub_body.push(InputAtom::Unification {
+                inner: Unification {
+                    binding: result_arg.clone(),
+                    expr: Expr::Const {
+                        val: DataValue::from(true),
+                        span,
+                    },
+                    one_many_unif: false,
+                    span,
+                },
+            });
+        }
+
+        let mut sub_vars = BTreeSet::new();
+        for atom in &sub_body {
+            collect_atom_vars(atom, &mut sub_vars)?;
+        }
+        let mut aux_head = sub_vars
+            .intersection(&outer_vars)
+            .filter(|v| **v != result_arg && !v.is_ignored_symbol() && !v.is_internal_symbol())
+            .cloned()
+            .collect_vec();
+        let mut sub_bound = BTreeSet::new();
+        for atom in &sub_body {
+            collect_bound_vars(atom, &mut sub_bound);
+        }
+        let unbound = aux_head
+            .iter()
+            .filter(|v| !sub_bound.contains(*v))
+            .cloned()
+            .collect_vec();
+        if !unbound.is_empty() {
+            let context_name = Symbol::new(
+                format!("{}*subquery_context@{}", rule_name.name, span.0),
+                span,
+            );
+            sub_body.insert(
+                0,
+                InputAtom::Rule {
+                    inner: InputRuleApplyAtom {
+                        name: context_name.clone(),
+                        args: unbound
+                            .iter()
+                            .map(|v| Expr::Binding {
+                                var: v.clone(),
+                                tuple_pos: None,
+                            })
+                            .collect(),
+                        span,
+                    },
+                },
+            );
+            aux_rules.push((
+                context_name,
+                InputInlineRule {
+                    aggr: vec![None; unbound.len()],
+                    head: unbound,
+                    body: independent_atoms.clone(),
+                    span,
+                },
+            ));
+        }
+        let mut aux_aggr = vec![None; aux_head.len()];
+        let mut args = aux_head
+            .iter()
+            .map(|v| Expr::Binding {
+                var: v.clone(),
+                tuple_pos: None,
+            })
+            .collect_vec();
+        aux_head.push(result_arg);
+        aux_aggr.push(result_aggr);
+        let matched = if empty_result == DataValue::Null {
+            result
+        } else {
+            let matched = Symbol::new(format!("{}*matched", result.name), span);
+            body_clauses.push(InputAtom::Unification {
+                inner: Unification {
+                    binding: result,
+                    expr: Expr::Apply {
+                        op: &OP_COALESCE,
+                        args: [
+                            Expr::Binding {
+                                var: matched.clone(),
+                                tuple_pos: None,
+                            },
+                            Expr::Const {
+                                val: empty_result,
+                                span,
+                            },
+                        ]
+                        .into(),
+                        span,
+                    },
+                    one_many_unif: false,
+                    span,
+                },
+            });
+            matched
+        };
+        args.push(Expr::Binding {
+            var: matched,
+            tuple_pos: None,
+        });
+
+        body_clauses.push(InputAtom::Optional {
+            inner: Box::new(InputAtom::Rule {
+                inner: InputRuleApplyAtom {
+                    name: aux_name.clone(),
+                    args,
+                    span,
+                },
+            }),
             span,
-        },
-    ))
+        });
+        aux_rules.push((
+            aux_name,
+            InputInlineRule {
+                head: aux_head,
+                aggr: aux_aggr,
+                body: sub_body,
+                span,
+            },
+        ));
+    }
+    Ok(body_clauses)
+}
+
+/// Collect the variables the atom binds, rather than only uses.
+fn collect_bound_vars(atom: &InputAtom, coll: &mut BTreeSet<Symbol>) {
+    let bind = |arg: &Expr| {
+        if let Expr::Binding { var, .. } = arg {
+            coll.insert(var.clone());
+        }
+    };
+    match atom {
+        InputAtom::Rule { inner } => inner.args.iter().for_each(bind),
+        InputAtom::NamedFieldRelation { inner } => inner.args.values().for_each(bind),
+        InputAtom::Relation { inner } => inner.args.iter().for_each(bind),
+        InputAtom::Search { inner } => inner.bindings.values().for_each(bind),
+        InputAtom::Predicate { .. } | InputAtom::Negation { .. } => {}
+        InputAtom::Optional { inner, .. } => collect_bound_vars(inner, coll),
+        InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+            for a in inner {
+                collect_bound_vars(a, coll);
+            }
+        }
+        InputAtom::Unification { inner } => {
+            coll.insert(inner.binding.clone());
+        }
+        InputAtom::TableFunction { inner } => coll.extend(inner.bindings.iter().cloned()),
+    }
+}
+
+fn collect_atom_vars(atom: &InputAtom, coll: &mut BTreeSet<Symbol>) -> Result<()> {
+    match atom {
+        InputAtom::Rule { inner } => {
+            for arg in &inner.args {
+                arg.collect_bindings(coll)?;
+            }
+        }
+        InputAtom::NamedFieldRelation { inner } => {
+            for arg in inner.args.values() {
+                arg.collect_bindings(coll)?;
+            }
+        }
+        InputAtom::Relation { inner } => {
+            for arg in &inner.args {
+                arg.collect_bindings(coll)?;
+            }
+        }
+        InputAtom::Predicate { inner } => inner.collect_bindings(coll)?,
+        InputAtom::Negation { inner, .. } | InputAtom::Optional { inner, .. } => {
+            collect_atom_vars(inner, coll)?
+        }
+        InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+            for a in inner {
+                collect_atom_vars(a, coll)?;
+            }
+        }
+        InputAtom::Unification { inner } => {
+            coll.insert(inner.binding.clone());
+            inner.expr.collect_bindings(coll)?;
+        }
+        InputAtom::Search { inner } => {
+            for arg in inner.bindings.values().chain(inner.parameters.values()) {
+                arg.collect_bindings(coll)?;
+            }
+        }
+        InputAtom::TableFunction { inner } => {
+            coll.extend(inner.bindings.iter().cloned());
+            for arg in &inner.args {
+                arg.collect_bindings(coll)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites a rule with window functions in its head into a rule computing all the
+/// bindings required, and a fixed rule computing the windows over the former
+/// after it is complete.
+fn rewrite_window_rule(
+    name: &Symbol,
+    rule: InputInlineRule,
+    windows: Vec<Option<WindowApply>>,
+) -> Result<(Symbol, InputInlineRule, FixedRuleApply)> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Window functions cannot be combined with aggregations in the same rule head")]
+    #[diagnostic(code(parser::window_with_aggr))]
+    struct WindowWithAggrError(#[label] SourceSpan);
+
+    ensure!(
+        rule.aggr.iter().all(|a| a.is_none()),
+        WindowWithAggrError(merge_spans(&rule.head))
+    );
+
+    fn position_of(symb: &Symbol, inner_head: &mut Vec<Symbol>) -> usize {
+        match inner_head.iter().position(|s| s == symb) {
+            Some(i) => i,
+            None => {
+                inner_head.push(symb.clone());
+                inner_head.len() - 1
+            }
+        }
+    }
+
+    let mut inner_head = vec![];
+    for (symb, window) in rule.head.iter().zip(windows.iter()) {
+        if window.is_none() {
+            position_of(symb, &mut inner_head);
+        }
+    }
+    let mut columns = vec![];
+    for (symb, window) in rule.head.iter().zip(windows) {
+        columns.push(match window {
+            None => WindowColumn::Binding(position_of(symb, &mut inner_head)),
+            Some(w) => WindowColumn::Apply {
+                func: w.func,
+                arg: w.arg.map(|a| position_of(&a, &mut inner_head)),
+                params: w.params,
+                partition_by: w
+                    .partition_by
+                    .iter()
+                    .map(|s| position_of(s, &mut inner_head))
+                    .collect(),
+                order_by: w
+                    .order_by
+                    .iter()
+                    .map(|(s, dir)| (position_of(s, &mut inner_head), *dir))
+                    .collect(),
+                span: w.span,
+            },
+        })
+    }
+
+    let inner_name = Symbol::new(format!("{}*window", name.name), name.span);
+    let inner_rule = InputInlineRule {
+        aggr: vec![None; inner_head.len()],
+        head: inner_head.clone(),
+        body: rule.body,
+        span: rule.span,
+    };
+    let fixed = FixedRuleApply {
+        fixed_handle: FixedRuleHandle::new("Window", rule.span),
+        rule_args: vec![FixedRuleArg::InMem {
+            name: inner_name.clone(),
+            bindings: inner_head,
+            span: rule.span,
+        }],
+        options: Default::default(),
+        head: rule.head,
+        arity: columns.len(),
+        span: rule.span,
+        fixed_impl: Arc::new(Box::new(Window { columns })),
+    };
+    Ok((inner_name, inner_rule, fixed))
+}
+
+fn ensure_no_window(windows: &[Option<WindowApply>]) -> Result<()> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Window functions can only be used in the heads of Horn-clause rules")]
+    #[diagnostic(code(parser::window_not_allowed))]
+    struct WindowNotAllowedError(#[label] SourceSpan);
+
+    match windows.iter().flatten().next() {
+        Some(w) => bail!(WindowNotAllowedError(w.span)),
+        None => Ok(()),
+    }
 }
 
 fn parse_disjunction(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
     ignored_counter: &mut u32,
 ) -> Result<InputAtom> {
@@ -582,7 +1111,13 @@ fn parse_disjunction(
         .into_inner()
         .filter_map(|v| match v.as_rule() {
             Rule::or_op => None,
-            _ => Some(parse_atom(v, param_pool, cur_vld, ignored_counter)),
+            _ => Some(parse_atom(
+                v,
+                param_pool,
+                registries,
+                cur_vld,
+                ignored_counter,
+            )),
         })
         .try_collect()?;
     Ok(if res.len() == 1 {
@@ -594,7 +1129,8 @@ fn parse_disjunction(
 
 fn parse_atom(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
     ignored_counter: &mut u32,
 ) -> Result<InputAtom> {
@@ -603,26 +1139,50 @@ fn parse_atom(
             let span = src.extract_span();
             let grouped: Vec<_> = src
                 .into_inner()
-                .map(|v| parse_disjunction(v, param_pool, cur_vld, ignored_counter))
+                .map(|v| parse_disjunction(v, param_pool, registries, cur_vld, ignored_counter))
                 .try_collect()?;
             InputAtom::Conjunction {
                 inner: grouped,
                 span,
             }
         }
-        Rule::disjunction => parse_disjunction(src, param_pool, cur_vld, ignored_counter)?,
+        Rule::disjunction => {
+            parse_disjunction(src, param_pool, registries, cur_vld, ignored_counter)?
+        }
         Rule::negation => {
             let span = src.extract_span();
             let mut src = src.into_inner();
             src.next().unwrap();
-            let inner = parse_atom(src.next().unwrap(), param_pool, cur_vld, ignored_counter)?;
+            let inner = parse_atom(
+                src.next().unwrap(),
+                param_pool,
+                registries,
+                cur_vld,
+                ignored_counter,
+            )?;
             InputAtom::Negation {
                 inner: inner.into(),
                 span,
             }
         }
+        Rule::optional => {
+            let span = src.extract_span();
+            let mut src = src.into_inner();
+            src.next().unwrap();
+            let inner = parse_atom(
+                src.next().unwrap(),
+                param_pool,
+                registries,
+                cur_vld,
+                ignored_counter,
+            )?;
+            InputAtom::Optional {
+                inner: inner.into(),
+                span,
+            }
+        }
         Rule::expr => {
-            let expr = build_expr(src, param_pool)?;
+            let expr = build_expr(src, param_pool, registries.functions)?;
             InputAtom::Predicate { inner: expr }
         }
         Rule::unify => {
@@ -634,7 +1194,7 @@ fn parse_atom(
                 symb.name = format!("*^*{}", *ignored_counter).into();
                 *ignored_counter += 1;
             }
-            let expr = build_expr(src.next().unwrap(), param_pool)?;
+            let expr = build_expr(src.next().unwrap(), param_pool, registries.functions)?;
             InputAtom::Unification {
                 inner: Unification {
                     binding: symb,
@@ -654,7 +1214,7 @@ fn parse_atom(
                 *ignored_counter += 1;
             }
             src.next().unwrap();
-            let expr = build_expr(src.next().unwrap(), param_pool)?;
+            let expr = build_expr(src.next().unwrap(), param_pool, registries.functions)?;
             InputAtom::Unification {
                 inner: Unification {
                     binding: symb,
@@ -664,6 +1224,67 @@ fn parse_atom(
                 },
             }
         }
+        Rule::table_apply => {
+            let span = src.extract_span();
+            let mut bindings = vec![];
+            let mut src = src.into_inner();
+            for p in src.by_ref() {
+                if p.as_rule() != Rule::var {
+                    break;
+                }
+                let mut symb = Symbol::new(p.as_str(), p.extract_span());
+                if symb.is_ignored_symbol() {
+                    symb.name = format!("*^*{}", *ignored_counter).into();
+                    *ignored_counter += 1;
+                }
+                bindings.push(symb);
+            }
+            let name_p = src.next().unwrap();
+            let args: Vec<_> = src
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(|v| build_expr(v, param_pool, registries.functions))
+                .try_collect()?;
+
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Table function '{0}' not found")]
+            #[diagnostic(code(parser::table_function_not_found))]
+            struct TableFunctionNotFoundError(String, #[label] SourceSpan);
+
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Wrong number of arguments for table function '{0}'")]
+            #[diagnostic(code(parser::table_function_arity_mismatch))]
+            struct TableFunctionArityMismatch(String, #[label] SourceSpan);
+
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Table function '{0}' produces {1} columns, but is bound to {2} variables")]
+            #[diagnostic(code(parser::table_function_columns_mismatch))]
+            struct TableFunctionColumnsMismatch(String, usize, usize, #[label] SourceSpan);
+
+            let name = name_p.as_str();
+            let func = registries
+                .table_functions
+                .get(name)
+                .ok_or_else(|| TableFunctionNotFoundError(name.to_string(), name_p.extract_span()))?
+                .clone();
+            ensure!(
+                func.accepts_arity(args.len()),
+                TableFunctionArityMismatch(name.to_string(), span)
+            );
+            ensure!(
+                func.columns == bindings.len(),
+                TableFunctionColumnsMismatch(name.to_string(), func.columns, bindings.len(), span)
+            );
+            InputAtom::TableFunction {
+                inner: TableFunctionApply {
+                    func,
+                    args,
+                    bindings,
+                    span,
+                },
+            }
+        }
         Rule::rule_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
@@ -672,7 +1293,7 @@ fn parse_atom(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|v| build_expr(v, param_pool))
+                .map(|v| build_expr(v, param_pool, registries.functions))
                 .try_collect()?;
             InputAtom::Rule {
                 inner: InputRuleApplyAtom {
@@ -690,12 +1311,16 @@ fn parse_atom(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|v| build_expr(v, param_pool))
+                .map(|v| build_expr(v, param_pool, registries.functions))
                 .try_collect()?;
             let valid_at = match src.next() {
                 None => None,
                 Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
+                    let vld_expr = build_expr(
+                        vld_clause.into_inner().next().unwrap(),
+                        param_pool,
+                        registries.functions,
+                    )?;
                     Some(expr2vld_spec(vld_expr, cur_vld)?)
                 }
             };
@@ -729,10 +1354,10 @@ fn parse_atom(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|arg| extract_named_apply_arg(arg, param_pool))
+                .map(|arg| extract_named_apply_arg(arg, param_pool, registries.functions))
                 .try_collect()?;
             let parameters: BTreeMap<SmartString<LazyCompact>, Expr> = src
-                .map(|arg| extract_named_apply_arg(arg, param_pool))
+                .map(|arg| extract_named_apply_arg(arg, param_pool, registries.functions))
                 .try_collect()?;
 
             let opts = SearchInput {
@@ -754,12 +1379,16 @@ fn parse_atom(
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|arg| extract_named_apply_arg(arg, param_pool))
+                .map(|arg| extract_named_apply_arg(arg, param_pool, registries.functions))
                 .try_collect()?;
             let valid_at = match src.next() {
                 None => None,
                 Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
+                    let vld_expr = build_expr(
+                        vld_clause.into_inner().next().unwrap(),
+                        param_pool,
+                        registries.functions,
+                    )?;
                     Some(expr2vld_spec(vld_expr, cur_vld)?)
                 }
             };
@@ -778,13 +1407,14 @@ fn parse_atom(
 
 fn extract_named_apply_arg(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
+    functions: &BTreeMap<String, Arc<CustomFunction>>,
 ) -> Result<(SmartString<LazyCompact>, Expr)> {
     let mut inner = pair.into_inner();
     let name_p = inner.next().unwrap();
     let name = SmartString::from(name_p.as_str());
     let arg = match inner.next() {
-        Some(a) => build_expr(a, param_pool)?,
+        Some(a) => build_expr(a, param_pool, functions)?,
         None => Expr::Binding {
             var: Symbol::new(name.clone(), name_p.extract_span()),
             tuple_pos: None,
@@ -793,24 +1423,34 @@ fn extract_named_apply_arg(
     Ok((name, arg))
 }
 
+#[allow(clippy::type_complexity)]
 fn parse_rule_head(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
 ) -> Result<(
     Symbol,
     Vec<Symbol>,
     Vec<Option<(Aggregation, Vec<DataValue>)>>,
+    Vec<Option<WindowApply>>,
 )> {
     let mut src = src.into_inner();
     let name = src.next().unwrap();
     let mut args = vec![];
     let mut aggrs = vec![];
+    let mut windows = vec![];
     for p in src {
-        let (arg, aggr) = parse_rule_head_arg(p, param_pool)?;
+        let (arg, aggr, window) = parse_rule_head_arg(p, param_pool, registries)?;
         args.push(arg);
         aggrs.push(aggr);
+        windows.push(window);
     }
-    Ok((Symbol::new(name.as_str(), name.extract_span()), args, aggrs))
+    Ok((
+        Symbol::new(name.as_str(), name.extract_span()),
+        args,
+        aggrs,
+        windows,
+    ))
 }
 
 #[derive(Error, Diagnostic, Debug)]
@@ -818,29 +1458,102 @@ fn parse_rule_head(
 #[error("Aggregation '{0}' not found")]
 struct AggrNotFound(String, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[diagnostic(code(parser::window_fn_not_found))]
+#[error("Window function '{0}' not found")]
+struct WindowFunctionNotFound(String, #[label] SourceSpan);
+
+#[allow(clippy::type_complexity)]
 fn parse_rule_head_arg(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-) -> Result<(Symbol, Option<(Aggregation, Vec<DataValue>)>)> {
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
+) -> Result<(
+    Symbol,
+    Option<(Aggregation, Vec<DataValue>)>,
+    Option<WindowApply>,
+)> {
     let src = src.into_inner().next().unwrap();
     Ok(match src.as_rule() {
-        Rule::var => (Symbol::new(src.as_str(), src.extract_span()), None),
+        Rule::var => (Symbol::new(src.as_str(), src.extract_span()), None, None),
+        Rule::window_arg => {
+            let span = src.extract_span();
+            let mut inner = src.into_inner();
+            let out = inner.next().unwrap();
+            let func_p = inner.next().unwrap();
+            let func_name = func_p.as_str();
+            let func = WindowFunction::from_name(func_name).ok_or_else(|| {
+                WindowFunctionNotFound(func_name.to_string(), func_p.extract_span())
+            })?;
+            let mut arg = None;
+            let mut params = vec![];
+            let mut partition_by = vec![];
+            let mut order_by = vec![];
+            for p in inner {
+                match p.as_rule() {
+                    Rule::var => arg = Some(Symbol::new(p.as_str(), p.extract_span())),
+                    Rule::window_partition => {
+                        partition_by = p
+                            .into_inner()
+                            .map(|v| Symbol::new(v.as_str(), v.extract_span()))
+                            .collect()
+                    }
+                    Rule::window_order => {
+                        for sorter in p.into_inner() {
+                            let mut parts = sorter.into_inner();
+                            let mut dir = SortDir::Asc;
+                            let mut var = parts.next().unwrap();
+                            if var.as_rule() != Rule::var {
+                                if var.as_rule() == Rule::sort_desc {
+                                    dir = SortDir::Dsc;
+                                }
+                                var = parts.next().unwrap();
+                            }
+                            order_by.push((Symbol::new(var.as_str(), var.extract_span()), dir));
+                        }
+                    }
+                    _ => params
+                        .push(build_expr(p, param_pool, registries.functions)?.eval_to_const()?),
+                }
+            }
+            (
+                Symbol::new(out.as_str(), out.extract_span()),
+                None,
+                Some(WindowApply::new(
+                    func,
+                    arg,
+                    params,
+                    partition_by,
+                    order_by,
+                    span,
+                )?),
+            )
+        }
         Rule::aggr_arg => {
             let mut inner = src.into_inner();
             let aggr_p = inner.next().unwrap();
             let aggr_name = aggr_p.as_str();
             let var = inner.next().unwrap();
             let args: Vec<_> = inner
-                .map(|v| -> Result<DataValue> { build_expr(v, param_pool)?.eval_to_const() })
+                .map(|v| -> Result<DataValue> {
+                    build_expr(v, param_pool, registries.functions)?.eval_to_const()
+                })
                 .try_collect()?;
             (
                 Symbol::new(var.as_str(), var.extract_span()),
                 Some((
-                    parse_aggr(aggr_name)
-                        .ok_or_else(|| AggrNotFound(aggr_name.to_string(), aggr_p.extract_span()))?
-                        .clone(),
+                    match parse_aggr(aggr_name) {
+                        Some(aggr) => aggr.clone(),
+                        None => match registries.aggregations.get(aggr_name) {
+                            Some(aggr) => Aggregation::custom(aggr_name, aggr.clone()),
+                            None => {
+                                bail!(AggrNotFound(aggr_name.to_string(), aggr_p.extract_span()))
+                            }
+                        },
+                    },
                     args,
                 )),
+                None,
             )
         }
         _ => unreachable!(),
@@ -854,12 +1567,14 @@ struct BadValiditySpecification(#[label] SourceSpan);
 
 fn parse_fixed_rule(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
 ) -> Result<(Symbol, FixedRuleApply)> {
     let mut src = src.into_inner();
-    let (out_symbol, head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
+    let (out_symbol, head, aggr, windows) =
+        parse_rule_head(src.next().unwrap(), param_pool, registries)?;
+    ensure_no_window(&windows)?;
 
     #[derive(Debug, Error, Diagnostic)]
     #[error("fixed rule cannot be combined with aggregation")]
@@ -941,7 +1656,8 @@ fn parse_fixed_rule(
                                 }
                                 Rule::validity_clause => {
                                     let vld_inner = v.into_inner().next().unwrap();
-                                    let vld_expr = build_expr(vld_inner, param_pool)?;
+                                    let vld_expr =
+                                        build_expr(vld_inner, param_pool, registries.functions)?;
                                     valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?)
                                 }
                                 _ => unreachable!(),
@@ -986,7 +1702,8 @@ fn parse_fixed_rule(
                                 }
                                 Rule::validity_clause => {
                                     let vld_inner = p.into_inner().next().unwrap();
-                                    let vld_expr = build_expr(vld_inner, param_pool)?;
+                                    let vld_expr =
+                                        build_expr(vld_inner, param_pool, registries.functions)?;
                                     valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?)
                                 }
                                 _ => unreachable!(),
@@ -1010,7 +1727,7 @@ fn parse_fixed_rule(
                 let mut inner = nxt.into_inner();
                 let name = inner.next().unwrap().as_str();
                 let val = inner.next().unwrap();
-                let val = build_expr(val, param_pool)?;
+                let val = build_expr(val, param_pool, registries.functions)?;
                 options.insert(SmartString::from(name), val);
             }
             _ => unreachable!(),
@@ -1019,7 +1736,8 @@ fn parse_fixed_rule(
 
     let fixed = FixedRuleHandle::new(fixed_name, name_pair.extract_span());
 
-    let fixed_impl = fixed_rules
+    let fixed_impl = registries
+        .fixed_rules
         .get(&fixed.name as &str)
         .ok_or_else(|| FixedRuleNotFoundError(fixed.name.to_string(), name_pair.extract_span()))?;
     fixed_impl.init_options(&mut options, args_list_span)?;
@@ -6,13 +6,15 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result, IntoDiagnostic};
 use smartstring::SmartString;
 use thiserror::Error;
 
+use crate::data::expr::CustomFunction;
 use crate::data::relation::{VecElementType, ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
@@ -21,6 +23,7 @@ use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
 
 pub(crate) fn parse_schema(
     pair: Pair<'_>,
+    functions: &BTreeMap<String, Arc<CustomFunction>>,
 ) -> Result<(StoredRelationMetadata, Vec<Symbol>, Vec<Symbol>)> {
     let mut src = pair.into_inner();
     let mut keys = vec![];
@@ -35,7 +38,7 @@ pub(crate) fn parse_schema(
     struct DuplicateNameInCols(String, #[label] SourceSpan);
     for p in src.next().unwrap().into_inner() {
         let span = p.extract_span();
-        let (col, ident) = parse_col(p)?;
+        let (col, ident) = parse_col(p, functions)?;
         if !seen_names.insert(col.name.clone()) {
             bail!(DuplicateNameInCols(col.name.to_string(), span));
         }
@@ -45,7 +48,7 @@ pub(crate) fn parse_schema(
     if let Some(ps) = src.next() {
         for p in ps.into_inner() {
             let span = p.extract_span();
-            let (col, ident) = parse_col(p)?;
+            let (col, ident) = parse_col(p, functions)?;
             if !seen_names.insert(col.name.clone()) {
                 bail!(DuplicateNameInCols(col.name.to_string(), span));
             }
@@ -64,7 +67,10 @@ pub(crate) fn parse_schema(
     ))
 }
 
-fn parse_col(pair: Pair<'_>) -> Result<(ColumnDef, Symbol)> {
+fn parse_col(
+    pair: Pair<'_>,
+    functions: &BTreeMap<String, Arc<CustomFunction>>,
+) -> Result<(ColumnDef, Symbol)> {
     let mut src = pair.into_inner();
     let name_p = src.next().unwrap();
     let name = SmartString::from(name_p.as_str());
@@ -77,7 +83,10 @@ fn parse_col(pair: Pair<'_>) -> Result<(ColumnDef, Symbol)> {
     for nxt in src {
         match nxt.as_rule() {
             Rule::col_type => typing = parse_nullable_type(nxt)?,
-            Rule::expr => default_gen = Some(build_expr(nxt, &Default::default())?),
+            Rule::expr => {
+                ensure_no_custom_function(&nxt, functions)?;
+                default_gen = Some(build_expr(nxt, &Default::default(), &Default::default())?)
+            }
             Rule::out_arg => {
                 binding_candidate = Some(Symbol::new(nxt.as_str(), nxt.extract_span()))
             }
@@ -96,6 +105,30 @@ fn parse_col(pair: Pair<'_>) -> Result<(ColumnDef, Symbol)> {
     ))
 }
 
+/// Default values are stored with the relation, so they cannot refer to functions
+/// registered at runtime, which cannot be restored when the relation is read back.
+fn ensure_no_custom_function(
+    pair: &Pair<'_>,
+    functions: &BTreeMap<String, Arc<CustomFunction>>,
+) -> Result<()> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Custom function '{0}' cannot be used in the default value of a column")]
+    #[diagnostic(code(parser::custom_fn_in_default))]
+    #[diagnostic(help("Compute the value in the query putting the rows instead"))]
+    struct CustomFunctionInDefault(String, #[label] SourceSpan);
+
+    for p in pair.clone().into_inner().flatten() {
+        if p.as_rule() == Rule::apply {
+            let name = p.clone().into_inner().next().unwrap().as_str();
+            ensure!(
+                !functions.contains_key(name),
+                CustomFunctionInDefault(name.to_string(), p.extract_span())
+            );
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn parse_nullable_type(pair: Pair<'_>) -> Result<NullableColType> {
     let nullable = pair.as_str().ends_with('?');
     let coltype = parse_type_inner(pair.into_inner().next().unwrap())?;
@@ -113,6 +146,11 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         Rule::uuid_type => ColType::Uuid,
         Rule::json_type => ColType::Json,
         Rule::validity_type => ColType::Validity,
+        Rule::date_type => ColType::Date,
+        Rule::time_type => ColType::Time,
+        Rule::timestamp_type => ColType::Timestamp,
+        Rule::duration_type => ColType::Duration,
+        Rule::decimal_type => ColType::Decimal,
         Rule::list_type => {
             let mut inner = pair.into_inner();
             let eltype = parse_nullable_type(inner.next().unwrap())?;
@@ -120,7 +158,7 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
                 None => None,
                 Some(len_p) => {
                     let span = len_p.extract_span();
-                    let expr = build_expr(len_p, &Default::default())?;
+                    let expr = build_expr(len_p, &Default::default(), &Default::default())?;
                     let dv = expr.eval_to_const()?;
 
                     #[derive(Debug, Error, Diagnostic)]
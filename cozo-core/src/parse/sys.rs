@@ -6,9 +6,6 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
-use std::sync::Arc;
-
 use itertools::Itertools;
 use miette::{bail, ensure, miette, Diagnostic, Result};
 use ordered_float::OrderedFloat;
@@ -22,13 +19,15 @@ use crate::data::value::{DataValue, ValidityTs};
 use crate::fts::TokenizerConfig;
 use crate::parse::expr::{build_expr, parse_string};
 use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, Pairs, ParamPool, Registries, Rule, SourceSpan};
 use crate::runtime::relation::AccessLevel;
-use crate::{Expr, FixedRule};
+use crate::Expr;
 
 #[derive(Debug)]
 pub enum SysOp {
     Compact,
+    Analyze(Symbol),
+    Changelog(Symbol, ChangelogOp),
     ListColumns(Symbol),
     ListIndices(Symbol),
     ListRelations,
@@ -49,6 +48,14 @@ pub enum SysOp {
     DescribeRelation(Symbol, SmartString<LazyCompact>),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangelogOp {
+    Show,
+    Enable,
+    Disable,
+    Truncate(u64),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FtsIndexConfig {
     pub base_relation: SmartString<LazyCompact>,
@@ -102,19 +109,53 @@ pub enum HnswDistance {
 #[diagnostic(code(parser::not_proc_id))]
 struct ProcessIdError(String, #[label] SourceSpan);
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("Cannot interpret {0} as a changelog sequence number")]
+#[diagnostic(code(parser::bad_changelog_seq))]
+struct BadSequenceNumber(DataValue, #[label] SourceSpan);
+
 pub(crate) fn parse_sys(
     mut src: Pairs<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
-    algorithms: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    param_pool: &ParamPool<'_>,
+    registries: &Registries<'_>,
     cur_vld: ValidityTs,
 ) -> Result<SysOp> {
     let inner = src.next().unwrap();
     Ok(match inner.as_rule() {
         Rule::compact_op => SysOp::Compact,
+        Rule::analyze_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            SysOp::Analyze(rel)
+        }
+        Rule::changelog_op => {
+            let mut inner = inner.into_inner();
+            let rels_p = inner.next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let op = match inner.next() {
+                None => ChangelogOp::Show,
+                Some(p) => match p.as_rule() {
+                    Rule::changelog_on => ChangelogOp::Enable,
+                    Rule::changelog_off => ChangelogOp::Disable,
+                    Rule::changelog_truncate => {
+                        let seq_p = p.into_inner().next().unwrap();
+                        let span = seq_p.extract_span();
+                        let seq =
+                            build_expr(seq_p, param_pool, registries.functions)?.eval_to_const()?;
+                        match seq.get_non_neg_int() {
+                            Some(seq) => ChangelogOp::Truncate(seq),
+                            None => bail!(BadSequenceNumber(seq, span)),
+                        }
+                    }
+                    _ => unreachable!(),
+                },
+            };
+            SysOp::Changelog(rel, op)
+        }
         Rule::running_op => SysOp::ListRunning,
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
-            let i_val = build_expr(i_expr, param_pool)?;
+            let i_val = build_expr(i_expr, param_pool, registries.functions)?;
             let i_val = i_val.eval_to_const()?;
             let i_val = i_val
                 .get_int()
@@ -125,7 +166,7 @@ pub(crate) fn parse_sys(
             let prog = parse_query(
                 inner.into_inner().next().unwrap().into_inner(),
                 param_pool,
-                algorithms,
+                registries,
                 cur_vld,
             )?;
             SysOp::Explain(Box::new(prog))
@@ -209,7 +250,7 @@ pub(crate) fn parse_sys(
                 parse_query(
                     script.into_inner(),
                     &Default::default(),
-                    algorithms,
+                    registries,
                     cur_vld,
                 )?;
                 match op.as_rule() {
@@ -246,7 +287,8 @@ pub(crate) fn parse_sys(
                         let opt_val = opt_inner.next().unwrap();
                         match opt_name.as_str() {
                             "false_positive_weight" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 let v = expr.eval_to_const()?;
                                 false_positive_weight = v.get_float().ok_or_else(|| {
@@ -254,7 +296,8 @@ pub(crate) fn parse_sys(
                                 })?;
                             }
                             "false_negative_weight" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 let v = expr.eval_to_const()?;
                                 false_negative_weight = v.get_float().ok_or_else(|| {
@@ -262,7 +305,8 @@ pub(crate) fn parse_sys(
                                 })?;
                             }
                             "n_gram" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 let v = expr.eval_to_const()?;
                                 n_gram = v
@@ -271,7 +315,8 @@ pub(crate) fn parse_sys(
                                     as usize;
                             }
                             "n_perm" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 let v = expr.eval_to_const()?;
                                 n_perm = v
@@ -280,7 +325,8 @@ pub(crate) fn parse_sys(
                                     as usize;
                             }
                             "target_threshold" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 let v = expr.eval_to_const()?;
                                 target_threshold = v
@@ -288,17 +334,18 @@ pub(crate) fn parse_sys(
                                     .ok_or_else(|| miette!("target_threshold must be a float"))?;
                             }
                             "extractor" => {
-                                let mut ex = build_expr(opt_val, param_pool)?;
+                                let mut ex = build_expr(opt_val, param_pool, registries.functions)?;
                                 ex.partial_eval()?;
                                 extractor = ex.to_string();
                             }
                             "extract_filter" => {
-                                let mut ex = build_expr(opt_val, param_pool)?;
+                                let mut ex = build_expr(opt_val, param_pool, registries.functions)?;
                                 ex.partial_eval()?;
                                 extract_filter = ex.to_string();
                             }
                             "tokenizer" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 match expr {
                                     Expr::UnboundApply { op, args, .. } => {
@@ -318,7 +365,8 @@ pub(crate) fn parse_sys(
                                 }
                             }
                             "filters" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 match expr {
                                     Expr::Apply { op, args, .. } => {
@@ -422,17 +470,18 @@ pub(crate) fn parse_sys(
                         let opt_val = opt_inner.next().unwrap();
                         match opt_name.as_str() {
                             "extractor" => {
-                                let mut ex = build_expr(opt_val, param_pool)?;
+                                let mut ex = build_expr(opt_val, param_pool, registries.functions)?;
                                 ex.partial_eval()?;
                                 extractor = ex.to_string();
                             }
                             "extract_filter" => {
-                                let mut ex = build_expr(opt_val, param_pool)?;
+                                let mut ex = build_expr(opt_val, param_pool, registries.functions)?;
                                 ex.partial_eval()?;
                                 extract_filter = ex.to_string();
                             }
                             "tokenizer" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 match expr {
                                     Expr::UnboundApply { op, args, .. } => {
@@ -452,7 +501,8 @@ pub(crate) fn parse_sys(
                                 }
                             }
                             "filters" => {
-                                let mut expr = build_expr(opt_val, param_pool)?;
+                                let mut expr =
+                                    build_expr(opt_val, param_pool, registries.functions)?;
                                 expr.partial_eval()?;
                                 match expr {
                                     Expr::Apply { op, args, .. } => {
@@ -537,7 +587,7 @@ pub(crate) fn parse_sys(
                         let opt_val_str = opt_val.as_str();
                         match opt_name.as_str() {
                             "dim" => {
-                                let v = build_expr(opt_val, param_pool)?
+                                let v = build_expr(opt_val, param_pool, registries.functions)?
                                     .eval_to_const()?
                                     .get_int()
                                     .ok_or_else(|| miette!("Invalid vec_dim: {}", opt_val_str))?;
@@ -545,7 +595,7 @@ pub(crate) fn parse_sys(
                                 vec_dim = v as usize;
                             }
                             "ef_construction" | "ef" => {
-                                let v = build_expr(opt_val, param_pool)?
+                                let v = build_expr(opt_val, param_pool, registries.functions)?
                                     .eval_to_const()?
                                     .get_int()
                                     .ok_or_else(|| {
@@ -555,7 +605,7 @@ pub(crate) fn parse_sys(
                                 ef_construction = v as usize;
                             }
                             "m_neighbours" | "m" => {
-                                let v = build_expr(opt_val, param_pool)?
+                                let v = build_expr(opt_val, param_pool, registries.functions)?
                                     .eval_to_const()?
                                     .get_int()
                                     .ok_or_else(|| {
@@ -574,7 +624,8 @@ pub(crate) fn parse_sys(
                                 }
                             }
                             "fields" => {
-                                let fields = build_expr(opt_val, &Default::default())?;
+                                let fields =
+                                    build_expr(opt_val, &Default::default(), &Default::default())?;
                                 vec_fields = fields.to_var_list()?;
                             }
                             "distance" | "dist" => {
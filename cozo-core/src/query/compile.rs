@@ -7,6 +7,7 @@
  */
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 
 use itertools::Itertools;
 use miette::{bail, ensure, Context, Diagnostic, Result};
@@ -15,19 +16,19 @@ use thiserror::Error;
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::program::{
-    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRulesOrFixed, MagicSymbol,
-    StratifiedMagicProgram,
+    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRelationApplyAtom, MagicRulesOrFixed,
+    MagicSymbol, StratifiedMagicProgram,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
-use crate::query::ra::RelAlgebra;
-use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::query::ra::{join_is_prefix, RelAlgebra};
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle};
 use crate::runtime::transact::SessionTx;
 
 pub(crate) type CompiledProgram = BTreeMap<MagicSymbol, CompiledRuleSet>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) enum CompiledRuleSet {
     Rules(Vec<CompiledRule>),
     Fixed(MagicFixedRuleApply),
@@ -75,6 +76,22 @@ impl CompiledRuleSet {
             CompiledRuleSet::Fixed(_) => AggrKind::None,
         }
     }
+    /// Replace the parameters of a prepared query by their values.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) -> Result<()> {
+        match self {
+            CompiledRuleSet::Rules(rules) => {
+                for rule in rules {
+                    rule.relation.bind_params(params)?;
+                }
+            }
+            CompiledRuleSet::Fixed(fixed) => {
+                for option in Arc::make_mut(&mut fixed.options).values_mut() {
+                    option.bind_params(params)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -83,7 +100,7 @@ pub(crate) enum ContainedRuleMultiplicity {
     Many,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct CompiledRule {
     pub(crate) aggr: Vec<Option<(Aggregation, Vec<DataValue>)>>,
     pub(crate) relation: RelAlgebra,
@@ -161,6 +178,80 @@ impl<'a> SessionTx<'a> {
             .try_collect()?;
         Ok(compiled)
     }
+    /// Plans the join order of the stored relations in a rule body using the
+    /// statistics collected by `::analyze`: the first relation placed is iterated
+    /// and each later one is probed with the variables bound so far, so at every
+    /// step the relation expected to produce the fewest rows per probe goes next.
+    /// A relation may move ahead of other atoms as long as it does not need a
+    /// variable that only those atoms bind. Other atoms keep their relative order,
+    /// and the body is left alone unless at least two relations have statistics.
+    fn reorder_by_statistics(
+        &self,
+        body: &[MagicAtom],
+        bound: &BTreeSet<Symbol>,
+    ) -> Result<Vec<MagicAtom>> {
+        let mut movable = Vec::with_capacity(body.len());
+        for atom in body {
+            let handle = match atom {
+                MagicAtom::Relation(rel_app) => {
+                    let handle = self.get_relation(&rel_app.name, false)?;
+                    (handle.stats.is_some() && handle.arity() == rel_app.args.len())
+                        .then_some(handle)
+                }
+                _ => None,
+            };
+            movable.push(handle);
+        }
+        if movable.iter().filter(|h| h.is_some()).count() < 2 {
+            return Ok(body.to_vec());
+        }
+        let mut ret = Vec::with_capacity(body.len());
+        let mut bound = bound.clone();
+        let mut placed = vec![false; body.len()];
+        while let Some(next) = placed.iter().position(|p| !*p) {
+            let mut chosen = next;
+            if movable[next].is_some() {
+                let mut best_est = f64::INFINITY;
+                for i in next..body.len() {
+                    if placed[i] {
+                        continue;
+                    }
+                    let (MagicAtom::Relation(rel_app), Some(handle)) = (&body[i], &movable[i])
+                    else {
+                        continue;
+                    };
+                    let mut skipped = (next..i).filter(|j| !placed[*j]).map(|j| &body[j]);
+                    if !skipped.all(|a| can_move_before(a, rel_app)) {
+                        continue;
+                    }
+                    let est = estimate_rows_per_probe(rel_app, handle, &bound);
+                    if est < best_est {
+                        best_est = est;
+                        chosen = i;
+                    }
+                }
+            }
+            placed[chosen] = true;
+            let atom = &body[chosen];
+            match atom {
+                MagicAtom::Relation(rel_app) => bound.extend(rel_app.args.iter().cloned()),
+                MagicAtom::Rule(rule_app) => bound.extend(rule_app.args.iter().cloned()),
+                MagicAtom::Unification(u) => {
+                    bound.insert(u.binding.clone());
+                }
+                MagicAtom::HnswSearch(s) => bound.extend(s.all_bindings().cloned()),
+                MagicAtom::FtsSearch(s) => bound.extend(s.all_bindings().cloned()),
+                MagicAtom::LshSearch(s) => bound.extend(s.all_bindings().cloned()),
+                MagicAtom::Optional(o) => bound.extend(o.bindings()),
+                MagicAtom::TableFunction(t) => bound.extend(t.bindings.iter().cloned()),
+                MagicAtom::Predicate(_)
+                | MagicAtom::NegatedRule(_)
+                | MagicAtom::NegatedRelation(_) => {}
+            }
+            ret.push(atom.clone());
+        }
+        Ok(ret)
+    }
     pub(crate) fn compile_magic_rule_body(
         &mut self,
         rule: &MagicInlineRule,
@@ -168,15 +259,58 @@ impl<'a> SessionTx<'a> {
         store_arities: &BTreeMap<MagicSymbol, usize>,
         ret_vars: &[Symbol],
     ) -> Result<RelAlgebra> {
-        let mut ret = RelAlgebra::unit(rule_name.symbol().span);
         let mut seen_variables = BTreeSet::new();
         let mut serial_id = 0;
-        let mut gen_symb = |span| {
-            let ret = Symbol::new(&format!("**{serial_id}") as &str, span);
-            serial_id += 1;
-            ret
-        };
-        for atom in &rule.body {
+        let body = self.reorder_by_statistics(&rule.body, &seen_variables)?;
+        let mut ret = self.compile_magic_atoms(
+            &body,
+            RelAlgebra::unit(rule_name.symbol().span),
+            &mut seen_variables,
+            &mut serial_id,
+            store_arities,
+        )?;
+
+        let ret_vars_set = ret_vars.iter().cloned().collect();
+        ret.eliminate_temp_vars(&ret_vars_set)?;
+        let cur_ret_set: BTreeSet<_> = ret.bindings_after_eliminate().into_iter().collect();
+        if cur_ret_set != ret_vars_set {
+            let ret_span = ret.span();
+            ret = ret.cartesian_join(RelAlgebra::unit(ret_span), ret_span);
+            ret.eliminate_temp_vars(&ret_vars_set)?;
+        }
+
+        let cur_ret_set: BTreeSet<_> = ret.bindings_after_eliminate().into_iter().collect();
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Symbol '{0}' in rule head is unbound")]
+        #[diagnostic(code(eval::unbound_symb_in_head))]
+        #[diagnostic(help(
+            "Note that symbols occurring only in negated positions are not considered bound"
+        ))]
+        struct UnboundSymbolInRuleHead(String, #[label] SourceSpan);
+
+        ensure!(cur_ret_set == ret_vars_set, {
+            let unbound = ret_vars_set.difference(&cur_ret_set).next().unwrap();
+            UnboundSymbolInRuleHead(unbound.to_string(), unbound.span)
+        });
+        let cur_ret_bindings = ret.bindings_after_eliminate();
+        if ret_vars != cur_ret_bindings {
+            ret = ret.reorder(ret_vars.to_vec());
+        }
+
+        Ok(ret)
+    }
+
+    /// Compiles the atoms of a rule body one after another on top of `ret`. The variables
+    /// in `seen_variables` are bound by `ret`, and the ones bound by the atoms are added.
+    fn compile_magic_atoms(
+        &mut self,
+        body: &[MagicAtom],
+        mut ret: RelAlgebra,
+        seen_variables: &mut BTreeSet<Symbol>,
+        serial_id: &mut usize,
+        store_arities: &BTreeMap<MagicSymbol, usize>,
+    ) -> Result<RelAlgebra> {
+        for atom in body {
             match atom {
                 MagicAtom::Rule(rule_app) => {
                     let store_arity = store_arities.get(&rule_app.name).ok_or_else(|| {
@@ -202,7 +336,7 @@ impl<'a> SessionTx<'a> {
                     for var in &rule_app.args {
                         if seen_variables.contains(var) {
                             prev_joiner_vars.push(var.clone());
-                            let rk = gen_symb(var.span);
+                            let rk = gen_symb(serial_id, var.span);
                             right_vars.push(rk.clone());
                             right_joiner_vars.push(rk);
                         } else {
@@ -214,7 +348,14 @@ impl<'a> SessionTx<'a> {
                     let right =
                         RelAlgebra::derived(right_vars, rule_app.name.clone(), rule_app.span);
                     debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
-                    ret = ret.join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
+                    ret = join_relations(
+                        ret,
+                        right,
+                        prev_joiner_vars,
+                        right_joiner_vars,
+                        self.hash_join_memory_budget,
+                        rule_app.span,
+                    );
                 }
                 MagicAtom::Relation(rel_app) => {
                     let store = self.get_relation(&rel_app.name, false)?;
@@ -250,7 +391,7 @@ impl<'a> SessionTx<'a> {
                     for (i, var) in rel_app.args.iter().enumerate() {
                         if seen_variables.contains(var) {
                             prev_joiner_vars.push(var.clone());
-                            let rk = gen_symb(var.span);
+                            let rk = gen_symb(serial_id, var.span);
                             right_vars.push(rk.clone());
                             right_joiner_vars.push(rk);
                             right_joiner_vars_pos.push(i);
@@ -280,8 +421,14 @@ impl<'a> SessionTx<'a> {
                                 rel_app.valid_at,
                             )?;
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
-                            ret =
-                                ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
+                            ret = join_relations(
+                                ret,
+                                right,
+                                prev_joiner_vars,
+                                right_joiner_vars,
+                                self.hash_join_memory_budget,
+                                rel_app.span,
+                            );
                         }
                         Some((chosen_index, mapper, false)) => {
                             // index-only
@@ -296,8 +443,14 @@ impl<'a> SessionTx<'a> {
                                 rel_app.valid_at,
                             )?;
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
-                            ret =
-                                ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
+                            ret = join_relations(
+                                ret,
+                                right,
+                                prev_joiner_vars,
+                                right_joiner_vars,
+                                self.hash_join_memory_budget,
+                                rel_app.span,
+                            );
                         }
                         Some((chosen_index, mapper, true)) => {
                             // index-with-join
@@ -309,7 +462,7 @@ impl<'a> SessionTx<'a> {
                                 let mut right_keys = vec![];
                                 for &orig_idx in mapper.iter() {
                                     // Create a new symbol for the column in the index relation
-                                    let tv = gen_symb(right_vars[orig_idx].span);
+                                    let tv = gen_symb(serial_id, right_vars[orig_idx].span);
                                     // Check for the existance of this column among the joiner columns
                                     if let Some(join_idx) = right_joiner_vars_pos_rev[orig_idx] {
                                         // Mark the field as bound, since it is used in the join
@@ -398,7 +551,7 @@ impl<'a> SessionTx<'a> {
                     for var in &rule_app.args {
                         if seen_variables.contains(var) {
                             prev_joiner_vars.push(var.clone());
-                            let rk = gen_symb(var.span);
+                            let rk = gen_symb(serial_id, var.span);
                             right_vars.push(rk.clone());
                             right_joiner_vars.push(rk);
                         } else {
@@ -437,7 +590,7 @@ impl<'a> SessionTx<'a> {
                     for (i, var) in rel_app.args.iter().enumerate() {
                         if seen_variables.contains(var) {
                             prev_joiner_vars.push(var.clone());
-                            let rk = gen_symb(var.span);
+                            let rk = gen_symb(serial_id, var.span);
                             right_vars.push(rk.clone());
                             right_joiner_vars.push(rk);
                             right_joiner_vars_pos.push(i);
@@ -506,7 +659,7 @@ impl<'a> SessionTx<'a> {
                     let mut post_filters = vec![];
                     for var in s.all_bindings() {
                         if seen_variables.contains(var) {
-                            let rk = gen_symb(var.span);
+                            let rk = gen_symb(serial_id, var.span);
                             post_filters.push(Expr::build_equate(
                                 vec![
                                     Expr::Binding {
@@ -540,7 +693,7 @@ impl<'a> SessionTx<'a> {
                     let mut post_filters = vec![];
                     for var in s.all_bindings() {
                         if seen_variables.contains(var) {
-                            let rk = gen_symb(var.span);
+                            let rk = gen_symb(serial_id, var.span);
                             post_filters.push(Expr::build_equate(
                                 vec![
                                     Expr::Binding {
@@ -574,7 +727,7 @@ impl<'a> SessionTx<'a> {
                     let mut post_filters = vec![];
                     for var in s.all_bindings() {
                         if seen_variables.contains(var) {
-                            let rk = gen_symb(var.span);
+                            let rk = gen_symb(serial_id, var.span);
                             post_filters.push(Expr::build_equate(
                                 vec![
                                     Expr::Binding {
@@ -599,6 +752,56 @@ impl<'a> SessionTx<'a> {
                         ret = ret.filter(Expr::build_and(post_filters, s.span))?;
                     }
                 }
+                MagicAtom::Optional(o) => {
+                    // the inner body is compiled on top of the atoms so far, so that it can use
+                    // their variables, and its rows are then left-joined to them on all these
+                    // variables. The atoms so far are thus evaluated twice.
+                    let join_vars = seen_variables.iter().cloned().collect_vec();
+                    let mut inner_seen = seen_variables.clone();
+                    let inner_body = self.reorder_by_statistics(&o.body, &inner_seen)?;
+                    let mut right = self.compile_magic_atoms(
+                        &inner_body,
+                        ret.clone(),
+                        &mut inner_seen,
+                        serial_id,
+                        store_arities,
+                    )?;
+                    let mut right_vars: BTreeSet<_> = join_vars.iter().cloned().collect();
+                    right_vars.extend(o.bindings());
+                    right.eliminate_temp_vars(&right_vars)?;
+                    seen_variables.extend(o.bindings());
+                    ret = ret.left_join(right, join_vars.clone(), join_vars, o.span);
+                }
+                MagicAtom::TableFunction(t) => {
+                    let mut own_bindings = vec![];
+                    let mut post_filters = vec![];
+                    for var in &t.bindings {
+                        if seen_variables.contains(var) {
+                            let rk = gen_symb(serial_id, var.span);
+                            post_filters.push(Expr::build_equate(
+                                vec![
+                                    Expr::Binding {
+                                        var: var.clone(),
+                                        tuple_pos: None,
+                                    },
+                                    Expr::Binding {
+                                        var: rk.clone(),
+                                        tuple_pos: None,
+                                    },
+                                ],
+                                var.span,
+                            ));
+                            own_bindings.push(rk);
+                        } else {
+                            seen_variables.insert(var.clone());
+                            own_bindings.push(var.clone());
+                        }
+                    }
+                    ret = ret.table_function(t.func.clone(), t.args.clone(), own_bindings, t.span);
+                    if !post_filters.is_empty() {
+                        ret = ret.filter(Expr::build_and(post_filters, t.span))?;
+                    }
+                }
                 MagicAtom::Unification(u) => {
                     if seen_variables.contains(&u.binding) {
                         let expr = if u.one_many_unif {
@@ -632,34 +835,86 @@ impl<'a> SessionTx<'a> {
                 }
             }
         }
+        Ok(ret)
+    }
+}
 
-        let ret_vars_set = ret_vars.iter().cloned().collect();
-        ret.eliminate_temp_vars(&ret_vars_set)?;
-        let cur_ret_set: BTreeSet<_> = ret.bindings_after_eliminate().into_iter().collect();
-        if cur_ret_set != ret_vars_set {
-            let ret_span = ret.span();
-            ret = ret.cartesian_join(RelAlgebra::unit(ret_span), ret_span);
-            ret.eliminate_temp_vars(&ret_vars_set)?;
-        }
+/// Generates a temporary variable, numbered by `serial_id`.
+fn gen_symb(serial_id: &mut usize, span: SourceSpan) -> Symbol {
+    let ret = Symbol::new(&format!("**{serial_id}") as &str, span);
+    *serial_id += 1;
+    ret
+}
 
-        let cur_ret_set: BTreeSet<_> = ret.bindings_after_eliminate().into_iter().collect();
-        #[derive(Debug, Error, Diagnostic)]
-        #[error("Symbol '{0}' in rule head is unbound")]
-        #[diagnostic(code(eval::unbound_symb_in_head))]
-        #[diagnostic(help(
-            "Note that symbols occurring only in negated positions are not considered bound"
-        ))]
-        struct UnboundSymbolInRuleHead(String, #[label] SourceSpan);
+/// Whether a stored relation can be joined before `atom`. Searches, table
+/// functions and optional atoms bind their own variables rather than join on
+/// them, so the relation must stay after them if it uses any of those variables.
+fn can_move_before(atom: &MagicAtom, rel_app: &MagicRelationApplyAtom) -> bool {
+    let bindings: BTreeSet<Symbol> = match atom {
+        MagicAtom::HnswSearch(s) => s.all_bindings().cloned().collect(),
+        MagicAtom::FtsSearch(s) => s.all_bindings().cloned().collect(),
+        MagicAtom::LshSearch(s) => s.all_bindings().cloned().collect(),
+        MagicAtom::Optional(o) => o.bindings(),
+        MagicAtom::TableFunction(t) => t.bindings.iter().cloned().collect(),
+        _ => return true,
+    };
+    !rel_app.args.iter().any(|arg| bindings.contains(arg))
+}
 
-        ensure!(cur_ret_set == ret_vars_set, {
-            let unbound = ret_vars_set.difference(&cur_ret_set).next().unwrap();
-            UnboundSymbolInRuleHead(unbound.to_string(), unbound.span)
-        });
-        let cur_ret_bindings = ret.bindings_after_eliminate();
-        if ret_vars != cur_ret_bindings {
-            ret = ret.reorder(ret_vars.to_vec());
+/// Estimated number of rows fetched from `handle` for each tuple coming from the left,
+/// taking into account the key prefix of the relation or any of its indices bound by `bound`.
+fn estimate_rows_per_probe(
+    rel_app: &MagicRelationApplyAtom,
+    handle: &RelationHandle,
+    bound: &BTreeSet<Symbol>,
+) -> f64 {
+    let n_keys = handle.metadata.keys.len();
+    let prefix_len = rel_app.args[..n_keys]
+        .iter()
+        .take_while(|arg| bound.contains(*arg))
+        .count();
+    let mut est = match &handle.stats {
+        None => f64::INFINITY,
+        Some(stats) => stats.rows_per_prefix(prefix_len),
+    };
+    for (idx_handle, mapper) in handle.indices.values() {
+        if let Some(stats) = &idx_handle.stats {
+            let idx_prefix_len = mapper
+                .iter()
+                .take_while(|i| bound.contains(&rel_app.args[**i]))
+                .count();
+            est = est.min(stats.rows_per_prefix(idx_prefix_len));
         }
+    }
+    est
+}
 
-        Ok(ret)
+/// Joins `right` onto `left`. When the join columns are not a prefix of the tuples
+/// of `right`, the usual join materializes `right` in memory. A hash join is chosen
+/// instead only when both sides have estimates (derived relations never do) and
+/// either `left` is the smaller side, or `right` is too large for the memory budget.
+fn join_relations(
+    left: RelAlgebra,
+    right: RelAlgebra,
+    left_keys: Vec<Symbol>,
+    right_keys: Vec<Symbol>,
+    hash_join_memory_budget: usize,
+    span: SourceSpan,
+) -> RelAlgebra {
+    if !right_keys.is_empty() && !left.is_unit() {
+        let right_bindings = right.bindings_after_eliminate();
+        let right_join_indices = right_keys
+            .iter()
+            .map(|k| right_bindings.iter().position(|b| b == k).unwrap())
+            .collect_vec();
+        if !join_is_prefix(&right_join_indices) {
+            if let (Some(l), Some(r)) = (left.estimated_rows(), right.estimated_rows()) {
+                let build_left = l < r;
+                if build_left || r > hash_join_memory_budget as f64 {
+                    return left.hash_join(right, left_keys, right_keys, build_left, span);
+                }
+            }
+        }
     }
+    left.join(right, left_keys, right_keys, span)
 }
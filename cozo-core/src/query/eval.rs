@@ -30,6 +30,29 @@ use crate::runtime::db::Poison;
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 
+/// The states of the normal aggregations of a rule, for each group of keys.
+type AggrWork = BTreeMap<Vec<DataValue>, Vec<Aggregation>>;
+
+#[allow(clippy::mutable_key_type)]
+fn merge_aggr_work(into: &mut AggrWork, other: AggrWork) -> Result<()> {
+    for (keys, aggrs) in other {
+        match into.entry(keys) {
+            Entry::Occupied(mut ent) => {
+                for (l, r) in ent.get_mut().iter_mut().zip(aggrs.iter()) {
+                    l.normal_op
+                        .as_mut()
+                        .unwrap()
+                        .merge(r.normal_op.as_deref().unwrap())?;
+                }
+            }
+            Entry::Vacant(ent) => {
+                ent.insert(aggrs);
+            }
+        }
+    }
+    Ok(())
+}
+
 pub(crate) struct QueryLimiter {
     total: Option<usize>,
     skip: Option<usize>,
@@ -70,6 +93,29 @@ impl<'a> SessionTx<'a> {
         num_to_skip: Option<usize>,
         poison: Poison,
     ) -> Result<(EpochStore, bool)> {
+        let (mut stores, early_return) = self.stratified_magic_evaluate_stores(
+            strata,
+            store_lifetimes,
+            total_num_to_take,
+            num_to_skip,
+            poison,
+        )?;
+        let entry_symbol = MagicSymbol::Muggle {
+            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        };
+        let ret_area = stores.remove(&entry_symbol).ok_or(NoEntryError)?;
+        Ok((ret_area, early_return))
+    }
+    /// Evaluates all strata, returning every store that is still alive after the last stratum.
+    /// Used directly when the caller wants to evaluate the entry rule by itself.
+    pub(crate) fn stratified_magic_evaluate_stores(
+        &self,
+        strata: &[CompiledProgram],
+        store_lifetimes: BTreeMap<MagicSymbol, usize>,
+        total_num_to_take: Option<usize>,
+        num_to_skip: Option<usize>,
+        poison: Poison,
+    ) -> Result<(BTreeMap<MagicSymbol, EpochStore>, bool)> {
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
         let mut early_return = false;
         for (stratum, cur_prog) in strata.iter().enumerate() {
@@ -103,11 +149,7 @@ impl<'a> SessionTx<'a> {
                 poison.clone(),
             )?;
         }
-        let entry_symbol = MagicSymbol::Muggle {
-            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
-        };
-        let ret_area = stores.remove(&entry_symbol).ok_or(NoEntryError)?;
-        Ok((ret_area, early_return))
+        Ok((stores, early_return))
     }
     /// returns true if early return is activated
     fn semi_naive_magic_evaluate(
@@ -378,6 +420,70 @@ impl<'a> SessionTx<'a> {
         }
         Ok(out_store)
     }
+    #[allow(clippy::mutable_key_type)]
+    fn accumulate_normal_aggr(
+        &self,
+        rule_symb: &MagicSymbol,
+        rule_n: usize,
+        rule: &CompiledRule,
+        stores: &BTreeMap<MagicSymbol, EpochStore>,
+        aggr_work: &mut AggrWork,
+    ) -> Result<()> {
+        debug!(
+            "Calculation for normal aggr rule {:?}.{}",
+            rule_symb, rule_n
+        );
+        trace!("{:?}", rule);
+
+        let keys_indices = rule
+            .aggr
+            .iter()
+            .enumerate()
+            .filter_map(|(i, a)| if a.is_none() { Some(i) } else { None })
+            .collect_vec();
+        let extract_keys = |t: &Tuple| -> Vec<DataValue> {
+            keys_indices.iter().map(|i| t[*i].clone()).collect_vec()
+        };
+
+        let val_indices_and_aggrs = rule
+            .aggr
+            .iter()
+            .enumerate()
+            .filter_map(|(i, a)| a.as_ref().map(|aggr| (i, aggr.clone())))
+            .collect_vec();
+
+        for item_res in rule.relation.iter(self, None, stores)? {
+            let item = item_res?;
+            trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
+
+            let keys = extract_keys(&item);
+
+            match aggr_work.entry(keys) {
+                Entry::Occupied(mut ent) => {
+                    let aggr_ops = ent.get_mut();
+                    for (aggr_idx, (tuple_idx, _)) in val_indices_and_aggrs.iter().enumerate() {
+                        aggr_ops[aggr_idx]
+                            .normal_op
+                            .as_mut()
+                            .unwrap()
+                            .set(&item[*tuple_idx])?;
+                    }
+                }
+                Entry::Vacant(ent) => {
+                    let mut aggr_ops = Vec::with_capacity(val_indices_and_aggrs.len());
+                    for (i, (aggr, params)) in &val_indices_and_aggrs {
+                        let mut cur_aggr = aggr.clone();
+                        cur_aggr.normal_init(params)?;
+                        cur_aggr.normal_op.as_mut().unwrap().set(&item[*i])?;
+                        aggr_ops.push(cur_aggr)
+                    }
+                    ent.insert(aggr_ops);
+                }
+            }
+        }
+        Ok(())
+    }
+    #[allow(clippy::mutable_key_type)]
     fn initial_rule_aggr_eval(
         &self,
         rule_symb: &MagicSymbol,
@@ -388,62 +494,38 @@ impl<'a> SessionTx<'a> {
     ) -> Result<(bool, RegularTempStore)> {
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
-        let mut aggr_work: BTreeMap<Vec<DataValue>, Vec<Aggregation>> = BTreeMap::new();
+        let mut aggr_work: AggrWork = BTreeMap::new();
 
-        for (rule_n, rule) in ruleset.iter().enumerate() {
-            debug!(
-                "Calculation for normal aggr rule {:?}.{}",
-                rule_symb, rule_n
-            );
-            trace!("{:?}", rule);
-
-            let keys_indices = rule
-                .aggr
-                .iter()
-                .enumerate()
-                .filter_map(|(i, a)| if a.is_none() { Some(i) } else { None })
-                .collect_vec();
-            let extract_keys = |t: &Tuple| -> Vec<DataValue> {
-                keys_indices.iter().map(|i| t[*i].clone()).collect_vec()
-            };
-
-            let val_indices_and_aggrs = rule
-                .aggr
-                .iter()
+        let mergeable = ruleset.len() > 1
+            && ruleset.iter().all(|rule| {
+                rule.aggr
+                    .iter()
+                    .flatten()
+                    .all(|(aggr, _)| aggr.is_mergeable())
+            });
+        if mergeable {
+            // each rule accumulates partial states on its own, and these are merged afterwards
+            #[cfg(not(target_arch = "wasm32"))]
+            let rules = ruleset.par_iter();
+            #[cfg(target_arch = "wasm32")]
+            let rules = ruleset.iter();
+            let partials = rules
                 .enumerate()
-                .filter_map(|(i, a)| a.as_ref().map(|aggr| (i, aggr.clone())))
-                .collect_vec();
-
-            for item_res in rule.relation.iter(self, None, stores)? {
-                let item = item_res?;
-                trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
-
-                let keys = extract_keys(&item);
-
-                match aggr_work.entry(keys) {
-                    Entry::Occupied(mut ent) => {
-                        let aggr_ops = ent.get_mut();
-                        for (aggr_idx, (tuple_idx, _)) in val_indices_and_aggrs.iter().enumerate() {
-                            aggr_ops[aggr_idx]
-                                .normal_op
-                                .as_mut()
-                                .unwrap()
-                                .set(&item[*tuple_idx])?;
-                        }
-                    }
-                    Entry::Vacant(ent) => {
-                        let mut aggr_ops = Vec::with_capacity(val_indices_and_aggrs.len());
-                        for (i, (aggr, params)) in &val_indices_and_aggrs {
-                            let mut cur_aggr = aggr.clone();
-                            cur_aggr.normal_init(params)?;
-                            cur_aggr.normal_op.as_mut().unwrap().set(&item[*i])?;
-                            aggr_ops.push(cur_aggr)
-                        }
-                        ent.insert(aggr_ops);
-                    }
-                }
+                .map(|(rule_n, rule)| -> Result<AggrWork> {
+                    let mut partial = BTreeMap::new();
+                    self.accumulate_normal_aggr(rule_symb, rule_n, rule, stores, &mut partial)?;
+                    poison.check()?;
+                    Ok(partial)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            for partial in partials {
+                merge_aggr_work(&mut aggr_work, partial)?;
+            }
+        } else {
+            for (rule_n, rule) in ruleset.iter().enumerate() {
+                self.accumulate_normal_aggr(rule_symb, rule_n, rule, stores, &mut aggr_work)?;
+                poison.check()?;
             }
-            poison.check()?;
         }
 
         let mut inv_indices = Vec::with_capacity(ruleset[0].aggr.len());
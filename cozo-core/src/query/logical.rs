@@ -15,7 +15,8 @@ use thiserror::Error;
 use crate::data::expr::Expr;
 use crate::data::program::{
     InputAtom, InputNamedFieldRelationApplyAtom, InputRelationApplyAtom, InputRuleApplyAtom,
-    NormalFormAtom, NormalFormRelationApplyAtom, NormalFormRuleApplyAtom, TempSymbGen, Unification,
+    NormalFormAtom, NormalFormOptional, NormalFormRelationApplyAtom, NormalFormRuleApplyAtom,
+    TempSymbGen, Unification,
 };
 use crate::parse::SourceSpan;
 use crate::query::reorder::UnsafeNegation;
@@ -79,6 +80,10 @@ impl InputAtom {
                 span,
             },
             InputAtom::Unification { inner: unif } => InputAtom::Unification { inner: unif },
+            InputAtom::Optional { inner, span } => InputAtom::Optional {
+                inner: Box::new(inner.negation_normal_form()?),
+                span,
+            },
             InputAtom::Negation { inner: arg, span } => match *arg {
                 a @ (InputAtom::Rule { .. }
                 | InputAtom::NamedFieldRelation { .. }
@@ -124,8 +129,15 @@ impl InputAtom {
                 InputAtom::Search { inner } => {
                     bail!(UnsafeNegation(inner.span))
                 }
+                InputAtom::TableFunction { inner } => {
+                    bail!(UnsafeNegation(inner.span))
+                }
+                InputAtom::Optional { span, .. } => {
+                    bail!(UnsafeNegation(span))
+                }
             },
             InputAtom::Search { inner } => InputAtom::Search { inner },
+            InputAtom::TableFunction { inner } => InputAtom::TableFunction { inner },
         })
     }
 
@@ -232,6 +244,17 @@ impl InputAtom {
                 Disjunction::singlet(NormalFormAtom::Unification(u))
             }
             InputAtom::Search { inner } => inner.normalize(gen, tx)?,
+            InputAtom::TableFunction { inner } => {
+                Disjunction::singlet(NormalFormAtom::TableFunction(inner))
+            }
+            InputAtom::Optional { inner, span } => {
+                let mut inner = inner.do_disjunctive_normal_form(gen, tx)?.inner;
+                ensure!(inner.len() == 1, DisjunctionInOptional(span));
+                Disjunction::singlet(NormalFormAtom::Optional(NormalFormOptional {
+                    body: inner.pop().unwrap().0,
+                    span,
+                }))
+            }
         })
     }
 }
@@ -358,6 +381,14 @@ impl InputRelationApplyAtom {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The body of an optional atom cannot contain disjunctions")]
+#[diagnostic(code(eval::disjunction_in_optional))]
+#[diagnostic(help(
+    "Define the disjunction as a separate rule and apply that rule inside the optional atom"
+))]
+struct DisjunctionInOptional(#[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("stored relation '{0}' does not have field '{1}'")]
 #[diagnostic(code(eval::named_field_not_found))]
@@ -16,8 +16,8 @@ use smartstring::SmartString;
 
 use crate::data::program::{
     FixedRuleArg, MagicAtom, MagicFixedRuleApply, MagicFixedRuleRuleArg, MagicInlineRule,
-    MagicProgram, MagicRelationApplyAtom, MagicRuleApplyAtom, MagicRulesOrFixed, MagicSymbol,
-    NormalFormAtom, NormalFormInlineRule, NormalFormProgram, NormalFormRulesOrFixed,
+    MagicOptional, MagicProgram, MagicRelationApplyAtom, MagicRuleApplyAtom, MagicRulesOrFixed,
+    MagicSymbol, NormalFormAtom, NormalFormInlineRule, NormalFormProgram, NormalFormRulesOrFixed,
     StratifiedMagicProgram, StratifiedNormalFormProgram,
 };
 use crate::data::relation::{ColType, NullableColType};
@@ -188,6 +188,14 @@ fn magic_rewrite_ruleset(
                     seen_bindings.extend(s.all_bindings().cloned());
                     collected_atoms.push(MagicAtom::LshSearch(s));
                 }
+                MagicAtom::Optional(o) => {
+                    seen_bindings.extend(o.bindings());
+                    collected_atoms.push(MagicAtom::Optional(o));
+                }
+                MagicAtom::TableFunction(t) => {
+                    seen_bindings.extend(t.bindings.iter().cloned());
+                    collected_atoms.push(MagicAtom::TableFunction(t));
+                }
                 MagicAtom::Rule(r_app) => {
                     if r_app.name.has_bound_adornment() {
                         // we are guaranteed to have a magic rule application
@@ -277,7 +285,8 @@ impl NormalFormProgram {
             match rules {
                 NormalFormRulesOrFixed::Rules { rules } => {
                     for rule in rules {
-                        for atom in rule.body.iter() {
+                        let mut atoms = rule.body.iter().collect_vec();
+                        while let Some(atom) = atoms.pop() {
                             match atom {
                                 NormalFormAtom::Rule(r_app)
                                 | NormalFormAtom::NegatedRule(r_app) => {
@@ -285,6 +294,7 @@ impl NormalFormProgram {
                                         downstream_rules.insert(r_app.name.clone());
                                     }
                                 }
+                                NormalFormAtom::Optional(o) => atoms.extend(o.body.iter()),
                                 _ => {}
                             }
                         }
@@ -609,6 +619,21 @@ impl NormalFormAtom {
                 seen_bindings.insert(u.binding.clone());
                 MagicAtom::Unification(u.clone())
             }
+            NormalFormAtom::TableFunction(t) => {
+                seen_bindings.extend(t.bindings.iter().cloned());
+                MagicAtom::TableFunction(t.clone())
+            }
+            NormalFormAtom::Optional(o) => {
+                // rules applied in the inner body are adorned as if nothing were bound, so they are never rewritten
+                let mut inner_seen = BTreeSet::new();
+                let body = o
+                    .body
+                    .iter()
+                    .map(|a| a.adorn(pending, &mut inner_seen, &BTreeSet::new()))
+                    .collect();
+                seen_bindings.extend(o.bindings());
+                MagicAtom::Optional(MagicOptional { body, span: o.span })
+            }
         }
     }
 }
@@ -6,21 +6,29 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter, Write};
+use std::hash::{Hash, Hasher};
 use std::iter;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
 use log::{debug, error};
 use miette::{bail, Diagnostic, Result};
+use rustc_hash::FxHashMap;
 use smartstring::SmartString;
 use thiserror::Error;
 
-use crate::data::expr::{compute_bounds, eval_bytecode, eval_bytecode_pred, Bytecode, Expr};
+use crate::data::expr::{
+    bind_params_in_bytecode, compute_bounds, eval_bytecode, eval_bytecode_pred, Bytecode, Expr,
+};
 use crate::data::program::{FtsSearch, HnswSearch, MagicSymbol};
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
+use crate::data::table_func::TableFunction;
 use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
@@ -28,21 +36,25 @@ use crate::runtime::minhash_lsh::LshSearch;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
-use crate::utils::swap_option_result;
+use crate::utils::{swap_option_result, TempCollector};
 
+#[derive(Clone)]
 pub(crate) enum RelAlgebra {
     Fixed(InlineFixedRA),
     TempStore(TempStoreRA),
     Stored(StoredRA),
     StoredWithValidity(StoredWithValidityRA),
     Join(Box<InnerJoin>),
+    HashJoin(Box<HashJoin>),
     NegJoin(Box<NegJoin>),
+    LeftJoin(Box<LeftJoin>),
     Reorder(ReorderRA),
     Filter(FilteredRA),
     Unification(UnificationRA),
     HnswSearch(HnswSearchRA),
     FtsSearch(FtsSearchRA),
     LshSearch(LshSearchRA),
+    TableFunction(TableFunctionRA),
 }
 
 impl RelAlgebra {
@@ -52,7 +64,9 @@ impl RelAlgebra {
             RelAlgebra::TempStore(i) => i.span,
             RelAlgebra::Stored(i) => i.span,
             RelAlgebra::Join(i) => i.span,
+            RelAlgebra::HashJoin(i) => i.span,
             RelAlgebra::NegJoin(i) => i.span,
+            RelAlgebra::LeftJoin(i) => i.span,
             RelAlgebra::Reorder(i) => i.relation.span(),
             RelAlgebra::Filter(i) => i.span,
             RelAlgebra::Unification(i) => i.span,
@@ -60,10 +74,12 @@ impl RelAlgebra {
             RelAlgebra::HnswSearch(i) => i.hnsw_search.span,
             RelAlgebra::FtsSearch(i) => i.fts_search.span,
             RelAlgebra::LshSearch(i) => i.lsh_search.span,
+            RelAlgebra::TableFunction(i) => i.span,
         }
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct UnificationRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) binding: Symbol,
@@ -177,6 +193,108 @@ impl UnificationRA {
     }
 }
 
+#[derive(Clone)]
+pub(crate) struct TableFunctionRA {
+    pub(crate) parent: Box<RelAlgebra>,
+    pub(crate) func: Arc<TableFunction>,
+    pub(crate) args: Vec<Expr>,
+    pub(crate) args_bytecodes: Vec<Vec<Bytecode>>,
+    pub(crate) bindings: Vec<Symbol>,
+    pub(crate) to_eliminate: BTreeSet<Symbol>,
+    pub(crate) span: SourceSpan,
+}
+
+impl TableFunctionRA {
+    fn fill_binding_indices_and_compile(&mut self) -> Result<()> {
+        let parent_bindings: BTreeMap<_, _> = self
+            .parent
+            .bindings_after_eliminate()
+            .into_iter()
+            .enumerate()
+            .map(|(a, b)| (b, a))
+            .collect();
+        self.args_bytecodes.clear();
+        for arg in self.args.iter_mut() {
+            arg.fill_binding_indices(&parent_bindings)?;
+            self.args_bytecodes.push(arg.compile()?);
+        }
+        Ok(())
+    }
+    pub(crate) fn do_eliminate_temp_vars(&mut self, used: &BTreeSet<Symbol>) -> Result<()> {
+        for binding in self.parent.bindings_before_eliminate() {
+            if !used.contains(&binding) {
+                self.to_eliminate.insert(binding.clone());
+            }
+        }
+        for binding in &self.bindings {
+            if !used.contains(binding) {
+                self.to_eliminate.insert(binding.clone());
+            }
+        }
+        let mut nxt = used.clone();
+        for arg in &self.args {
+            nxt.extend(arg.bindings()?);
+        }
+        self.parent.eliminate_temp_vars(&nxt)?;
+        Ok(())
+    }
+
+    fn iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'a>> {
+        let mut bindings = self.parent.bindings_after_eliminate();
+        bindings.extend_from_slice(&self.bindings);
+        let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
+        let eliminate_indices = Rc::new(eliminate_indices);
+        let mut stack = vec![];
+        let it =
+            self.parent
+                .iter(tx, delta_rule, stores)?
+                .flat_map(move |tuple| -> TupleIter<'a> {
+                    let tuple = match tuple {
+                        Ok(tuple) => tuple,
+                        Err(err) => return Box::new(iter::once(Err(err))),
+                    };
+                    let rows = match self.call(&tuple, &mut stack) {
+                        Ok(rows) => rows,
+                        Err(err) => return Box::new(iter::once(Err(err))),
+                    };
+                    let eliminate_indices = eliminate_indices.clone();
+                    // rows are generated lazily and may be unbounded, so check the poison for each
+                    Box::new(rows.map(move |row| -> Result<Tuple> {
+                        tx.poison.check()?;
+                        let mut ret = tuple.clone();
+                        ret.extend(row?);
+                        Ok(eliminate_from_tuple(ret, &eliminate_indices))
+                    }))
+                });
+        Ok(Box::new(it))
+    }
+    fn call<'a>(
+        &'a self,
+        tuple: &Tuple,
+        stack: &mut Vec<DataValue>,
+    ) -> Result<impl Iterator<Item = Result<Tuple>> + 'a> {
+        let args: Vec<_> = self
+            .args_bytecodes
+            .iter()
+            .map(|bytecode| eval_bytecode(bytecode, tuple, stack))
+            .try_collect()?;
+        self.func.call(&args).map_err(|err| {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Evaluation of table function '{0}' failed")]
+            #[diagnostic(code(eval::table_function))]
+            struct TableFunctionError(String, #[label] SourceSpan, #[help] String);
+
+            TableFunctionError(self.func.name.to_string(), self.span, err.to_string()).into()
+        })
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct FilteredRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) filters: Vec<Expr>,
@@ -318,6 +436,14 @@ impl Debug for RelAlgebra {
                         .finish()
                 }
             }
+            RelAlgebra::HashJoin(r) => f
+                .debug_tuple("HashJoin")
+                .field(&bindings)
+                .field(&r.joiner)
+                .field(&r.build_left)
+                .field(&r.left)
+                .field(&r.right)
+                .finish(),
             RelAlgebra::NegJoin(r) => f
                 .debug_tuple("NegJoin")
                 .field(&bindings)
@@ -325,6 +451,13 @@ impl Debug for RelAlgebra {
                 .field(&r.left)
                 .field(&r.right)
                 .finish(),
+            RelAlgebra::LeftJoin(r) => f
+                .debug_tuple("LeftJoin")
+                .field(&bindings)
+                .field(&r.joiner)
+                .field(&r.left)
+                .field(&r.right)
+                .finish(),
             RelAlgebra::Reorder(r) => f
                 .debug_tuple("Reorder")
                 .field(&r.new_order)
@@ -343,6 +476,13 @@ impl Debug for RelAlgebra {
                 .field(&r.binding)
                 .field(&r.expr)
                 .finish(),
+            RelAlgebra::TableFunction(r) => f
+                .debug_tuple("TableFunction")
+                .field(&bindings)
+                .field(&r.parent)
+                .field(&r.func)
+                .field(&r.args)
+                .finish(),
         }
     }
 }
@@ -391,16 +531,225 @@ impl RelAlgebra {
                 u.parent.fill_binding_indices_and_compile()?;
                 u.fill_binding_indices_and_compile()?
             }
+            RelAlgebra::TableFunction(t) => {
+                t.parent.fill_binding_indices_and_compile()?;
+                t.fill_binding_indices_and_compile()?
+            }
             RelAlgebra::Join(r) => {
                 r.left.fill_binding_indices_and_compile()?;
                 r.right.fill_binding_indices_and_compile()?;
             }
+            RelAlgebra::HashJoin(r) => {
+                r.left.fill_binding_indices_and_compile()?;
+                r.right.fill_binding_indices_and_compile()?;
+            }
+            RelAlgebra::LeftJoin(r) => {
+                r.left.fill_binding_indices_and_compile()?;
+                r.right.fill_binding_indices_and_compile()?;
+            }
+        }
+        Ok(())
+    }
+    /// Replace the parameters of a prepared query by their values,
+    /// in both the expressions and their compiled bytecodes.
+    pub(crate) fn bind_params(&mut self, params: &BTreeMap<String, DataValue>) -> Result<()> {
+        fn bind_filters(
+            filters: &mut [Expr],
+            filters_bytecodes: &mut [(Vec<Bytecode>, SourceSpan)],
+            params: &BTreeMap<String, DataValue>,
+        ) -> Result<()> {
+            for filter in filters {
+                filter.bind_params(params)?;
+            }
+            for (bytecodes, _) in filters_bytecodes {
+                bind_params_in_bytecode(bytecodes, params)?;
+            }
+            Ok(())
+        }
+        fn bind_search_filter(
+            filter: &mut Option<Expr>,
+            filter_bytecode: &mut Option<(Vec<Bytecode>, SourceSpan)>,
+            params: &BTreeMap<String, DataValue>,
+        ) -> Result<()> {
+            if let Some(filter) = filter {
+                filter.bind_params(params)?;
+            }
+            if let Some((bytecodes, _)) = filter_bytecode {
+                bind_params_in_bytecode(bytecodes, params)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            RelAlgebra::Fixed(_) => {}
+            RelAlgebra::TempStore(d) => {
+                bind_filters(&mut d.filters, &mut d.filters_bytecodes, params)?;
+            }
+            RelAlgebra::Stored(v) => {
+                bind_filters(&mut v.filters, &mut v.filters_bytecodes, params)?;
+            }
+            RelAlgebra::StoredWithValidity(v) => {
+                bind_filters(&mut v.filters, &mut v.filters_bytecodes, params)?;
+            }
+            RelAlgebra::HnswSearch(s) => {
+                s.parent.bind_params(params)?;
+                bind_search_filter(&mut s.hnsw_search.filter, &mut s.filter_bytecode, params)?;
+            }
+            RelAlgebra::FtsSearch(s) => {
+                s.parent.bind_params(params)?;
+                bind_search_filter(&mut s.fts_search.filter, &mut s.filter_bytecode, params)?;
+            }
+            RelAlgebra::LshSearch(s) => {
+                s.parent.bind_params(params)?;
+                bind_search_filter(&mut s.lsh_search.filter, &mut s.filter_bytecode, params)?;
+            }
+            RelAlgebra::Reorder(r) => {
+                r.relation.bind_params(params)?;
+            }
+            RelAlgebra::Filter(f) => {
+                f.parent.bind_params(params)?;
+                bind_filters(&mut f.filters, &mut f.filters_bytecodes, params)?;
+            }
+            RelAlgebra::Unification(u) => {
+                u.parent.bind_params(params)?;
+                u.expr.bind_params(params)?;
+                bind_params_in_bytecode(&mut u.expr_bytecode, params)?;
+            }
+            RelAlgebra::TableFunction(t) => {
+                t.parent.bind_params(params)?;
+                for (arg, bytecode) in t.args.iter_mut().zip(t.args_bytecodes.iter_mut()) {
+                    arg.bind_params(params)?;
+                    bind_params_in_bytecode(bytecode, params)?;
+                }
+            }
+            RelAlgebra::NegJoin(r) => {
+                r.left.bind_params(params)?;
+                r.right.bind_params(params)?;
+            }
+            RelAlgebra::Join(r) => {
+                r.left.bind_params(params)?;
+                r.right.bind_params(params)?;
+            }
+            RelAlgebra::HashJoin(r) => {
+                r.left.bind_params(params)?;
+                r.right.bind_params(params)?;
+            }
+            RelAlgebra::LeftJoin(r) => {
+                r.left.bind_params(params)?;
+                r.right.bind_params(params)?;
+            }
         }
         Ok(())
     }
     pub(crate) fn unit(span: SourceSpan) -> Self {
         Self::Fixed(InlineFixedRA::unit(span))
     }
+    /// Estimated number of rows produced by this node, based on the statistics
+    /// collected by `::analyze`. `None` if some relation involved has not been analyzed.
+    pub(crate) fn estimated_rows(&self) -> Option<f64> {
+        match self {
+            RelAlgebra::Fixed(f) => Some(f.data.len() as f64),
+            RelAlgebra::Stored(StoredRA { storage, .. })
+            | RelAlgebra::StoredWithValidity(StoredWithValidityRA { storage, .. }) => {
+                storage.stats.as_ref().map(|s| s.row_count as f64)
+            }
+            RelAlgebra::Join(inner) => {
+                let left = inner.left.estimated_rows()?;
+                let per_probe = match &inner.right {
+                    RelAlgebra::Stored(StoredRA {
+                        storage, bindings, ..
+                    })
+                    | RelAlgebra::StoredWithValidity(StoredWithValidityRA {
+                        storage,
+                        bindings,
+                        ..
+                    }) => {
+                        let prefix_len = bindings
+                            .iter()
+                            .take(storage.metadata.keys.len())
+                            .take_while(|b| inner.joiner.right_keys.contains(b))
+                            .count();
+                        storage.stats.as_ref()?.rows_per_prefix(prefix_len)
+                    }
+                    r => r.estimated_rows()?,
+                };
+                Some(left * per_probe)
+            }
+            RelAlgebra::HashJoin(inner) => {
+                // without statistics on the non-key join columns, assume every
+                // tuple on the larger side finds a partner
+                let left = inner.left.estimated_rows()?;
+                let right = inner.right.estimated_rows()?;
+                Some(left.max(right))
+            }
+            RelAlgebra::NegJoin(inner) => inner.left.estimated_rows(),
+            RelAlgebra::LeftJoin(inner) => {
+                let left = inner.left.estimated_rows()?;
+                let right = inner.right.estimated_rows()?;
+                Some(left.max(right))
+            }
+            RelAlgebra::Reorder(r) => r.relation.estimated_rows(),
+            RelAlgebra::Filter(f) => f.parent.estimated_rows(),
+            RelAlgebra::Unification(u) => {
+                if u.is_multi {
+                    None
+                } else {
+                    u.parent.estimated_rows()
+                }
+            }
+            RelAlgebra::TempStore(_)
+            | RelAlgebra::HnswSearch(_)
+            | RelAlgebra::FtsSearch(_)
+            | RelAlgebra::LshSearch(_)
+            | RelAlgebra::TableFunction(_) => None,
+        }
+    }
+    /// Whether the rows produced by this node are known to be distinct without deduplication.
+    /// Stored relations and temporary stores hold sets. Joining sets gives a set, as long as
+    /// the only columns eliminated are the join columns of the right side, which repeat those
+    /// of the left side. Other eliminations may make rows collide.
+    pub(crate) fn has_distinct_rows(&self) -> bool {
+        match self {
+            RelAlgebra::Stored(_)
+            | RelAlgebra::StoredWithValidity(_)
+            | RelAlgebra::TempStore(_) => true,
+            RelAlgebra::Fixed(f) => f.data.len() <= 1,
+            RelAlgebra::Reorder(r) => r.relation.has_distinct_rows(),
+            RelAlgebra::Filter(f) => f.to_eliminate.is_empty() && f.parent.has_distinct_rows(),
+            RelAlgebra::Unification(u) => {
+                !u.is_multi
+                    && u.to_eliminate.iter().all(|s| *s == u.binding)
+                    && u.parent.has_distinct_rows()
+            }
+            RelAlgebra::Join(inner) => {
+                inner
+                    .to_eliminate
+                    .iter()
+                    .all(|s| inner.joiner.right_keys.contains(s))
+                    && inner.left.has_distinct_rows()
+                    && inner.right.has_distinct_rows()
+            }
+            RelAlgebra::HashJoin(inner) => {
+                inner
+                    .to_eliminate
+                    .iter()
+                    .all(|s| inner.joiner.right_keys.contains(s))
+                    && inner.left.has_distinct_rows()
+                    && inner.right.has_distinct_rows()
+            }
+            RelAlgebra::NegJoin(inner) => {
+                inner.to_eliminate.is_empty() && inner.left.has_distinct_rows()
+            }
+            // the matches of each left tuple are deduplicated when the hash table is built
+            RelAlgebra::LeftJoin(inner) => {
+                inner.to_eliminate.is_empty() && inner.left.has_distinct_rows()
+            }
+            RelAlgebra::HnswSearch(_)
+            | RelAlgebra::FtsSearch(_)
+            | RelAlgebra::LshSearch(_)
+            | RelAlgebra::TableFunction(_) => false,
+        }
+    }
     pub(crate) fn is_unit(&self) -> bool {
         if let RelAlgebra::Fixed(r) = self {
             r.bindings.is_empty() && r.data.len() == 1
@@ -469,10 +818,12 @@ impl RelAlgebra {
             s @ (RelAlgebra::Fixed(_)
             | RelAlgebra::Reorder(_)
             | RelAlgebra::NegJoin(_)
+            | RelAlgebra::LeftJoin(_)
             | RelAlgebra::Unification(_)
             | RelAlgebra::HnswSearch(_)
             | RelAlgebra::FtsSearch(_)
-            | RelAlgebra::LshSearch(_)) => {
+            | RelAlgebra::LshSearch(_)
+            | RelAlgebra::TableFunction(_)) => {
                 let span = filter.span();
                 RelAlgebra::Filter(FilteredRA {
                     parent: Box::new(s),
@@ -594,6 +945,53 @@ impl RelAlgebra {
                 }
                 joined
             }
+            RelAlgebra::HashJoin(inner) => {
+                let filters = filter.to_conjunction();
+                let left_bindings: BTreeSet<Symbol> =
+                    inner.left.bindings_before_eliminate().into_iter().collect();
+                let right_bindings: BTreeSet<Symbol> = inner
+                    .right
+                    .bindings_before_eliminate()
+                    .into_iter()
+                    .collect();
+                let mut remaining = vec![];
+                let HashJoin {
+                    mut left,
+                    mut right,
+                    joiner,
+                    build_left,
+                    to_eliminate,
+                    span,
+                } = *inner;
+                for filter in filters {
+                    let f_bindings = filter.bindings()?;
+                    if f_bindings.is_subset(&left_bindings) {
+                        left = left.filter(filter)?;
+                    } else if f_bindings.is_subset(&right_bindings) {
+                        right = right.filter(filter)?;
+                    } else {
+                        remaining.push(filter);
+                    }
+                }
+                let mut joined = RelAlgebra::HashJoin(Box::new(HashJoin {
+                    left,
+                    right,
+                    joiner,
+                    build_left,
+                    to_eliminate,
+                    span,
+                }));
+                if !remaining.is_empty() {
+                    joined = RelAlgebra::Filter(FilteredRA {
+                        parent: Box::new(joined),
+                        filters: remaining,
+                        filters_bytecodes: vec![],
+                        to_eliminate: Default::default(),
+                        span,
+                    });
+                }
+                joined
+            }
         })
     }
     pub(crate) fn unify(
@@ -613,6 +1011,23 @@ impl RelAlgebra {
             span,
         })
     }
+    pub(crate) fn table_function(
+        self,
+        func: Arc<TableFunction>,
+        args: Vec<Expr>,
+        bindings: Vec<Symbol>,
+        span: SourceSpan,
+    ) -> Self {
+        RelAlgebra::TableFunction(TableFunctionRA {
+            parent: Box::new(self),
+            func,
+            args,
+            args_bytecodes: vec![],
+            bindings,
+            to_eliminate: Default::default(),
+            span,
+        })
+    }
     pub(crate) fn hnsw_search(
         self,
         hnsw_search: HnswSearch,
@@ -667,6 +1082,26 @@ impl RelAlgebra {
             span,
         }))
     }
+    pub(crate) fn hash_join(
+        self,
+        right: RelAlgebra,
+        left_keys: Vec<Symbol>,
+        right_keys: Vec<Symbol>,
+        build_left: bool,
+        span: SourceSpan,
+    ) -> Self {
+        RelAlgebra::HashJoin(Box::new(HashJoin {
+            left: self,
+            right,
+            joiner: Joiner {
+                left_keys,
+                right_keys,
+            },
+            build_left,
+            to_eliminate: Default::default(),
+            span,
+        }))
+    }
     pub(crate) fn neg_join(
         self,
         right: RelAlgebra,
@@ -685,9 +1120,27 @@ impl RelAlgebra {
             span,
         }))
     }
+    pub(crate) fn left_join(
+        self,
+        right: RelAlgebra,
+        left_keys: Vec<Symbol>,
+        right_keys: Vec<Symbol>,
+        span: SourceSpan,
+    ) -> Self {
+        RelAlgebra::LeftJoin(Box::new(LeftJoin {
+            left: self,
+            right,
+            joiner: Joiner {
+                left_keys,
+                right_keys,
+            },
+            to_eliminate: Default::default(),
+            span,
+        }))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct ReorderRA {
     pub(crate) relation: Box<RelAlgebra>,
     pub(crate) new_order: Vec<Symbol>,
@@ -733,7 +1186,7 @@ impl ReorderRA {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct InlineFixedRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) data: Vec<Vec<DataValue>>,
@@ -883,7 +1336,7 @@ fn get_eliminate_indices(bindings: &[Symbol], eliminate: &BTreeSet<Symbol>) -> B
         .collect::<BTreeSet<_>>()
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct StoredRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) storage: RelationHandle,
@@ -892,7 +1345,7 @@ pub(crate) struct StoredRA {
     pub(crate) span: SourceSpan,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct HnswSearchRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) hnsw_search: HnswSearch,
@@ -900,7 +1353,7 @@ pub(crate) struct HnswSearchRA {
     pub(crate) own_bindings: Vec<Symbol>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct LshSearchRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) lsh_search: LshSearch,
@@ -973,7 +1426,7 @@ impl LshSearchRA {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct FtsSearchRA {
     pub(crate) parent: Box<RelAlgebra>,
     pub(crate) fts_search: FtsSearch,
@@ -1121,7 +1574,7 @@ impl HnswSearchRA {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct StoredWithValidityRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) storage: RelationHandle,
@@ -1178,7 +1631,11 @@ impl StoredWithValidityRA {
                     .collect_vec();
 
                 if !skip_range_check && !self.filters.is_empty() {
-                    let other_bindings = &self.bindings[right_join_indices.len()..];
+                    // only key columns can bound the scan: a bound on a value column would
+                    // extend the lower bound past the keys it is compared against
+                    let key_len = self.storage.metadata.keys.len();
+                    let other_bindings = &self.bindings
+                        [right_join_indices.len()..key_len.max(right_join_indices.len())];
                     let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
                         Ok(b) => b,
                         _ => (vec![], vec![]),
@@ -1341,7 +1798,9 @@ impl StoredRA {
                 let mut stack = vec![];
 
                 if !skip_range_check && !self.filters.is_empty() {
-                    let other_bindings = &self.bindings[right_join_indices.len()..];
+                    let key_len = self.storage.metadata.keys.len();
+                    let other_bindings = &self.bindings
+                        [right_join_indices.len()..key_len.max(right_join_indices.len())];
                     let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
                         Ok(b) => b,
                         _ => (vec![], vec![]),
@@ -1506,7 +1965,7 @@ impl StoredRA {
     }
 }
 
-fn join_is_prefix(right_join_indices: &[usize]) -> bool {
+pub(crate) fn join_is_prefix(right_join_indices: &[usize]) -> bool {
     // We do not consider partial index match to be "prefix", e.g. [a, u => c]
     // with a, c bound and u unbound is not "prefix", as it is not clear that
     // using prefix scanning in this case will really save us computation.
@@ -1516,7 +1975,7 @@ fn join_is_prefix(right_join_indices: &[usize]) -> bool {
     indices.into_iter().eq(0..l)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct TempStoreRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) storage_key: MagicSymbol,
@@ -1769,6 +2228,7 @@ impl TempStoreRA {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct Joiner {
     // invariant: these are of the same lengths
     pub(crate) left_keys: Vec<Symbol>,
@@ -1826,13 +2286,16 @@ impl RelAlgebra {
             RelAlgebra::Stored(_v) => Ok(()),
             RelAlgebra::StoredWithValidity(_v) => Ok(()),
             RelAlgebra::Join(r) => r.do_eliminate_temp_vars(used),
+            RelAlgebra::HashJoin(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::Reorder(r) => r.relation.eliminate_temp_vars(used),
             RelAlgebra::Filter(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::NegJoin(r) => r.do_eliminate_temp_vars(used),
+            RelAlgebra::LeftJoin(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::Unification(r) => r.do_eliminate_temp_vars(used),
             RelAlgebra::HnswSearch(_) => Ok(()),
             RelAlgebra::FtsSearch(_) => Ok(()),
             RelAlgebra::LshSearch(_) => Ok(()),
+            RelAlgebra::TableFunction(r) => r.do_eliminate_temp_vars(used),
         }
     }
 
@@ -1843,13 +2306,16 @@ impl RelAlgebra {
             RelAlgebra::Stored(_) => None,
             RelAlgebra::StoredWithValidity(_) => None,
             RelAlgebra::Join(r) => Some(&r.to_eliminate),
+            RelAlgebra::HashJoin(r) => Some(&r.to_eliminate),
             RelAlgebra::Reorder(_) => None,
             RelAlgebra::Filter(r) => Some(&r.to_eliminate),
             RelAlgebra::NegJoin(r) => Some(&r.to_eliminate),
+            RelAlgebra::LeftJoin(r) => Some(&r.to_eliminate),
             RelAlgebra::Unification(u) => Some(&u.to_eliminate),
             RelAlgebra::HnswSearch(_) => None,
             RelAlgebra::FtsSearch(_) => None,
             RelAlgebra::LshSearch(_) => None,
+            RelAlgebra::TableFunction(t) => Some(&t.to_eliminate),
         }
     }
 
@@ -1871,9 +2337,11 @@ impl RelAlgebra {
             RelAlgebra::Stored(v) => v.bindings.clone(),
             RelAlgebra::StoredWithValidity(v) => v.bindings.clone(),
             RelAlgebra::Join(j) => j.bindings(),
+            RelAlgebra::HashJoin(j) => j.bindings(),
             RelAlgebra::Reorder(r) => r.bindings(),
             RelAlgebra::Filter(r) => r.parent.bindings_after_eliminate(),
             RelAlgebra::NegJoin(j) => j.left.bindings_after_eliminate(),
+            RelAlgebra::LeftJoin(j) => j.bindings(),
             RelAlgebra::Unification(u) => {
                 let mut bindings = u.parent.bindings_after_eliminate();
                 bindings.push(u.binding.clone());
@@ -1894,6 +2362,11 @@ impl RelAlgebra {
                 bindings.extend_from_slice(&s.own_bindings);
                 bindings
             }
+            RelAlgebra::TableFunction(t) => {
+                let mut bindings = t.parent.bindings_after_eliminate();
+                bindings.extend_from_slice(&t.bindings);
+                bindings
+            }
         }
     }
     pub(crate) fn iter<'a>(
@@ -1908,18 +2381,21 @@ impl RelAlgebra {
             RelAlgebra::Stored(v) => v.iter(tx),
             RelAlgebra::StoredWithValidity(v) => v.iter(tx),
             RelAlgebra::Join(j) => j.iter(tx, delta_rule, stores),
+            RelAlgebra::HashJoin(j) => j.iter(tx, delta_rule, stores),
             RelAlgebra::Reorder(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::Filter(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::NegJoin(r) => r.iter(tx, delta_rule, stores),
+            RelAlgebra::LeftJoin(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::Unification(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::HnswSearch(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::FtsSearch(r) => r.iter(tx, delta_rule, stores),
             RelAlgebra::LshSearch(r) => r.iter(tx, delta_rule, stores),
+            RelAlgebra::TableFunction(r) => r.iter(tx, delta_rule, stores),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct NegJoin {
     pub(crate) left: RelAlgebra,
     pub(crate) right: RelAlgebra,
@@ -2024,7 +2500,7 @@ impl NegJoin {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct InnerJoin {
     pub(crate) left: RelAlgebra,
     pub(crate) right: RelAlgebra,
@@ -2111,9 +2587,12 @@ impl InnerJoin {
                     "stored_mat_join"
                 }
             }
-            RelAlgebra::Join(_) | RelAlgebra::Filter(_) | RelAlgebra::Unification(_) => {
-                "generic_mat_join"
-            }
+            RelAlgebra::Join(_)
+            | RelAlgebra::HashJoin(_)
+            | RelAlgebra::LeftJoin(_)
+            | RelAlgebra::Filter(_)
+            | RelAlgebra::Unification(_)
+            | RelAlgebra::TableFunction(_) => "generic_mat_join",
             RelAlgebra::Reorder(_) => {
                 panic!("joining on reordered")
             }
@@ -2204,11 +2683,14 @@ impl InnerJoin {
                 }
             }
             RelAlgebra::Join(_)
+            | RelAlgebra::HashJoin(_)
+            | RelAlgebra::LeftJoin(_)
             | RelAlgebra::Filter(_)
             | RelAlgebra::Unification(_)
             | RelAlgebra::HnswSearch(_)
             | RelAlgebra::FtsSearch(_)
-            | RelAlgebra::LshSearch(_) => {
+            | RelAlgebra::LshSearch(_)
+            | RelAlgebra::TableFunction(_) => {
                 self.materialized_join(tx, eliminate_indices, delta_rule, stores)
             }
             RelAlgebra::Reorder(_) => {
@@ -2373,9 +2855,389 @@ impl<'a> Iterator for CachedMaterializedIterator<'a> {
     }
 }
 
+/// Default for the number of build-side tuples a hash join keeps in memory before
+/// partitioning both sides into buckets that can spill to disk.
+pub(crate) const DEFAULT_HASH_JOIN_MEMORY_BUDGET: usize = 1 << 20;
+const HASH_JOIN_PARTITIONS: usize = 64;
+
+#[derive(Clone, Debug)]
+pub(crate) struct HashJoin {
+    pub(crate) left: RelAlgebra,
+    pub(crate) right: RelAlgebra,
+    pub(crate) joiner: Joiner,
+    /// build the hash table on the left side and probe with the right side
+    pub(crate) build_left: bool,
+    pub(crate) to_eliminate: BTreeSet<Symbol>,
+    pub(crate) span: SourceSpan,
+}
+
+impl HashJoin {
+    pub(crate) fn do_eliminate_temp_vars(&mut self, used: &BTreeSet<Symbol>) -> Result<()> {
+        for binding in self.bindings() {
+            if !used.contains(&binding) {
+                self.to_eliminate.insert(binding.clone());
+            }
+        }
+        let mut left = used.clone();
+        left.extend(self.joiner.left_keys.clone());
+        self.left.eliminate_temp_vars(&left)?;
+        let mut right = used.clone();
+        right.extend(self.joiner.right_keys.clone());
+        self.right.eliminate_temp_vars(&right)?;
+        Ok(())
+    }
+    pub(crate) fn bindings(&self) -> Vec<Symbol> {
+        let mut ret = self.left.bindings_after_eliminate();
+        ret.extend(self.right.bindings_after_eliminate());
+        debug_assert_eq!(ret.len(), ret.iter().collect::<BTreeSet<_>>().len());
+        ret
+    }
+    pub(crate) fn join_type(&self) -> &str {
+        if self.build_left {
+            "hash_join_build_left"
+        } else {
+            "hash_join"
+        }
+    }
+    pub(crate) fn iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'a>> {
+        debug!("using hash join");
+        let bindings = self.bindings();
+        let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
+        let (left_join_indices, right_join_indices) = self
+            .joiner
+            .join_indices(
+                &self.left.bindings_after_eliminate(),
+                &self.right.bindings_after_eliminate(),
+            )
+            .unwrap();
+        let left = self.left.iter(tx, delta_rule, stores)?;
+        let right = self.right.iter(tx, delta_rule, stores)?;
+        let joined = if self.build_left {
+            hash_join_tuples(
+                left,
+                left_join_indices,
+                right,
+                right_join_indices,
+                tx.hash_join_memory_budget,
+            )?
+        } else {
+            Box::new(
+                hash_join_tuples(
+                    right,
+                    right_join_indices,
+                    left,
+                    left_join_indices,
+                    tx.hash_join_memory_budget,
+                )?
+                .map_ok(|(r, l)| (l, r)),
+            )
+        };
+        Ok(Box::new(joined.map_ok(move |(mut l, r)| {
+            l.extend(r);
+            eliminate_from_tuple(l, &eliminate_indices)
+        })))
+    }
+}
+
+/// Left outer join used for `optional { ... }` atoms: the right side, which is the left side
+/// joined with the body of the atom, is built into a hash table, and left tuples without any
+/// match are padded with nulls. The join columns of the right side are not part of the output.
+#[derive(Clone, Debug)]
+pub(crate) struct LeftJoin {
+    pub(crate) left: RelAlgebra,
+    pub(crate) right: RelAlgebra,
+    pub(crate) joiner: Joiner,
+    pub(crate) to_eliminate: BTreeSet<Symbol>,
+    pub(crate) span: SourceSpan,
+}
+
+impl LeftJoin {
+    pub(crate) fn do_eliminate_temp_vars(&mut self, used: &BTreeSet<Symbol>) -> Result<()> {
+        for binding in self.bindings() {
+            if !used.contains(&binding) {
+                self.to_eliminate.insert(binding.clone());
+            }
+        }
+        let mut left = used.clone();
+        left.extend(self.joiner.left_keys.clone());
+        self.left.eliminate_temp_vars(&left)?;
+        // the right side is already reduced to the join columns and the bindings of the atom
+        Ok(())
+    }
+    /// Positions of the right side columns that are kept in the output
+    fn right_output_indices(&self) -> Vec<usize> {
+        self.right
+            .bindings_after_eliminate()
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| !self.joiner.right_keys.contains(b))
+            .map(|(i, _)| i)
+            .collect_vec()
+    }
+    pub(crate) fn bindings(&self) -> Vec<Symbol> {
+        let mut ret = self.left.bindings_after_eliminate();
+        let right = self.right.bindings_after_eliminate();
+        ret.extend(
+            self.right_output_indices()
+                .into_iter()
+                .map(|i| right[i].clone()),
+        );
+        debug_assert_eq!(ret.len(), ret.iter().collect::<BTreeSet<_>>().len());
+        ret
+    }
+    pub(crate) fn join_type(&self) -> &str {
+        "left_join"
+    }
+    pub(crate) fn iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'a>> {
+        debug!("using left join");
+        let bindings = self.bindings();
+        let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
+        let (left_join_indices, right_join_indices) = self
+            .joiner
+            .join_indices(
+                &self.left.bindings_after_eliminate(),
+                &self.right.bindings_after_eliminate(),
+            )
+            .unwrap();
+        let right_output_indices = self.right_output_indices();
+        let left = self.left.iter(tx, delta_rule, stores)?;
+        let right = self.right.iter(tx, delta_rule, stores)?;
+        let joined = hash_outer_join_tuples(
+            right,
+            right_join_indices,
+            left,
+            left_join_indices,
+            tx.hash_join_memory_budget,
+        )?;
+        Ok(Box::new(joined.map_ok(move |(r, mut l)| {
+            match r {
+                Some(r) => l.extend(right_output_indices.iter().map(|i| r[*i].clone())),
+                None => l.extend(right_output_indices.iter().map(|_| DataValue::Null)),
+            }
+            eliminate_from_tuple(l, &eliminate_indices)
+        })))
+    }
+}
+
+type TuplePairIter<'a> = Box<dyn Iterator<Item = Result<(Tuple, Tuple)>> + 'a>;
+type OuterTuplePairIter<'a> = Box<dyn Iterator<Item = Result<(Option<Tuple>, Tuple)>> + 'a>;
+
+/// Joins `build` and `probe` on the given key columns, yielding `(build, probe)` pairs.
+/// If `build` has more than `in_memory_limit` tuples, both sides are partitioned by
+/// the hash of the join key into spillable buckets, which are then joined one by one.
+fn hash_join_tuples<'a>(
+    build: TupleIter<'a>,
+    build_keys: Vec<usize>,
+    probe: TupleIter<'a>,
+    probe_keys: Vec<usize>,
+    in_memory_limit: usize,
+) -> Result<TuplePairIter<'a>> {
+    partitioned_hash_join(
+        build,
+        build_keys,
+        probe,
+        probe_keys,
+        in_memory_limit,
+        false,
+        probe_hash_table,
+    )
+}
+
+/// Same as `hash_join_tuples`, but probe tuples without any match are kept
+/// and paired with `None`, and identical build tuples are only kept once.
+fn hash_outer_join_tuples<'a>(
+    build: TupleIter<'a>,
+    build_keys: Vec<usize>,
+    probe: TupleIter<'a>,
+    probe_keys: Vec<usize>,
+    in_memory_limit: usize,
+) -> Result<OuterTuplePairIter<'a>> {
+    partitioned_hash_join(
+        build,
+        build_keys,
+        probe,
+        probe_keys,
+        in_memory_limit,
+        true,
+        probe_hash_table_outer,
+    )
+}
+
+#[allow(clippy::mutable_key_type)]
+fn partitioned_hash_join<'a, T: 'a, I: Iterator<Item = Result<T>> + 'a>(
+    build: TupleIter<'a>,
+    build_keys: Vec<usize>,
+    probe: TupleIter<'a>,
+    probe_keys: Vec<usize>,
+    in_memory_limit: usize,
+    dedup_build: bool,
+    probe_table: fn(FxHashMap<Tuple, Vec<Tuple>>, TupleIter<'a>, Vec<usize>) -> I,
+) -> Result<Box<dyn Iterator<Item = Result<T>> + 'a>> {
+    let mut in_memory = vec![];
+    let mut build = build;
+    for tuple in build.by_ref() {
+        in_memory.push(tuple?);
+        if in_memory.len() > in_memory_limit {
+            break;
+        }
+    }
+    if in_memory.len() <= in_memory_limit {
+        let table = build_hash_table(in_memory, &build_keys, dedup_build);
+        return Ok(Box::new(probe_table(table, probe, probe_keys)));
+    }
+
+    let mut build_parts: Vec<TempCollector<Tuple>> = (0..HASH_JOIN_PARTITIONS)
+        .map(|_| Default::default())
+        .collect_vec();
+    for tuple in in_memory.into_iter().map(Ok).chain(build) {
+        let tuple = tuple?;
+        build_parts[join_key_partition(&tuple, &build_keys)].push(tuple);
+    }
+    let mut probe_parts: Vec<TempCollector<Tuple>> = (0..HASH_JOIN_PARTITIONS)
+        .map(|_| Default::default())
+        .collect_vec();
+    for tuple in probe {
+        let tuple = tuple?;
+        probe_parts[join_key_partition(&tuple, &probe_keys)].push(tuple);
+    }
+    Ok(Box::new(build_parts.into_iter().zip(probe_parts).flat_map(
+        move |(build_part, probe_part)| {
+            let table = build_hash_table(build_part.into_iter(), &build_keys, dedup_build);
+            probe_table(
+                table,
+                Box::new(probe_part.into_iter().map(Ok)),
+                probe_keys.clone(),
+            )
+        },
+    )))
+}
+
+#[allow(clippy::mutable_key_type)]
+fn build_hash_table(
+    tuples: impl IntoIterator<Item = Tuple>,
+    key_indices: &[usize],
+    dedup: bool,
+) -> FxHashMap<Tuple, Vec<Tuple>> {
+    let mut table: FxHashMap<Tuple, Vec<Tuple>> = FxHashMap::default();
+    for tuple in tuples {
+        let key = key_indices.iter().map(|i| tuple[*i].clone()).collect_vec();
+        table.entry(key).or_default().push(tuple);
+    }
+    if dedup {
+        for found in table.values_mut() {
+            found.sort();
+            found.dedup();
+        }
+    }
+    table
+}
+
+#[allow(clippy::mutable_key_type)]
+fn probe_hash_table<'a>(
+    table: FxHashMap<Tuple, Vec<Tuple>>,
+    probe: TupleIter<'a>,
+    key_indices: Vec<usize>,
+) -> impl Iterator<Item = Result<(Tuple, Tuple)>> + 'a {
+    HashProbe::new(table, probe, key_indices, false).filter_map_ok(|(b, p)| Some((b?, p)))
+}
+
+#[allow(clippy::mutable_key_type)]
+fn probe_hash_table_outer<'a>(
+    table: FxHashMap<Tuple, Vec<Tuple>>,
+    probe: TupleIter<'a>,
+    key_indices: Vec<usize>,
+) -> impl Iterator<Item = Result<(Option<Tuple>, Tuple)>> + 'a {
+    HashProbe::new(table, probe, key_indices, true)
+}
+
+/// Looks up each probe tuple in the hash table, yielding `(build, probe)` pairs.
+/// Probe tuples without any match are paired with `None` if `keep_unmatched`.
+/// The join key of the probe tuples is collected into one reused buffer.
+struct HashProbe<'a> {
+    table: FxHashMap<Tuple, Vec<Tuple>>,
+    probe: TupleIter<'a>,
+    key_indices: Vec<usize>,
+    keep_unmatched: bool,
+    key: Tuple,
+    /// the probe tuple being matched, and the position of its next match
+    current: Option<(Tuple, usize)>,
+}
+
+impl<'a> HashProbe<'a> {
+    #[allow(clippy::mutable_key_type)]
+    fn new(
+        table: FxHashMap<Tuple, Vec<Tuple>>,
+        probe: TupleIter<'a>,
+        key_indices: Vec<usize>,
+        keep_unmatched: bool,
+    ) -> Self {
+        Self {
+            table,
+            probe,
+            key: Vec::with_capacity(key_indices.len()),
+            key_indices,
+            keep_unmatched,
+            current: None,
+        }
+    }
+}
+
+impl Iterator for HashProbe<'_> {
+    type Item = Result<(Option<Tuple>, Tuple)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((tuple, pos)) = &mut self.current {
+                let found = &self.table[&self.key];
+                let matched = found[*pos].clone();
+                *pos += 1;
+                return if *pos == found.len() {
+                    let (tuple, _) = self.current.take().unwrap();
+                    Some(Ok((Some(matched), tuple)))
+                } else {
+                    Some(Ok((Some(matched), tuple.clone())))
+                };
+            }
+            let tuple = match self.probe.next()? {
+                Ok(tuple) => tuple,
+                Err(e) => return Some(Err(e)),
+            };
+            self.key.clear();
+            self.key
+                .extend(self.key_indices.iter().map(|i| tuple[*i].clone()));
+            if self.table.contains_key(&self.key) {
+                self.current = Some((tuple, 0));
+            } else if self.keep_unmatched {
+                return Some(Ok((None, tuple)));
+            }
+        }
+    }
+}
+
+fn join_key_partition(tuple: &Tuple, key_indices: &[usize]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    for i in key_indices {
+        tuple[*i].hash(&mut hasher);
+    }
+    (hasher.finish() % HASH_JOIN_PARTITIONS as u64) as usize
+}
+
 #[cfg(test)]
 mod tests {
+    use itertools::Itertools;
+
     use crate::data::value::DataValue;
+    use crate::query::ra::hash_join_tuples;
     use crate::DbInstance;
 
     #[test]
@@ -2395,4 +3257,116 @@ mod tests {
             vec![vec![DataValue::from(1)], vec![DataValue::from(2)]]
         )
     }
+
+    #[test]
+    fn test_hash_join() {
+        let db = DbInstance::default();
+        let res = db
+            .run_default(
+                r#"
+        data[a, b] <- [[1, 2], [1, 3], [2, 3]]
+        other[c, d] <- [[2, 'x'], [3, 'y'], [4, 'z']]
+        ?[a, d] := other[c, d], data[a, c]
+        "#,
+            )
+            .unwrap()
+            .into_json();
+        assert_eq!(
+            res["rows"],
+            serde_json::json!([[1, "x"], [1, "y"], [2, "y"]])
+        );
+        let expl = db
+            .run_default(
+                r#"
+        ::explain {
+            data[a, b] <- [[1, 2], [1, 3], [2, 3]]
+            other[c, d] <- [[2, 'x'], [3, 'y'], [4, 'z']]
+            ?[a, d] := other[c, d], data[a, c]
+        }
+        "#,
+            )
+            .unwrap();
+        // derived relations have no estimates, so they keep the materialized join
+        assert!(expl
+            .rows
+            .iter()
+            .any(|row| row[4] == DataValue::from("mem_mat_join")));
+        assert!(!expl
+            .rows
+            .iter()
+            .any(|row| row[4].get_str().is_some_and(|s| s.starts_with("hash_join"))));
+    }
+
+    #[test]
+    fn test_partitioned_hash_join() {
+        let build = (0..100).map(|i| Ok(vec![DataValue::from(i % 10), DataValue::from(i)]));
+        let probe = (0..20).map(|i| Ok(vec![DataValue::from(i)]));
+        let joined: Vec<_> =
+            hash_join_tuples(Box::new(build), vec![0], Box::new(probe), vec![0], 2)
+                .unwrap()
+                .try_collect()
+                .unwrap();
+        assert_eq!(joined.len(), 100);
+        assert!(joined.iter().all(|(b, p)| b[0] == p[0]));
+    }
+
+    #[test]
+    fn test_hash_join_memory_budget() {
+        let db = DbInstance::default();
+        db.run_default(r"?[a, b] <- [[1, 2], [1, 3], [2, 3], [3, 5]] :create data {a, b}")
+            .unwrap();
+        db.run_default(
+            r"?[c, d] <- [[2, 'x'], [3, 'y'], [4, 'z'], [5, 'w']] :create other {c => d}",
+        )
+        .unwrap();
+        db.run_default("::analyze data").unwrap();
+        db.run_default("::analyze other").unwrap();
+        let query = "?[a, d] := *other{c, d}, *data{a, b: c}";
+        let join_type = |db: &DbInstance| {
+            db.run_default(&format!("::explain {{ {query} }}"))
+                .unwrap()
+                .rows
+                .into_iter()
+                .find(|row| row[4].get_str().is_some_and(|s| s.contains("join")))
+                .unwrap()[4]
+                .clone()
+        };
+        let expected = db.run_default(query).unwrap().rows;
+        assert_eq!(expected.len(), 4);
+        assert_eq!(join_type(&db), DataValue::from("stored_mat_join"));
+        // the right side no longer fits in memory, so it is hashed in partitions
+        db.set_hash_join_memory_budget(1);
+        assert_eq!(join_type(&db), DataValue::from("hash_join"));
+        assert_eq!(db.run_default(query).unwrap().rows, expected);
+    }
+
+    #[test]
+    fn test_hash_join_build_side() {
+        let db = DbInstance::default();
+        db.run_default(r"?[a, b] <- [[1, 2], [2, 3]] :create small {a => b}")
+            .unwrap();
+        db.run_default(r"?[x, y] <- [[1, 2], [1, 3], [2, 3]] :create big {x, y}")
+            .unwrap();
+        let query = "?[a, x] := *small{a, b}, *big{x, y: b}";
+        let join_type = |db: &DbInstance| {
+            db.run_default(&format!("::explain {{ {query} }}"))
+                .unwrap()
+                .rows
+                .into_iter()
+                .find(|row| row[4].get_str().is_some_and(|s| s.contains("join")))
+                .unwrap()[4]
+                .clone()
+        };
+        let expected = db.run_default(query).unwrap().into_json();
+        assert_eq!(
+            expected["rows"],
+            serde_json::json!([[1, 1], [2, 1], [2, 2]])
+        );
+        assert_eq!(join_type(&db), DataValue::from("stored_mat_join"));
+        db.run_default("::analyze small").unwrap();
+        assert_eq!(join_type(&db), DataValue::from("stored_mat_join"));
+        db.run_default("::analyze big").unwrap();
+        assert_eq!(join_type(&db), DataValue::from("hash_join_build_left"));
+        assert_eq!(db.run_default(query).unwrap().into_json(), expected);
+    }
 }
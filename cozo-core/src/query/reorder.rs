@@ -13,6 +13,7 @@ use miette::{bail, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::program::{NormalFormAtom, NormalFormInlineRule};
+use crate::data::symb::Symbol;
 use crate::parse::SourceSpan;
 
 #[derive(Diagnostic, Debug, Error)]
@@ -32,211 +33,291 @@ pub(crate) struct UnboundVariable(#[label] pub(crate) SourceSpan);
 
 impl NormalFormInlineRule {
     pub(crate) fn convert_to_well_ordered_rule(self) -> Result<Self> {
-        let mut seen_variables = BTreeSet::default();
-        let mut round_1_collected = vec![];
-        let mut pending = vec![];
+        Ok(NormalFormInlineRule {
+            head: self.head,
+            aggr: self.aggr,
+            body: well_order_body(self.body, &BTreeSet::new())?,
+        })
+    }
+}
 
-        // first round: collect all unifications that are completely bounded
-        for atom in self.body {
-            match atom {
-                NormalFormAtom::Unification(u) => {
-                    if u.is_const() {
+/// Orders the atoms of `body` so that each atom comes after the atoms binding the variables
+/// it needs. The variables in `bound` are already bound by an enclosing body.
+fn well_order_body(
+    body: Vec<NormalFormAtom>,
+    bound: &BTreeSet<Symbol>,
+) -> Result<Vec<NormalFormAtom>> {
+    let mut seen_variables = bound.clone();
+    let mut round_1_collected = vec![];
+    let mut pending = vec![];
+    let mut optionals = vec![];
+
+    // first round: collect all unifications that are completely bounded
+    for atom in body {
+        match atom {
+            NormalFormAtom::Unification(u) => {
+                if u.is_const() {
+                    seen_variables.insert(u.binding.clone());
+                    round_1_collected.push(NormalFormAtom::Unification(u));
+                } else {
+                    let unif_vars = u.bindings_in_expr()?;
+                    if unif_vars.is_subset(&seen_variables) {
                         seen_variables.insert(u.binding.clone());
                         round_1_collected.push(NormalFormAtom::Unification(u));
                     } else {
-                        let unif_vars = u.bindings_in_expr()?;
-                        if unif_vars.is_subset(&seen_variables) {
-                            seen_variables.insert(u.binding.clone());
-                            round_1_collected.push(NormalFormAtom::Unification(u));
-                        } else {
-                            pending.push(NormalFormAtom::Unification(u));
-                        }
+                        pending.push(NormalFormAtom::Unification(u));
                     }
                 }
-                NormalFormAtom::Rule(mut r) => {
-                    for arg in &mut r.args {
-                        seen_variables.insert(arg.clone());
-                    }
-                    round_1_collected.push(NormalFormAtom::Rule(r))
+            }
+            NormalFormAtom::Rule(mut r) => {
+                for arg in &mut r.args {
+                    seen_variables.insert(arg.clone());
+                }
+                round_1_collected.push(NormalFormAtom::Rule(r))
+            }
+            NormalFormAtom::Relation(v) => {
+                for arg in &v.args {
+                    seen_variables.insert(arg.clone());
+                }
+                round_1_collected.push(NormalFormAtom::Relation(v))
+            }
+            NormalFormAtom::NegatedRule(r) => pending.push(NormalFormAtom::NegatedRule(r)),
+            NormalFormAtom::NegatedRelation(v) => pending.push(NormalFormAtom::NegatedRelation(v)),
+            NormalFormAtom::Predicate(p) => {
+                pending.push(NormalFormAtom::Predicate(p));
+            }
+            NormalFormAtom::HnswSearch(s) => {
+                if seen_variables.contains(&s.query) {
+                    seen_variables.extend(s.all_bindings().cloned());
+                    round_1_collected.push(NormalFormAtom::HnswSearch(s));
+                } else {
+                    pending.push(NormalFormAtom::HnswSearch(s));
+                }
+            }
+            NormalFormAtom::FtsSearch(s) => {
+                if seen_variables.contains(&s.query) {
+                    seen_variables.extend(s.all_bindings().cloned());
+                    round_1_collected.push(NormalFormAtom::FtsSearch(s));
+                } else {
+                    pending.push(NormalFormAtom::FtsSearch(s));
+                }
+            }
+            NormalFormAtom::LshSearch(s) => {
+                if seen_variables.contains(&s.query) {
+                    seen_variables.extend(s.all_bindings().cloned());
+                    round_1_collected.push(NormalFormAtom::LshSearch(s));
+                } else {
+                    pending.push(NormalFormAtom::LshSearch(s));
                 }
-                NormalFormAtom::Relation(v) => {
-                    for arg in &v.args {
-                        seen_variables.insert(arg.clone());
+            }
+            NormalFormAtom::TableFunction(t) => {
+                if t.bindings_in_args()?.is_subset(&seen_variables) {
+                    seen_variables.extend(t.bindings.iter().cloned());
+                    round_1_collected.push(NormalFormAtom::TableFunction(t));
+                } else {
+                    pending.push(NormalFormAtom::TableFunction(t));
+                }
+            }
+            NormalFormAtom::Optional(o) => optionals.push(o),
+        }
+    }
+
+    let mut collected = vec![];
+    seen_variables = bound.clone();
+    let mut last_pending = vec![];
+    // second round: insert pending where possible
+    for atom in round_1_collected {
+        mem::swap(&mut last_pending, &mut pending);
+        pending.clear();
+        match atom {
+            NormalFormAtom::Rule(r) => {
+                seen_variables.extend(r.args.iter().cloned());
+                collected.push(NormalFormAtom::Rule(r))
+            }
+            NormalFormAtom::Relation(v) => {
+                seen_variables.extend(v.args.iter().cloned());
+                collected.push(NormalFormAtom::Relation(v))
+            }
+            NormalFormAtom::NegatedRule(_)
+            | NormalFormAtom::NegatedRelation(_)
+            | NormalFormAtom::Predicate(_)
+            | NormalFormAtom::Optional(_) => {
+                unreachable!()
+            }
+            NormalFormAtom::Unification(u) => {
+                seen_variables.insert(u.binding.clone());
+                collected.push(NormalFormAtom::Unification(u));
+            }
+            NormalFormAtom::HnswSearch(s) => {
+                seen_variables.extend(s.all_bindings().cloned());
+                collected.push(NormalFormAtom::HnswSearch(s));
+            }
+            NormalFormAtom::FtsSearch(s) => {
+                seen_variables.extend(s.all_bindings().cloned());
+                collected.push(NormalFormAtom::FtsSearch(s));
+            }
+            NormalFormAtom::LshSearch(s) => {
+                seen_variables.extend(s.all_bindings().cloned());
+                collected.push(NormalFormAtom::LshSearch(s));
+            }
+            NormalFormAtom::TableFunction(t) => {
+                seen_variables.extend(t.bindings.iter().cloned());
+                collected.push(NormalFormAtom::TableFunction(t));
+            }
+        }
+        for atom in last_pending.iter() {
+            match atom {
+                NormalFormAtom::Rule(_)
+                | NormalFormAtom::Relation(_)
+                | NormalFormAtom::Optional(_) => unreachable!(),
+                NormalFormAtom::NegatedRule(r) => {
+                    if r.args.iter().all(|a| seen_variables.contains(a)) {
+                        collected.push(NormalFormAtom::NegatedRule(r.clone()));
+                    } else {
+                        pending.push(NormalFormAtom::NegatedRule(r.clone()));
                     }
-                    round_1_collected.push(NormalFormAtom::Relation(v))
                 }
-                NormalFormAtom::NegatedRule(r) => pending.push(NormalFormAtom::NegatedRule(r)),
                 NormalFormAtom::NegatedRelation(v) => {
-                    pending.push(NormalFormAtom::NegatedRelation(v))
-                }
-                NormalFormAtom::Predicate(p) => {
-                    pending.push(NormalFormAtom::Predicate(p));
+                    if v.args.iter().all(|a| seen_variables.contains(a)) {
+                        collected.push(NormalFormAtom::NegatedRelation(v.clone()));
+                    } else {
+                        pending.push(NormalFormAtom::NegatedRelation(v.clone()));
+                    }
                 }
                 NormalFormAtom::HnswSearch(s) => {
                     if seen_variables.contains(&s.query) {
                         seen_variables.extend(s.all_bindings().cloned());
-                        round_1_collected.push(NormalFormAtom::HnswSearch(s));
+                        collected.push(NormalFormAtom::HnswSearch(s.clone()));
                     } else {
-                        pending.push(NormalFormAtom::HnswSearch(s));
+                        pending.push(NormalFormAtom::HnswSearch(s.clone()));
                     }
                 }
                 NormalFormAtom::FtsSearch(s) => {
                     if seen_variables.contains(&s.query) {
                         seen_variables.extend(s.all_bindings().cloned());
-                        round_1_collected.push(NormalFormAtom::FtsSearch(s));
+                        collected.push(NormalFormAtom::FtsSearch(s.clone()));
                     } else {
-                        pending.push(NormalFormAtom::FtsSearch(s));
+                        pending.push(NormalFormAtom::FtsSearch(s.clone()));
                     }
                 }
                 NormalFormAtom::LshSearch(s) => {
                     if seen_variables.contains(&s.query) {
                         seen_variables.extend(s.all_bindings().cloned());
-                        round_1_collected.push(NormalFormAtom::LshSearch(s));
+                        collected.push(NormalFormAtom::LshSearch(s.clone()));
+                    } else {
+                        pending.push(NormalFormAtom::LshSearch(s.clone()));
+                    }
+                }
+                NormalFormAtom::Predicate(p) => {
+                    if p.bindings()?.is_subset(&seen_variables) {
+                        collected.push(NormalFormAtom::Predicate(p.clone()));
+                    } else {
+                        pending.push(NormalFormAtom::Predicate(p.clone()));
+                    }
+                }
+                NormalFormAtom::Unification(u) => {
+                    if u.bindings_in_expr()?.is_subset(&seen_variables) {
+                        seen_variables.insert(u.binding.clone());
+                        collected.push(NormalFormAtom::Unification(u.clone()));
                     } else {
-                        pending.push(NormalFormAtom::LshSearch(s));
+                        pending.push(NormalFormAtom::Unification(u.clone()));
+                    }
+                }
+                NormalFormAtom::TableFunction(t) => {
+                    if t.bindings_in_args()?.is_subset(&seen_variables) {
+                        seen_variables.extend(t.bindings.iter().cloned());
+                        collected.push(NormalFormAtom::TableFunction(t.clone()));
+                    } else {
+                        pending.push(NormalFormAtom::TableFunction(t.clone()));
                     }
                 }
             }
         }
+    }
+
+    place_resolved(&mut pending, &mut seen_variables, &mut collected)?;
+
+    // optional atoms are left-joined after all the mandatory atoms, and their bodies
+    // are ordered knowing the variables bound so far, which they may use
+    for mut o in optionals {
+        o.body = well_order_body(o.body, &seen_variables)?;
+        seen_variables.extend(o.bindings());
+        collected.push(NormalFormAtom::Optional(o));
+        place_resolved(&mut pending, &mut seen_variables, &mut collected)?;
+    }
 
-        let mut collected = vec![];
-        seen_variables.clear();
-        let mut last_pending = vec![];
-        // second round: insert pending where possible
-        for atom in round_1_collected {
-            mem::swap(&mut last_pending, &mut pending);
-            pending.clear();
+    if !pending.is_empty() {
+        for atom in pending {
             match atom {
-                NormalFormAtom::Rule(r) => {
-                    seen_variables.extend(r.args.iter().cloned());
-                    collected.push(NormalFormAtom::Rule(r))
+                NormalFormAtom::Rule(_)
+                | NormalFormAtom::Relation(_)
+                | NormalFormAtom::Optional(_) => unreachable!(),
+                NormalFormAtom::NegatedRule(r) => {
+                    if r.args.iter().any(|a| seen_variables.contains(a)) {
+                        collected.push(NormalFormAtom::NegatedRule(r.clone()));
+                    } else {
+                        bail!(UnsafeNegation(r.span));
+                    }
                 }
-                NormalFormAtom::Relation(v) => {
-                    seen_variables.extend(v.args.iter().cloned());
-                    collected.push(NormalFormAtom::Relation(v))
+                NormalFormAtom::NegatedRelation(v) => {
+                    if v.args.iter().any(|a| seen_variables.contains(a)) {
+                        collected.push(NormalFormAtom::NegatedRelation(v.clone()));
+                    } else {
+                        bail!(UnsafeNegation(v.span));
+                    }
                 }
-                NormalFormAtom::NegatedRule(_)
-                | NormalFormAtom::NegatedRelation(_)
-                | NormalFormAtom::Predicate(_) => {
-                    unreachable!()
+                NormalFormAtom::Predicate(p) => {
+                    bail!(UnboundVariable(p.span()))
                 }
                 NormalFormAtom::Unification(u) => {
-                    seen_variables.insert(u.binding.clone());
-                    collected.push(NormalFormAtom::Unification(u));
+                    bail!(UnboundVariable(u.span))
                 }
                 NormalFormAtom::HnswSearch(s) => {
-                    seen_variables.extend(s.all_bindings().cloned());
-                    collected.push(NormalFormAtom::HnswSearch(s));
+                    bail!(UnboundVariable(s.span))
                 }
                 NormalFormAtom::FtsSearch(s) => {
-                    seen_variables.extend(s.all_bindings().cloned());
-                    collected.push(NormalFormAtom::FtsSearch(s));
+                    bail!(UnboundVariable(s.span))
                 }
                 NormalFormAtom::LshSearch(s) => {
-                    seen_variables.extend(s.all_bindings().cloned());
-                    collected.push(NormalFormAtom::LshSearch(s));
-                }
-            }
-            for atom in last_pending.iter() {
-                match atom {
-                    NormalFormAtom::Rule(_) | NormalFormAtom::Relation(_) => unreachable!(),
-                    NormalFormAtom::NegatedRule(r) => {
-                        if r.args.iter().all(|a| seen_variables.contains(a)) {
-                            collected.push(NormalFormAtom::NegatedRule(r.clone()));
-                        } else {
-                            pending.push(NormalFormAtom::NegatedRule(r.clone()));
-                        }
-                    }
-                    NormalFormAtom::NegatedRelation(v) => {
-                        if v.args.iter().all(|a| seen_variables.contains(a)) {
-                            collected.push(NormalFormAtom::NegatedRelation(v.clone()));
-                        } else {
-                            pending.push(NormalFormAtom::NegatedRelation(v.clone()));
-                        }
-                    }
-                    NormalFormAtom::HnswSearch(s) => {
-                        if seen_variables.contains(&s.query) {
-                            seen_variables.extend(s.all_bindings().cloned());
-                            collected.push(NormalFormAtom::HnswSearch(s.clone()));
-                        } else {
-                            pending.push(NormalFormAtom::HnswSearch(s.clone()));
-                        }
-                    }
-                    NormalFormAtom::FtsSearch(s) => {
-                        if seen_variables.contains(&s.query) {
-                            seen_variables.extend(s.all_bindings().cloned());
-                            collected.push(NormalFormAtom::FtsSearch(s.clone()));
-                        } else {
-                            pending.push(NormalFormAtom::FtsSearch(s.clone()));
-                        }
-                    }
-                    NormalFormAtom::LshSearch(s) => {
-                        if seen_variables.contains(&s.query) {
-                            seen_variables.extend(s.all_bindings().cloned());
-                            collected.push(NormalFormAtom::LshSearch(s.clone()));
-                        } else {
-                            pending.push(NormalFormAtom::LshSearch(s.clone()));
-                        }
-                    }
-                    NormalFormAtom::Predicate(p) => {
-                        if p.bindings()?.is_subset(&seen_variables) {
-                            collected.push(NormalFormAtom::Predicate(p.clone()));
-                        } else {
-                            pending.push(NormalFormAtom::Predicate(p.clone()));
-                        }
-                    }
-                    NormalFormAtom::Unification(u) => {
-                        if u.bindings_in_expr()?.is_subset(&seen_variables) {
-                            collected.push(NormalFormAtom::Unification(u.clone()));
-                        } else {
-                            pending.push(NormalFormAtom::Unification(u.clone()));
-                        }
-                    }
+                    bail!(UnboundVariable(s.span))
+                }
+                NormalFormAtom::TableFunction(t) => {
+                    bail!(UnboundVariable(t.span))
                 }
             }
         }
+    }
 
-        if !pending.is_empty() {
-            for atom in pending {
-                match atom {
-                    NormalFormAtom::Rule(_) | NormalFormAtom::Relation(_) => unreachable!(),
-                    NormalFormAtom::NegatedRule(r) => {
-                        if r.args.iter().any(|a| seen_variables.contains(a)) {
-                            collected.push(NormalFormAtom::NegatedRule(r.clone()));
-                        } else {
-                            bail!(UnsafeNegation(r.span));
-                        }
-                    }
-                    NormalFormAtom::NegatedRelation(v) => {
-                        if v.args.iter().any(|a| seen_variables.contains(a)) {
-                            collected.push(NormalFormAtom::NegatedRelation(v.clone()));
-                        } else {
-                            bail!(UnsafeNegation(v.span));
-                        }
-                    }
-                    NormalFormAtom::Predicate(p) => {
-                        bail!(UnboundVariable(p.span()))
-                    }
-                    NormalFormAtom::Unification(u) => {
-                        bail!(UnboundVariable(u.span))
-                    }
-                    NormalFormAtom::HnswSearch(s) => {
-                        bail!(UnboundVariable(s.span))
-                    }
-                    NormalFormAtom::FtsSearch(s) => {
-                        bail!(UnboundVariable(s.span))
-                    }
-                    NormalFormAtom::LshSearch(s) => {
-                        bail!(UnboundVariable(s.span))
-                    }
+    Ok(collected)
+}
+
+/// Places the pending unifications and predicates whose variables are all bound.
+/// Unifications may depend on each other, so they are resolved until stuck.
+fn place_resolved(
+    pending: &mut Vec<NormalFormAtom>,
+    seen_variables: &mut BTreeSet<Symbol>,
+    collected: &mut Vec<NormalFormAtom>,
+) -> Result<()> {
+    loop {
+        let mut progressed = false;
+        for atom in mem::take(pending) {
+            let ready = match &atom {
+                NormalFormAtom::Unification(u) => u.bindings_in_expr()?.is_subset(seen_variables),
+                NormalFormAtom::Predicate(p) => p.bindings()?.is_subset(seen_variables),
+                _ => false,
+            };
+            if ready {
+                if let NormalFormAtom::Unification(u) = &atom {
+                    seen_variables.insert(u.binding.clone());
                 }
+                collected.push(atom);
+                progressed = true;
+            } else {
+                pending.push(atom);
             }
         }
-
-        Ok(NormalFormInlineRule {
-            head: self.head,
-            aggr: self.aggr,
-            body: collected,
-        })
+        if !progressed {
+            return Ok(());
+        }
     }
 }
@@ -6,8 +6,9 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::sync::Arc;
 
 use itertools::Itertools;
 use miette::Result;
@@ -17,36 +18,202 @@ use crate::data::symb::Symbol;
 use crate::data::tuple::Tuple;
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
+use crate::utils::TempCollector;
+
+/// Default for the maximum number of rows a sort holds in memory before spilling to disk.
+pub(crate) const DEFAULT_SORT_MEMORY_BUDGET: usize = 1 << 20;
+/// Number of rows written to or read from disk at once for each spilled run.
+const SORT_SPILL_BATCH_SIZE: usize = 1024;
 
 impl<'a> SessionTx<'a> {
+    /// Sort the results in `original` according to `sorters`.
+    ///
+    /// If `num_to_keep` is given, only that many rows from the start of the sorted
+    /// results are kept, using a bounded heap. Otherwise, when there are more rows
+    /// than `memory_budget`, sorted runs are spilled to disk and merged lazily.
+    /// If `after` is given, only rows sorting strictly after it are kept.
+    ///
+    /// Rows are moved out of `original` as they are read, so the memory of the rows
+    /// already spilled is released before the next run is sorted.
     pub(crate) fn sort_and_collect(
         &mut self,
         original: EpochStore,
         sorters: &[(Symbol, SortDir)],
         head: &[Symbol],
-    ) -> Result<Vec<Tuple>> {
+        num_to_keep: Option<usize>,
+        after: Option<&Tuple>,
+        memory_budget: usize,
+    ) -> Result<Box<dyn Iterator<Item = Tuple>>> {
         let head_indices: BTreeMap<_, _> = head.iter().enumerate().map(|(i, k)| (k, i)).collect();
-        let idx_sorters = sorters
+        let idx_sorters: Arc<[(usize, SortDir)]> = sorters
             .iter()
             .map(|(k, dir)| (head_indices[k], *dir))
-            .collect_vec();
-
-        let mut all_data: Vec<_> = original.all_iter().map(|v| v.into_tuple()).collect_vec();
-        all_data.sort_by(|a, b| {
-            for (idx, dir) in &idx_sorters {
-                match a[*idx].cmp(&b[*idx]) {
-                    Ordering::Equal => {}
-                    o => {
-                        return match dir {
-                            SortDir::Asc => o,
-                            SortDir::Dsc => o.reverse(),
-                        }
-                    }
+            .collect_vec()
+            .into();
+        let memory_budget = memory_budget.max(1);
+        let filter_sorters = idx_sorters.clone();
+        let rows = original.into_all_iter().filter(move |t| match after {
+            None => true,
+            Some(after) => compare_tuples(t, after, &filter_sorters) == Ordering::Greater,
+        });
+
+        if let Some(k) = num_to_keep {
+            if k <= memory_budget {
+                let top = top_k(rows, idx_sorters, k);
+                return Ok(Box::new(top.into_iter()));
+            }
+        }
+
+        let mut runs: Vec<TempCollector<Tuple>> = vec![];
+        let mut all_data = vec![];
+        for tuple in rows {
+            all_data.push(tuple);
+            if all_data.len() >= memory_budget {
+                sort_tuples(&mut all_data, &idx_sorters);
+                let mut run = TempCollector::spilling(SORT_SPILL_BATCH_SIZE.min(memory_budget));
+                for tuple in all_data.drain(..) {
+                    run.push(tuple);
                 }
+                runs.push(run);
             }
-            Ordering::Equal
-        });
+        }
+        sort_tuples(&mut all_data, &idx_sorters);
+        if runs.is_empty() {
+            return Ok(Box::new(all_data.into_iter()));
+        }
+
+        let mut sources: Vec<Box<dyn Iterator<Item = Tuple>>> = runs
+            .into_iter()
+            .map(|run| -> Box<dyn Iterator<Item = Tuple>> { Box::new(run.into_iter()) })
+            .collect();
+        if !all_data.is_empty() {
+            sources.push(Box::new(all_data.into_iter()));
+        }
+        Ok(Box::new(MergeSorted::new(sources, idx_sorters)))
+    }
+}
+
+fn compare_tuples(a: &Tuple, b: &Tuple, sorters: &[(usize, SortDir)]) -> Ordering {
+    for (idx, dir) in sorters {
+        match a[*idx].cmp(&b[*idx]) {
+            Ordering::Equal => {}
+            o => {
+                return match dir {
+                    SortDir::Asc => o,
+                    SortDir::Dsc => o.reverse(),
+                }
+            }
+        }
+    }
+    // ties are broken by the tuples themselves, which is the order they come out of the store,
+    // so the result is the same no matter how the sort is carried out
+    a.cmp(b)
+}
+
+fn sort_tuples(data: &mut [Tuple], sorters: &[(usize, SortDir)]) {
+    data.sort_unstable_by(|a, b| compare_tuples(a, b, sorters));
+}
+
+/// A tuple ordered by the sorters of the query, for use in heaps.
+struct SortKey {
+    tuple: Tuple,
+    sorters: Arc<[(usize, SortDir)]>,
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortKey {}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_tuples(&self.tuple, &other.tuple, &self.sorters)
+    }
+}
+
+/// Selects the first `k` tuples in sorted order, holding at most `k` tuples at any time.
+fn top_k(
+    data: impl Iterator<Item = Tuple>,
+    sorters: Arc<[(usize, SortDir)]>,
+    k: usize,
+) -> Vec<Tuple> {
+    let mut heap = BinaryHeap::with_capacity(k.saturating_add(1).min(1 << 16));
+    if k > 0 {
+        for tuple in data {
+            let key = SortKey {
+                tuple,
+                sorters: sorters.clone(),
+            };
+            if heap.len() < k {
+                heap.push(key);
+            } else if let Some(mut largest) = heap.peek_mut() {
+                if key < *largest {
+                    *largest = key;
+                }
+            }
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|key| key.tuple)
+        .collect()
+}
+
+/// Lazily merges sorted runs of tuples.
+struct MergeSorted {
+    sources: Vec<Box<dyn Iterator<Item = Tuple>>>,
+    heads: BinaryHeap<Reverse<(SortKey, usize)>>,
+    sorters: Arc<[(usize, SortDir)]>,
+}
+
+impl MergeSorted {
+    fn new(
+        mut sources: Vec<Box<dyn Iterator<Item = Tuple>>>,
+        sorters: Arc<[(usize, SortDir)]>,
+    ) -> Self {
+        let mut heads = BinaryHeap::with_capacity(sources.len());
+        for (i, source) in sources.iter_mut().enumerate() {
+            if let Some(tuple) = source.next() {
+                heads.push(Reverse((
+                    SortKey {
+                        tuple,
+                        sorters: sorters.clone(),
+                    },
+                    i,
+                )));
+            }
+        }
+        Self {
+            sources,
+            heads,
+            sorters,
+        }
+    }
+}
+
+impl Iterator for MergeSorted {
+    type Item = Tuple;
 
-        Ok(all_data)
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((key, i)) = self.heads.pop()?;
+        if let Some(tuple) = self.sources[i].next() {
+            self.heads.push(Reverse((
+                SortKey {
+                    tuple,
+                    sorters: self.sorters.clone(),
+                },
+                i,
+            )));
+        }
+        Some(key.tuple)
     }
 }
@@ -25,7 +25,7 @@ use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
 use crate::fts::tokenizer::TextAnalyzer;
 use crate::parse::expr::build_expr;
-use crate::parse::{parse_script, CozoScriptParser, Rule};
+use crate::parse::{parse_script_with_registries, CozoScriptParser, Registries, Rule};
 use crate::runtime::callback::{CallbackCollector, CallbackOp};
 use crate::runtime::minhash_lsh::HashPermutations;
 use crate::runtime::relation::{
@@ -72,6 +72,13 @@ impl<'a> SessionTx<'a> {
                     struct ReplaceRelationWithIndices(String);
                     bail!(ReplaceRelationWithIndices(old_handle.name.to_string()))
                 }
+                if old_handle.changelog.is_some() {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("cannot replace relation {0} since it has a changelog")]
+                    #[diagnostic(code(eval::replace_rel_with_changelog))]
+                    struct ReplaceRelationWithChangelog(String);
+                    bail!(ReplaceRelationWithChangelog(old_handle.name.to_string()))
+                }
                 if old_handle.access_level < AccessLevel::Normal {
                     bail!(InsufficientAccessLevel(
                         old_handle.name.to_string(),
@@ -83,10 +90,15 @@ impl<'a> SessionTx<'a> {
                     replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
                 }
                 for trigger in &old_handle.replace_triggers {
-                    let program = parse_script(
+                    let program = parse_script_with_registries(
                         trigger,
                         &Default::default(),
-                        &db.fixed_rules.read().unwrap(),
+                        &Registries {
+                            functions: &db.functions.read().unwrap(),
+                            fixed_rules: &db.fixed_rules.read().unwrap(),
+                            aggregations: &db.aggregations.read().unwrap(),
+                            table_functions: &db.table_functions.read().unwrap(),
+                        },
                         cur_vld,
                     )?
                     .get_single_program()?;
@@ -223,8 +235,8 @@ impl<'a> SessionTx<'a> {
         force_collect: &str,
         span: SourceSpan,
     ) -> Result<()> {
-        let is_callback_target = callback_targets.contains(&relation_store.name)
-            || force_collect == relation_store.name;
+        let is_callback_target =
+            callback_targets.contains(&relation_store.name) || force_collect == relation_store.name;
 
         if relation_store.access_level < AccessLevel::Protected {
             bail!(InsufficientAccessLevel(
@@ -244,6 +256,7 @@ impl<'a> SessionTx<'a> {
         let need_to_collect = !force_collect.is_empty()
             || (!relation_store.is_temp
                 && (is_callback_target
+                    || relation_store.changelog.is_some()
                     || (propagate_triggers && !relation_store.put_triggers.is_empty())));
         let has_indices = !relation_store.indices.is_empty();
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
@@ -269,7 +282,7 @@ impl<'a> SessionTx<'a> {
         };
         key_extractors.extend(val_extractors);
         let mut stack = vec![];
-        let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
+        let hnsw_filters = self.make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
 
@@ -474,7 +487,8 @@ impl<'a> SessionTx<'a> {
                 .into_diagnostic()?
                 .next()
                 .unwrap();
-            let mut code_expr = build_expr(parsed, &Default::default())?;
+            let mut code_expr =
+                build_expr(parsed, &Default::default(), &self.functions.read().unwrap())?;
             let binding_map = relation_store.raw_binding_map();
             code_expr.fill_binding_indices(&binding_map)?;
             let extractor = code_expr.compile()?;
@@ -489,7 +503,8 @@ impl<'a> SessionTx<'a> {
                 .into_diagnostic()?
                 .next()
                 .unwrap();
-            let mut code_expr = build_expr(parsed, &Default::default())?;
+            let mut code_expr =
+                build_expr(parsed, &Default::default(), &self.functions.read().unwrap())?;
             let binding_map = relation_store.raw_binding_map();
             code_expr.fill_binding_indices(&binding_map)?;
             let extractor = code_expr.compile()?;
@@ -499,6 +514,7 @@ impl<'a> SessionTx<'a> {
     }
 
     fn make_hnsw_filters(
+        &self,
         relation_store: &RelationHandle,
     ) -> Result<BTreeMap<SmartString<LazyCompact>, Vec<Bytecode>>> {
         let mut hnsw_filters = BTreeMap::new();
@@ -508,7 +524,8 @@ impl<'a> SessionTx<'a> {
                     .into_diagnostic()?
                     .next()
                     .unwrap();
-                let mut code_expr = build_expr(parsed, &Default::default())?;
+                let mut code_expr =
+                    build_expr(parsed, &Default::default(), &self.functions.read().unwrap())?;
                 let binding_map = relation_store.raw_binding_map();
                 code_expr.fill_binding_indices(&binding_map)?;
                 hnsw_filters.insert(name.clone(), code_expr.compile()?);
@@ -533,8 +550,8 @@ impl<'a> SessionTx<'a> {
         force_collect: &str,
         span: SourceSpan,
     ) -> Result<()> {
-        let is_callback_target = callback_targets.contains(&relation_store.name)
-            || force_collect == relation_store.name;
+        let is_callback_target =
+            callback_targets.contains(&relation_store.name) || force_collect == relation_store.name;
 
         if relation_store.access_level < AccessLevel::Protected {
             bail!(InsufficientAccessLevel(
@@ -554,6 +571,7 @@ impl<'a> SessionTx<'a> {
         let need_to_collect = !force_collect.is_empty()
             || (!relation_store.is_temp
                 && (is_callback_target
+                    || relation_store.changelog.is_some()
                     || (propagate_triggers && !relation_store.put_triggers.is_empty())));
         let has_indices = !relation_store.indices.is_empty();
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
@@ -570,7 +588,7 @@ impl<'a> SessionTx<'a> {
         )?;
 
         let mut stack = vec![];
-        let hnsw_filters = Self::make_hnsw_filters(relation_store)?;
+        let hnsw_filters = self.make_hnsw_filters(relation_store)?;
         let fts_lsh_processors = self.make_fts_lsh_processors(relation_store)?;
         let lsh_perms = self.make_lsh_hash_perms(relation_store);
 
@@ -695,10 +713,15 @@ impl<'a> SessionTx<'a> {
         let kv_bindings = bindings;
         if propagate_triggers {
             for trigger in &relation_store.put_triggers {
-                let mut program = parse_script(
+                let mut program = parse_script_with_registries(
                     trigger,
                     &Default::default(),
-                    &db.fixed_rules.read().unwrap(),
+                    &Registries {
+                        functions: &db.functions.read().unwrap(),
+                        fixed_rules: &db.fixed_rules.read().unwrap(),
+                        aggregations: &db.aggregations.read().unwrap(),
+                        table_functions: &db.table_functions.read().unwrap(),
+                    },
                     cur_vld,
                 )?
                 .get_single_program()?;
@@ -736,6 +759,10 @@ impl<'a> SessionTx<'a> {
             }
         }
 
+        if relation_store.changelog.is_some() {
+            self.append_to_changelog(relation_store, CallbackOp::Put, &new_tuples, &old_tuples)?;
+        }
+
         if is_callback_target {
             let target_collector = callback_collector
                 .entry(relation_store.name.clone())
@@ -948,6 +975,7 @@ impl<'a> SessionTx<'a> {
         let need_to_collect = !force_collect.is_empty()
             || (!relation_store.is_temp
                 && (is_callback_target
+                    || relation_store.changelog.is_some()
                     || (propagate_triggers && !relation_store.rm_triggers.is_empty())));
         let has_indices = !relation_store.indices.is_empty();
         let has_hnsw_indices = !relation_store.hnsw_indices.is_empty();
@@ -978,7 +1006,12 @@ impl<'a> SessionTx<'a> {
                     });
                 }
             }
-            if need_to_collect || has_indices || has_hnsw_indices || has_fts_indices || has_lsh_indices {
+            if need_to_collect
+                || has_indices
+                || has_hnsw_indices
+                || has_fts_indices
+                || has_lsh_indices
+            {
                 if let Some(existing) = self.store_tx.get(&key, false)? {
                     let mut tup = extracted.clone();
                     extend_tuple_from_v(&mut tup, &existing);
@@ -1032,10 +1065,15 @@ impl<'a> SessionTx<'a> {
 
             if propagate_triggers {
                 for trigger in &relation_store.rm_triggers {
-                    let mut program = parse_script(
+                    let mut program = parse_script_with_registries(
                         trigger,
                         &Default::default(),
-                        &db.fixed_rules.read().unwrap(),
+                        &Registries {
+                            functions: &db.functions.read().unwrap(),
+                            fixed_rules: &db.fixed_rules.read().unwrap(),
+                            aggregations: &db.aggregations.read().unwrap(),
+                            table_functions: &db.table_functions.read().unwrap(),
+                        },
                         cur_vld,
                     )?
                     .get_single_program()?;
@@ -1069,6 +1107,10 @@ impl<'a> SessionTx<'a> {
                 }
             }
 
+            if relation_store.changelog.is_some() {
+                self.append_to_changelog(relation_store, CallbackOp::Rm, &new_tuples, &old_tuples)?;
+            }
+
             if is_callback_target {
                 let target_collector = callback_collector
                     .entry(relation_store.name.clone())
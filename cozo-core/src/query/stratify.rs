@@ -32,9 +32,18 @@ impl NormalFormAtom {
             | NormalFormAtom::Unification(_)
             | NormalFormAtom::HnswSearch(_)
             | NormalFormAtom::FtsSearch(_)
-            | NormalFormAtom::LshSearch(_) => Default::default(),
+            | NormalFormAtom::LshSearch(_)
+            | NormalFormAtom::TableFunction(_) => Default::default(),
             NormalFormAtom::Rule(r) => BTreeMap::from([(&r.name, false)]),
             NormalFormAtom::NegatedRule(r) => BTreeMap::from([(&r.name, true)]),
+            // whether the left join yields nulls depends on the absence of inner matches,
+            // so inner rules must be completely evaluated first, as with negation
+            NormalFormAtom::Optional(o) => o
+                .body
+                .iter()
+                .flat_map(|a| a.contained_rules().into_keys())
+                .map(|k| (k, true))
+                .collect(),
         }
     }
 }
@@ -167,7 +176,8 @@ fn verify_no_cycle(g: &StratifiedGraph<&'_ Symbol>, sccs: &[BTreeSet<&Symbol>])
                     #[diagnostic(help(
                         "The rule '{0}' is in the strongly connected component {1:?},\n\
                     and is involved in at least one forbidden dependency \n\
-                    (negation, non-meet aggregation, or algorithm-application)."
+                    (negation, optional atoms or subqueries, non-meet aggregation,\n\
+                    or algorithm-application)."
                     ))]
                     struct UnStratifiableProgram(String, Vec<String>);
 
@@ -0,0 +1,428 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use itertools::Itertools;
+use miette::{bail, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use twox_hash::XxHash64;
+
+use crate::{Db, Storage, StoreTx};
+
+const BACKUPS_DIR: &str = "backups";
+const CHUNKS_DIR: &str = "chunks";
+const SHARED_DIR: &str = "shared";
+const CHECKPOINT_DIR: &str = "checkpoint";
+const MANIFEST_FILE: &str = "manifest.json";
+/// A chunk ends after a key whose hash is divisible by this, so chunk boundaries
+/// depend only on the keys near them, and unchanged chunks are shared between backups.
+const CHUNK_KEYS: u64 = 4096;
+const MAX_CHUNK_BYTES: usize = 8 << 20;
+
+/// How the data of a backup is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupKind {
+    /// Key-value pairs in content-addressed chunks, restorable into any storage engine
+    Chunks,
+    /// The files of a checkpoint of the storage engine
+    Checkpoint,
+}
+
+/// A file of a backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFile {
+    /// Path relative to the backup directory
+    pub path: String,
+    /// For checkpoints, the path relative to the restored database directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Size in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 digest of the content
+    pub sha256: String,
+}
+
+/// The manifest of a backup, stored in the backup directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// Backups in a directory are numbered from 1
+    pub id: u64,
+    /// The previous backup in the directory, if any
+    pub parent: Option<u64>,
+    /// Seconds since the UNIX epoch
+    pub created_at: f64,
+    /// The kind of storage engine the backup was taken from
+    pub engine: String,
+    /// How the data is stored
+    pub kind: BackupKind,
+    /// All files needed to restore the backup, in order
+    pub files: Vec<BackupFile>,
+    /// Number of files written by this backup, the rest are shared with earlier backups
+    pub new_files: usize,
+    /// Number of bytes written by this backup
+    pub new_bytes: u64,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Backup {0} not found in {1}")]
+#[diagnostic(code(backup::not_found))]
+struct BackupNotFound(u64, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("File {0} of the backup is missing or corrupt")]
+#[diagnostic(code(backup::corrupt_file))]
+struct CorruptBackupFile(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Backup {0} is a checkpoint of the storage engine")]
+#[diagnostic(code(backup::checkpoint_restore))]
+#[diagnostic(help(
+    "Use `restore_checkpoint_backup` to restore it as a database directory, and open that instead"
+))]
+struct RestoreCheckpointIntoDb(u64);
+
+/// List the complete backups in the backup directory `dir`, oldest first.
+pub fn list_backups(dir: impl AsRef<Path>) -> Result<Vec<BackupManifest>> {
+    let backups_dir = dir.as_ref().join(BACKUPS_DIR);
+    if !backups_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut ret = vec![];
+    for entry in fs::read_dir(&backups_dir).into_diagnostic()? {
+        let manifest_path = entry.into_diagnostic()?.path().join(MANIFEST_FILE);
+        // an interrupted backup has no manifest
+        if manifest_path.exists() {
+            let manifest = serde_json::from_slice(&fs::read(&manifest_path).into_diagnostic()?)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("when reading {}", manifest_path.display()))?;
+            ret.push(manifest);
+        }
+    }
+    ret.sort_by_key(|m: &BackupManifest| m.id);
+    Ok(ret)
+}
+
+/// Check that all files of a backup are present and intact.
+/// If `id` is `None`, the latest backup is checked.
+pub fn verify_backup(dir: impl AsRef<Path>, id: Option<u64>) -> Result<BackupManifest> {
+    let manifest = find_backup(dir.as_ref(), id)?;
+    for file in &manifest.files {
+        read_verified(dir.as_ref(), file, |_| Ok(()))?;
+    }
+    Ok(manifest)
+}
+
+/// Restore a backup of kind [BackupKind::Checkpoint] as a database directory at `target`,
+/// which must not exist or be empty. The directory can then be opened with the
+/// engine the backup was taken from. If `id` is `None`, the latest backup is restored.
+pub fn restore_checkpoint_backup(
+    dir: impl AsRef<Path>,
+    id: Option<u64>,
+    target: impl AsRef<Path>,
+) -> Result<BackupManifest> {
+    let manifest = find_backup(dir.as_ref(), id)?;
+    if manifest.kind != BackupKind::Checkpoint {
+        bail!(
+            "backup {} is not a checkpoint, restore it into a database instead",
+            manifest.id
+        )
+    }
+    let target = target.as_ref();
+    if target.exists() && fs::read_dir(target).into_diagnostic()?.next().is_some() {
+        bail!("cannot restore into {}: it is not empty", target.display())
+    }
+    for file in &manifest.files {
+        let dest = target.join(file.target.as_ref().unwrap_or(&file.path));
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).into_diagnostic()?;
+        }
+        let mut out = File::create(&dest).into_diagnostic()?;
+        read_verified(dir.as_ref(), file, |buf| {
+            out.write_all(buf).into_diagnostic()
+        })?;
+        out.sync_all().into_diagnostic()?;
+    }
+    Ok(manifest)
+}
+
+fn find_backup(dir: &Path, id: Option<u64>) -> Result<BackupManifest> {
+    let mut backups = list_backups(dir)?;
+    let found = match id {
+        None => backups.pop(),
+        Some(id) => backups.into_iter().find(|m| m.id == id),
+    };
+    found.ok_or_else(|| match id {
+        None => miette!("no backup found in {}", dir.display()),
+        Some(id) => miette!(BackupNotFound(id, dir.display().to_string())),
+    })
+}
+
+/// Read a backup file in blocks, checking its size and digest at the end.
+fn read_verified(
+    dir: &Path,
+    file: &BackupFile,
+    mut f: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let corrupt = || CorruptBackupFile(file.path.clone());
+    let mut src = File::open(dir.join(&file.path)).map_err(|_| corrupt())?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = src.read(&mut buf).map_err(|_| corrupt())?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+        f(&buf[..n])?;
+    }
+    if size != file.size || format!("{:x}", hasher.finalize()) != file.sha256 {
+        bail!(corrupt())
+    }
+    Ok(())
+}
+
+fn sha256_of_file(path: &Path) -> Result<String> {
+    let mut src = File::open(path).into_diagnostic()?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut src, &mut hasher).into_diagnostic()?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Path relative to `base`, with `/` as separator
+fn relative_path(path: &Path, base: &Path) -> Result<String> {
+    let rel = path.strip_prefix(base).into_diagnostic()?;
+    Ok(rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .join("/"))
+}
+
+fn files_in(dir: &Path, collected: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).into_diagnostic()? {
+        let path = entry.into_diagnostic()?.path();
+        if path.is_dir() {
+            files_in(&path, collected)?;
+        } else {
+            collected.push(path);
+        }
+    }
+    Ok(())
+}
+
+struct BackupWriter<'a> {
+    dir: &'a Path,
+    backup_dir: PathBuf,
+    files: Vec<BackupFile>,
+    new_files: usize,
+    new_bytes: u64,
+}
+
+impl BackupWriter<'_> {
+    fn write_chunk(&mut self, chunk: &mut Vec<u8>) -> Result<()> {
+        let digest = format!("{:x}", Sha256::digest(&chunk));
+        let path = format!("{}/{}/{}", CHUNKS_DIR, &digest[..2], digest);
+        let full_path = self.dir.join(&path);
+        if !full_path.exists() {
+            fs::create_dir_all(full_path.parent().unwrap()).into_diagnostic()?;
+            let tmp_path = full_path.with_extension("tmp");
+            let mut out = File::create(&tmp_path).into_diagnostic()?;
+            out.write_all(chunk).into_diagnostic()?;
+            out.sync_all().into_diagnostic()?;
+            fs::rename(&tmp_path, &full_path).into_diagnostic()?;
+            self.new_files += 1;
+            self.new_bytes += chunk.len() as u64;
+        }
+        self.files.push(BackupFile {
+            path,
+            target: None,
+            size: chunk.len() as u64,
+            sha256: digest,
+        });
+        chunk.clear();
+        Ok(())
+    }
+
+    /// Move the immutable files of the checkpoint to the shared directory,
+    /// where they are reused by later backups.
+    fn add_checkpoint(&mut self, checkpoint_dir: &Path) -> Result<()> {
+        let mut paths = vec![];
+        files_in(checkpoint_dir, &mut paths)?;
+        paths.sort();
+        for src in paths {
+            let target = relative_path(&src, checkpoint_dir)?;
+            let size = fs::metadata(&src).into_diagnostic()?.len();
+            let sha256 = sha256_of_file(&src)?;
+            let path = if target.ends_with(".sst") {
+                // SST file names are only unique within a database, so they are shared by content
+                let path = format!("{SHARED_DIR}/{sha256}.sst");
+                let shared = self.dir.join(&path);
+                if shared.exists() {
+                    fs::remove_file(&src).into_diagnostic()?;
+                } else {
+                    fs::create_dir_all(shared.parent().unwrap()).into_diagnostic()?;
+                    fs::rename(&src, &shared).into_diagnostic()?;
+                    self.new_files += 1;
+                    self.new_bytes += size;
+                }
+                path
+            } else {
+                self.new_files += 1;
+                self.new_bytes += size;
+                relative_path(&src, self.dir)?
+            };
+            self.files.push(BackupFile {
+                path,
+                target: Some(target),
+                size,
+                sha256,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Back up the database into the backup directory `dir`, creating it if necessary.
+    ///
+    /// Backups taken into the same directory share unchanged data, so only the changes since
+    /// the previous backup are written. Engines supporting checkpoints (RocksDB) are backed up
+    /// by checkpointing, the others by copying their key-value pairs in chunks, all of which are
+    /// read but only the changed ones written. The database remains available throughout.
+    /// Each backup has a manifest listing its files with their digests, for [verify_backup].
+    pub fn backup_to_dir(&'s self, dir: impl AsRef<Path>) -> Result<BackupManifest> {
+        let dir = dir.as_ref();
+        let previous = list_backups(dir)?;
+        let parent = previous.last().map(|m| m.id);
+        let id = parent.unwrap_or(0) + 1;
+        let backup_dir = dir.join(BACKUPS_DIR).join(id.to_string());
+        if backup_dir.exists() {
+            // left over from an interrupted backup
+            fs::remove_dir_all(&backup_dir).into_diagnostic()?;
+        }
+        fs::create_dir_all(&backup_dir).into_diagnostic()?;
+        let mut writer = BackupWriter {
+            dir,
+            backup_dir,
+            files: vec![],
+            new_files: 0,
+            new_bytes: 0,
+        };
+        let kind = if self.db.supports_checkpoint() {
+            let checkpoint_dir = writer.backup_dir.join(CHECKPOINT_DIR);
+            self.db.checkpoint(&checkpoint_dir)?;
+            writer.add_checkpoint(&checkpoint_dir)?;
+            BackupKind::Checkpoint
+        } else {
+            // values are copied as they are stored, so that backups of encrypted databases are too
+            let tx = self.db.transact(false)?;
+            let mut chunk = vec![];
+            for pair in tx.range_scan(&[], &[0xFF]) {
+                let (k, v) = pair?;
+                chunk.write_u32::<LE>(k.len() as u32).into_diagnostic()?;
+                chunk.extend_from_slice(&k);
+                chunk.write_u32::<LE>(v.len() as u32).into_diagnostic()?;
+                chunk.extend_from_slice(&v);
+                let mut hasher = XxHash64::with_seed(0);
+                hasher.write(&k);
+                if hasher.finish().is_multiple_of(CHUNK_KEYS) || chunk.len() >= MAX_CHUNK_BYTES {
+                    writer.write_chunk(&mut chunk)?;
+                }
+            }
+            if !chunk.is_empty() {
+                writer.write_chunk(&mut chunk)?;
+            }
+            BackupKind::Chunks
+        };
+        let manifest = BackupManifest {
+            id,
+            parent,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64(),
+            engine: self.db.storage_kind().to_string(),
+            kind,
+            files: writer.files,
+            new_files: writer.new_files,
+            new_bytes: writer.new_bytes,
+        };
+        // the backup is complete once its manifest exists
+        let tmp_path = writer.backup_dir.join("manifest.tmp");
+        fs::write(
+            &tmp_path,
+            serde_json::to_vec_pretty(&manifest).into_diagnostic()?,
+        )
+        .into_diagnostic()?;
+        fs::rename(&tmp_path, writer.backup_dir.join(MANIFEST_FILE)).into_diagnostic()?;
+        Ok(manifest)
+    }
+
+    /// Restore a backup taken by [Db::backup_to_dir] into this database, which must be empty.
+    /// If `id` is `None`, the latest backup in `dir` is restored.
+    /// Backups of kind [BackupKind::Checkpoint] are restored by [restore_checkpoint_backup] instead.
+    pub fn restore_from_dir(
+        &'s self,
+        dir: impl AsRef<Path>,
+        id: Option<u64>,
+    ) -> Result<BackupManifest> {
+        self.ensure_writable("restore a backup")?;
+        let dir = dir.as_ref();
+        let manifest = find_backup(dir, id)?;
+        if manifest.kind != BackupKind::Chunks {
+            bail!(RestoreCheckpointIntoDb(manifest.id))
+        }
+        {
+            let mut tx = self.transact()?;
+            let store_id = tx.relation_store_id.load(Ordering::SeqCst);
+            if store_id != 0 {
+                bail!(
+                    "Cannot restore backup: data exists in the current database. \
+                You can only restore into a new database (store id: {}).",
+                    store_id
+                );
+            }
+            tx.commit_tx()?;
+        }
+        let pairs = manifest
+            .files
+            .iter()
+            .map(|file| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+                let mut chunk = vec![];
+                read_verified(dir, file, |buf| {
+                    chunk.extend_from_slice(buf);
+                    Ok(())
+                })?;
+                let mut reader = &chunk[..];
+                let mut pairs = vec![];
+                while !reader.is_empty() {
+                    let mut k = vec![0; reader.read_u32::<LE>().into_diagnostic()? as usize];
+                    reader.read_exact(&mut k).into_diagnostic()?;
+                    let mut v = vec![0; reader.read_u32::<LE>().into_diagnostic()? as usize];
+                    reader.read_exact(&mut v).into_diagnostic()?;
+                    pairs.push((k, v));
+                }
+                Ok(pairs)
+            })
+            .flatten_ok();
+        self.db.batch_put(self.encrypt_restored(Box::new(pairs))?)?;
+        self.load_last_ids()?;
+        Ok(manifest)
+    }
+}
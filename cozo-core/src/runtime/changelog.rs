@@ -0,0 +1,359 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::callback::CallbackOp;
+use crate::runtime::relation::{
+    decode_tuple_from_kv, AccessLevel, InputRelationHandle, InsufficientAccessLevel,
+    RelationHandle, RelationId,
+};
+use crate::runtime::transact::SessionTx;
+use crate::storage::write_log::decode_seq;
+use crate::{Db, NamedRows, Storage};
+
+/// The changelog of `rel` is stored in the relation `rel:changelog`,
+/// so no index can be named like this while the changelog exists.
+pub(crate) const CHANGELOG_NAME: &str = "changelog";
+
+/// The durable log of the mutations of a stored relation, kept in its metadata.
+#[derive(Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct Changelog {
+    /// The relation holding the entries, keyed by sequence number
+    pub(crate) handle: RelationHandle,
+}
+
+impl Changelog {
+    /// Under this key is the sequence number of the last entry, absent if none was ever
+    /// written. It is not kept in the metadata, so that writing an entry does not change
+    /// the handle of the relation, and the cached plans of the queries using it.
+    fn seq_key(&self) -> Vec<u8> {
+        vec![
+            DataValue::Null,
+            DataValue::from("CHANGELOG_SEQ"),
+            DataValue::from(self.handle.id.0 as i64),
+        ]
+        .encode_as_key(RelationId::SYSTEM)
+    }
+}
+
+/// An entry of the changelog of a stored relation, see [Db::read_changelog].
+///
+/// Every query statement mutating the relation writes one entry, holding the same
+/// rows as those sent to callbacks.
+#[derive(Debug, Clone)]
+pub struct ChangelogEntry {
+    /// Sequence number, the first entry has `1` and each entry increases it by one
+    pub seq: u64,
+    /// The kind of the mutation
+    pub op: CallbackOp,
+    /// For [CallbackOp::Put], the rows put. For [CallbackOp::Rm], the keys removed
+    pub new: NamedRows,
+    /// The rows that were replaced or removed
+    pub old: NamedRows,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' does not have a changelog")]
+#[diagnostic(code(tx::no_changelog))]
+#[diagnostic(help("Enable it with `::changelog {0} on`"))]
+pub(crate) struct NoChangelog(pub(crate) String);
+
+impl SessionTx<'_> {
+    pub(crate) fn enable_changelog(&mut self, rel: &Symbol) -> Result<()> {
+        let mut handle = self.get_relation(rel, true)?;
+        if handle.is_temp {
+            bail!(
+                "Cannot keep a changelog for the temp relation '{}'",
+                rel.name
+            )
+        }
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "enabling the changelog".to_string(),
+                handle.access_level
+            ))
+        }
+        if handle.changelog.is_some() {
+            bail!("Stored relation '{}' already has a changelog", rel.name)
+        }
+        let name = format!("{}:{CHANGELOG_NAME}", rel.name);
+        if handle.has_index(CHANGELOG_NAME) || self.relation_exists(&name)? {
+            bail!(
+                "Cannot create the changelog of '{}' as the relation '{name}' already exists",
+                rel.name
+            )
+        }
+
+        let column = |name: &str, coltype: ColType| ColumnDef {
+            name: SmartString::from(name),
+            typing: NullableColType {
+                coltype,
+                nullable: false,
+            },
+            default_gen: None,
+        };
+        let metadata = StoredRelationMetadata {
+            keys: vec![column("seq", ColType::Int)],
+            non_keys: vec![
+                column("op", ColType::String),
+                column("new", ColType::Any),
+                column("old", ColType::Any),
+            ],
+        };
+        let bindings = |cols: &[ColumnDef]| {
+            cols.iter()
+                .map(|col| Symbol::new(col.name.clone(), Default::default()))
+                .collect_vec()
+        };
+        let mut log_handle = self.create_relation(InputRelationHandle {
+            name: Symbol::new(name, rel.span),
+            key_bindings: bindings(&metadata.keys),
+            dep_bindings: bindings(&metadata.non_keys),
+            metadata,
+            span: rel.span,
+        })?;
+        // entries are only ever written by the database itself
+        log_handle.access_level = AccessLevel::ReadOnly;
+        self.put_relation_metadata(&log_handle)?;
+
+        handle.changelog = Some(Box::new(Changelog { handle: log_handle }));
+        self.put_relation_metadata(&handle)
+    }
+
+    /// Returns the key range of the removed entries, to be cleared by the caller.
+    pub(crate) fn disable_changelog(&mut self, rel: &Symbol) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut handle = self.get_relation(rel, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "disabling the changelog".to_string(),
+                handle.access_level
+            ))
+        }
+        let changelog = handle
+            .changelog
+            .take()
+            .ok_or_else(|| NoChangelog(rel.name.to_string()))?;
+        let bounds = self.destroy_changelog(&changelog)?;
+        self.put_relation_metadata(&handle)?;
+        Ok(bounds)
+    }
+
+    pub(crate) fn destroy_changelog(
+        &mut self,
+        changelog: &Changelog,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let name_key =
+            vec![DataValue::Str(changelog.handle.name.clone())].encode_as_key(RelationId::SYSTEM);
+        self.store_tx.del(&name_key)?;
+        self.store_tx.del(&changelog.seq_key())?;
+        let lower = Tuple::default().encode_as_key(changelog.handle.id);
+        let upper = Tuple::default().encode_as_key(changelog.handle.id.next());
+        Ok((lower, upper))
+    }
+
+    /// Returns the key range of the entries up to and including `up_to`,
+    /// to be cleared by the caller. Sequence numbers are never reused.
+    pub(crate) fn truncate_changelog(
+        &mut self,
+        rel: &Symbol,
+        up_to: u64,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let handle = self.get_relation(rel, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "truncating the changelog".to_string(),
+                handle.access_level
+            ))
+        }
+        let changelog = handle
+            .changelog
+            .ok_or_else(|| NoChangelog(rel.name.to_string()))?;
+        let lower = Tuple::default().encode_as_key(changelog.handle.id);
+        let upper = changelog
+            .handle
+            .encode_partial_key_for_store(&[DataValue::from(seq_to_int(up_to) + 1)]);
+        Ok((lower, upper))
+    }
+
+    pub(crate) fn changelog_status(&self, rel: &Symbol) -> Result<NamedRows> {
+        let handle = self.get_relation(rel, false)?;
+        let (enabled, last_seq) = match &handle.changelog {
+            None => (false, DataValue::Null),
+            Some(changelog) => (
+                true,
+                DataValue::from(seq_to_int(self.last_changelog_seq(changelog, false)?)),
+            ),
+        };
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "enabled".to_string(),
+                "last_seq".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(&handle.name as &str),
+                DataValue::from(enabled),
+                last_seq,
+            ]],
+        ))
+    }
+
+    /// Writes an entry to the changelog of `relation` if it has one.
+    /// The metadata is read again since `relation` may be stale
+    /// when the relation was already mutated in this transaction.
+    pub(crate) fn append_to_changelog(
+        &mut self,
+        relation: &RelationHandle,
+        op: CallbackOp,
+        new: &[DataValue],
+        old: &[DataValue],
+    ) -> Result<()> {
+        let handle = self.get_relation(&relation.name, false)?;
+        let changelog = match &handle.changelog {
+            None => return Ok(()),
+            Some(changelog) => changelog,
+        };
+        let seq = self.last_changelog_seq(changelog, true)? + 1;
+        let entry = vec![
+            DataValue::from(seq_to_int(seq)),
+            DataValue::from(op.as_str()),
+            DataValue::List(new.to_vec()),
+            DataValue::List(old.to_vec()),
+        ];
+        let key = changelog
+            .handle
+            .encode_key_for_store(&entry, Default::default())?;
+        let val = changelog
+            .handle
+            .encode_val_for_store(&entry, Default::default())?;
+        self.store_tx.put(&key, &val)?;
+        self.store_tx.put(&changelog.seq_key(), &seq.to_be_bytes())
+    }
+
+    fn last_changelog_seq(&self, changelog: &Changelog, for_update: bool) -> Result<u64> {
+        Ok(self
+            .store_tx
+            .get(&changelog.seq_key(), for_update)?
+            .map(|val| decode_seq(&val))
+            .unwrap_or(0))
+    }
+
+    pub(crate) fn read_changelog(
+        &self,
+        rel: &str,
+        after: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<ChangelogEntry>> {
+        let handle = self.get_relation(rel, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "reading the changelog".to_string(),
+                handle.access_level
+            ))
+        }
+        let changelog = handle
+            .changelog
+            .as_ref()
+            .ok_or_else(|| NoChangelog(rel.to_string()))?;
+        let key_headers = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let mut kv_headers = key_headers.clone();
+        kv_headers.extend(
+            handle
+                .metadata
+                .non_keys
+                .iter()
+                .map(|col| col.name.to_string()),
+        );
+
+        let lower = changelog
+            .handle
+            .encode_partial_key_for_store(&[DataValue::from(seq_to_int(after) + 1)]);
+        let upper = Tuple::default().encode_as_key(changelog.handle.id.next());
+        let mut ret = vec![];
+        for kv in self.store_tx.range_scan(&lower, &upper) {
+            if limit == Some(ret.len()) {
+                break;
+            }
+            let (k, v) = kv?;
+            let mut entry = decode_tuple_from_kv(&k, &v, Some(4)).into_iter();
+            let (Some(seq), Some(op), Some(new), Some(old)) =
+                (entry.next(), entry.next(), entry.next(), entry.next())
+            else {
+                bail!("Malformed changelog entry of relation '{rel}'")
+            };
+            let op = match op.get_str() {
+                Some("Put") => CallbackOp::Put,
+                Some("Rm") => CallbackOp::Rm,
+                _ => bail!("Malformed changelog entry of relation '{rel}'"),
+            };
+            let new_headers = match op {
+                CallbackOp::Put => kv_headers.clone(),
+                CallbackOp::Rm => key_headers.clone(),
+            };
+            ret.push(ChangelogEntry {
+                seq: seq.get_int().unwrap_or_default() as u64,
+                op,
+                new: NamedRows::new(new_headers, rows_of(new)),
+                old: NamedRows::new(kv_headers.clone(), rows_of(old)),
+            });
+        }
+        Ok(ret)
+    }
+}
+
+fn seq_to_int(seq: u64) -> i64 {
+    seq.min(i64::MAX as u64 - 1) as i64
+}
+
+fn rows_of(val: DataValue) -> Vec<Vec<DataValue>> {
+    match val {
+        DataValue::List(rows) => rows
+            .into_iter()
+            .map(|row| match row {
+                DataValue::List(row) => row,
+                val => vec![val],
+            })
+            .collect_vec(),
+        _ => vec![],
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Read the changelog of a stored relation, which must have been enabled
+    /// with `::changelog <relation> on`: returns the entries with a sequence number
+    /// greater than `after`, in order, and at most `limit` of them.
+    ///
+    /// A consumer can remember the sequence number of the last entry it has processed
+    /// and resume from it, even after the database is restarted.
+    pub fn read_changelog(
+        &'s self,
+        relation: &str,
+        after: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<ChangelogEntry>> {
+        let tx = self.transact()?;
+        tx.read_changelog(relation, after, limit)
+    }
+}
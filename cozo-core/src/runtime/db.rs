@@ -11,9 +11,10 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
 use std::iter;
+use std::ops::{Bound, RangeBounds};
 use std::path::Path;
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 #[allow(unused_imports)]
 use std::thread;
@@ -32,21 +33,31 @@ use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::aggr::{is_builtin_aggr, CustomAggregation};
+use crate::data::expr::{get_op, CustomFunction};
 use crate::data::functions::current_validity;
 use crate::data::json::JsonValue;
-use crate::data::program::{InputProgram, QueryAssertion, RelationOp, ReturnMutation};
+use crate::data::program::{
+    page_cursor, InputProgram, MagicSymbol, QueryAssertion, QueryOutOptions, RelationOp,
+    ReturnMutation,
+};
 use crate::data::relation::ColumnDef;
+use crate::data::table_func::{TableFunction, DEFAULT_TABLE_FUNCTIONS};
 use crate::data::tuple::{Tuple, TupleT};
 use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
 use crate::fts::TokenizerCache;
-use crate::parse::sys::SysOp;
-use crate::parse::{parse_expressions, parse_script, CozoScript, SourceSpan};
-use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::parse::sys::{ChangelogOp, SysOp};
+use crate::parse::{
+    parse_expressions, parse_script_with_registries, CozoScript, Registries, SourceSpan,
+};
+use crate::query::compile::{AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet};
 use crate::query::ra::{
-    FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RelAlgebra, ReorderRA,
-    StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
+    FilteredRA, FtsSearchRA, HashJoin, HnswSearchRA, InnerJoin, LeftJoin, LshSearchRA, NegJoin,
+    RelAlgebra, ReorderRA, StoredRA, StoredWithValidityRA, TableFunctionRA, TempStoreRA,
+    UnificationRA, DEFAULT_HASH_JOIN_MEMORY_BUDGET,
 };
+use crate::query::sort::DEFAULT_SORT_MEMORY_BUDGET;
 #[allow(unused_imports)]
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
@@ -55,10 +66,22 @@ use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
 };
 use crate::runtime::transact::SessionTx;
+use crate::storage::encrypted::EncryptionState;
+use crate::storage::read_only::{ReadOnlyDb, ReadOnlyTx};
 use crate::storage::temp::TempStorage;
+use crate::storage::write_log::{WriteLogState, WriteLogTx};
 use crate::storage::Storage;
 use crate::{decode_tuple_from_kv, FixedRule, Symbol};
 
+/// A query compiled against the stored relations as they were at compilation time.
+#[derive(Clone)]
+pub(crate) struct QueryPlan {
+    pub(crate) entry_head: Vec<Symbol>,
+    pub(crate) compiled: Vec<CompiledProgram>,
+    pub(crate) store_lifetimes: BTreeMap<MagicSymbol, usize>,
+    pub(crate) out_opts: QueryOutOptions,
+}
+
 pub(crate) struct RunningQueryHandle {
     pub(crate) started_at: f64,
     pub(crate) poison: Poison,
@@ -97,16 +120,24 @@ pub enum ScriptMutability {
 pub struct Db<S> {
     pub(crate) db: S,
     temp_db: TempStorage,
-    relation_store_id: Arc<AtomicU64>,
+    pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) queries_count: Arc<AtomicU64>,
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
+    pub(crate) aggregations: Arc<ShardedLock<BTreeMap<String, Arc<dyn CustomAggregation>>>>,
+    pub(crate) functions: Arc<ShardedLock<BTreeMap<String, Arc<CustomFunction>>>>,
+    pub(crate) table_functions: Arc<ShardedLock<BTreeMap<String, Arc<TableFunction>>>>,
     pub(crate) tokenizers: Arc<TokenizerCache>,
     #[cfg(not(target_arch = "wasm32"))]
     callback_count: Arc<AtomicU32>,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    sort_memory_budget: Arc<AtomicUsize>,
+    hash_join_memory_budget: Arc<AtomicUsize>,
+    /// Sequence numbers of the write sets, `None` if the write log is not enabled
+    pub(crate) write_log: Arc<Mutex<Option<WriteLogState>>>,
+    pub(crate) encryption: Arc<ShardedLock<EncryptionState>>,
 }
 
 impl<S> Debug for Db<S> {
@@ -271,6 +302,9 @@ impl<'s, S: Storage<'s>> Db<S> {
             queries_count: Default::default(),
             running_queries: Default::default(),
             fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
+            aggregations: Default::default(),
+            functions: Default::default(),
+            table_functions: Arc::new(ShardedLock::new(DEFAULT_TABLE_FUNCTIONS.clone())),
             tokenizers: Arc::new(Default::default()),
             #[cfg(not(target_arch = "wasm32"))]
             callback_count: Default::default(),
@@ -278,10 +312,27 @@ impl<'s, S: Storage<'s>> Db<S> {
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            sort_memory_budget: Arc::new(AtomicUsize::new(DEFAULT_SORT_MEMORY_BUDGET)),
+            hash_join_memory_budget: Arc::new(AtomicUsize::new(DEFAULT_HASH_JOIN_MEMORY_BUDGET)),
+            write_log: Default::default(),
+            encryption: Default::default(),
         };
         Ok(ret)
     }
 
+    /// Set the maximum number of rows that sorting the results of a query holds in memory.
+    /// Larger results are sorted in runs which are spilled to disk and then merged.
+    pub fn set_sort_memory_budget(&self, rows: usize) {
+        self.sort_memory_budget.store(rows, Ordering::Relaxed);
+    }
+
+    /// Set the maximum number of rows that a hash join holds in its hash table.
+    /// When the build side is larger, both sides are partitioned into buckets
+    /// which are spilled to disk and joined one by one.
+    pub fn set_hash_join_memory_budget(&self, rows: usize) {
+        self.hash_join_memory_budget.store(rows, Ordering::Relaxed);
+    }
+
     /// Must be called after creation of the database to initialize the runtime state.
     pub fn initialize(&'s self) -> Result<()> {
         self.load_last_ids()?;
@@ -342,18 +393,26 @@ impl<'s, S: Storage<'s>> Db<S> {
                     break;
                 }
                 TransactionPayload::Query((script, params)) => {
-                    let p =
-                        match parse_script(&script, &params, &self.fixed_rules.read().unwrap(), ts)
-                        {
-                            Ok(p) => p,
-                            Err(err) => {
-                                if results.send(Err(err)).is_err() {
-                                    break;
-                                } else {
-                                    continue;
-                                }
+                    let p = match parse_script_with_registries(
+                        &script,
+                        &params,
+                        &Registries {
+                            functions: &self.functions.read().unwrap(),
+                            fixed_rules: &self.fixed_rules.read().unwrap(),
+                            aggregations: &self.aggregations.read().unwrap(),
+                            table_functions: &self.table_functions.read().unwrap(),
+                        },
+                        ts,
+                    ) {
+                        Ok(p) => p,
+                        Err(err) => {
+                            if results.send(Err(err)).is_err() {
+                                break;
+                            } else {
+                                continue;
                             }
-                        };
+                        }
+                    };
 
                     let p = match p.get_single_program() {
                         Ok(p) => p,
@@ -399,6 +458,21 @@ impl<'s, S: Storage<'s>> Db<S> {
         return self.fixed_rules.read().unwrap().clone();
     }
 
+    /// This returns the set of custom functions registered for this specific backend.
+    pub fn get_functions(&'s self) -> BTreeMap<String, Arc<CustomFunction>> {
+        self.functions.read().unwrap().clone()
+    }
+
+    /// This returns the set of custom aggregations registered for this specific backend.
+    pub fn get_aggregations(&'s self) -> BTreeMap<String, Arc<dyn CustomAggregation>> {
+        self.aggregations.read().unwrap().clone()
+    }
+
+    /// This returns the set of table functions, built-in and custom, for this specific backend.
+    pub fn get_table_functions(&'s self) -> BTreeMap<String, Arc<TableFunction>> {
+        self.table_functions.read().unwrap().clone()
+    }
+
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
     pub fn run_script(
         &'s self,
@@ -406,16 +480,37 @@ impl<'s, S: Storage<'s>> Db<S> {
         params: BTreeMap<String, DataValue>,
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
-        self.run_script_ast(
-            parse_script(
-                payload,
-                &params,
-                &self.get_fixed_rules(),
-                current_validity(),
-            )?,
+        self.run_script_page(payload, params, mutability)
+            .map(|(res, _)| res)
+    }
+
+    /// Run the CozoScript passed in, as [Db::run_script] does. For queries with `:after`
+    /// that fill their `:limit`, the cursor to pass to `:after` to get the next page
+    /// is returned with the rows.
+    pub fn run_script_page(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<(NamedRows, Option<String>)> {
+        let script = parse_script_with_registries(
+            payload,
+            &params,
+            &Registries {
+                functions: &self.functions.read().unwrap(),
+                fixed_rules: &self.fixed_rules.read().unwrap(),
+                aggregations: &self.aggregations.read().unwrap(),
+                table_functions: &self.table_functions.read().unwrap(),
+            },
             current_validity(),
-            mutability,
-        )
+        )?;
+        let page_limit = match &script {
+            CozoScript::Single(p) => p.out_opts.page_limit(),
+            _ => None,
+        };
+        let res = self.run_script_ast(script, current_validity(), mutability)?;
+        let cursor = page_cursor(page_limit, &res.rows);
+        Ok((res, cursor))
     }
 
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
@@ -442,6 +537,200 @@ impl<'s, S: Storage<'s>> Db<S> {
         }
     }
 
+    /// Run a read-only query and send its results row by row, instead of materializing
+    /// them into [NamedRows]. The headers are sent to `headers` first, together with the cursor
+    /// for the next page as in [Db::run_script_page], then rows are sent to `rows` as they
+    /// are produced. Errors encountered before the headers are sent go to `headers`,
+    /// later errors go to `rows`.
+    ///
+    /// The read transaction is held until all rows are sent, or until the receiving end
+    /// of `rows` is dropped, whichever comes first.
+    /// When the query is not sorted and the entry rule has a single, non-aggregated
+    /// definition whose rows cannot repeat, such as a scan of a stored relation keeping
+    /// all of its keys, the entry rule is evaluated lazily: `:limit` and `:offset` are
+    /// applied as rows are pulled, and no more rows than necessary are computed.
+    /// The rules the entry rule depends on are still evaluated in full. Other entry rules
+    /// are evaluated in full before their rows are sent, to remove duplicates.
+    pub fn run_script_iter(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        headers: Sender<Result<(Vec<String>, Option<String>)>>,
+        rows: Sender<Result<Tuple>>,
+    ) {
+        if let Err(err) = self.stream_script(payload, params, &headers, &rows) {
+            // if the headers have been sent already, the error goes to the rows
+            if let Err(Err(err)) = headers.try_send(Err(err)).map_err(|e| e.into_inner()) {
+                let _ = rows.send(Err(err));
+            }
+        }
+    }
+
+    fn stream_script(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        headers: &Sender<Result<(Vec<String>, Option<String>)>>,
+        rows: &Sender<Result<Tuple>>,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Only read-only queries can be iterated over")]
+        #[diagnostic(code(eval::iter_requires_read_only))]
+        #[diagnostic(help("Use `run_script` for scripts that mutate the database"))]
+        struct IterRequiresReadOnly;
+
+        let cur_vld = current_validity();
+        let p = parse_script_with_registries(
+            payload,
+            &params,
+            &Registries {
+                functions: &self.functions.read().unwrap(),
+                fixed_rules: &self.fixed_rules.read().unwrap(),
+                aggregations: &self.aggregations.read().unwrap(),
+                table_functions: &self.table_functions.read().unwrap(),
+            },
+            cur_vld,
+        )?
+        .get_single_program()?;
+        ensure!(
+            p.needs_write_lock().is_none() && p.out_opts.store_relation.is_none(),
+            IterRequiresReadOnly
+        );
+        let mut tx = self.transact()?;
+
+        if !p.out_opts.sorters.is_empty() || p.out_opts.assertion.is_some() {
+            // sorting and assertions need the complete result anyway,
+            // and so does the cursor, as queries with `:after` are sorted
+            let page_limit = p.out_opts.page_limit();
+            let (res, _) = self.run_query(
+                &mut tx,
+                p,
+                cur_vld,
+                &Default::default(),
+                &mut BTreeMap::new(),
+                true,
+            )?;
+            let cursor = page_cursor(page_limit, &res.rows);
+            if headers.send(Ok((res.headers, cursor))).is_err() {
+                return Ok(());
+            }
+            for row in res.rows {
+                if rows.send(Ok(row)).is_err() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        let entry_head_or_default = p.get_entry_out_head_or_default()?;
+        let (normalized_program, out_opts) = p.into_normalized_program(&tx)?;
+        let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
+        let program = stratified_program.magic_sets_rewrite(&tx)?;
+        let mut compiled = tx.stratified_magic_compile(program)?;
+
+        let poison = Poison::default();
+        if let Some(secs) = out_opts.timeout {
+            poison.set_timeout(secs)?;
+        }
+        let _guard = self.register_running_query(poison.clone())?;
+        tx.poison = poison.clone();
+
+        // the entry rule can be evaluated lazily if it is a plain, non-recursive rule
+        // with a single definition, producing distinct rows that need no deduplication
+        let entry_is_lazy = compiled.iter().all(|prog| {
+            prog.values().all(|ruleset| match ruleset {
+                CompiledRuleSet::Rules(rules) => rules
+                    .iter()
+                    .all(|rule| !rule.contained_rules.keys().any(|k| k.is_prog_entry())),
+                CompiledRuleSet::Fixed(_) => true,
+            })
+        }) && compiled.last().is_some_and(|prog| {
+            prog.iter().any(|(k, ruleset)| {
+                k.is_prog_entry()
+                    && ruleset.aggr_kind() == AggrKind::None
+                    && matches!(ruleset, CompiledRuleSet::Rules(rules)
+                        if rules.len() == 1 && rules[0].relation.has_distinct_rows())
+            })
+        });
+
+        let headers_to_send = entry_head_or_default
+            .iter()
+            .map(|s| s.to_string())
+            .collect_vec();
+
+        if !entry_is_lazy {
+            let (result_store, early_return) = tx.stratified_magic_evaluate(
+                &compiled,
+                store_lifetimes,
+                out_opts.num_to_take(),
+                out_opts.offset,
+                poison,
+            )?;
+            if headers.send(Ok((headers_to_send, None))).is_err() {
+                return Ok(());
+            }
+            let scan = if early_return {
+                Left(result_store.early_returned_iter())
+            } else {
+                Right(
+                    result_store
+                        .all_iter()
+                        .skip(out_opts.offset.unwrap_or(0))
+                        .take(out_opts.limit.unwrap_or(usize::MAX)),
+                )
+            };
+            for tuple in scan {
+                if rows.send(Ok(tuple.into_tuple())).is_err() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        let last_prog = compiled.last_mut().unwrap();
+        let entry_key = last_prog
+            .keys()
+            .find(|k| k.is_prog_entry())
+            .unwrap()
+            .clone();
+        let entry_rules = match last_prog.remove(&entry_key) {
+            Some(CompiledRuleSet::Rules(rules)) => rules,
+            _ => unreachable!(),
+        };
+        let (stores, _) = tx.stratified_magic_evaluate_stores(
+            &compiled,
+            store_lifetimes,
+            None,
+            None,
+            poison.clone(),
+        )?;
+        if headers.send(Ok((headers_to_send, None))).is_err() {
+            return Ok(());
+        }
+
+        let mut to_skip = out_opts.offset.unwrap_or(0);
+        let mut to_take = out_opts.limit.unwrap_or(usize::MAX);
+        if to_take == 0 {
+            return Ok(());
+        }
+        for item in entry_rules[0].relation.iter(&tx, None, &stores)? {
+            let item = item?;
+            poison.check()?;
+            if to_skip > 0 {
+                to_skip -= 1;
+                continue;
+            }
+            if rows.send(Ok(item)).is_err() {
+                return Ok(());
+            }
+            to_take -= 1;
+            if to_take == 0 {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
     /// Export relations to JSON data.
     ///
     /// `relations` contains names of the stored relations to export.
@@ -498,7 +787,8 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// The target stored relations must already exist in the database.
     /// Any associated indices will be updated.
     ///
-    /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
+    /// Note that triggers and callbacks are _not_ run for the relations, if any exists,
+    /// and nothing is written to their changelogs.
     /// If you need to activate triggers or callbacks, use queries with parameters.
     pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
         #[derive(Debug, Diagnostic, Error)]
@@ -644,14 +934,26 @@ impl<'s, S: Storage<'s>> Db<S> {
     pub fn backup_db(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
         #[cfg(feature = "storage-sqlite")]
         {
+            use crate::storage::write_log::write_log_seq_key;
+            use crate::storage::StoreTx;
+
             let sqlite_db = crate::new_cozo_sqlite(out_file)?;
             if sqlite_db.relation_store_id.load(Ordering::SeqCst) != 0 {
                 bail!("Cannot create backup: data exists in the target database.");
             }
-            let mut tx = self.transact()?;
-            let iter = tx.store_tx.range_scan(&[], &[0xFF]);
+            // taken before the snapshot, so that the write sets up to it are in the backup:
+            // a replica restored from it applies the later ones, some of them again
+            let write_log_seq = self.write_log_last_seq();
+            // values are copied as they are stored, so that backups of encrypted databases are too
+            let mut tx = self.db.transact(false)?;
+            let iter = tx.range_scan(&[], &[0xFF]);
             sqlite_db.db.batch_put(iter)?;
-            tx.commit_tx()?;
+            tx.commit()?;
+            if let Some(seq) = write_log_seq {
+                let mut tx = sqlite_db.db.transact(true)?;
+                tx.put(&write_log_seq_key(), &seq.to_be_bytes())?;
+                tx.commit()?;
+            }
             Ok(())
         }
         #[cfg(not(feature = "storage-sqlite"))]
@@ -662,8 +964,11 @@ impl<'s, S: Storage<'s>> Db<S> {
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
         #[cfg(feature = "storage-sqlite")]
         {
+            use crate::storage::StoreTx;
+
+            self.ensure_writable("restore a backup")?;
             let sqlite_db = crate::new_cozo_sqlite(in_file)?;
-            let mut s_tx = sqlite_db.transact()?;
+            let mut s_tx = sqlite_db.db.transact(false)?;
             {
                 let mut tx = self.transact()?;
                 let store_id = tx.relation_store_id.load(Ordering::SeqCst);
@@ -676,10 +981,10 @@ impl<'s, S: Storage<'s>> Db<S> {
                 }
                 tx.commit_tx()?;
             }
-            let iter = s_tx.store_tx.total_scan();
+            let iter = self.encrypt_restored(s_tx.total_scan())?;
             self.db.batch_put(iter)?;
-            s_tx.commit_tx()?;
-            Ok(())
+            s_tx.commit()?;
+            self.load_last_ids()
         }
         #[cfg(not(feature = "storage-sqlite"))]
         bail!("backup requires the 'storage-sqlite' feature to be enabled")
@@ -689,7 +994,8 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// have any associated indices. If you want to import into relations with indices,
     /// use [Db::import_relations].
     ///
-    /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
+    /// Note that triggers and callbacks are _not_ run for the relations, if any exists,
+    /// and nothing is written to their changelogs.
     /// If you need to activate triggers or callbacks, use queries with parameters.
     #[allow(unused_variables)]
     pub fn import_from_backup(
@@ -707,6 +1013,11 @@ impl<'s, S: Storage<'s>> Db<S> {
             let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
             let source_db = crate::new_cozo_sqlite(in_file)?;
+            if source_db.is_encrypted() {
+                if let Some(key) = self.encryption_secret() {
+                    source_db.enable_encryption(&key)?;
+                }
+            }
             let mut src_tx = source_db.transact()?;
             let mut dst_tx = self.transact_write()?;
 
@@ -783,6 +1094,160 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
     }
 
+    /// Register a custom aggregation, which can then be used in rule heads
+    /// in the same way as the built-in aggregations.
+    pub fn register_aggregation<A>(&self, name: String, aggr_impl: A) -> Result<()>
+    where
+        A: CustomAggregation + 'static,
+    {
+        if is_builtin_aggr(&name) {
+            bail!("Cannot override builtin aggregation {}", name);
+        }
+        match self.aggregations.write().unwrap().entry(name) {
+            Entry::Vacant(ent) => {
+                ent.insert(Arc::new(aggr_impl));
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "An aggregation with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister a custom aggregation.
+    pub fn unregister_aggregation(&self, name: &str) -> Result<bool> {
+        if is_builtin_aggr(name) {
+            bail!("Cannot unregister builtin aggregation {}", name);
+        }
+        Ok(self.aggregations.write().unwrap().remove(name).is_some())
+    }
+
+    /// Register a custom scalar function, which can then be used in expressions
+    /// in the same way as the built-in functions.
+    ///
+    /// * `arity`: the numbers of arguments the function accepts, e.g. `1..=2` or `1..`.
+    /// * `func`: the function implementation, called with the evaluated arguments.
+    pub fn register_function<F>(
+        &self,
+        name: String,
+        arity: impl RangeBounds<usize>,
+        func: F,
+    ) -> Result<()>
+    where
+        F: Fn(&[DataValue]) -> Result<DataValue> + Send + Sync + 'static,
+    {
+        if get_op(&name).is_some() {
+            bail!("Cannot override builtin function {}", name);
+        }
+        let min_arity = match arity.start_bound() {
+            Bound::Included(n) => *n,
+            Bound::Excluded(n) => *n + 1,
+            Bound::Unbounded => 0,
+        };
+        let max_arity = match arity.end_bound() {
+            Bound::Included(n) => Some(*n),
+            Bound::Excluded(n) => Some(n.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+        ensure!(
+            !matches!(max_arity, Some(max) if max < min_arity),
+            "The arity range of function {} is empty",
+            name
+        );
+        match self.functions.write().unwrap().entry(name) {
+            Entry::Vacant(ent) => {
+                let func = CustomFunction {
+                    name: SmartString::from(ent.key().as_str()),
+                    min_arity,
+                    max_arity,
+                    inner: Box::new(func),
+                };
+                ent.insert(Arc::new(func));
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "A function with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister a custom scalar function.
+    pub fn unregister_function(&self, name: &str) -> Result<bool> {
+        if get_op(name).is_some() {
+            bail!("Cannot unregister builtin function {}", name);
+        }
+        Ok(self.functions.write().unwrap().remove(name).is_some())
+    }
+
+    /// Register a custom table function, which can then be used in rule bodies
+    /// as `[a, b] in name(args..)`, binding each row it produces.
+    ///
+    /// * `arity`: the numbers of arguments the function accepts, e.g. `1..=2` or `1..`.
+    /// * `columns`: the number of values in each row produced.
+    /// * `func`: the function implementation, called with the evaluated arguments.
+    ///   The rows returned are consumed lazily, so they may be generated on demand.
+    pub fn register_table_function<F, I>(
+        &self,
+        name: String,
+        arity: impl RangeBounds<usize>,
+        columns: usize,
+        func: F,
+    ) -> Result<()>
+    where
+        F: Fn(&[DataValue]) -> Result<I> + Send + Sync + 'static,
+        I: IntoIterator<Item = Vec<DataValue>>,
+        I::IntoIter: 'static,
+    {
+        if DEFAULT_TABLE_FUNCTIONS.contains_key(&name) {
+            bail!("Cannot override builtin table function {}", name);
+        }
+        let min_arity = match arity.start_bound() {
+            Bound::Included(n) => *n,
+            Bound::Excluded(n) => *n + 1,
+            Bound::Unbounded => 0,
+        };
+        let max_arity = match arity.end_bound() {
+            Bound::Included(n) => Some(*n),
+            Bound::Excluded(n) => Some(n.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+        ensure!(
+            !matches!(max_arity, Some(max) if max < min_arity),
+            "The arity range of table function {} is empty",
+            name
+        );
+        match self.table_functions.write().unwrap().entry(name) {
+            Entry::Vacant(ent) => {
+                let func =
+                    TableFunction::new(ent.key(), min_arity, max_arity, columns, move |args| {
+                        Ok(Box::new(func(args)?.into_iter()))
+                    });
+                ent.insert(Arc::new(func));
+                Ok(())
+            }
+            Entry::Occupied(ent) => {
+                bail!(
+                    "A table function with the name {} is already registered",
+                    ent.key()
+                )
+            }
+        }
+    }
+
+    /// Unregister a custom table function.
+    pub fn unregister_table_function(&self, name: &str) -> Result<bool> {
+        if DEFAULT_TABLE_FUNCTIONS.contains_key(name) {
+            bail!("Cannot unregister builtin table function {}", name);
+        }
+        Ok(self.table_functions.write().unwrap().remove(name).is_some())
+    }
+
     /// Register callback channel to receive changes when the requested relation are successfully committed.
     /// The returned ID can be used to unregister the callback channel.
     #[cfg(not(target_arch = "wasm32"))]
@@ -856,36 +1321,73 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 
     fn compact_relation(&'s self) -> Result<()> {
+        self.ensure_writable("compact")?;
         let l = Tuple::default().encode_as_key(RelationId(0));
         let u = vec![DataValue::Bot].encode_as_key(RelationId(u64::MAX));
         self.db.range_compact(&l, &u)?;
         Ok(())
     }
 
-    fn load_last_ids(&'s self) -> Result<()> {
-        let mut tx = self.transact_write()?;
-        self.relation_store_id
-            .store(tx.init_storage()?.0, Ordering::Release);
-        tx.commit_tx()?;
+    /// Whether the database was opened read-only, in which case all writes fail.
+    pub fn is_read_only(&self) -> bool {
+        self.db.is_read_only()
+    }
+    /// Make visible the changes made by the process writing to the database,
+    /// if it was opened as a secondary instance. Does nothing otherwise.
+    pub fn catch_up_with_primary(&'s self) -> Result<()> {
+        self.db.catch_up_with_primary()?;
+        self.load_last_ids()
+    }
+    pub(crate) fn ensure_writable(&self, op: &'static str) -> Result<()> {
+        if self.db.is_read_only() {
+            bail!(ReadOnlyDb(op))
+        }
         Ok(())
     }
+    pub(crate) fn load_last_ids(&'s self) -> Result<()> {
+        {
+            let mut tx = self.transact_write()?;
+            self.relation_store_id
+                .store(tx.init_storage()?.0, Ordering::Release);
+            tx.commit_tx()?;
+        }
+        self.load_write_log_seq()?;
+        self.load_encryption_state()
+    }
     pub(crate) fn transact(&'s self) -> Result<SessionTx<'_>> {
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(false)?),
+            store_tx: self.with_encryption(self.db.transact(false)?),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            functions: self.functions.clone(),
+            poison: Default::default(),
+            hash_join_memory_budget: self.hash_join_memory_budget.load(Ordering::Relaxed),
         };
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        // values are encrypted before they are recorded in the write log
+        let store_tx = if self.db.is_read_only() {
+            self.with_encryption(ReadOnlyTx(self.db.transact(false)?))
+        } else if self.write_log_last_seq().is_some() {
+            self.with_encryption(WriteLogTx::new(
+                self.db.transact(true)?,
+                self.write_log.clone(),
+            ))
+        } else {
+            self.with_encryption(self.db.transact(true)?)
+        };
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx,
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            functions: self.functions.clone(),
+            poison: Default::default(),
+            hash_join_memory_budget: self.hash_join_memory_budget.load(Ordering::Relaxed),
         };
         Ok(ret)
     }
@@ -917,8 +1419,37 @@ impl<'s, S: Storage<'s>> Db<S> {
         p: InputProgram,
         read_only: bool,
     ) -> Result<NamedRows, Report> {
-        let mut callback_collector = BTreeMap::new();
         let write_lock_names = p.needs_write_lock();
+        self.execute_in_single_tx(
+            write_lock_names,
+            read_only,
+            |tx, cleanups, callback_targets, callback_collector| {
+                self.execute_single_program(
+                    p,
+                    tx,
+                    cleanups,
+                    cur_vld,
+                    callback_targets,
+                    callback_collector,
+                )
+            },
+        )
+    }
+    /// Run `f` in a transaction of its own, taking the write lock on the relation
+    /// named in `write_lock_names` if there is one, and sending callbacks after
+    /// the transaction is committed.
+    pub(crate) fn execute_in_single_tx<T>(
+        &'s self,
+        write_lock_names: Option<SmartString<LazyCompact>>,
+        read_only: bool,
+        f: impl FnOnce(
+            &mut SessionTx<'_>,
+            &mut Vec<(Vec<u8>, Vec<u8>)>,
+            &BTreeSet<SmartString<LazyCompact>>,
+            &mut CallbackCollector,
+        ) -> Result<T>,
+    ) -> Result<T> {
+        let mut callback_collector = BTreeMap::new();
         let is_write = write_lock_names.is_some();
         if read_only && is_write {
             bail!("write lock required for read-only query");
@@ -943,11 +1474,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                 self.transact()?
             };
 
-            res = self.execute_single_program(
-                p,
+            res = f(
                 &mut tx,
                 &mut cleanups,
-                cur_vld,
                 &callback_targets,
                 &mut callback_collector,
             )?;
@@ -976,6 +1505,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         const OUT_BINDINGS: &str = "out_relation";
         const JOINS_ON: &str = "joins_on";
         const FILTERS: &str = "filters/expr";
+        const EST_ROWS: &str = "est_rows";
 
         let headers = vec![
             STRATUM.to_string(),
@@ -987,6 +1517,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             JOINS_ON.to_string(),
             FILTERS.to_string(),
             OUT_BINDINGS.to_string(),
+            EST_ROWS.to_string(),
         ];
 
         for (stratum, p) in strata.iter().enumerate() {
@@ -1016,7 +1547,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                                 OP: atom_type,
                                 RULE_IDX: clause_idx,
                                 RULE_NAME: rule_name.to_string(),
-                                OUT_BINDINGS: relation.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec()
+                                OUT_BINDINGS: relation.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec(),
+                                EST_ROWS: relation.estimated_rows().map(|n| n.round()),
                             }));
                             idx += 1;
 
@@ -1072,6 +1604,18 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         rel_stack.push(right);
                                         (t, json!(null), json!(joiner.as_map()), json!(null))
                                     }
+                                    RelAlgebra::HashJoin(inner) => {
+                                        let t = inner.join_type();
+                                        let HashJoin {
+                                            left,
+                                            right,
+                                            joiner,
+                                            ..
+                                        } = inner.as_ref();
+                                        rel_stack.push(left);
+                                        rel_stack.push(right);
+                                        (t, json!(null), json!(joiner.as_map()), json!(null))
+                                    }
                                     RelAlgebra::NegJoin(inner) => {
                                         let t = inner.join_type();
                                         let NegJoin {
@@ -1084,6 +1628,18 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         rel_stack.push(right);
                                         (t, json!(null), json!(joiner.as_map()), json!(null))
                                     }
+                                    RelAlgebra::LeftJoin(inner) => {
+                                        let t = inner.join_type();
+                                        let LeftJoin {
+                                            left,
+                                            right,
+                                            joiner,
+                                            ..
+                                        } = inner.as_ref();
+                                        rel_stack.push(left);
+                                        rel_stack.push(right);
+                                        (t, json!(null), json!(joiner.as_map()), json!(null))
+                                    }
                                     RelAlgebra::Reorder(ReorderRA { relation, .. }) => {
                                         rel_stack.push(relation);
                                         ("reorder", json!(null), json!(null), json!(null))
@@ -1116,6 +1672,20 @@ impl<'s, S: Storage<'s>> Db<S> {
                                             json!(expr.to_string()),
                                         )
                                     }
+                                    RelAlgebra::TableFunction(TableFunctionRA {
+                                        parent,
+                                        func,
+                                        args,
+                                        ..
+                                    }) => {
+                                        rel_stack.push(parent);
+                                        (
+                                            "table_function",
+                                            json!(func.name),
+                                            json!(null),
+                                            json!(args.iter().map(|a| a.to_string()).collect_vec()),
+                                        )
+                                    }
                                     RelAlgebra::HnswSearch(HnswSearchRA {
                                         hnsw_search, ..
                                     }) => (
@@ -1159,6 +1729,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                                     OUT_BINDINGS: rel.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec(),
                                     JOINS_ON: joins_on,
                                     FILTERS: filters,
+                                    EST_ROWS: rel.estimated_rows().map(|n| n.round()),
                                 }));
                                 idx += 1;
                             }
@@ -1251,6 +1822,67 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::Analyze(rel_name) => {
+                if read_only {
+                    bail!("Cannot analyze relations in read-only mode");
+                }
+                let stats = if skip_locking {
+                    tx.analyze_relation(rel_name)?
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.analyze_relation(rel_name)?
+                };
+                Ok(NamedRows::new(
+                    vec![
+                        "relation".to_string(),
+                        "rows".to_string(),
+                        "key_prefix_cardinalities".to_string(),
+                    ],
+                    vec![vec![
+                        DataValue::from(&rel_name.name as &str),
+                        DataValue::from(stats.row_count as i64),
+                        DataValue::List(
+                            stats
+                                .key_prefix_cardinalities
+                                .iter()
+                                .map(|c| DataValue::from(*c as i64))
+                                .collect_vec(),
+                        ),
+                    ]],
+                ))
+            }
+            SysOp::Changelog(rel_name, op) => {
+                if read_only && *op != ChangelogOp::Show {
+                    bail!("Cannot change changelogs in read-only mode");
+                }
+                let lock = if skip_locking {
+                    None
+                } else {
+                    self.obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                };
+                let _guard = lock.as_ref().map(|l| l.write().unwrap());
+                let to_clear = match op {
+                    ChangelogOp::Show => return tx.changelog_status(rel_name),
+                    ChangelogOp::Enable => {
+                        tx.enable_changelog(rel_name)?;
+                        None
+                    }
+                    ChangelogOp::Disable => Some(tx.disable_changelog(rel_name)?),
+                    ChangelogOp::Truncate(up_to) => Some(tx.truncate_changelog(rel_name, *up_to)?),
+                };
+                if let Some((lower, upper)) = to_clear {
+                    tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+                }
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::DescribeRelation(rel_name, description) => {
                 tx.describe_relation(rel_name, description)?;
                 Ok(NamedRows::new(
@@ -1451,6 +2083,26 @@ impl<'s, S: Storage<'s>> Db<S> {
         tx.commit_tx()?;
         Ok(res)
     }
+    /// Give the query an ID and store it so that it can be queried and cancelled.
+    /// The query is removed when the returned guard is dropped.
+    fn register_running_query(&self, poison: Poison) -> Result<RunningQueryCleanup> {
+        let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
+
+        // time the query
+        let since_the_epoch = seconds_since_the_epoch()?;
+
+        let handle = RunningQueryHandle {
+            started_at: since_the_epoch,
+            poison,
+        };
+        self.running_queries.lock().unwrap().insert(id, handle);
+
+        // RAII cleanups of running query handle
+        Ok(RunningQueryCleanup {
+            id,
+            running_queries: self.running_queries.clone(),
+        })
+    }
     /// This is the entry to query evaluation
     pub(crate) fn run_query(
         &self,
@@ -1461,11 +2113,23 @@ impl<'s, S: Storage<'s>> Db<S> {
         callback_collector: &mut CallbackCollector,
         top_level: bool,
     ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
-        // cleanups contain stored relations that should be deleted at the end of query
-        let mut clean_ups = vec![];
-
-        // Some checks in case the query specifies mutation
-        if let Some((meta, op, _)) = &input_program.out_opts.store_relation {
+        Self::check_store_relation(tx, &input_program.out_opts)?;
+        let plan = Self::compile_query(tx, input_program)?;
+        self.run_query_plan(
+            tx,
+            plan,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            top_level,
+        )
+    }
+    /// Some checks in case the query specifies mutation
+    pub(crate) fn check_store_relation(
+        tx: &SessionTx<'_>,
+        out_opts: &QueryOutOptions,
+    ) -> Result<()> {
+        if let Some((meta, op, _)) = &out_opts.store_relation {
             if *op == RelationOp::Create {
                 #[derive(Debug, Error, Diagnostic)]
                 #[error("Stored relation {0} conflicts with an existing one")]
@@ -1495,36 +2159,49 @@ impl<'s, S: Storage<'s>> Db<S> {
                 )?;
             }
         };
-
-        // query compilation
-        let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
+        Ok(())
+    }
+    pub(crate) fn compile_query(
+        tx: &mut SessionTx<'_>,
+        input_program: InputProgram,
+    ) -> Result<QueryPlan> {
+        let entry_head = input_program.get_entry_out_head_or_default()?;
         let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
         let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
         let program = stratified_program.magic_sets_rewrite(tx)?;
         let compiled = tx.stratified_magic_compile(program)?;
+        Ok(QueryPlan {
+            entry_head,
+            compiled,
+            store_lifetimes,
+            out_opts,
+        })
+    }
+    pub(crate) fn run_query_plan(
+        &self,
+        tx: &mut SessionTx<'_>,
+        plan: QueryPlan,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        top_level: bool,
+    ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        // cleanups contain stored relations that should be deleted at the end of query
+        let mut clean_ups = vec![];
+        let QueryPlan {
+            entry_head: entry_head_or_default,
+            compiled,
+            store_lifetimes,
+            out_opts,
+        } = plan;
 
         // poison is used to terminate queries early
         let poison = Poison::default();
         if let Some(secs) = out_opts.timeout {
             poison.set_timeout(secs)?;
         }
-        // give the query an ID and store it so that it can be queried and cancelled
-        let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
-
-        // time the query
-        let since_the_epoch = seconds_since_the_epoch()?;
-
-        let handle = RunningQueryHandle {
-            started_at: since_the_epoch,
-            poison: poison.clone(),
-        };
-        self.running_queries.lock().unwrap().insert(id, handle);
-
-        // RAII cleanups of running query handle
-        let _guard = RunningQueryCleanup {
-            id,
-            running_queries: self.running_queries.clone(),
-        };
+        let _guard = self.register_running_query(poison.clone())?;
+        tx.poison = poison.clone();
 
         let total_num_to_take = if out_opts.sorters.is_empty() {
             out_opts.num_to_take()
@@ -1575,12 +2252,18 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         if !out_opts.sorters.is_empty() {
             // sort outputs if required
-            let sorted_result =
-                tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?;
+            let sorted_result = tx.sort_and_collect(
+                result_store,
+                &out_opts.sorters,
+                &entry_head_or_default,
+                out_opts.num_to_take(),
+                out_opts.after.as_ref().filter(|row| !row.is_empty()),
+                self.sort_memory_budget.load(Ordering::Relaxed),
+            )?;
             let sorted_iter = if let Some(offset) = out_opts.offset {
-                Left(sorted_result.into_iter().skip(offset))
+                Left(sorted_result.skip(offset))
             } else {
-                Right(sorted_result.into_iter())
+                Right(sorted_result)
             };
             let sorted_iter = if let Some(limit) = out_opts.limit {
                 Left(sorted_iter.take(limit))
@@ -1758,6 +2441,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                     "n_bands": manifest.n_bands,
                     "n_rows_in_band": manifest.n_rows_in_band,
                     "threshold": manifest.threshold,
+                    "false_positive_weight": manifest.false_positive_weight,
+                    "false_negative_weight": manifest.false_negative_weight,
                 }),
             ]);
         }
@@ -1835,12 +2520,14 @@ impl<'s, S: Storage<'s>> Db<S> {
             let n_keys = meta.metadata.keys.len();
             let n_dependents = meta.metadata.non_keys.len();
             let arity = n_keys + n_dependents;
-            let name = meta.name;
-            let access_level = if name.contains(':') {
+            let access_level = if meta.is_changelog() {
+                "changelog".to_string()
+            } else if meta.name.contains(':') {
                 "index".to_string()
             } else {
                 meta.access_level.to_string()
             };
+            let name = meta.name;
             rows.push(vec![
                 json!(name),
                 json!(arity),
@@ -1905,7 +2592,7 @@ fn _evaluate_expressions(
     params: &BTreeMap<String, DataValue>,
     vars: &BTreeMap<String, DataValue>,
 ) -> Result<DataValue> {
-    let mut expr = parse_expressions(src, params)?;
+    let mut expr = parse_expressions(src, params, &Default::default())?;
     let mut ctx = vec![];
     let mut binding_map = BTreeMap::new();
     for (i, (k, v)) in vars.iter().enumerate() {
@@ -1917,7 +2604,7 @@ fn _evaluate_expressions(
 }
 
 fn _get_variables(src: &str, params: &BTreeMap<String, DataValue>) -> Result<BTreeSet<String>> {
-    let expr = parse_expressions(src, params)?;
+    let expr = parse_expressions(src, params, &Default::default())?;
     expr.get_variables()
 }
 
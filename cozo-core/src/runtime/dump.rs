@@ -0,0 +1,492 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::data::relation::ColumnDef;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::fts::TokenizerConfig;
+use crate::runtime::relation::{AccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{decode_tuple_from_kv, Db, NamedRows, ScriptMutability, Storage};
+
+const DUMP_FORMAT: &str = "cozo-dump";
+const DUMP_VERSION: u32 = 1;
+const DUMP_CHUNK_ROWS: usize = 4096;
+
+/// Counts of what was written by [Db::dump] or read by [Db::load].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpSummary {
+    /// Number of stored relations
+    pub relations: usize,
+    /// Number of rows in all relations
+    pub rows: u64,
+}
+
+/// A relation in a dump, with everything needed to recreate it but its rows.
+///
+/// The schema and the indices are kept as CozoScript, so that the dump does not depend
+/// on how the storage engine or the Cozo version lays out relations.
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpedRelation {
+    name: String,
+    /// The `:create` script of the relation
+    ddl: String,
+    /// Keys followed by non-keys, the column order of the rows
+    columns: Vec<String>,
+    /// `::index`, `::hnsw`, `::fts` and `::lsh` scripts of the indices
+    indices: Vec<String>,
+    put_triggers: Vec<String>,
+    rm_triggers: Vec<String>,
+    replace_triggers: Vec<String>,
+    description: String,
+    access_level: AccessLevel,
+    /// Whether the relation keeps a changelog, whose entries are not dumped
+    #[serde(default)]
+    changelog: bool,
+}
+
+/// The dump is a stream of these records: a header, all relations, their rows
+/// in chunks, and an end marker, which tells a complete dump from a truncated one.
+/// Each record is written as its length followed by its MessagePack encoding.
+#[derive(Debug, Serialize, Deserialize)]
+enum DumpRecord {
+    Header {
+        format: String,
+        version: u32,
+        cozo_version: String,
+    },
+    Relation(DumpedRelation),
+    Rows {
+        relation: String,
+        rows: Vec<Vec<DataValue>>,
+    },
+    End(DumpSummary),
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Not a Cozo dump")]
+#[diagnostic(code(dump::bad_format))]
+struct NotADump;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The dump has format version {0}, only versions up to {DUMP_VERSION} are supported")]
+#[diagnostic(code(dump::unsupported_version))]
+#[diagnostic(help("The dump was made by Cozo {1}, try loading it with that version"))]
+struct UnsupportedDumpVersion(u32, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot dump index '{0}' on its own")]
+#[diagnostic(code(dump::index_relation))]
+#[diagnostic(help("Indices are dumped together with the relation they belong to"))]
+struct DumpIndexRelation(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The dump contains rows for relation '{0}' before its schema")]
+#[diagnostic(code(dump::rows_without_relation))]
+struct RowsWithoutRelation(String);
+
+fn write_record(writer: &mut impl Write, record: &DumpRecord) -> Result<()> {
+    let payload = rmp_serde::to_vec_named(record).into_diagnostic()?;
+    writer
+        .write_u64::<LE>(payload.len() as u64)
+        .into_diagnostic()?;
+    writer.write_all(&payload).into_diagnostic()
+}
+
+fn read_record(reader: &mut impl Read, buf: &mut Vec<u8>) -> Result<DumpRecord> {
+    let len = reader.read_u64::<LE>().into_diagnostic()?;
+    buf.clear();
+    reader.take(len).read_to_end(buf).into_diagnostic()?;
+    if buf.len() as u64 != len {
+        bail!("unexpected end of the dump")
+    }
+    // values such as vectors are only decoded from a buffer
+    rmp_serde::from_slice(buf).into_diagnostic()
+}
+
+fn tokenizer_script(config: &TokenizerConfig) -> String {
+    if config.args.is_empty() {
+        config.name.to_string()
+    } else {
+        format!("{}({})", config.name, config.args.iter().join(", "))
+    }
+}
+
+fn relation_ddl(handle: &RelationHandle) -> String {
+    let column = |col: &ColumnDef| match &col.default_gen {
+        None => format!("{}: {}", col.name, col.typing),
+        Some(expr) => format!("{}: {} default {}", col.name, col.typing, expr),
+    };
+    let keys = handle.metadata.keys.iter().map(column).join(", ");
+    if handle.metadata.non_keys.is_empty() {
+        format!(":create {} {{{}}}", handle.name, keys)
+    } else {
+        let non_keys = handle.metadata.non_keys.iter().map(column).join(", ");
+        format!(":create {} {{{} => {}}}", handle.name, keys, non_keys)
+    }
+}
+
+fn index_scripts(handle: &RelationHandle) -> Vec<String> {
+    let col_name = |i: &usize| {
+        handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .nth(*i)
+            .unwrap()
+            .name
+            .clone()
+    };
+    let mut scripts = vec![];
+    for (name, (_, cols)) in &handle.indices {
+        scripts.push(format!(
+            "::index create {}:{} {{{}}}",
+            handle.name,
+            name,
+            cols.iter().map(col_name).join(", ")
+        ));
+    }
+    for (name, (_, manifest)) in &handle.hnsw_indices {
+        let mut opts = vec![
+            format!("dim: {}", manifest.vec_dim),
+            format!("m: {}", manifest.m_neighbours),
+            format!("dtype: {:?}", manifest.dtype),
+            format!(
+                "fields: [{}]",
+                manifest.vec_fields.iter().map(col_name).join(", ")
+            ),
+            format!("distance: {:?}", manifest.distance).replace("InnerProduct", "IP"),
+            format!("ef_construction: {}", manifest.ef_construction),
+            format!("extend_candidates: {}", manifest.extend_candidates),
+            format!(
+                "keep_pruned_connections: {}",
+                manifest.keep_pruned_connections
+            ),
+        ];
+        if let Some(filter) = &manifest.index_filter {
+            opts.push(format!("filter: {filter}"));
+        }
+        scripts.push(format!(
+            "::hnsw create {}:{} {{{}}}",
+            handle.name,
+            name,
+            opts.join(", ")
+        ));
+    }
+    // an empty list of filters does not parse, so it is left out
+    let filters_opt = |filters: &[TokenizerConfig]| {
+        (!filters.is_empty()).then(|| {
+            format!(
+                "filters: [{}]",
+                filters.iter().map(tokenizer_script).join(", ")
+            )
+        })
+    };
+    for (name, (_, manifest)) in &handle.fts_indices {
+        let mut opts = vec![
+            format!("extractor: {}", manifest.extractor),
+            format!("tokenizer: {}", tokenizer_script(&manifest.tokenizer)),
+        ];
+        opts.extend(filters_opt(&manifest.filters));
+        scripts.push(format!(
+            "::fts create {}:{} {{{}}}",
+            handle.name,
+            name,
+            opts.join(", ")
+        ));
+    }
+    for (name, (_, _, manifest)) in &handle.lsh_indices {
+        let mut opts = vec![
+            format!("extractor: {}", manifest.extractor),
+            format!("tokenizer: {}", tokenizer_script(&manifest.tokenizer)),
+            format!("n_gram: {}", manifest.n_gram),
+            format!("n_perm: {}", manifest.num_perm),
+            format!("target_threshold: {:?}", manifest.threshold),
+            format!(
+                "false_positive_weight: {:?}",
+                manifest.false_positive_weight
+            ),
+            format!(
+                "false_negative_weight: {:?}",
+                manifest.false_negative_weight
+            ),
+        ];
+        opts.extend(filters_opt(&manifest.filters));
+        scripts.push(format!(
+            "::lsh create {}:{} {{{}}}",
+            handle.name,
+            name,
+            opts.join(", ")
+        ));
+    }
+    scripts
+}
+
+fn all_relations(tx: &SessionTx<'_>) -> Result<Vec<RelationHandle>> {
+    let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+    let upper =
+        vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+    let mut ret = vec![];
+    for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+        let (k_slice, v_slice) = kv_res?;
+        if upper <= k_slice {
+            break;
+        }
+        let handle = RelationHandle::decode(&v_slice)?;
+        if !handle.name.contains(':') {
+            ret.push(handle);
+        }
+    }
+    Ok(ret)
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Dump stored relations to `writer` in a format that [Db::load] reads back,
+    /// into a database using any storage engine and a later Cozo version.
+    ///
+    /// If `relations` is empty, all stored relations are dumped. Along with the rows,
+    /// the schema, indices, triggers, description, access level and whether the changelog
+    /// is enabled are kept for each relation. The entries of changelogs are not.
+    /// The dump is written as it is read, from a single snapshot of the database.
+    pub fn dump<I, T>(&'s self, writer: impl Write, relations: I) -> Result<DumpSummary>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>,
+    {
+        let mut writer = BufWriter::new(writer);
+        let tx = self.transact()?;
+        let requested = relations.into_iter().collect_vec();
+        let handles = if requested.is_empty() {
+            all_relations(&tx)?
+        } else {
+            requested
+                .iter()
+                .map(|name| {
+                    let name = name.as_ref();
+                    if name.contains(':') {
+                        bail!(DumpIndexRelation(name.to_string()))
+                    }
+                    tx.get_relation(name, false)
+                })
+                .try_collect()?
+        };
+        write_record(
+            &mut writer,
+            &DumpRecord::Header {
+                format: DUMP_FORMAT.to_string(),
+                version: DUMP_VERSION,
+                cozo_version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+        )?;
+        for handle in &handles {
+            let dumped = DumpedRelation {
+                name: handle.name.to_string(),
+                ddl: relation_ddl(handle),
+                columns: handle
+                    .metadata
+                    .keys
+                    .iter()
+                    .chain(handle.metadata.non_keys.iter())
+                    .map(|col| col.name.to_string())
+                    .collect(),
+                indices: index_scripts(handle),
+                put_triggers: handle.put_triggers.clone(),
+                rm_triggers: handle.rm_triggers.clone(),
+                replace_triggers: handle.replace_triggers.clone(),
+                description: handle.description.to_string(),
+                access_level: handle.access_level,
+                changelog: handle.changelog.is_some(),
+            };
+            write_record(&mut writer, &DumpRecord::Relation(dumped))?;
+        }
+        let mut summary = DumpSummary {
+            relations: handles.len(),
+            rows: 0,
+        };
+        for handle in &handles {
+            let size_hint = handle.metadata.keys.len() + handle.metadata.non_keys.len();
+            let start = Tuple::default().encode_as_key(handle.id);
+            let end = Tuple::default().encode_as_key(handle.id.next());
+            let mut rows = vec![];
+            for data in tx.store_tx.range_scan(&start, &end) {
+                let (k, v) = data?;
+                rows.push(decode_tuple_from_kv(&k, &v, Some(size_hint)));
+                if rows.len() >= DUMP_CHUNK_ROWS {
+                    summary.rows += rows.len() as u64;
+                    write_record(
+                        &mut writer,
+                        &DumpRecord::Rows {
+                            relation: handle.name.to_string(),
+                            rows: std::mem::take(&mut rows),
+                        },
+                    )?;
+                }
+            }
+            if !rows.is_empty() {
+                summary.rows += rows.len() as u64;
+                write_record(
+                    &mut writer,
+                    &DumpRecord::Rows {
+                        relation: handle.name.to_string(),
+                        rows,
+                    },
+                )?;
+            }
+        }
+        write_record(&mut writer, &DumpRecord::End(summary.clone()))?;
+        writer.flush().into_diagnostic()?;
+        Ok(summary)
+    }
+
+    /// Load a dump made by [Db::dump] from `reader`.
+    ///
+    /// The relations in the dump must not exist in this database.
+    /// Rows are loaded in chunks, each in its own transaction, and indices are built
+    /// after all rows are in. Triggers are not run for the loaded rows, and relations
+    /// that kept a changelog start a new one, empty. If loading fails, the relations
+    /// created so far are removed again before the error is returned.
+    pub fn load(&'s self, reader: impl Read) -> Result<DumpSummary> {
+        let mut reader = BufReader::new(reader);
+        let mut buf = vec![];
+        let mut next_record = || -> Result<DumpRecord> {
+            read_record(&mut reader, &mut buf)
+                .wrap_err("Failed to read the dump, it may be truncated or corrupt")
+        };
+        match next_record().map_err(|_| NotADump)? {
+            DumpRecord::Header {
+                format,
+                version,
+                cozo_version,
+            } => {
+                if format != DUMP_FORMAT {
+                    bail!(NotADump)
+                }
+                if version > DUMP_VERSION {
+                    bail!(UnsupportedDumpVersion(version, cozo_version))
+                }
+            }
+            _ => bail!(NotADump),
+        }
+        let mut relations: BTreeMap<String, DumpedRelation> = BTreeMap::new();
+        match self.load_relations(&mut next_record, &mut relations) {
+            Ok(loaded) => Ok(loaded),
+            Err(err) => match self.remove_loaded(relations.keys()) {
+                Ok(()) => Err(err),
+                Err(cleanup) => Err(err.wrap_err(format!(
+                    "Failed to remove the partially loaded relations: {cleanup}"
+                ))),
+            },
+        }
+    }
+
+    fn load_relations(
+        &'s self,
+        next_record: &mut impl FnMut() -> Result<DumpRecord>,
+        relations: &mut BTreeMap<String, DumpedRelation>,
+    ) -> Result<DumpSummary> {
+        let mut loaded = DumpSummary::default();
+        let expected = loop {
+            match next_record()? {
+                DumpRecord::Header { .. } => bail!(NotADump),
+                DumpRecord::Relation(rel) => {
+                    self.run_script(&rel.ddl, Default::default(), ScriptMutability::Mutable)
+                        .wrap_err_with(|| format!("Failed to create relation '{}'", rel.name))?;
+                    loaded.relations += 1;
+                    relations.insert(rel.name.clone(), rel);
+                }
+                DumpRecord::Rows { relation, rows } => {
+                    let rel = relations
+                        .get(&relation)
+                        .ok_or_else(|| RowsWithoutRelation(relation.clone()))?;
+                    loaded.rows += rows.len() as u64;
+                    self.import_relations(BTreeMap::from([(
+                        relation,
+                        NamedRows::new(rel.columns.clone(), rows),
+                    )]))?;
+                }
+                DumpRecord::End(summary) => break summary,
+            }
+        };
+        if expected != loaded {
+            bail!(
+                "The dump is inconsistent: it should contain {} relations with {} rows, found {} with {}",
+                expected.relations,
+                expected.rows,
+                loaded.relations,
+                loaded.rows
+            )
+        }
+        for rel in relations.values() {
+            for script in &rel.indices {
+                self.run_script(script, Default::default(), ScriptMutability::Mutable)
+                    .wrap_err_with(|| format!("Failed to create index of '{}'", rel.name))?;
+            }
+        }
+        let mut tx = self.transact_write()?;
+        for rel in relations.values() {
+            let name = Symbol::new(rel.name.as_str(), Default::default());
+            if !rel.put_triggers.is_empty()
+                || !rel.rm_triggers.is_empty()
+                || !rel.replace_triggers.is_empty()
+            {
+                tx.set_relation_triggers(
+                    &name,
+                    &rel.put_triggers,
+                    &rel.rm_triggers,
+                    &rel.replace_triggers,
+                )?;
+            }
+            if !rel.description.is_empty() {
+                tx.describe_relation(&rel.name, &rel.description)?;
+            }
+            if rel.changelog {
+                tx.enable_changelog(&name)?;
+            }
+            if rel.access_level != AccessLevel::Normal {
+                tx.set_access_level(&name, rel.access_level)?;
+            }
+        }
+        tx.commit_tx()?;
+        Ok(loaded)
+    }
+
+    fn remove_loaded<'a>(&'s self, names: impl Iterator<Item = &'a String>) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        let mut bounds = vec![];
+        for name in names {
+            let handle = tx.get_relation(name, true)?;
+            let rel_name = Symbol::new(name.as_str(), Default::default());
+            let idx_names = handle
+                .indices
+                .keys()
+                .chain(handle.hnsw_indices.keys())
+                .chain(handle.lsh_indices.keys())
+                .chain(handle.fts_indices.keys());
+            for idx_name in idx_names {
+                let idx_name = Symbol::new(idx_name.as_str(), Default::default());
+                bounds.extend(tx.remove_index(&rel_name, &idx_name)?);
+            }
+            bounds.extend(tx.destroy_relation(name)?);
+        }
+        for (lower, upper) in bounds {
+            tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+        }
+        tx.commit_tx()
+    }
+}
@@ -0,0 +1,231 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use itertools::Itertools;
+use miette::{bail, Result};
+
+use crate::storage::encrypted::{
+    encryption_header_key, is_bookkeeping_key, is_clear, EncryptExistingDb, EncryptedTx,
+    EncryptionHeader, EncryptionKeyRequired, EncryptionState, Keyring, NotEncrypted,
+    RotationBlocked,
+};
+use crate::storage::write_log::{write_log_bounds, WriteOp, WriteSet};
+use crate::storage::{Storage, StoreTx};
+use crate::Db;
+
+type KvIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+/// Number of values re-encrypted between two seeks when rotating the data key.
+const REENCRYPT_BATCH_SIZE: usize = 1024;
+/// How many times, 10 milliseconds apart, rotating the data key checks whether
+/// the transactions started before it are finished, before giving up.
+const ROTATION_WAIT_ROUNDS: usize = 3000;
+
+/// Wait until the transactions using `keyring` are finished: they could otherwise
+/// write values encrypted with a data key about to be retired.
+fn wait_for_transactions(keyring: &Arc<Keyring>) -> Result<()> {
+    // one reference is held by the encryption state, and one by the caller
+    for _ in 0..ROTATION_WAIT_ROUNDS {
+        if Arc::strong_count(keyring) <= 2 {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    bail!(RotationBlocked)
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    pub(crate) fn load_encryption_state(&'s self) -> Result<()> {
+        let header = self
+            .db
+            .transact(false)?
+            .get(&encryption_header_key(), false)?;
+        let mut state = self.encryption.write().unwrap();
+        *state = match (header, &*state) {
+            (None, _) => EncryptionState::Disabled,
+            // the header may have been replaced by a restored backup
+            (Some(header), EncryptionState::Unlocked(keyring)) => {
+                EncryptionState::Unlocked(Arc::new(Keyring::unlock(
+                    &EncryptionHeader::decode(&header)?,
+                    &keyring.secret,
+                )?))
+            }
+            (Some(_), _) => EncryptionState::Locked,
+        };
+        Ok(())
+    }
+    /// Wrap the transaction so that values are encrypted, if the database is.
+    pub(crate) fn with_encryption<T: StoreTx<'s> + 's>(&self, tx: T) -> Box<dyn StoreTx<'s> + 's> {
+        match &*self.encryption.read().unwrap() {
+            EncryptionState::Disabled => Box::new(tx),
+            EncryptionState::Locked => Box::new(EncryptedTx {
+                inner: tx,
+                keyring: None,
+            }),
+            EncryptionState::Unlocked(keyring) => Box::new(EncryptedTx {
+                inner: tx,
+                keyring: Some(keyring.clone()),
+            }),
+        }
+    }
+    /// Give the key the database is encrypted with. If the database is new, it is encrypted
+    /// with the key from now on: a data key is generated and stored encrypted with a key derived
+    /// from `key`, and all values stored are encrypted with the data key using AES-256-GCM.
+    /// Keys are stored in the clear to preserve their order.
+    ///
+    /// [DbInstance::new](crate::DbInstance::new) calls this when given the `encryption_key` option.
+    /// Until it is called, an encrypted database fails all queries touching stored relations.
+    /// Rows spilled to disk by sorting large results are not encrypted.
+    pub fn enable_encryption(&'s self, key: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            bail!("the encryption key must not be empty")
+        }
+        let mut state = self.encryption.write().unwrap();
+        let header = self
+            .db
+            .transact(false)?
+            .get(&encryption_header_key(), false)?;
+        let keyring = match header {
+            Some(header) => Keyring::unlock(&EncryptionHeader::decode(&header)?, key)?,
+            None => {
+                self.ensure_writable("encrypt the database")?;
+                if self.relation_store_id.load(Ordering::Acquire) != 0 {
+                    bail!(EncryptExistingDb)
+                }
+                let keyring = Keyring::new(key);
+                let mut tx = self.db.transact(true)?;
+                tx.put(&encryption_header_key(), &keyring.header().encode())?;
+                tx.commit()?;
+                keyring
+            }
+        };
+        *state = EncryptionState::Unlocked(Arc::new(keyring));
+        Ok(())
+    }
+    /// Whether the database is encrypted, whether its key was given or not.
+    pub fn is_encrypted(&self) -> bool {
+        !matches!(*self.encryption.read().unwrap(), EncryptionState::Disabled)
+    }
+    /// Change the key the database is encrypted with to `new_key`. The data keys are
+    /// encrypted again with a key derived from `new_key`, which is cheap.
+    ///
+    /// If `new_data_key` is set, a new data key is generated as well, all values, including
+    /// those in the write log, are encrypted again with it in one transaction, and the old
+    /// data keys are forgotten. New transactions are not started until this is done, and
+    /// it first waits for the transactions already running to finish, failing if they
+    /// do not within 30 seconds. Replicas must then be bootstrapped again.
+    pub fn rotate_encryption_key(&'s self, new_key: &[u8], new_data_key: bool) -> Result<()> {
+        self.ensure_writable("rotate the encryption key")?;
+        if new_key.is_empty() {
+            bail!("the encryption key must not be empty")
+        }
+        let mut state = self.encryption.write().unwrap();
+        let old = match &*state {
+            EncryptionState::Disabled => bail!(NotEncrypted),
+            EncryptionState::Locked => bail!(EncryptionKeyRequired),
+            EncryptionState::Unlocked(keyring) => keyring.clone(),
+        };
+        let mut keyring = old.rotate(new_key, new_data_key);
+        if new_data_key {
+            wait_for_transactions(&old)?;
+        }
+        drop(old);
+        let mut tx = self.db.transact(true)?;
+        if new_data_key {
+            // the write log comes last, past the relations
+            let (log_lower, log_upper) = write_log_bounds();
+            let mut lower = vec![];
+            loop {
+                let batch: Vec<_> = tx
+                    .range_scan(&lower, &log_upper)
+                    .take(REENCRYPT_BATCH_SIZE)
+                    .try_collect()?;
+                let last = match batch.last() {
+                    None => break,
+                    Some((k, _)) => k.clone(),
+                };
+                for (k, v) in batch {
+                    if k >= log_lower {
+                        // the values written are recorded as they are stored
+                        let mut write_set = WriteSet::decode(&v)?;
+                        for op in write_set.ops.iter_mut() {
+                            if let WriteOp::Put(k, v) = op {
+                                if !is_clear(k, v) {
+                                    *v = keyring.encrypt(k, &keyring.decrypt(k, v)?);
+                                }
+                            }
+                        }
+                        tx.put(&k, &write_set.encode())?;
+                    } else if !is_clear(&k, &v) {
+                        let v = keyring.decrypt(&k, &v)?;
+                        tx.put(&k, &keyring.encrypt(&k, &v))?;
+                    }
+                }
+                lower = last;
+                lower.push(0);
+            }
+            keyring.retire_old_keys();
+        }
+        tx.put(&encryption_header_key(), &keyring.header().encode())?;
+        tx.commit()?;
+        *state = EncryptionState::Unlocked(Arc::new(keyring));
+        Ok(())
+    }
+    /// Prepare the key-value pairs of a backup for restoring into this database.
+    /// Encrypted backups are restored as they are if they are encrypted with the key
+    /// of this database, whose header they replace. Values of plain backups
+    /// are encrypted if this database is encrypted.
+    pub(crate) fn encrypt_restored<'a>(&self, pairs: KvIter<'a>) -> Result<KvIter<'a>> {
+        // the header is among the bookkeeping keys, which come first
+        let mut pairs = pairs.peekable();
+        let mut head = vec![];
+        while let Some(Ok((k, _))) = pairs.peek() {
+            if !is_bookkeeping_key(k) {
+                break;
+            }
+            head.push(pairs.next().unwrap()?);
+        }
+        let header_key = encryption_header_key();
+        let header = head.iter().find(|(k, _)| *k == header_key);
+        let state = self.encryption.read().unwrap().clone();
+        let keyring = match (header, state) {
+            (Some((_, header)), EncryptionState::Unlocked(keyring)) => {
+                Keyring::unlock(&EncryptionHeader::decode(header)?, &keyring.secret)?;
+                None
+            }
+            (Some(_), _) => bail!(EncryptionKeyRequired),
+            (None, EncryptionState::Unlocked(keyring)) => Some(keyring),
+            (None, EncryptionState::Locked) => bail!(EncryptionKeyRequired),
+            (None, EncryptionState::Disabled) => None,
+        };
+        let pairs = head.into_iter().map(Ok).chain(pairs);
+        Ok(match keyring {
+            None => Box::new(pairs),
+            Some(keyring) => Box::new(pairs.map_ok(move |(k, v)| {
+                if is_clear(&k, &v) {
+                    (k, v)
+                } else {
+                    let v = keyring.encrypt(&k, &v);
+                    (k, v)
+                }
+            })),
+        })
+    }
+    /// The key the database is encrypted with, if it is and it was given.
+    pub(crate) fn encryption_secret(&self) -> Option<Vec<u8>> {
+        match &*self.encryption.read().unwrap() {
+            EncryptionState::Unlocked(keyring) => Some(keyring.secret.clone()),
+            _ => None,
+        }
+    }
+}
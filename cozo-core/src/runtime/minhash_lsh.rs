@@ -236,6 +236,16 @@ pub(crate) struct MinHashLshIndexManifest {
     pub(crate) n_rows_in_band: usize,
     pub(crate) threshold: f64,
     pub(crate) perms: Vec<u8>,
+    /// Weights of false positives and false negatives the bands were chosen with, summing to 1
+    #[serde(default = "default_lsh_weight")]
+    pub(crate) false_positive_weight: f64,
+    #[serde(default = "default_lsh_weight")]
+    pub(crate) false_negative_weight: f64,
+}
+
+/// Indices created before the weights were kept are taken to use the default, equal weights.
+fn default_lsh_weight() -> f64 {
+    0.5
 }
 
 impl MinHashLshIndexManifest {
@@ -6,13 +6,19 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod backup;
 pub(crate) mod callback;
+pub(crate) mod changelog;
 pub(crate) mod db;
+pub(crate) mod dump;
+pub(crate) mod encryption;
 pub(crate) mod imperative;
 pub(crate) mod relation;
 pub(crate) mod temp_store;
 pub(crate) mod transact;
 pub(crate) mod hnsw;
 pub(crate) mod minhash_lsh;
+pub(crate) mod prepared;
+pub(crate) mod replication;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,195 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+#[allow(unused_imports)]
+use std::thread;
+#[allow(unused_imports)]
+use std::time::Duration;
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::is_unbound_param_error;
+use crate::data::functions::current_validity;
+use crate::data::program::InputProgram;
+use crate::parse::{parse_query_template, Registries};
+use crate::runtime::db::QueryPlan;
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::{DataValue, Db, NamedRows, ScriptMutability, Storage};
+
+/// A query prepared by [Db::prepare], to be run with [Db::run_prepared].
+///
+/// The query is parsed once. It is compiled the first time it is run, and the compiled
+/// plan is reused for later runs, with different parameters, until the schema,
+/// indices or triggers of a stored relation used by the query change.
+///
+/// Scripts that cannot be compiled without their parameters, such as those using
+/// parameters in `:limit` or in search options, or scripts that are not single queries,
+/// are still accepted, but are parsed and compiled anew each time they are run.
+pub struct PreparedQuery {
+    script: String,
+    template: Option<InputProgram>,
+    relations: BTreeSet<SmartString<LazyCompact>>,
+    cache: Mutex<Option<Arc<CachedPlan>>>,
+    not_cacheable: AtomicBool,
+}
+
+struct CachedPlan {
+    plan: QueryPlan,
+    relations: BTreeMap<SmartString<LazyCompact>, Option<RelationHandle>>,
+}
+
+impl Debug for PreparedQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreparedQuery")
+            .field("script", &self.script)
+            .field("cacheable", &self.is_cacheable())
+            .finish()
+    }
+}
+
+impl PreparedQuery {
+    /// The script this query is prepared from.
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+    /// Whether the compiled plan of this query is reused across runs.
+    pub fn is_cacheable(&self) -> bool {
+        self.template.is_some() && !self.not_cacheable.load(Ordering::Relaxed)
+    }
+    fn current_relations(
+        &self,
+        tx: &SessionTx<'_>,
+    ) -> BTreeMap<SmartString<LazyCompact>, Option<RelationHandle>> {
+        self.relations
+            .iter()
+            .map(|name| (name.clone(), tx.get_relation(name, false).ok()))
+            .collect()
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Prepare a query for repeated execution with [Db::run_prepared].
+    ///
+    /// Parameters in the script are not substituted now, but each time the query is run.
+    /// Syntax errors are reported here.
+    pub fn prepare(&'s self, script: &str) -> Result<PreparedQuery> {
+        let template = parse_query_template(
+            script,
+            &Registries {
+                functions: &self.functions.read().unwrap(),
+                fixed_rules: &self.fixed_rules.read().unwrap(),
+                aggregations: &self.aggregations.read().unwrap(),
+                table_functions: &self.table_functions.read().unwrap(),
+            },
+            current_validity(),
+        )?;
+        let (template, relations) = match template {
+            Some(template) => {
+                let (relations, time_travel) = template.stored_relations();
+                // the validity `'NOW'` is fixed when parsing
+                if time_travel {
+                    (None, Default::default())
+                } else {
+                    (Some(template), relations)
+                }
+            }
+            None => (None, Default::default()),
+        };
+        Ok(PreparedQuery {
+            script: script.to_string(),
+            template,
+            relations,
+            cache: Default::default(),
+            not_cacheable: Default::default(),
+        })
+    }
+
+    /// Run a query prepared with [Db::prepare], with the given parameters.
+    pub fn run_prepared(
+        &'s self,
+        query: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<NamedRows> {
+        let template = match &query.template {
+            Some(template) if query.is_cacheable() => template,
+            _ => return self.run_script(&query.script, params, mutability),
+        };
+        let cur_vld = current_validity();
+        let res = self.execute_in_single_tx(
+            template.needs_write_lock(),
+            mutability == ScriptMutability::Immutable,
+            |tx, cleanups, callback_targets, callback_collector| {
+                Self::check_store_relation(tx, &template.out_opts)?;
+                let mut plan = match Self::prepared_plan(query, tx, template)? {
+                    Some(plan) => plan,
+                    None => return Ok(None),
+                };
+                for stratum in plan.compiled.iter_mut() {
+                    for rule_set in stratum.values_mut() {
+                        rule_set.bind_params(&params)?;
+                    }
+                }
+                #[allow(unused_variables)]
+                let sleep_opt = plan.out_opts.sleep;
+                let (q_res, q_cleanups) = self.run_query_plan(
+                    tx,
+                    plan,
+                    cur_vld,
+                    callback_targets,
+                    callback_collector,
+                    true,
+                )?;
+                cleanups.extend(q_cleanups);
+                #[cfg(not(target_arch = "wasm32"))]
+                if let Some(secs) = sleep_opt {
+                    thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
+                }
+                Ok(Some(q_res))
+            },
+        )?;
+        match res {
+            Some(res) => Ok(res),
+            None => self.run_script(&query.script, params, mutability),
+        }
+    }
+    /// Returns `None` if the query turns out to need its parameters to be compiled.
+    fn prepared_plan(
+        query: &PreparedQuery,
+        tx: &mut SessionTx<'_>,
+        template: &InputProgram,
+    ) -> Result<Option<QueryPlan>> {
+        let relations = query.current_relations(tx);
+        let cached = query.cache.lock().unwrap().clone();
+        if let Some(cached) = cached {
+            if cached.relations == relations {
+                return Ok(Some(cached.plan.clone()));
+            }
+        }
+        let plan = match Self::compile_query(tx, template.clone()) {
+            Ok(plan) => plan,
+            Err(err) if is_unbound_param_error(&err) => {
+                query.not_cacheable.store(true, Ordering::Relaxed);
+                *query.cache.lock().unwrap() = None;
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+        *query.cache.lock().unwrap() = Some(Arc::new(CachedPlan {
+            plan: plan.clone(),
+            relations,
+        }));
+        Ok(Some(plan))
+    }
+}
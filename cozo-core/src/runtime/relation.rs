@@ -29,6 +29,7 @@ use crate::parse::expr::build_expr;
 use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
 use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
+use crate::runtime::changelog::{Changelog, CHANGELOG_NAME};
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
 use crate::runtime::transact::SessionTx;
@@ -90,6 +91,35 @@ pub(crate) struct RelationHandle {
         (RelationHandle, RelationHandle, MinHashLshIndexManifest),
     >,
     pub(crate) description: SmartString<LazyCompact>,
+    #[serde(default)]
+    pub(crate) stats: Option<RelationStats>,
+    #[serde(default)]
+    pub(crate) changelog: Option<Box<Changelog>>,
+}
+
+/// Statistics collected by `::analyze`, used for estimating join cardinalities.
+/// Writes to the relation do not update them: they are refreshed by the next `::analyze`,
+/// and only dropped when the relation is created or replaced.
+#[derive(Clone, Debug, Default, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct RelationStats {
+    pub(crate) row_count: u64,
+    /// The `i`-th element is the number of distinct values of the first `i + 1` key columns
+    pub(crate) key_prefix_cardinalities: Vec<u64>,
+}
+
+impl RelationStats {
+    /// Estimated number of rows returned when the first `prefix_len` key columns are bound.
+    pub(crate) fn rows_per_prefix(&self, prefix_len: usize) -> f64 {
+        if prefix_len == 0 {
+            return self.row_count as f64;
+        }
+        let idx = prefix_len.min(self.key_prefix_cardinalities.len());
+        if idx == 0 {
+            return self.row_count as f64;
+        }
+        let distinct = self.key_prefix_cardinalities[idx - 1].max(1);
+        self.row_count as f64 / distinct as f64
+    }
 }
 
 impl RelationHandle {
@@ -98,6 +128,13 @@ impl RelationHandle {
             || self.hnsw_indices.contains_key(index_name)
             || self.fts_indices.contains_key(index_name)
             || self.lsh_indices.contains_key(index_name)
+            || (index_name == CHANGELOG_NAME && self.changelog.is_some())
+    }
+    /// Whether this is the relation holding the changelog of another one,
+    /// the only relations with a `:` in their name that are read-only.
+    pub(crate) fn is_changelog(&self) -> bool {
+        self.access_level == AccessLevel::ReadOnly
+            && self.name.ends_with(&format!(":{CHANGELOG_NAME}"))
     }
     pub(crate) fn has_no_index(&self) -> bool {
         self.indices.is_empty()
@@ -620,6 +657,8 @@ impl<'a> SessionTx<'a> {
             fts_indices: Default::default(),
             lsh_indices: Default::default(),
             description: Default::default(),
+            stats: None,
+            changelog: None,
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -678,6 +717,53 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    pub(crate) fn analyze_relation(&mut self, name: &str) -> Result<RelationStats> {
+        let mut meta = self.get_relation(name, true)?;
+        meta.stats = Some(self.collect_relation_stats(&meta)?);
+        let mut indices = std::mem::take(&mut meta.indices);
+        for (idx_handle, _) in indices.values_mut() {
+            idx_handle.stats = Some(self.collect_relation_stats(idx_handle)?);
+            self.put_relation_metadata(idx_handle)?;
+        }
+        meta.indices = indices;
+        self.put_relation_metadata(&meta)?;
+        Ok(meta.stats.unwrap())
+    }
+    fn collect_relation_stats(&self, rel: &RelationHandle) -> Result<RelationStats> {
+        let n_keys = rel.metadata.keys.len();
+        let mut stats = RelationStats {
+            row_count: 0,
+            key_prefix_cardinalities: vec![0; n_keys],
+        };
+        let mut prev: Option<Tuple> = None;
+        for tuple in rel.scan_all(self) {
+            let tuple = tuple?;
+            // tuples come out sorted by key, so a new distinct prefix starts
+            // exactly where the current tuple first differs from the previous one
+            let first_diff = match &prev {
+                None => 0,
+                Some(p) => (0..n_keys).find(|i| p[*i] != tuple[*i]).unwrap_or(n_keys),
+            };
+            for card in stats.key_prefix_cardinalities[first_diff..].iter_mut() {
+                *card += 1;
+            }
+            stats.row_count += 1;
+            prev = Some(tuple);
+        }
+        Ok(stats)
+    }
+    pub(crate) fn put_relation_metadata(&mut self, meta: &RelationHandle) -> Result<()> {
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        if meta.is_temp {
+            self.temp_store_tx.put(&name_key, &meta_val)?;
+        } else {
+            self.store_tx.put(&name_key, &meta_val)?;
+        }
+        Ok(())
+    }
     pub(crate) fn destroy_relation(&mut self, name: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let is_temp = name.starts_with('_');
         let mut to_clean = vec![];
@@ -710,6 +796,10 @@ impl<'a> SessionTx<'a> {
             to_clean.extend(more_to_clean);
         }
 
+        if let Some(changelog) = &store.changelog {
+            to_clean.push(self.destroy_changelog(changelog)?);
+        }
+
         let key = DataValue::from(name);
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
         if is_temp {
@@ -812,6 +902,8 @@ impl<'a> SessionTx<'a> {
             n_rows_in_band: params.r,
             threshold: config.target_threshold.0,
             perms: perms.as_bytes().to_vec(),
+            false_positive_weight: config.false_positive_weight.0,
+            false_negative_weight: config.false_negative_weight.0,
         };
 
         // populate index
@@ -822,7 +914,8 @@ impl<'a> SessionTx<'a> {
             .into_diagnostic()?
             .next()
             .unwrap();
-        let mut code_expr = build_expr(parsed, &Default::default())?;
+        let mut code_expr =
+            build_expr(parsed, &Default::default(), &self.functions.read().unwrap())?;
         let binding_map = rel_handle.raw_binding_map();
         code_expr.fill_binding_indices(&binding_map)?;
         let extractor = code_expr.compile()?;
@@ -958,7 +1051,8 @@ impl<'a> SessionTx<'a> {
             .into_diagnostic()?
             .next()
             .unwrap();
-        let mut code_expr = build_expr(parsed, &Default::default())?;
+        let mut code_expr =
+            build_expr(parsed, &Default::default(), &self.functions.read().unwrap())?;
         let binding_map = rel_handle.raw_binding_map();
         code_expr.fill_binding_indices(&binding_map)?;
         let extractor = code_expr.compile()?;
@@ -1160,7 +1254,8 @@ impl<'a> SessionTx<'a> {
                 .into_diagnostic()?
                 .next()
                 .unwrap();
-            let mut code_expr = build_expr(parsed, &Default::default())?;
+            let mut code_expr =
+                build_expr(parsed, &Default::default(), &self.functions.read().unwrap())?;
             let binding_map = rel_handle.raw_binding_map();
             code_expr.fill_binding_indices(&binding_map)?;
             code_expr.compile()?
@@ -0,0 +1,182 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::storage::write_log::{
+    decode_log_key, decode_seq, write_log_bounds, write_log_key, write_log_seq_key, WriteLogState,
+    WriteSet,
+};
+use crate::storage::{Storage, StoreTx};
+use crate::Db;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The write log is not enabled")]
+#[diagnostic(code(db::no_write_log))]
+#[diagnostic(help("Enable it with `enable_write_log`"))]
+pub(crate) struct NoWriteLog;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot apply write set {got}: the last applied one is {last}")]
+#[diagnostic(code(db::write_log_gap))]
+#[diagnostic(help(
+    "The write sets in between may have been truncated, bootstrap the replica from a new backup"
+))]
+pub(crate) struct WriteLogGap {
+    got: u64,
+    last: u64,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    pub(crate) fn load_write_log_seq(&'s self) -> Result<()> {
+        let tx = self.db.transact(false)?;
+        let state = match tx.get(&write_log_seq_key(), false)? {
+            None => None,
+            Some(val) => {
+                let mut last = decode_seq(&val);
+                let (_, upper) = write_log_bounds();
+                for kv in tx.range_scan(&write_log_key(last.saturating_add(1)), &upper) {
+                    last = decode_log_key(&kv?.0);
+                }
+                Some(WriteLogState::new(last))
+            }
+        };
+        *self.write_log.lock().unwrap() = state;
+        Ok(())
+    }
+    /// Start recording the writes of every transaction committed from now on,
+    /// to be read with [Db::read_write_log] and applied to replicas
+    /// with [Db::apply_write_sets]. Returns the sequence number of the last
+    /// write set, as the log stays enabled across restarts once enabled.
+    ///
+    /// The sequence number is kept with the data and copied by [Db::backup_db],
+    /// so that a replica restored from the backup knows where to resume,
+    /// but the write sets themselves are not.
+    pub fn enable_write_log(&'s self) -> Result<u64> {
+        self.ensure_writable("enable the write log")?;
+        let mut state = self.write_log.lock().unwrap();
+        if let Some(state) = &*state {
+            return Ok(state.settled());
+        }
+        let mut tx = self.db.transact(true)?;
+        tx.put(&write_log_seq_key(), &0u64.to_be_bytes())?;
+        tx.commit()?;
+        *state = Some(WriteLogState::new(0));
+        Ok(0)
+    }
+    /// The sequence number of the last write set committed or applied,
+    /// `None` if the write log is not enabled. The transactions given
+    /// the sequence numbers up to it are all done committing.
+    pub fn write_log_last_seq(&self) -> Option<u64> {
+        self.write_log
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|state| state.settled())
+    }
+    /// Read the write log: returns the write sets with a sequence number
+    /// greater than `after` and at most [Db::write_log_last_seq], in order,
+    /// and at most `limit` of them.
+    ///
+    /// Transactions that failed to commit leave empty write sets, so that the
+    /// sequence numbers returned have no holes. If the write sets right after
+    /// `after` were truncated, the first one returned is the first one left.
+    pub fn read_write_log(&'s self, after: u64, limit: Option<usize>) -> Result<Vec<WriteSet>> {
+        let last = match self.write_log_last_seq() {
+            None => bail!(NoWriteLog),
+            Some(seq) => seq,
+        };
+        let tx = self.db.transact(false)?;
+        let truncated = match tx.get(&write_log_seq_key(), false)? {
+            None => 0,
+            Some(val) => decode_seq(&val),
+        };
+        let mut next = after.max(truncated).saturating_add(1);
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut ret = vec![];
+        for kv in tx.range_scan(&write_log_key(next), &write_log_key(last.saturating_add(1))) {
+            let write_set = WriteSet::decode(&kv?.1)?;
+            while next < write_set.seq && ret.len() < limit {
+                ret.push(WriteSet {
+                    seq: next,
+                    ops: vec![],
+                });
+                next += 1;
+            }
+            if ret.len() == limit {
+                return Ok(ret);
+            }
+            next = write_set.seq + 1;
+            ret.push(write_set);
+        }
+        while next <= last && ret.len() < limit {
+            ret.push(WriteSet {
+                seq: next,
+                ops: vec![],
+            });
+            next += 1;
+        }
+        Ok(ret)
+    }
+    /// Remove the write sets with a sequence number up to and including `up_to`
+    /// once all replicas have applied them. Sequence numbers are never reused.
+    pub fn truncate_write_log(&'s self, up_to: u64) -> Result<()> {
+        self.ensure_writable("truncate the write log")?;
+        let up_to = match self.write_log_last_seq() {
+            None => bail!(NoWriteLog),
+            Some(seq) => up_to.min(seq),
+        };
+        let (lower, _) = write_log_bounds();
+        let upper = write_log_key(up_to.saturating_add(1));
+        let mut tx = self.db.transact(true)?;
+        let truncated = match tx.get(&write_log_seq_key(), true)? {
+            None => 0,
+            Some(val) => decode_seq(&val),
+        };
+        if up_to <= truncated {
+            return Ok(());
+        }
+        tx.del_range_from_persisted(&lower, &upper)?;
+        tx.put(&write_log_seq_key(), &up_to.to_be_bytes())?;
+        tx.commit()
+    }
+    /// Apply write sets read from the write log of another database,
+    /// which this database must be a copy of: either it was restored from a backup
+    /// of the other one, or it is empty and has applied all write sets from the first.
+    /// Write sets that were already applied are skipped, and each is applied in
+    /// its own transaction, so that this database can itself be read from
+    /// with [Db::read_write_log]. Returns the sequence number of the last applied write set.
+    ///
+    /// Nothing but applying write sets should write to this database, as other writes
+    /// would not be seen by the other one and make them diverge.
+    pub fn apply_write_sets(&'s self, write_sets: &[WriteSet]) -> Result<u64> {
+        self.ensure_writable("apply write sets")?;
+        let mut state = self.write_log.lock().unwrap();
+        let mut applied = state.as_ref().map(|state| state.settled()).unwrap_or(0);
+        for write_set in write_sets {
+            if write_set.seq <= applied {
+                continue;
+            }
+            if write_set.seq != applied + 1 {
+                bail!(WriteLogGap {
+                    got: write_set.seq,
+                    last: applied
+                })
+            }
+            let mut tx = self.db.transact(true)?;
+            write_set.apply(&mut tx)?;
+            tx.commit()?;
+            applied = write_set.seq;
+            *state = Some(WriteLogState::new(applied));
+        }
+        drop(state);
+        self.load_last_ids()?;
+        Ok(applied)
+    }
+}
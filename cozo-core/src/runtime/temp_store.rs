@@ -334,6 +334,17 @@ impl EpochStore {
     pub(crate) fn early_returned_iter(&self) -> impl Iterator<Item = TupleInIter<'_>> {
         self.all_iter().filter(|t| !t.should_skip())
     }
+    /// Same tuples as `all_iter`, but moved out of the store, which releases its memory
+    /// as they are consumed.
+    pub(crate) fn into_all_iter(self) -> impl Iterator<Item = Tuple> {
+        match self.total {
+            TempStore::Normal(n) => Left(n.inner.into_keys()),
+            TempStore::MeetAggr(m) => Right(m.inner.into_iter().map(|(mut k, v)| {
+                k.extend(v);
+                k
+            })),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
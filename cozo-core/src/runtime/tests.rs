@@ -17,13 +17,21 @@ use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
 use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
 use crate::fts::{TokenizerCache, TokenizerConfig};
 use crate::parse::SourceSpan;
 use crate::runtime::callback::CallbackOp;
 use crate::runtime::db::Poison;
-use crate::{DbInstance, FixedRule, RegularTempStore, ScriptMutability};
+use crate::runtime::relation::RelationId;
+use crate::storage::encrypted::{is_clear, EncryptionState};
+use crate::storage::write_log::WriteOp;
+use crate::storage::{Storage, StoreTx};
+use crate::{
+    list_backups, verify_backup, AggregationCall, BackupKind, CustomAggregation, DbInstance,
+    DumpSummary, FixedRule, MemStorage, RegularTempStore, ScriptMutability, SimpleAggregation,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -430,6 +438,413 @@ fn test_callback() {
     assert_eq!(collected[2].2.rows[0].len(), 3);
 }
 
+#[test]
+fn changelog() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db");
+    {
+        let db = DbInstance::new("mem", &path, r#"{"wal": true}"#).unwrap();
+        db.run_default(":create friends {fr: Int, to: Int => data: Any}")
+            .unwrap();
+        db.run_default("?[fr, to, data] <- [[1, 2, 3]] :put friends {fr, to => data}")
+            .unwrap();
+        assert!(db.read_changelog("friends", 0, None).is_err());
+        db.run_default("::changelog friends on").unwrap();
+        db.run_default(r"?[fr, to, data] <- [[1,2,4],[4,5,6]] :put friends {fr, to => data}")
+            .unwrap();
+        db.run_default(r"?[fr, to] <- [[1,9],[4,5]] :rm friends {fr, to}")
+            .unwrap();
+        // a failed transaction leaves no entry
+        assert!(db
+            .run_default(r"?[fr, to, data] <- [[1,2,0]] :insert friends {fr, to => data}")
+            .is_err());
+        db.run_default(
+            r"
+            {?[fr, to, data] <- [[7,8,9]] :put friends {fr, to => data}}
+            {?[fr, to, data] <- [[1,2,10]] :update friends {fr, to => data}}
+            ",
+        )
+        .unwrap();
+    }
+    let db = DbInstance::new("mem", &path, r#"{"wal": true}"#).unwrap();
+    let entries = db.read_changelog("friends", 0, None).unwrap();
+    assert_eq!(
+        entries.iter().map(|e| e.seq).collect_vec(),
+        vec![1, 2, 3, 4]
+    );
+    assert_eq!(entries[0].op, CallbackOp::Put);
+    assert_eq!(entries[0].new.headers, vec!["fr", "to", "data"]);
+    assert_eq!(entries[0].new.rows.len(), 2);
+    assert_eq!(
+        entries[0].old.rows,
+        vec![vec![
+            DataValue::from(1),
+            DataValue::from(2),
+            DataValue::from(3)
+        ]]
+    );
+    assert_eq!(entries[1].op, CallbackOp::Rm);
+    assert_eq!(entries[1].new.headers, vec!["fr", "to"]);
+    assert_eq!(entries[1].new.rows.len(), 2);
+    assert_eq!(entries[1].old.rows.len(), 1);
+    assert_eq!(entries[3].new.rows[0][2], DataValue::from(10));
+    assert_eq!(entries[3].old.rows[0][2], DataValue::from(4));
+
+    let entries = db.read_changelog("friends", 1, Some(2)).unwrap();
+    assert_eq!(entries.iter().map(|e| e.seq).collect_vec(), vec![2, 3]);
+    assert_eq!(
+        db.run_default("?[seq, op] := *friends:changelog{seq, op}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, "Put"], [2, "Rm"], [3, "Put"], [4, "Put"]])
+    );
+    assert!(db
+        .run_default("?[seq, op, new, old] <- [[9, 'Put', [], []]] :put friends:changelog {seq => op, new, old}")
+        .is_err());
+    assert!(db
+        .run_default("::index create friends:changelog {to}")
+        .is_err());
+    assert_eq!(
+        db.run_default("::relations").unwrap().into_json()["rows"][1][2],
+        json!("changelog")
+    );
+
+    db.run_default("::changelog friends truncate 3").unwrap();
+    db.run_default("?[fr, to] <- [[7, 8]] :rm friends {fr, to}")
+        .unwrap();
+    let entries = db.read_changelog("friends", 0, None).unwrap();
+    assert_eq!(entries.iter().map(|e| e.seq).collect_vec(), vec![4, 5]);
+    assert_eq!(
+        db.run_default("::changelog friends").unwrap().into_json()["rows"],
+        json!([["friends", true, 5]])
+    );
+
+    // writing entries leaves the relation handle, which cached plans depend on, unchanged
+    let mem = crate::new_cozo_mem().unwrap();
+    let run = |script: &str| {
+        mem.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    run(":create friends {fr: Int => to: Int}");
+    run("::changelog friends on");
+    let handle = || {
+        mem.transact()
+            .unwrap()
+            .get_relation("friends", false)
+            .unwrap()
+    };
+    let before = handle();
+    run("?[fr, to] <- [[1, 2]] :put friends {fr => to}");
+    assert!(handle() == before);
+    assert_eq!(
+        run("::changelog friends").into_json()["rows"],
+        json!([["friends", true, 1]])
+    );
+
+    db.run_default("::changelog friends off").unwrap();
+    assert!(db.read_changelog("friends", 0, None).is_err());
+    assert_eq!(db.run_default("::relations").unwrap().rows.len(), 1);
+    db.run_default("::changelog friends on").unwrap();
+    db.run_default("::remove friends").unwrap();
+    assert_eq!(db.run_default("::relations").unwrap().rows.len(), 0);
+}
+
+#[test]
+fn write_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let primary = DbInstance::default();
+    primary
+        .run_default(":create friends {fr: Int, to: Int => data: Any}")
+        .unwrap();
+    assert_eq!(primary.write_log_last_seq(), None);
+    assert!(primary.read_write_log(0, None).is_err());
+    assert_eq!(primary.enable_write_log().unwrap(), 0);
+    primary
+        .run_default("?[fr, to, data] <- [[1, 2, 3], [4, 5, 6]] :put friends {fr, to => data}")
+        .unwrap();
+    // reads and failed transactions leave no write set
+    primary.run_default("?[fr] := *friends{fr}").unwrap();
+    assert!(primary
+        .run_default("?[fr, to, data] <- [[1, 2, 0]] :insert friends {fr, to => data}")
+        .is_err());
+    assert_eq!(primary.write_log_last_seq(), Some(1));
+
+    // a replica bootstrapped from a backup resumes after the backed up write sets
+    let backup = dir.path().join("backup.db");
+    primary.backup_db(&backup).unwrap();
+    let replica = DbInstance::default();
+    replica.restore_backup(&backup).unwrap();
+    assert_eq!(replica.write_log_last_seq(), Some(1));
+
+    primary
+        .run_default("::index create friends:rev {to, fr}")
+        .unwrap();
+    primary
+        .run_default("?[fr, to] <- [[1, 2]] :rm friends {fr, to}")
+        .unwrap();
+    primary.run_default(":create pets {name: String}").unwrap();
+    primary
+        .run_default("?[name] <- [['tom']] :put pets {name}")
+        .unwrap();
+    primary.run_default("::remove pets").unwrap();
+    primary
+        .run_default("?[fr, to, data] <- [[7, 8, 9]] :put friends {fr, to => data}")
+        .unwrap();
+    assert_eq!(primary.write_log_last_seq(), Some(7));
+
+    let write_sets = primary.read_write_log(0, None).unwrap();
+    assert_eq!(
+        write_sets.iter().map(|w| w.seq).collect_vec(),
+        (1..=7).collect_vec()
+    );
+    assert_eq!(
+        primary.read_write_log(2, Some(2)).unwrap(),
+        write_sets[2..4]
+    );
+    assert!(replica.apply_write_sets(&write_sets[3..]).is_err());
+    assert_eq!(replica.apply_write_sets(&write_sets[..3]).unwrap(), 3);
+    assert_eq!(replica.apply_write_sets(&write_sets).unwrap(), 7);
+    assert_eq!(replica.apply_write_sets(&write_sets).unwrap(), 7);
+
+    let query = "?[fr, to, data] := *friends:rev{fr, to}, *friends{fr, to, data}";
+    assert_eq!(
+        replica.run_default(query).unwrap().into_json()["rows"],
+        json!([[4, 5, 6], [7, 8, 9]])
+    );
+    assert_eq!(
+        replica.run_default("::relations").unwrap().rows,
+        primary.run_default("::relations").unwrap().rows
+    );
+    // and be read from in turn
+    assert_eq!(replica.read_write_log(3, None).unwrap(), write_sets[3..]);
+    // the relation ids of the primary are known
+    replica.run_default(":create other {a: Int}").unwrap();
+    assert_eq!(replica.run_default("::relations").unwrap().rows.len(), 3);
+
+    // a replica can start empty if the log was enabled before anything was written
+    let other = DbInstance::default();
+    other.enable_write_log().unwrap();
+    other.run_default(":create pets {name: String}").unwrap();
+    other
+        .run_default("?[name] <- [['tom']] :put pets {name}")
+        .unwrap();
+    let replica = DbInstance::default();
+    replica
+        .apply_write_sets(&other.read_write_log(0, None).unwrap())
+        .unwrap();
+    assert_eq!(
+        replica
+            .run_default("?[name] := *pets{name}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([["tom"]])
+    );
+
+    primary.truncate_write_log(5).unwrap();
+    assert_eq!(
+        primary
+            .read_write_log(0, None)
+            .unwrap()
+            .iter()
+            .map(|w| w.seq)
+            .collect_vec(),
+        vec![6, 7]
+    );
+
+    primary.truncate_write_log(9).unwrap();
+    assert_eq!(primary.read_write_log(0, None).unwrap(), vec![]);
+
+    // concurrent writers do not conflict on the write log,
+    // and a transaction failing to commit leaves an empty write set
+    let db = crate::new_cozo_mem().unwrap();
+    db.enable_write_log().unwrap();
+    let key = |k: i64| vec![DataValue::from(k)].encode_as_key(RelationId(100));
+    let mut tx1 = db.transact_write().unwrap();
+    let mut tx2 = db.transact_write().unwrap();
+    let mut tx3 = db.transact_write().unwrap();
+    tx1.store_tx.put(&key(1), b"a").unwrap();
+    tx2.store_tx.put(&key(2), b"b").unwrap();
+    tx3.store_tx.put(&key(1), b"c").unwrap();
+    tx1.commit_tx().unwrap();
+    tx2.commit_tx().unwrap();
+    assert!(tx3.commit_tx().is_err());
+    let mut tx4 = db.transact_write().unwrap();
+    tx4.store_tx.put(&key(3), b"d").unwrap();
+    tx4.commit_tx().unwrap();
+    assert_eq!(db.write_log_last_seq(), Some(4));
+    assert_eq!(
+        db.read_write_log(0, None)
+            .unwrap()
+            .iter()
+            .map(|w| (w.seq, w.ops.len()))
+            .collect_vec(),
+        vec![(1, 1), (2, 1), (3, 0), (4, 1)]
+    );
+}
+
+#[test]
+fn encryption() {
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("encrypted.db");
+    let backup = dir.path().join("backup.db");
+    let query = "?[name, email] := *people:city{city: 'Paris', name}, *people{name, email}";
+    let history = "?[v] := *history{k: 1, v @ 'NOW'}";
+    let open = |key: &str| {
+        DbInstance::new(
+            "sqlite",
+            &path,
+            &json!({ "encryption_key": key }).to_string(),
+        )
+    };
+    {
+        let db = open("first key").unwrap();
+        assert!(db.is_encrypted());
+        db.run_default(":create people {name: String => email: String, city: String}")
+            .unwrap();
+        db.run_default("::index create people:city {city}").unwrap();
+        db.run_default(
+            r"?[name, email, city] <- [['alice', 'alice@example.com', 'Paris'],
+                                        ['bob', 'bob@example.com', 'Oslo']]
+              :put people {name => email, city}",
+        )
+        .unwrap();
+        db.run_default(":create history {k: Int, vld: Validity => v: String}")
+            .unwrap();
+        db.run_default(
+            "?[k, vld, v] <- [[1, [1, true], 'old'], [1, [2, true], 'new']] :put history {k, vld => v}",
+        )
+        .unwrap();
+        db.run_default(":create notes {id: Int => text: String}")
+            .unwrap();
+        db.run_default("?[id, text] <- [[1, 'private note']] :put notes {id => text}")
+            .unwrap();
+        assert_eq!(
+            db.run_default(query).unwrap().into_json()["rows"],
+            json!([["alice", "alice@example.com"]])
+        );
+        assert_eq!(
+            db.run_default(history).unwrap().into_json()["rows"],
+            json!([["new"]])
+        );
+    }
+    // values are encrypted, keys are not
+    let raw = std::fs::read(&path).unwrap();
+    assert!(contains(&raw, b"alice"));
+    assert!(!contains(&raw, b"alice@example.com"));
+    assert!(!contains(&raw, b"private note"));
+
+    {
+        let db = DbInstance::new("sqlite", &path, "").unwrap();
+        assert!(db.is_encrypted());
+        assert!(db.run_default(query).is_err());
+    }
+    assert!(open("other key").is_err());
+
+    {
+        let db = open("first key").unwrap();
+        db.rotate_encryption_key(b"second key", false).unwrap();
+        assert_eq!(
+            db.run_default(query).unwrap().into_json()["rows"],
+            json!([["alice", "alice@example.com"]])
+        );
+        db.rotate_encryption_key(b"third key", true).unwrap();
+        assert_eq!(
+            db.run_default(query).unwrap().into_json()["rows"],
+            json!([["alice", "alice@example.com"]])
+        );
+        db.run_default(
+            "?[name, email, city] <- [['carol', 'carol@example.com', 'Paris']] :put people {name => email, city}",
+        )
+        .unwrap();
+        db.backup_db(&backup).unwrap();
+        db.backup_to_dir(dir.path()).unwrap();
+    }
+    assert!(open("first key").is_err());
+    assert!(open("second key").is_err());
+    let expected = json!([
+        ["alice", "alice@example.com"],
+        ["carol", "carol@example.com"]
+    ]);
+    let db = open("third key").unwrap();
+    assert_eq!(db.run_default(query).unwrap().into_json()["rows"], expected);
+    assert_eq!(
+        db.run_default(history).unwrap().into_json()["rows"],
+        json!([["new"]])
+    );
+
+    // backups are encrypted, and restored into databases encrypted with the same key
+    let raw = std::fs::read(&backup).unwrap();
+    assert!(!contains(&raw, b"carol@example.com"));
+    let with_key = |key: &str| {
+        DbInstance::new("mem", "", &json!({ "encryption_key": key }).to_string()).unwrap()
+    };
+    let restored = with_key("third key");
+    restored.restore_backup(&backup).unwrap();
+    assert_eq!(
+        restored.run_default(query).unwrap().into_json()["rows"],
+        expected
+    );
+    // the ids of the restored data keys are known
+    restored.rotate_encryption_key(b"fourth key", true).unwrap();
+    assert_eq!(
+        restored.run_default(query).unwrap().into_json()["rows"],
+        expected
+    );
+    let restored = with_key("third key");
+    restored.restore_from_dir(dir.path(), None).unwrap();
+    assert_eq!(
+        restored.run_default(query).unwrap().into_json()["rows"],
+        expected
+    );
+    assert!(DbInstance::default().restore_backup(&backup).is_err());
+    assert!(with_key("other key").restore_backup(&backup).is_err());
+
+    let imported = with_key("third key");
+    imported
+        .run_default(":create notes {id: Int => text: String}")
+        .unwrap();
+    imported
+        .import_from_backup(&backup, &["notes".to_string()])
+        .unwrap();
+    assert_eq!(
+        imported
+            .run_default("?[text] := *notes{text}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([["private note"]])
+    );
+
+    // plain backups are encrypted when restored into encrypted databases
+    let plain = DbInstance::default();
+    plain
+        .run_default(":create notes {id: Int => text: String}")
+        .unwrap();
+    plain
+        .run_default("?[id, text] <- [[1, 'plain note']] :put notes {id => text}")
+        .unwrap();
+    let plain_backup = dir.path().join("plain.db");
+    plain.backup_db(&plain_backup).unwrap();
+    assert!(plain.enable_encryption(b"key").is_err());
+    let restored = with_key("key");
+    restored.restore_backup(&plain_backup).unwrap();
+    assert_eq!(
+        restored
+            .run_default("?[text] := *notes{text}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([["plain note"]])
+    );
+    if let DbInstance::Mem(db) = &restored {
+        let tx = db.db.transact(false).unwrap();
+        for kv in tx.total_scan() {
+            assert!(!contains(&kv.unwrap().1, b"plain note"));
+        }
+    }
+}
+
 #[test]
 fn test_update() {
     let db = DbInstance::default();
@@ -667,6 +1082,279 @@ fn test_multi_tx() {
     assert!(db.run_default("?[a] := *a[a]").is_err());
 }
 
+#[test]
+fn mem_snapshot_isolation() {
+    let storage = MemStorage::default();
+    let mut tx = storage.transact(true).unwrap();
+    tx.put(b"a", b"1").unwrap();
+    tx.put(b"b", b"1").unwrap();
+    tx.commit().unwrap();
+
+    let reader = storage.transact(false).unwrap();
+    let mut writer_1 = storage.transact(true).unwrap();
+    let mut writer_2 = storage.transact(true).unwrap();
+    writer_1.put(b"a", b"2").unwrap();
+    writer_1.del(b"b").unwrap();
+    writer_2.put(b"a", b"3").unwrap();
+    writer_1.commit().unwrap();
+    assert!(writer_2.commit().is_err());
+
+    // the reader does not see changes committed after it started
+    assert_eq!(reader.get(b"a", false).unwrap(), Some(b"1".to_vec()));
+    assert_eq!(reader.total_scan().count(), 2);
+    drop(reader);
+    let reader = storage.transact(false).unwrap();
+    assert_eq!(reader.get(b"a", false).unwrap(), Some(b"2".to_vec()));
+    assert_eq!(reader.total_scan().count(), 1);
+
+    // keys read for update are checked too
+    let mut writer_1 = storage.transact(true).unwrap();
+    let mut writer_2 = storage.transact(true).unwrap();
+    writer_1.get(b"a", true).unwrap();
+    writer_1.put(b"c", b"1").unwrap();
+    writer_2.put(b"a", b"4").unwrap();
+    writer_2.commit().unwrap();
+    assert!(writer_1.commit().is_err());
+
+    // writes to different keys do not conflict
+    let mut writer_1 = storage.transact(true).unwrap();
+    let mut writer_2 = storage.transact(true).unwrap();
+    writer_1.put(b"c", b"1").unwrap();
+    writer_2.del(b"a").unwrap();
+    writer_2.commit().unwrap();
+    writer_1.commit().unwrap();
+    assert_eq!(reader.get(b"a", false).unwrap(), Some(b"2".to_vec()));
+    drop(reader);
+    let reader = storage.transact(false).unwrap();
+    assert_eq!(
+        reader.total_scan().map(|kv| kv.unwrap().0).collect_vec(),
+        vec![b"c".to_vec()]
+    );
+}
+
+#[test]
+fn mem_durable_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db");
+    let last_segment = |path: &std::path::Path| {
+        std::fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| p.file_name().unwrap().to_str().unwrap().starts_with("wal."))
+            .max()
+            .unwrap()
+    };
+    {
+        let db = DbInstance::new("mem", &path, r#"{"wal": true}"#).unwrap();
+        // the directory is locked while the database is open
+        assert!(DbInstance::new("mem", &path, r#"{"wal": true}"#).is_err());
+        db.run_default(":create a {k => v}").unwrap();
+        db.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :put a {k => v}")
+            .unwrap();
+        db.run_default("?[k] <- [[2]] :rm a {k}").unwrap();
+    }
+    // a torn record at the end of the log is discarded
+    let torn = last_segment(&path);
+    let mut wal = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&torn)
+        .unwrap();
+    std::io::Write::write_all(&mut wal, &[1, 2, 3]).unwrap();
+    drop(wal);
+    {
+        let db = DbInstance::new("mem", &path, r#"{"wal": true, "checkpoint_size": 1}"#).unwrap();
+        assert_eq!(
+            db.run_default("?[k, v] := *a[k, v]").unwrap().into_json()["rows"],
+            json!([[1, "a"]])
+        );
+        db.run_default("?[k, v] <- [[3, 'c']] :put a {k => v}")
+            .unwrap();
+    }
+    // the checkpoint, finished when the database is dropped, removed the log it covers
+    assert!(!torn.exists());
+    {
+        let db = DbInstance::new("mem", &path, r#"{"wal": true}"#).unwrap();
+        assert_eq!(
+            db.run_default("?[k, v] := *a[k, v]").unwrap().into_json()["rows"],
+            json!([[1, "a"], [3, "c"]])
+        );
+        db.run_default("?[k, v] <- [[4, 'd']] :put a {k => v}")
+            .unwrap();
+        db.run_default("?[k, v] <- [[5, 'e']] :put a {k => v}")
+            .unwrap();
+    }
+    // a corrupt record followed by others is an error
+    let segment = last_segment(&path);
+    let mut content = std::fs::read(&segment).unwrap();
+    content[20] ^= 0xFF;
+    std::fs::write(&segment, content).unwrap();
+    assert!(DbInstance::new("mem", &path, r#"{"wal": true}"#).is_err());
+    assert!(DbInstance::new("mem", "", r#"{"wal": true}"#).is_err());
+}
+
+#[test]
+fn incremental_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = DbInstance::default();
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default("?[k, v] := k in int_range(20000), v = k :put a {k => v}")
+        .unwrap();
+    let first = db.backup_to_dir(dir.path()).unwrap();
+    assert_eq!(first.id, 1);
+    assert_eq!(first.kind, BackupKind::Chunks);
+    assert!(first.files.len() > 1);
+    assert_eq!(first.new_files, first.files.len());
+
+    db.run_default("?[k, v] <- [[12345, 'changed']] :put a {k => v}")
+        .unwrap();
+    let second = db.backup_to_dir(dir.path()).unwrap();
+    assert_eq!(second.parent, Some(1));
+    assert_eq!(second.new_files, 1);
+    assert_eq!(list_backups(dir.path()).unwrap().len(), 2);
+    verify_backup(dir.path(), None).unwrap();
+
+    for (id, expected) in [(1, json!(12345)), (2, json!("changed"))] {
+        let restored = DbInstance::default();
+        restored.restore_from_dir(dir.path(), Some(id)).unwrap();
+        let res = restored.run_default("?[v] := *a[12345, v]").unwrap();
+        assert_eq!(res.into_json()["rows"], json!([[expected]]));
+        let res = restored.run_default("?[count(k)] := *a[k, _]").unwrap();
+        assert_eq!(res.into_json()["rows"], json!([[20000]]));
+        // ids of new relations do not clash with restored ones
+        restored.run_default(":create b {k}").unwrap();
+        assert!(restored
+            .run_default("?[k] := *b[k]")
+            .unwrap()
+            .rows
+            .is_empty());
+    }
+    assert!(db.restore_from_dir(dir.path(), None).is_err());
+    // the in-memory engine makes no checkpoints, and says so
+    let mem = crate::new_cozo_mem().unwrap();
+    assert!(!mem.db.supports_checkpoint());
+    assert!(mem.db.checkpoint(&dir.path().join("checkpoint")).is_err());
+
+    let changed = second
+        .files
+        .iter()
+        .find(|f| first.files.iter().all(|g| g.path != f.path))
+        .unwrap();
+    std::fs::write(dir.path().join(&changed.path), b"garbage").unwrap();
+    assert!(verify_backup(dir.path(), Some(2)).is_err());
+    verify_backup(dir.path(), Some(1)).unwrap();
+}
+
+#[test]
+fn dump_and_load() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        {:create person {id: Int => name: String, tags: [String]? default null, v: <F32; 2>}}
+        {:create log {at: Float default 0. => event: String}}
+        ",
+    )
+    .unwrap();
+    db.run_default(
+        "?[id, name, v] := id in int_range(5000), name = concat('p', to_string(id)), v = vec([id, 1]) \
+        :put person {id => name, v}",
+    )
+    .unwrap();
+    db.run_default("::index create person:by_name {name}")
+        .unwrap();
+    db.run_default(
+        "::hnsw create person:vec {dim: 2, m: 4, fields: [v], ef: 4, distance: Cosine}",
+    )
+    .unwrap();
+    db.run_default(
+        "::fts create person:text {extractor: name, tokenizer: Simple, filters: [Lowercase]}",
+    )
+    .unwrap();
+    db.run_default(
+        "::lsh create person:lsh {extractor: name, tokenizer: Simple, n_gram: 3, n_perm: 100, \
+        target_threshold: 0.7, false_positive_weight: 5, false_negative_weight: 1}",
+    )
+    .unwrap();
+    db.run_default(
+        "::set_triggers person on put { ?[at, event] := _new[id, name, _, _], at = 1., event = name :put log {at => event} }",
+    )
+    .unwrap();
+    db.run_default("::access_level protected log").unwrap();
+    db.run_default("::describe person 'people'").unwrap();
+    db.run_default("::changelog log on").unwrap();
+
+    let mut buf = vec![];
+    let summary = db.dump(&mut buf, Vec::<String>::new()).unwrap();
+    assert_eq!(summary.relations, 2);
+    assert_eq!(summary.rows, 5000);
+
+    let loaded = DbInstance::default();
+    assert_eq!(loaded.load(buf.as_slice()).unwrap(), summary);
+    let res = loaded
+        .run_default("?[id] := *person:by_name{name: 'p4321', id}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[4321]]));
+    let res = loaded
+        .run_default("?[id] := ~person:text{id | query: 'P123', k: 1}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[123]]));
+    let res = loaded
+        .run_default("?[count(id)] := ~person:vec{id | query: vec([1, 1]), k: 3, ef: 16}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
+    // LSH bands are picked with the same weights
+    assert_eq!(
+        loaded.run_default("::indices person").unwrap().into_json(),
+        db.run_default("::indices person").unwrap().into_json()
+    );
+    let relations = loaded.run_default("::relations").unwrap().into_json()["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|row| !row[0].as_str().unwrap().contains(':'))
+        .map(|row| json!([row[0], row[2], row[5], row[8]]))
+        .collect_vec();
+    assert_eq!(
+        relations,
+        vec![
+            json!(["log", "protected", 0, ""]),
+            json!(["person", "normal", 1, "people"])
+        ]
+    );
+
+    loaded
+        .run_default("?[id, name, v] <- [[9999, 'new', vec([0, 1])]] :put person {id => name, v}")
+        .unwrap();
+    let res = loaded.run_default("?[event] := *log{event}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["new"]]));
+    // the changelog is enabled again, starting anew
+    let entries = loaded.read_changelog("log", 0, None).unwrap();
+    assert_eq!(entries.iter().map(|e| e.seq).collect_vec(), vec![1]);
+
+    // relations are not overwritten
+    assert!(loaded.load(buf.as_slice()).is_err());
+    let res = loaded.run_default("?[count(id)] := *person{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[5001]]));
+
+    // a failed load leaves no relations behind
+    let truncated = DbInstance::default();
+    assert!(truncated.load(&buf[..buf.len() - 1]).is_err());
+    let res = truncated.run_default("::relations").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([]));
+
+    let mut buf = vec![];
+    let summary = db.dump(&mut buf, ["log"]).unwrap();
+    assert_eq!(
+        summary,
+        DumpSummary {
+            relations: 1,
+            rows: 0
+        }
+    );
+    assert!(db.dump(vec![], ["person:by_name"]).is_err());
+    assert!(DbInstance::default().load(&buf[..buf.len() - 1]).is_err());
+    assert!(DbInstance::default().load(&b"not a dump"[..]).is_err());
+}
+
 #[test]
 fn test_vec_types() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -946,6 +1634,24 @@ fn filtering() {
     assert_eq!(0, res.rows.len());
 }
 
+#[test]
+fn range_scan_with_value_columns() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k => v}").unwrap();
+    db.run_default("?[k, v] := k in int_range(5), v = k :put a {k => v}")
+        .unwrap();
+    let res = db.run_default("?[k] := *a[k, v], k >= 3").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3], [4]]));
+    let res = db.run_default("?[k] := *a[k, v], k >= 3, v < 4").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
+
+    db.run_default(":create b {k, at: Validity => v}").unwrap();
+    db.run_default("?[k, at, v] := k in int_range(5), at = 'ASSERT', v = k :put b {k, at => v}")
+        .unwrap();
+    let res = db.run_default("?[k] := *b{k, v @ 'NOW'}, k >= 3").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3], [4]]));
+}
+
 #[test]
 fn test_lsh_indexing4() {
     for i in 1..10 {
@@ -1612,3 +2318,1081 @@ fn fts_drop() {
     )
     .unwrap();
 }
+
+#[test]
+fn analyze_and_join_order() {
+    let db = DbInstance::default();
+    db.run_default(r"?[a, b] := a in int_range(100), b = a * 2 :create big {a => b}")
+        .unwrap();
+    db.run_default(r"?[a] <- [[3], [5]] :create small {a}")
+        .unwrap();
+    let query = "::explain { ?[a, b] := *big{a, b}, *small{a} }";
+
+    let expl = db.run_default(query).unwrap();
+    assert_eq!(expl.rows[0][5], DataValue::from(":big"));
+    assert_eq!(expl.rows.last().unwrap()[9], DataValue::Null);
+
+    let res = db.run_default("::analyze big").unwrap().into_json();
+    assert_eq!(res["rows"], json!([["big", 100, [100]]]));
+    db.run_default("::analyze small").unwrap();
+
+    let expl = db.run_default(query).unwrap();
+    assert_eq!(expl.rows[0][5], DataValue::from(":small"));
+    assert_eq!(expl.rows.last().unwrap()[9], DataValue::from(2.));
+
+    let res = db
+        .run_default("?[a, b] := *big{a, b}, *small{a}")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[3, 6], [5, 10]]));
+
+    let expl = db
+        .run_default("::explain { ?[a, b] := *big{a, b}, b > 0, *small{a} }")
+        .unwrap();
+    assert_eq!(expl.rows[0][5], DataValue::from(":small"));
+
+    // writes keep the statistics, stale until the next `::analyze`
+    db.run_default(r"?[a] <- [[7]] :put small {a}").unwrap();
+    let expl = db.run_default(query).unwrap();
+    assert_eq!(expl.rows[0][5], DataValue::from(":small"));
+    assert_eq!(expl.rows.last().unwrap()[9], DataValue::from(2.));
+
+    db.run_default(r"?[a] <- [[7]] :replace small {a}").unwrap();
+    let expl = db.run_default(query).unwrap();
+    assert_eq!(expl.rows[0][5], DataValue::from(":big"));
+    assert_eq!(expl.rows.last().unwrap()[9], DataValue::Null);
+}
+
+#[test]
+fn run_script_iter() {
+    let db = DbInstance::default();
+    db.run_default(r"?[a] := a in int_range(1000) :create nums {a}")
+        .unwrap();
+
+    let it = db
+        .run_script_iter("?[a] := *nums{a}, a % 2 == 0", Default::default())
+        .unwrap();
+    assert_eq!(it.headers, vec!["a".to_string()]);
+    let rows: Vec<_> = it.map(|r| r.unwrap()).collect();
+    assert_eq!(rows.len(), 500);
+
+    let rows: Vec<_> = db
+        .run_script_iter("?[a] := *nums{a} :offset 10 :limit 3", Default::default())
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec![DataValue::from(10)],
+            vec![DataValue::from(11)],
+            vec![DataValue::from(12)]
+        ]
+    );
+
+    let rows: Vec<_> = db
+        .run_script_iter(
+            "?[a] := *nums{a}, a < 5 :order -a :limit 2",
+            Default::default(),
+        )
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(
+        rows,
+        vec![vec![DataValue::from(4)], vec![DataValue::from(3)]]
+    );
+
+    let rows: Vec<_> = db
+        .run_script_iter("?[count(a)] := *nums{a}", Default::default())
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(rows, vec![vec![DataValue::from(1000)]]);
+
+    // entry rules whose rows may repeat are still deduplicated
+    let rows: Vec<_> = db
+        .run_script_iter("?[b] := *nums{a}, b = a % 3", Default::default())
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(rows.len(), 3);
+    let rows: Vec<_> = db
+        .run_script_iter(
+            "?[a] := *nums{a}, a < 2; ?[a] := *nums{a}, a < 3",
+            Default::default(),
+        )
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(rows.len(), 3);
+
+    // the cursor of a page is the same as with run_script_page
+    let query = "?[a] := *nums{a} :limit 3 :after $cursor";
+    let params = BTreeMap::from([("cursor".to_string(), DataValue::Null)]);
+    let (_, expected) = db
+        .run_script_page(query, params.clone(), ScriptMutability::Immutable)
+        .unwrap();
+    assert!(expected.is_some());
+    let it = db.run_script_iter(query, params).unwrap();
+    assert_eq!(it.cursor, expected);
+    assert_eq!(it.count(), 3);
+    let it = db
+        .run_script_iter(
+            query,
+            BTreeMap::from([("cursor".to_string(), DataValue::from(expected.unwrap()))]),
+        )
+        .unwrap();
+    assert!(it.cursor.is_some());
+    assert_eq!(
+        it.map(|r| r.unwrap()).collect_vec(),
+        vec![
+            vec![DataValue::from(3)],
+            vec![DataValue::from(4)],
+            vec![DataValue::from(5)]
+        ]
+    );
+    assert!(db
+        .run_script_iter("?[a] := *nums{a} :limit 3", Default::default())
+        .unwrap()
+        .cursor
+        .is_none());
+
+    // dropping the iterator early releases the transaction
+    let mut it = db
+        .run_script_iter("?[a] := *nums{a}", Default::default())
+        .unwrap();
+    assert_eq!(it.next().unwrap().unwrap(), vec![DataValue::from(0)]);
+    drop(it);
+    db.run_default("?[a] <- [[1000]] :put nums {a}").unwrap();
+
+    assert!(db
+        .run_script_iter("?[a] <- [[1001]] :put nums {a}", Default::default())
+        .is_err());
+    assert!(db
+        .run_script_iter("?[a] := *not_there{a}", Default::default())
+        .is_err());
+}
+
+#[test]
+fn external_sort_and_top_k() {
+    let db = DbInstance::default();
+    db.run_default(r"?[a, b] := a in int_range(500), b = a % 7 :create nums {a => b}")
+        .unwrap();
+    let query = "?[a, b] := *nums{a, b} :order -b, a";
+    let expected = db.run_default(query).unwrap().rows;
+    assert_eq!(expected.len(), 500);
+    assert_eq!(expected[0], vec![DataValue::from(6), DataValue::from(6)]);
+    assert_eq!(expected[1], vec![DataValue::from(13), DataValue::from(6)]);
+
+    db.set_sort_memory_budget(16);
+    let external = db.run_default(query).unwrap().rows;
+    assert_eq!(external, expected);
+
+    // top-k selection
+    let res = db
+        .run_default(&format!("{query} :offset 3 :limit 10"))
+        .unwrap()
+        .rows;
+    assert_eq!(res, expected[3..13].to_vec());
+
+    // limit larger than the memory budget
+    let res = db
+        .run_default(&format!("{query} :offset 40 :limit 100"))
+        .unwrap()
+        .rows;
+    assert_eq!(res, expected[40..140].to_vec());
+
+    // rows of meet aggregations are put back together from their groups and values
+    let res = db
+        .run_default("?[b, min(a)] := *nums{a, b} :order -b")
+        .unwrap()
+        .rows;
+    assert_eq!(
+        res,
+        (0..7)
+            .rev()
+            .map(|b| vec![DataValue::from(b), DataValue::from(b)])
+            .collect_vec()
+    );
+}
+
+#[test]
+fn window_functions() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[dept, name, salary] <- [['a', 'x', 10], ['a', 'y', 30], ['a', 'z', 30],
+                                  ['b', 'u', 5], ['b', 'v', 7]]
+        :create emp {dept, name => salary}",
+    )
+    .unwrap();
+
+    // window functions are only allowed in rule heads
+    assert!(db
+        .run_default("?[name, rn] := *emp{name, salary}, rn = row_number() over (order by salary)")
+        .is_err());
+
+    let res = db
+        .run_default(
+            r"
+            ?[dept, name, rn = row_number() over (partition by dept order by -salary),
+              rk = rank() over (partition by dept order by -salary),
+              drk = dense_rank() over (order by salary)] := *emp{dept, name, salary}
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["a", "x", 3, 3, 3],
+            ["a", "y", 1, 1, 4],
+            ["a", "z", 2, 1, 4],
+            ["b", "u", 2, 2, 1],
+            ["b", "v", 1, 1, 2]
+        ])
+    );
+
+    let res = db
+        .run_default(
+            r"
+            ?[name, prev = lag(salary) over (order by name),
+              next = lead(salary, 2, -1) over (order by name)] := *emp{name, salary}
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["u", null, 10],
+            ["v", 5, 30],
+            ["x", 7, 30],
+            ["y", 10, -1],
+            ["z", 30, -1]
+        ])
+    );
+
+    let res = db
+        .run_default(
+            r"
+            ?[name, total = sum(salary) over (partition by dept order by name),
+              avg = mean(salary, 2) over (partition by dept order by name)] :=
+                *emp{dept, name, salary}
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["u", 5, 5.0],
+            ["v", 12, 6.0],
+            ["x", 10, 10.0],
+            ["y", 40, 20.0],
+            ["z", 70, 30.0]
+        ])
+    );
+
+    // rows tying on the ordering are peers and share the running aggregate,
+    // and without an ordering the whole partition is
+    let res = db
+        .run_default(
+            r"
+            ?[name, total = sum(salary) over (partition by dept order by salary),
+              n = count(salary) over (partition by dept)] := *emp{dept, name, salary}
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["u", 5, 2],
+            ["v", 12, 2],
+            ["x", 10, 3],
+            ["y", 70, 3],
+            ["z", 70, 3]
+        ])
+    );
+
+    // windowed rules have a single definition, whichever comes first
+    let err = db
+        .run_default(
+            r"
+            ?[name, rn = row_number() over ()] := *emp{name}
+            ?[name, rn] := name = 'w', rn = 0
+            ",
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::window_rule_multiple_clauses"
+    );
+    let err = db
+        .run_default(
+            r"
+            ?[name, rn] := name = 'w', rn = 0
+            ?[name, rn = row_number() over ()] := *emp{name}
+            ",
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::window_rule_multiple_clauses"
+    );
+
+    // windows cannot be mixed with aggregations
+    assert!(db
+        .run_default("?[count(name), rn = row_number() over ()] := *emp{name}")
+        .is_err());
+    // nor used in constant rules
+    assert!(db
+        .run_default("?[a, rn = row_number() over ()] <- [[1]]")
+        .is_err());
+    // unknown window functions
+    assert!(db
+        .run_default("?[name, rn = no_such_fn() over ()] := *emp{name}")
+        .is_err());
+    // wrong arguments
+    assert!(db
+        .run_default("?[name, rn = lag() over ()] := *emp{name}")
+        .is_err());
+}
+
+#[test]
+fn custom_aggregations() {
+    struct SumSquares;
+
+    impl CustomAggregation for SumSquares {
+        fn init(&self, args: &[DataValue]) -> miette::Result<DataValue> {
+            Ok(args.first().cloned().unwrap_or(DataValue::from(0)))
+        }
+
+        fn update(&self, state: &mut DataValue, value: &DataValue) -> miette::Result<()> {
+            let v = value.get_int().unwrap();
+            *state = DataValue::from(state.get_int().unwrap() + v * v);
+            Ok(())
+        }
+
+        fn merge(&self, state: &mut DataValue, other: &DataValue) -> miette::Result<bool> {
+            *state = DataValue::from(state.get_int().unwrap() + other.get_int().unwrap());
+            Ok(true)
+        }
+
+        fn finalize(&self, state: &DataValue) -> miette::Result<DataValue> {
+            Ok(state.clone())
+        }
+    }
+
+    let db = DbInstance::default();
+    db.register_aggregation("sum_squares".to_string(), SumSquares)
+        .unwrap();
+    assert!(db
+        .register_aggregation("sum_squares".to_string(), SumSquares)
+        .is_err());
+    assert!(db
+        .register_aggregation("sum".to_string(), SumSquares)
+        .is_err());
+
+    let res = db
+        .run_default(
+            r"
+            data[k, v] <- [['a', 1], ['a', 2], ['b', 3]]
+            ?[k, sum_squares(v), sum_squares(v, 100)] := data[k, v]
+            ",
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", 5, 105], ["b", 9, 109]])
+    );
+
+    // a meet aggregation usable in recursion
+    let min_dist = SimpleAggregation::new(true, |call| {
+        Ok(match call {
+            AggregationCall::Init(_) => DataValue::Null,
+            AggregationCall::Merge(DataValue::Null, v)
+            | AggregationCall::Merge(v, DataValue::Null) => v,
+            AggregationCall::Merge(l, r) => l.min(r),
+            AggregationCall::Update(..) | AggregationCall::Finalize(..) => unreachable!(),
+        })
+    });
+    db.register_aggregation("min_dist".to_string(), min_dist)
+        .unwrap();
+    let res = db
+        .run_default(
+            r"
+            edge[a, b, d] <- [[1, 2, 10], [2, 3, 10], [1, 3, 30], [3, 4, 1]]
+            dist[b, min_dist(d)] := edge[1, b, d]
+            dist[b, min_dist(d)] := dist[a, d1], edge[a, b, d2], d = d1 + d2
+            ?[b, d] := dist[b, d]
+            ",
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 10], [3, 20], [4, 21]]));
+
+    assert!(db.unregister_aggregation("min_dist").unwrap());
+    assert!(db.run_default("?[min_dist(x)] := x = 1").is_err());
+    assert!(db.unregister_aggregation("min").is_err());
+}
+
+#[test]
+fn custom_functions() {
+    let db = DbInstance::default();
+    db.register_function("add_or_double".to_string(), 1..=2, |args| {
+        let a = args[0].get_int().unwrap();
+        let b = args.get(1).map(|v| v.get_int().unwrap()).unwrap_or(a);
+        Ok(DataValue::from(a + b))
+    })
+    .unwrap();
+    assert!(db
+        .register_function("add_or_double".to_string(), 1..=2, |_| Ok(DataValue::Null))
+        .is_err());
+    assert!(db
+        .register_function("add".to_string(), .., |_| Ok(DataValue::Null))
+        .is_err());
+
+    let res = db
+        .run_default(
+            r"
+            ?[x, y] := x in [1, 2, 3], y = add_or_double(x, 10), add_or_double(x) > 2
+            ",
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 12], [3, 13]]));
+    assert!(db
+        .run_default("?[y] := y = add_or_double(1, 2, 3)")
+        .is_err());
+
+    db.run_default(":create data {k: Int => v: Int}").unwrap();
+    db.run_default(":create doubled {k: Int => v: Int}")
+        .unwrap();
+    db.run_default(
+        r"
+        ::set_triggers data
+        on put {
+            ?[k, v] := _new[k, x], v = add_or_double(x)
+            :put doubled {k => v}
+        }
+        ",
+    )
+    .unwrap();
+    db.run_default("?[k, v] <- [[1, 5]] :put data {k => v}")
+        .unwrap();
+    let res = db.run_default("?[k, v] := *doubled[k, v]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 10]]));
+
+    // defaults are stored with the relation and cannot refer to custom functions
+    let err = db
+        .run_default(":create with_default {k: Int => v: Int default add_or_double(1)}")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::custom_fn_in_default"
+    );
+
+    assert!(db.unregister_function("add_or_double").unwrap());
+    assert!(db.run_default("?[y] := y = add_or_double(1)").is_err());
+    assert!(db.unregister_function("add").is_err());
+}
+
+#[test]
+fn prepared_queries() {
+    let db = DbInstance::default();
+    db.run_default(":create person {name: String => age: Int}")
+        .unwrap();
+    db.run_default(
+        r"?[name, age] <- [['alice', 30], ['bob', 40], ['carol', 50]] :put person {name => age}",
+    )
+    .unwrap();
+
+    let older = db
+        .prepare("?[name] := *person{name, age}, age > $min_age")
+        .unwrap();
+    assert!(older.is_cacheable());
+    let run_older = |min_age: i64| {
+        db.run_prepared(
+            &older,
+            BTreeMap::from([("min_age".to_string(), DataValue::from(min_age))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(run_older(35), json!([["bob"], ["carol"]]));
+    assert_eq!(run_older(45), json!([["carol"]]));
+    assert!(db
+        .run_prepared(&older, Default::default(), ScriptMutability::Immutable)
+        .is_err());
+
+    // parameters in key positions are used for range scans
+    let by_name = db.prepare("?[age] := *person{name: $name, age}").unwrap();
+    let res = db
+        .run_prepared(
+            &by_name,
+            BTreeMap::from([("name".to_string(), DataValue::from("bob"))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[40]]));
+
+    // the plan is recompiled after the indices or the schema change
+    let positional = db.prepare("?[name, age] := *person[name, age]").unwrap();
+    assert_eq!(
+        db.run_prepared(&positional, Default::default(), ScriptMutability::Immutable)
+            .unwrap()
+            .rows
+            .len(),
+        3
+    );
+    db.run_default("::index create person:by_age {age, name}")
+        .unwrap();
+    assert_eq!(run_older(35), json!([["bob"], ["carol"]]));
+    db.run_default("::index drop person:by_age").unwrap();
+    db.run_default(
+        r"
+        ?[name, age, city] := *person{name, age}, city = 'x'
+        :replace person {name => age, city}
+        ",
+    )
+    .unwrap();
+    assert_eq!(run_older(45), json!([["carol"]]));
+    assert!(db
+        .run_prepared(&positional, Default::default(), ScriptMutability::Immutable)
+        .is_err());
+
+    // parameters needed at compile time make the query fall back to the script
+    let limited = db
+        .prepare("?[name] := *person{name} :order name :limit $n")
+        .unwrap();
+    assert!(!limited.is_cacheable());
+    let res = db
+        .run_prepared(
+            &limited,
+            BTreeMap::from([("n".to_string(), DataValue::from(1))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["alice"]]));
+
+    // writes
+    let insert = db
+        .prepare(
+            r"
+            ?[name, age, city] := name = $name, age = $age, city = 'y'
+            :put person {name => age, city}
+            ",
+        )
+        .unwrap();
+    assert!(insert.is_cacheable());
+    assert!(db
+        .run_prepared(
+            &insert,
+            BTreeMap::from([
+                ("name".to_string(), DataValue::from("dave")),
+                ("age".to_string(), DataValue::from(60)),
+            ]),
+            ScriptMutability::Immutable,
+        )
+        .is_err());
+    for (name, age) in [("dave", 60), ("erin", 70)] {
+        db.run_prepared(
+            &insert,
+            BTreeMap::from([
+                ("name".to_string(), DataValue::from(name)),
+                ("age".to_string(), DataValue::from(age)),
+            ]),
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+    }
+    assert_eq!(run_older(55), json!([["dave"], ["erin"]]));
+
+    assert!(db.prepare("?[name] := *person{name").is_err());
+}
+
+#[test]
+fn optional_atoms() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"?[id, name] <- [[1, 'alice'], [2, 'bob'], [3, 'carol']] :create person {id => name}",
+    )
+    .unwrap();
+    db.run_default(
+        r"?[person, email] <- [[1, 'a@x.org'], [1, 'alice@y.com'], [3, 'c@x.org']]
+        :create email {person, email}",
+    )
+    .unwrap();
+
+    let res = db
+        .run_default(
+            r"?[name, email] := *person{id, name}, optional { *email{person: id, email} } :order name, email",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["alice", "a@x.org"],
+            ["alice", "alice@y.com"],
+            ["bob", null],
+            ["carol", "c@x.org"]
+        ])
+    );
+
+    let res = db
+        .run_default(
+            r"?[name] := *person{id, name}, optional { *email{person: id, email} }, is_null(email)",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([["bob"]]));
+
+    // filters inside the optional body restrict the matches, not the outer rows
+    let res = db
+        .run_default(
+            r"
+            org[id, email] := *email{person: id, email}, ends_with(email, '.org')
+            ?[name, email] := *person{id, name}, optional { org[id, email] }
+            :order name
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([["alice", "a@x.org"], ["bob", null], ["carol", "c@x.org"]])
+    );
+
+    let res = db
+        .run_default(
+            r"
+            edge[a, b] <- [[1, 2], [2, 3]]
+            reach[a, b] := edge[a, b]
+            reach[a, c] := reach[a, b], edge[b, c]
+            ?[id, n] := *person{id}, optional { reach[id, r] }, n = if(is_null(r), 0, r)
+            :order id, n
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, 2], [1, 3], [2, 3], [3, 0]]));
+
+    // the optional body sees the variables bound outside of it
+    let res = db
+        .run_default(
+            r"?[name, email] := *person{id, name}, l = length(name),
+              optional { *email{person: id, email}, length(email) > 2 * l }
+              :order name",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([["alice", "alice@y.com"], ["bob", null], ["carol", null]])
+    );
+
+    // outer rows with the same values match the same inner rows only once each
+    let res = db
+        .run_default(
+            r"
+            p[id, tag] <- [[1, 'a'], [1, 'b'], [2, 'a']]
+            ?[id, tag, email] := p[id, tag], optional { *email{person: id, email}, ends_with(email, '.org') }
+            :order id, tag
+            ",
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([[1, "a", "a@x.org"], [1, "b", "a@x.org"], [2, "a", null]])
+    );
+
+    // the stored relation in the optional body is looked up by the outer key
+    let expl = db
+        .run_default(r"::explain { ?[name, email] := *person{id, name}, optional { *email{person: id, email} } }")
+        .unwrap();
+    assert!(expl
+        .rows
+        .iter()
+        .any(|row| row[4] == DataValue::from("left_join")));
+    assert!(expl
+        .rows
+        .iter()
+        .any(|row| row[4] == DataValue::from("stored_prefix_join")));
+
+    assert!(db
+        .run_default(r"?[id] := *person{id}, not optional { *email{person: id} }")
+        .is_err());
+    assert!(db
+        .run_default(
+            r"?[id, e] := *person{id}, optional { *email{person: id, email: e} or e = 'none' }"
+        )
+        .is_err());
+}
+
+#[test]
+fn subqueries() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[name, dept, salary] <- [['a', 1, 10], ['b', 1, 20], ['c', 2, 5], ['d', 3, 7]]
+        :create emp {name => dept, salary}
+        ",
+    )
+    .unwrap();
+    db.run_default(
+        r"?[dept, dname] <- [[1, 'x'], [2, 'y'], [4, 'z']] :create dept {dept => dname}",
+    )
+    .unwrap();
+
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+
+    assert_eq!(
+        rows(r"?[name] := *emp{name, salary}, salary == {?[max(s)] := *emp{salary: s}}"),
+        json!([["b"]])
+    );
+    // `dept` is shared with the enclosing rule, so the subquery is correlated on it
+    assert_eq!(
+        rows(
+            r"?[name] := *emp{name, dept, salary}, salary == {?[max(s)] := *emp{dept, salary: s}}"
+        ),
+        json!([["b"], ["c"], ["d"]])
+    );
+    assert_eq!(
+        rows(r"?[dname] := *dept{dept, dname}, exists { *emp{dept} }"),
+        json!([["x"], ["y"]])
+    );
+    assert_eq!(
+        rows(r"?[dname] := *dept{dept, dname}, not exists { *emp{dept} }"),
+        json!([["z"]])
+    );
+    assert_eq!(
+        rows(r"?[dname, n] := *dept{dept, dname}, n = {?[count(x)] := *emp{dept, name: x}}"),
+        json!([["x", 2], ["y", 1], ["z", 0]])
+    );
+    // without aggregation, the result is null when there is no match
+    assert_eq!(
+        rows(r"?[dname, n] := *dept{dept, dname}, n = {?[x] := *emp{dept, name: x}, x == 'c'}"),
+        json!([["x", null], ["y", "c"], ["z", null]])
+    );
+    // and there must not be more than one result for a row
+    let err = db
+        .run_default(r"?[dname, n] := *dept{dept, dname}, n = {?[x] := *emp{dept, name: x}}")
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("more than one row"), "{err:?}");
+    // `bar` is only used in a filter of the subquery, and taken from the enclosing rule
+    assert_eq!(
+        rows(
+            r"?[name, n] := *emp{name, salary: bar}, n = {?[count(x)] := *emp{name: x, salary}, salary > bar}"
+        ),
+        json!([["a", 1], ["b", 0], ["c", 3], ["d", 2]])
+    );
+    assert_eq!(
+        rows(r"?[dname, e] := *dept{dept, dname}, e = exists { *emp{dept, salary}, salary > 15 }"),
+        json!([["x", true], ["y", false], ["z", false]])
+    );
+    assert_eq!(
+        rows(r"?[x] := x = {?[max(s)] := *emp{salary: s}, s < {?[max(t)] := *emp{salary: t}}}"),
+        json!([[10]])
+    );
+
+    let err = db
+        .run_default(
+            r"
+            r[x] := x = 1
+            r[x] := r[y], x = y + 1, x < 3, not exists { r[x] }
+            ?[x] := r[x]
+            ",
+        )
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("unstratifiable"));
+}
+
+#[test]
+fn keyset_pagination() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[a, b, c] := a in int_range(4), b in int_range(3), c = a * 10 + b
+        :create grid {a, b => c}
+        ",
+    )
+    .unwrap();
+
+    let pages = |query: &str| {
+        let mut collected = vec![];
+        let mut cursor = DataValue::Null;
+        loop {
+            let (res, next) = db
+                .run_script_page(
+                    query,
+                    BTreeMap::from([("cursor".to_string(), cursor)]),
+                    ScriptMutability::Immutable,
+                )
+                .unwrap();
+            collected.push(res.rows.iter().map(|row| row[2].clone()).collect_vec());
+            match next {
+                None => return collected,
+                Some(c) => cursor = DataValue::from(c),
+            }
+        }
+    };
+    let c = |xs: &[i64]| xs.iter().map(|x| DataValue::from(*x)).collect_vec();
+
+    assert_eq!(
+        pages("?[a, b, c] := *grid{a, b, c} :limit 5 :after $cursor"),
+        vec![
+            c(&[0, 1, 2, 10, 11]),
+            c(&[12, 20, 21, 22, 30]),
+            c(&[31, 32])
+        ]
+    );
+    // rows tied on the leading key must not be skipped when resuming a range scan
+    assert_eq!(
+        pages("?[a, b, c] := *grid{a, b, c} :order a, -b :limit 4 :after $cursor"),
+        vec![
+            c(&[2, 1, 0, 12]),
+            c(&[11, 10, 22, 21]),
+            c(&[20, 32, 31, 30]),
+            c(&[])
+        ]
+    );
+    assert_eq!(
+        pages("?[a, b, c] := *grid{a, b, c}, b == 1 :order -c :limit 3 :after $cursor"),
+        vec![c(&[31, 21, 11]), c(&[1])]
+    );
+    assert_eq!(
+        pages("?[b, count(a), max(c)] := *grid{a, b, c} :limit 2 :after $cursor"),
+        vec![c(&[30, 31]), c(&[32])]
+    );
+
+    let query = "?[a, b, c] := *grid{a, b, c} :limit 5 :after null";
+    let (_, cursor) = db
+        .run_script_page(query, Default::default(), ScriptMutability::Immutable)
+        .unwrap();
+    let cursor = cursor.unwrap();
+    assert_eq!(
+        db.run_script_fold_err(query, Default::default(), ScriptMutability::Immutable)["cursor"],
+        json!(cursor)
+    );
+    let explained = db
+        .run_script(
+            "::explain { ?[a, b, c] := *grid{a, b, c} :limit 5 :after $cursor }",
+            BTreeMap::from([("cursor".to_string(), DataValue::from(cursor.clone()))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert!(explained
+        .rows
+        .iter()
+        .any(|row| row[4] == DataValue::from("load_stored")
+            && row[7].to_string().contains("after_cursor")));
+
+    assert!(db
+        .run_script(
+            "?[a] := *grid{a} :limit 5 :after $cursor",
+            BTreeMap::from([("cursor".to_string(), DataValue::from(cursor))]),
+            ScriptMutability::Immutable,
+        )
+        .is_err());
+    assert!(db
+        .run_default("?[a] := *grid{a} :after 'not a cursor'")
+        .is_err());
+
+    // a range scan must start at the bound itself when the key is followed by value columns
+    db.run_default("?[k, v] := k in int_range(5), v = -k :create kv {k => v}")
+        .unwrap();
+    let res = db
+        .run_default("?[k, v] := *kv{k, v}, k >= 3")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[3, -3], [4, -4]]));
+}
+
+#[test]
+fn table_functions() {
+    let db = DbInstance::default();
+    let rows = |q: &str| db.run_default(q).unwrap().into_json()["rows"].clone();
+
+    assert_eq!(
+        rows("?[x] := [x] in generate_series(1, 5, 2)"),
+        json!([[1], [3], [5]])
+    );
+    assert_eq!(
+        rows("?[x] := [x] in generate_series(3, 1, -1) :order -x"),
+        json!([[3], [2], [1]])
+    );
+    assert_eq!(
+        rows("?[x] := [x] in generate_series(0, 1, 0.5)"),
+        json!([[0.0], [0.5], [1.0]])
+    );
+    assert_eq!(
+        rows(r#"?[k, v] := [k, v] in json_each(parse_json('{"a": 1, "b": [2]}'))"#),
+        json!([["a", 1], ["b", [2]]])
+    );
+    assert_eq!(
+        rows("?[i, v] := [i, v] in json_each(['x', 'y'])"),
+        json!([[0, "x"], [1, "y"]])
+    );
+    assert_eq!(
+        rows(r"?[m, g] := [m, g] in regex_matches_rows('a1 b22 c', '([a-z])([0-9]+)')"),
+        json!([["a1", ["a", "1"]], ["b22", ["b", "22"]]])
+    );
+    // arguments may depend on variables bound earlier in the body
+    db.run_default(r"?[id, text] <- [[1, 'to be'], [2, 'or not']] :create docs {id => text}")
+        .unwrap();
+    assert_eq!(
+        rows("?[id, i, w] := *docs{id, text}, [i, w] in split_rows(text, ' ')"),
+        json!([[1, 0, "to"], [1, 1, "be"], [2, 0, "or"], [2, 1, "not"]])
+    );
+    // variables already bound act as filters, and `_` discards a column
+    assert_eq!(
+        rows("?[id] := *docs{id, text}, [_, w] in split_rows(text, ' '), w = 'not', [_, w] in json_each(['be', 'not'])"),
+        json!([[2]])
+    );
+    assert_eq!(
+        rows("?[x] := x = 3, [x] in generate_series(1, 5)"),
+        json!([[3]])
+    );
+
+    db.register_table_function("squares".to_string(), 1..=1, 2, |args| {
+        let n = args[0].get_int().unwrap();
+        Ok((1..=n).map(|i| vec![DataValue::from(i), DataValue::from(i * i)]))
+    })
+    .unwrap();
+    assert!(db
+        .register_table_function("squares".to_string(), 1..=1, 2, |_| Ok(vec![vec![]]))
+        .is_err());
+    assert!(db
+        .register_table_function("split_rows".to_string(), .., 2, |_| Ok(vec![vec![]]))
+        .is_err());
+    assert_eq!(
+        rows("?[i, s] := [i, s] in squares(3)"),
+        json!([[1, 1], [2, 4], [3, 9]])
+    );
+    let explained = db
+        .run_default("::explain { ?[i, s] := [i, s] in squares(3) }")
+        .unwrap();
+    assert!(explained
+        .rows
+        .iter()
+        .any(|row| row[4] == DataValue::from("table_function")
+            && row[5] == DataValue::from("squares")));
+
+    db.register_table_function("bad".to_string(), 0..=0, 2, |_| {
+        Ok(vec![vec![DataValue::from(1)]])
+    })
+    .unwrap();
+    assert!(db.run_default("?[a, b] := [a, b] in bad()").is_err());
+    assert!(db
+        .run_default("?[x] := [x] in generate_series(1, y)")
+        .is_err());
+    assert!(db
+        .run_default("?[x] := [x] in split_rows('a b', ' ')")
+        .is_err());
+    assert!(db.run_default("?[x] := [x] in generate_series(1)").is_err());
+    assert!(db
+        .run_default("?[x] := [x] in no_such_function(1)")
+        .is_err());
+    assert!(db
+        .run_default("?[x] := [x] in generate_series(1, 3, 0)")
+        .is_err());
+
+    // rows are generated lazily, so huge or unbounded series can be limited or timed out
+    assert_eq!(
+        rows("?[x] := [x] in generate_series(1, 3000000000) :limit 3"),
+        json!([[1], [2], [3]])
+    );
+    assert!(db
+        .run_default("?[x] := [x] in generate_series(1, 3000000000), x < 0 :timeout 0.2")
+        .is_err());
+    db.register_table_function("naturals".to_string(), 0..=0, 1, |_| {
+        Ok((0..).map(|i: i64| vec![DataValue::from(i)]))
+    })
+    .unwrap();
+    assert_eq!(
+        rows("?[x] := [x] in naturals(), x % 2 == 1 :limit 2"),
+        json!([[1], [3]])
+    );
+
+    assert!(db.unregister_table_function("squares").unwrap());
+    assert!(db.run_default("?[i, s] := [i, s] in squares(3)").is_err());
+    assert!(db.unregister_table_function("split_rows").is_err());
+}
+
+#[test]
+fn parallel_sketch_aggregation() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default(
+            r"
+        r[k, approx_count_distinct(x), approx_median(x), top_k_frequent(m, 1)] :=
+            x in int_range(0, 3000), k = 'a', m = x % 7
+        r[k, approx_count_distinct(x), approx_median(x), top_k_frequent(m, 1)] :=
+            x in int_range(2000, 4001), k = 'a', m = 0
+        ?[k, n, median, top] := r[k, n, median, top]
+    ",
+        )
+        .unwrap()
+        .into_json();
+    let row = &res["rows"][0];
+    assert_eq!(row[0], json!("a"));
+    let n = row[1].as_i64().unwrap();
+    assert!((3900..4100).contains(&n), "{}", n);
+    let median = row[2].as_f64().unwrap();
+    assert!((median - 2250.).abs() < 50., "{}", median);
+    assert_eq!(row[3][0][0], json!(0));
+}
+
+#[test]
+fn rotate_data_key_waits_for_transactions() {
+    let db = DbInstance::new("mem", "", &json!({ "encryption_key": "key" }).to_string()).unwrap();
+    db.run_default(":create notes {id: Int => text: String}")
+        .unwrap();
+    db.enable_write_log().unwrap();
+    db.run_default("?[id, text] <- [[1, 'first']] :put notes {id => text}")
+        .unwrap();
+
+    // the transaction holds the old data key, so the rotation waits for it to commit
+    let tx = db.multi_transaction(true);
+    tx.run_script(
+        "?[id, text] <- [[2, 'second']] :put notes {id => text}",
+        Default::default(),
+    )
+    .unwrap();
+    let rotating = {
+        let db = db.clone();
+        std::thread::spawn(move || db.rotate_encryption_key(b"new key", true))
+    };
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!rotating.is_finished());
+    tx.commit().unwrap();
+    rotating.join().unwrap().unwrap();
+    assert_eq!(
+        db.run_default("?[id, text] := *notes{id, text}")
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, "first"], [2, "second"]])
+    );
+
+    // the values recorded in the write log are encrypted with the new data key as well
+    let DbInstance::Mem(inner) = &db else {
+        unreachable!()
+    };
+    let EncryptionState::Unlocked(keyring) = inner.encryption.read().unwrap().clone() else {
+        unreachable!()
+    };
+    let write_sets = db.read_write_log(0, None).unwrap();
+    assert_eq!(write_sets.len(), 2);
+    for op in write_sets.iter().flat_map(|ws| ws.ops.iter()) {
+        if let WriteOp::Put(k, v) = op {
+            if !is_clear(k, v) {
+                keyring.decrypt(k, v).unwrap();
+            }
+        }
+    }
+}
@@ -6,17 +6,21 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU32, AtomicU64};
 use std::sync::Arc;
 
+use crossbeam::sync::ShardedLock;
 use miette::{bail, Result};
 use crate::data::program::ReturnMutation;
 
+use crate::data::expr::CustomFunction;
 use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
 use crate::fts::TokenizerCache;
 use crate::{CallbackOp, NamedRows};
 use crate::runtime::callback::CallbackCollector;
+use crate::runtime::db::Poison;
 use crate::runtime::relation::RelationId;
 use crate::storage::temp::TempTx;
 use crate::storage::StoreTx;
@@ -27,6 +31,12 @@ pub struct SessionTx<'a> {
     pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) temp_store_id: AtomicU32,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    pub(crate) functions: Arc<ShardedLock<BTreeMap<String, Arc<CustomFunction>>>>,
+    /// poison of the query being evaluated, for operators that may run for long
+    /// without producing any row
+    pub(crate) poison: Poison,
+    /// rows a hash join may hold in memory, see [crate::Db::set_hash_join_memory_budget]
+    pub(crate) hash_join_memory_budget: usize,
 }
 
 pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
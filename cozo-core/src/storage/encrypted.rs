@@ -0,0 +1,455 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use rand::Rng;
+use serde_bytes::ByteBuf;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::data::memcmp::NULL_TAG;
+use crate::data::tuple::{check_key_for_validity, Tuple, TupleT};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v, RelationId};
+use crate::storage::StoreTx;
+use crate::utils::swap_option_result;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The data is encrypted and no encryption key was given")]
+#[diagnostic(code(db::encryption_key_required))]
+#[diagnostic(help("Open the database with the `encryption_key` option"))]
+pub(crate) struct EncryptionKeyRequired;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The encryption key is not the one the data was encrypted with")]
+#[diagnostic(code(db::wrong_encryption_key))]
+pub(crate) struct WrongEncryptionKey;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot decrypt a value: it is corrupt or encrypted with an unknown data key")]
+#[diagnostic(code(db::corrupt_encrypted_value))]
+pub(crate) struct CorruptEncryptedValue;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database is not encrypted")]
+#[diagnostic(code(db::not_encrypted))]
+#[diagnostic(help(
+    "Back it up with `backup_db` and restore the backup into a new database opened with the `encryption_key` option"
+))]
+pub(crate) struct NotEncrypted;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot encrypt a database that already has data in it")]
+#[diagnostic(code(db::encrypt_existing_db))]
+#[diagnostic(help(
+    "Back it up with `backup_db` and restore the backup into a new database opened with the `encryption_key` option"
+))]
+pub(crate) struct EncryptExistingDb;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot change the data key while transactions started before are still running")]
+#[diagnostic(code(db::rotation_blocked))]
+#[diagnostic(help(
+    "Commit or abort the running transactions, such as multi-statement ones, and try again"
+))]
+pub(crate) struct RotationBlocked;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+const KEY_ID_LEN: usize = 4;
+/// Context of the key derived from the user-supplied key, which only encrypts data keys.
+const KEY_WRAPPING_INFO: &[u8] = b"cozo data key wrapping";
+
+/// Key of the [EncryptionHeader], present if and only if the database is encrypted.
+pub(crate) fn encryption_header_key() -> Vec<u8> {
+    vec![DataValue::Null, DataValue::from("ENCRYPTION")].encode_as_key(RelationId::SYSTEM)
+}
+
+/// Whether the key is one of the bookkeeping keys of the system relation, which start with `Null`.
+/// Their values are kept in the clear so that they can be read before the encryption key is known.
+pub(crate) fn is_bookkeeping_key(key: &[u8]) -> bool {
+    key.len() > 8 && key[..8] == RelationId::SYSTEM.raw_encode() && key[8] == NULL_TAG
+}
+
+/// Whether the value is stored as it is: values of bookkeeping keys and empty values,
+/// such as those of indices, which have nothing to hide.
+pub(crate) fn is_clear(key: &[u8], val: &[u8]) -> bool {
+    val.is_empty() || is_bookkeeping_key(key)
+}
+
+/// Stored in the database, it holds the data keys encrypted with a key derived from
+/// the user-supplied key, so that the latter can be changed without touching the data.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct EncryptionHeader {
+    #[serde(with = "serde_bytes")]
+    salt: Vec<u8>,
+    /// Id of the data key new values are encrypted with
+    current: u32,
+    keys: BTreeMap<u32, ByteBuf>,
+}
+
+impl EncryptionHeader {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).unwrap()
+    }
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        rmp_serde::from_slice(data).into_diagnostic()
+    }
+}
+
+#[derive(Clone)]
+struct DataKey {
+    raw: [u8; KEY_LEN],
+    cipher: Aes256Gcm,
+}
+
+impl DataKey {
+    fn new(raw: [u8; KEY_LEN]) -> Self {
+        Self {
+            raw,
+            cipher: Aes256Gcm::new(&raw.into()),
+        }
+    }
+}
+
+/// The unencrypted data keys of a database.
+///
+/// Each value is stored as the id of the data key (4 bytes), followed by a random nonce (12 bytes)
+/// and the value encrypted with AES-256-GCM, authenticated together with its key,
+/// so that values cannot be swapped between keys. Keys are left in the clear to preserve their order.
+#[derive(Clone)]
+pub(crate) struct Keyring {
+    /// The user-supplied key
+    pub(crate) secret: Vec<u8>,
+    current: u32,
+    keys: BTreeMap<u32, DataKey>,
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut ret = [0; N];
+    rand::thread_rng().fill(&mut ret[..]);
+    ret
+}
+
+fn key_wrapping_cipher(secret: &[u8], salt: &[u8]) -> Aes256Gcm {
+    let mut key = [0; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(salt), secret)
+        .expand(KEY_WRAPPING_INFO, &mut key)
+        .unwrap();
+    Aes256Gcm::new(&key.into())
+}
+
+fn seal(cipher: &Aes256Gcm, msg: &[u8], aad: &[u8]) -> Vec<u8> {
+    let nonce: [u8; NONCE_LEN] = random_bytes();
+    let mut ret = nonce.to_vec();
+    ret.extend(
+        cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg, aad })
+            .unwrap(),
+    );
+    ret
+}
+
+fn open(cipher: &Aes256Gcm, data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, msg) = data.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg, aad })
+        .ok()
+}
+
+impl Keyring {
+    /// A keyring with a new random data key.
+    pub(crate) fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            current: 0,
+            keys: BTreeMap::from([(0, DataKey::new(random_bytes()))]),
+        }
+    }
+    /// Decrypt the data keys of `header` with `secret`.
+    pub(crate) fn unlock(header: &EncryptionHeader, secret: &[u8]) -> Result<Self> {
+        let cipher = key_wrapping_cipher(secret, &header.salt);
+        let mut keys = BTreeMap::new();
+        for (id, wrapped) in &header.keys {
+            let raw = open(&cipher, wrapped, &id.to_be_bytes()).ok_or(WrongEncryptionKey)?;
+            let raw = raw.try_into().map_err(|_| WrongEncryptionKey)?;
+            keys.insert(*id, DataKey::new(raw));
+        }
+        if !keys.contains_key(&header.current) {
+            bail!(WrongEncryptionKey)
+        }
+        Ok(Self {
+            secret: secret.to_vec(),
+            current: header.current,
+            keys,
+        })
+    }
+    /// The header to store, with the data keys encrypted under a new salt.
+    pub(crate) fn header(&self) -> EncryptionHeader {
+        let salt: [u8; SALT_LEN] = random_bytes();
+        let cipher = key_wrapping_cipher(&self.secret, &salt);
+        EncryptionHeader {
+            salt: salt.to_vec(),
+            current: self.current,
+            keys: self
+                .keys
+                .iter()
+                .map(|(id, key)| {
+                    let wrapped = seal(&cipher, &key.raw, &id.to_be_bytes());
+                    (*id, ByteBuf::from(wrapped))
+                })
+                .collect(),
+        }
+    }
+    /// The same keyring under a new user-supplied key, and with a new data key
+    /// for encrypting values from now on if `new_data_key` is set.
+    pub(crate) fn rotate(&self, secret: &[u8], new_data_key: bool) -> Self {
+        let mut ret = self.clone();
+        ret.secret = secret.to_vec();
+        if new_data_key {
+            ret.current = self.keys.keys().max().unwrap() + 1;
+            ret.keys.insert(ret.current, DataKey::new(random_bytes()));
+        }
+        ret
+    }
+    /// Forget all data keys but the current one, once no value is encrypted with them.
+    pub(crate) fn retire_old_keys(&mut self) {
+        let current = self.current;
+        self.keys.retain(|id, _| *id == current);
+    }
+    pub(crate) fn encrypt(&self, key: &[u8], val: &[u8]) -> Vec<u8> {
+        let mut ret = self.current.to_be_bytes().to_vec();
+        ret.extend(seal(&self.keys[&self.current].cipher, val, key));
+        ret
+    }
+    pub(crate) fn decrypt(&self, key: &[u8], val: &[u8]) -> Result<Vec<u8>> {
+        if val.len() < KEY_ID_LEN {
+            bail!(CorruptEncryptedValue)
+        }
+        let (id, data) = val.split_at(KEY_ID_LEN);
+        let id = u32::from_be_bytes(id.try_into().unwrap());
+        let data_key = self.keys.get(&id).ok_or(CorruptEncryptedValue)?;
+        Ok(open(&data_key.cipher, data, key).ok_or(CorruptEncryptedValue)?)
+    }
+}
+
+/// Whether the database is encrypted, and if so whether its key was given.
+#[derive(Clone, Default)]
+pub(crate) enum EncryptionState {
+    #[default]
+    Disabled,
+    Locked,
+    Unlocked(Arc<Keyring>),
+}
+
+/// Remove the `encryption_key` field from the engine options, which is not for the engine.
+pub(crate) fn take_encryption_key(options: &str) -> Result<(String, Option<String>)> {
+    let mut fields: serde_json::Map<String, serde_json::Value> = match serde_json::from_str(options)
+    {
+        Ok(fields) => fields,
+        // the engine reports it
+        Err(_) => return Ok((options.to_string(), None)),
+    };
+    match fields.remove("encryption_key") {
+        None => Ok((options.to_string(), None)),
+        Some(serde_json::Value::String(key)) if !key.is_empty() => {
+            Ok((serde_json::Value::Object(fields).to_string(), Some(key)))
+        }
+        Some(_) => bail!("the `encryption_key` option must be a non-empty string"),
+    }
+}
+
+/// Transaction given out when the database is encrypted: keys are passed through,
+/// and values are encrypted when written and decrypted when read.
+pub(crate) struct EncryptedTx<T> {
+    pub(crate) inner: T,
+    /// `None` if the key was not given, in which case only values kept in the clear can be accessed.
+    pub(crate) keyring: Option<Arc<Keyring>>,
+}
+
+impl<T> EncryptedTx<T> {
+    fn keyring(&self) -> Result<&Keyring> {
+        Ok(self.keyring.as_deref().ok_or(EncryptionKeyRequired)?)
+    }
+    fn encrypt<'v>(&self, key: &[u8], val: &'v [u8]) -> Result<Cow<'v, [u8]>> {
+        Ok(if is_clear(key, val) {
+            Cow::Borrowed(val)
+        } else {
+            Cow::Owned(self.keyring()?.encrypt(key, val))
+        })
+    }
+    fn decrypt(&self, key: &[u8], val: Vec<u8>) -> Result<Vec<u8>> {
+        if is_clear(key, &val) {
+            Ok(val)
+        } else {
+            self.keyring()?.decrypt(key, &val)
+        }
+    }
+}
+
+impl<'s, T: StoreTx<'s>> StoreTx<'s> for EncryptedTx<T> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key, for_update)? {
+            None => Ok(None),
+            Some(val) => Ok(Some(self.decrypt(key, val)?)),
+        }
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner
+            .multi_get(keys, for_update)?
+            .into_iter()
+            .zip(keys)
+            .map(|(val, key)| val.map(|val| self.decrypt(key, val)).transpose())
+            .collect()
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        let val = self.encrypt(key, val)?;
+        self.inner.put(key, &val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.par_put(key, &self.encrypt(key, val)?)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(self.range_scan(lower, upper).map(|kv| {
+            let (k, v) = kv?;
+            Ok(decode_tuple_from_kv(&k, &v, None))
+        }))
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        Box::new(SkipIter {
+            tx: self,
+            valid_at,
+            next_bound: lower.to_vec(),
+            upper_bound: upper.to_vec(),
+        })
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(self.inner.range_scan(lower, upper).map(|kv| {
+            let (k, v) = kv?;
+            let v = self.decrypt(&k, v)?;
+            Ok((k, v))
+        }))
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(self.inner.total_scan().map(|kv| {
+            let (k, v) = kv?;
+            let v = self.decrypt(&k, v)?;
+            Ok((k, v))
+        }))
+    }
+}
+
+/// The values are only seen by the inner transaction encrypted, so the skip scan
+/// is done here by seeking with raw range scans.
+struct SkipIter<'a, T> {
+    tx: &'a EncryptedTx<T>,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+    upper_bound: Vec<u8>,
+}
+
+impl<'s, 'a, T: StoreTx<'s>> SkipIter<'a, T> {
+    fn next_inner(&mut self) -> Result<Option<Tuple>> {
+        loop {
+            let found = self
+                .tx
+                .inner
+                .range_scan(&self.next_bound, &self.upper_bound)
+                .next();
+            let (k, v) = match found {
+                None => return Ok(None),
+                Some(kv) => kv?,
+            };
+            let (ret, nxt_bound) = check_key_for_validity(&k, self.valid_at, None);
+            self.next_bound = nxt_bound;
+            if let Some(mut tup) = ret {
+                extend_tuple_from_v(&mut tup, &self.tx.decrypt(&k, v)?);
+                return Ok(Some(tup));
+            }
+        }
+    }
+}
+
+impl<'s, 'a, T: StoreTx<'s>> Iterator for SkipIter<'a, T> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        swap_option_result(self.next_inner())
+    }
+}
@@ -6,28 +6,36 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
+use crossbeam::sync::ShardedLock;
 use std::cmp::Ordering;
 use std::collections::btree_map::Range;
-use std::collections::BTreeMap;
+use std::collections::{btree_map, BTreeMap, BTreeSet};
 use std::default::Default;
 use std::iter::Fuse;
 use std::mem;
 use std::ops::Bound;
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
 
 use itertools::Itertools;
-use miette::{bail, Result};
+use log::error;
+use miette::{bail, Diagnostic, Result};
+use smallvec::{smallvec, SmallVec};
+use thiserror::Error;
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::wal::{Wal, WalOptions};
 use crate::storage::{Storage, StoreTx};
-use crate::utils::swap_option_result;
 
 /// Create a database backed by memory.
 /// This is the fastest storage, but non-persistent.
-/// Supports concurrent readers but only a single writer.
+/// Transactions read from a consistent snapshot and never block each other:
+/// writes are buffered until commit, which fails if a concurrent transaction
+/// has committed a change to any key written or read for update since the snapshot.
 pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     let ret = crate::Db::new(MemStorage::default())?;
 
@@ -35,10 +43,278 @@ pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     Ok(ret)
 }
 
-/// The non-persistent storage
+/// Create a database backed by memory, made durable by a write-ahead log and snapshots in
+/// the directory `path`. The data in `path` is loaded on creation, and the directory is
+/// locked against other databases until the returned one is dropped.
+/// Snapshots are written by a background thread, which the drop waits for.
+pub fn new_cozo_mem_durable(
+    path: impl AsRef<Path>,
+    options: WalOptions,
+) -> Result<crate::Db<MemStorage>> {
+    let (wal, data, seq) = Wal::open(path.as_ref(), options)?;
+    let store = MemStore {
+        data: Arc::new(ShardedLock::new(
+            data.into_iter()
+                .map(|(k, v)| (k, smallvec![(seq, Some(v))]))
+                .collect(),
+        )),
+        snapshots: Arc::new(Mutex::new(Snapshots {
+            last_committed: seq,
+            active: Default::default(),
+        })),
+        garbage: Mutex::new(Garbage {
+            keys: Default::default(),
+            horizon: seq,
+        }),
+        wal: Some(Mutex::new(wal)),
+        checkpointer: Default::default(),
+    };
+    let ret = crate::Db::new(MemStorage {
+        store: Arc::new(store),
+    })?;
+
+    ret.initialize()?;
+    Ok(ret)
+}
+
+/// The in-memory storage, optionally made durable by a write-ahead log
 #[derive(Default, Clone)]
 pub struct MemStorage {
-    store: Arc<ShardedLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    store: Arc<MemStore>,
+}
+
+/// Committed values of a key, with the sequence number of the commit
+/// that wrote them, oldest first. `None` marks a deletion.
+type Versions = SmallVec<[(u64, Option<Vec<u8>>); 1]>;
+
+const SCAN_BATCH_SIZE: usize = 256;
+const PUT_BATCH_SIZE: usize = 65536;
+
+type Data = BTreeMap<Vec<u8>, Versions>;
+
+#[derive(Default)]
+struct MemStore {
+    // shared with the checkpoint thread
+    data: Arc<ShardedLock<Data>>,
+    snapshots: Arc<Mutex<Snapshots>>,
+    // also serialises commits
+    garbage: Mutex<Garbage>,
+    wal: Option<Mutex<Wal>>,
+    checkpointer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for MemStore {
+    fn drop(&mut self) {
+        if let Some(handle) = self.checkpointer.get_mut().unwrap().take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Snapshots {
+    last_committed: u64,
+    active: BTreeMap<u64, usize>,
+}
+
+impl Snapshots {
+    fn release(&mut self, ts: u64) {
+        if let btree_map::Entry::Occupied(mut ent) = self.active.entry(ts) {
+            *ent.get_mut() -= 1;
+            if *ent.get() == 0 {
+                ent.remove();
+            }
+        }
+    }
+}
+
+/// Keys holding versions that may become invisible to every transaction.
+#[derive(Default)]
+struct Garbage {
+    keys: BTreeSet<Vec<u8>>,
+    horizon: u64,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Transaction conflicts with a concurrent transaction")]
+#[diagnostic(code(storage::tx_conflict))]
+#[diagnostic(help("The transaction may be retried"))]
+struct TxConflictError;
+
+fn visible(versions: &Versions, snapshot: u64) -> Option<&Vec<u8>> {
+    versions
+        .iter()
+        .rev()
+        .find(|(ts, _)| *ts <= snapshot)
+        .and_then(|(_, v)| v.as_ref())
+}
+
+fn scan_data(
+    data: &Data,
+    lower: Bound<&Vec<u8>>,
+    upper: Bound<&Vec<u8>>,
+    snapshot: u64,
+    limit: usize,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    data.range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((lower, upper))
+        .filter_map(|(k, vs)| visible(vs, snapshot).map(|v| (k.clone(), v.clone())))
+        .take(limit)
+        .collect_vec()
+}
+
+impl MemStore {
+    fn acquire_snapshot(&self) -> u64 {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let ts = snapshots.last_committed;
+        *snapshots.active.entry(ts).or_default() += 1;
+        ts
+    }
+
+    fn release_snapshot(&self, ts: u64) {
+        self.snapshots.lock().unwrap().release(ts)
+    }
+
+    fn get(&self, key: &[u8], snapshot: u64) -> Option<Vec<u8>> {
+        let data = self.data.read().unwrap();
+        data.get(key).and_then(|vs| visible(vs, snapshot)).cloned()
+    }
+
+    fn scan(
+        &self,
+        lower: Bound<&Vec<u8>>,
+        upper: Bound<&Vec<u8>>,
+        snapshot: u64,
+        limit: usize,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        scan_data(&self.data.read().unwrap(), lower, upper, snapshot, limit)
+    }
+
+    /// Commit `changes` as a new version.
+    /// If `snapshot` is given, fails if any key in `changes` or `checked` has been
+    /// committed to after it.
+    fn commit(
+        &self,
+        changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        snapshot: Option<u64>,
+        checked: &BTreeSet<Vec<u8>>,
+    ) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let mut garbage = self.garbage.lock().unwrap();
+        let mut data = self.data.write().unwrap();
+        if let Some(snapshot) = snapshot {
+            for key in changes.keys().chain(checked.iter()) {
+                if let Some((ts, _)) = data.get(key).and_then(|vs| vs.last()) {
+                    if *ts > snapshot {
+                        bail!(TxConflictError)
+                    }
+                }
+            }
+        }
+        let ts = self.snapshots.lock().unwrap().last_committed + 1;
+        if let Some(wal) = &self.wal {
+            wal.lock().unwrap().append(ts, &changes)?;
+        }
+        let mut written = vec![];
+        for (k, v) in changes {
+            match data.entry(k) {
+                btree_map::Entry::Occupied(mut ent) => {
+                    ent.get_mut().push((ts, v));
+                    written.push(ent.key().clone());
+                }
+                btree_map::Entry::Vacant(ent) => {
+                    if v.is_some() {
+                        ent.insert(smallvec![(ts, v)]);
+                    }
+                }
+            }
+        }
+        let horizon = {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            snapshots.last_committed = ts;
+            snapshots.active.keys().next().cloned().unwrap_or(ts)
+        };
+        // only keys written just now can have become collectable if no old snapshot is released
+        let candidates = if horizon > garbage.horizon {
+            garbage.horizon = horizon;
+            mem::take(&mut garbage.keys)
+                .into_iter()
+                .chain(written)
+                .collect_vec()
+        } else {
+            written
+        };
+        for key in candidates {
+            if Self::collect_garbage(&mut data, &key, horizon) {
+                garbage.keys.insert(key);
+            }
+        }
+        drop(data);
+        if let Some(wal) = &self.wal {
+            let mut wal = wal.lock().unwrap();
+            let mut checkpointer = self.checkpointer.lock().unwrap();
+            let running = matches!(&*checkpointer, Some(handle) if !handle.is_finished());
+            if wal.needs_checkpoint() && !running {
+                // the commits are already in the log, so a failed checkpoint loses nothing
+                match wal.start_checkpoint() {
+                    Ok(checkpoint) => {
+                        // still the latest commit, as commits are serialised by `garbage`
+                        let snapshot = self.acquire_snapshot();
+                        let data = self.data.clone();
+                        let snapshots = self.snapshots.clone();
+                        *checkpointer = Some(thread::spawn(move || {
+                            let mut next = Bound::Unbounded;
+                            let mut batch = vec![].into_iter();
+                            let latest = std::iter::from_fn(|| {
+                                if let Some(item) = batch.next() {
+                                    return Some(item);
+                                }
+                                let found = scan_data(
+                                    &data.read().unwrap(),
+                                    next.as_ref(),
+                                    Bound::Unbounded,
+                                    snapshot,
+                                    SCAN_BATCH_SIZE,
+                                );
+                                next = Bound::Excluded(found.last()?.0.clone());
+                                batch = found.into_iter();
+                                batch.next()
+                            });
+                            if let Err(err) = checkpoint.write(snapshot, latest) {
+                                error!("checkpoint of mem storage failed: {:?}", err);
+                            }
+                            snapshots.lock().unwrap().release(snapshot);
+                        }));
+                    }
+                    Err(err) => error!("checkpoint of mem storage failed: {:?}", err),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the versions of `key` invisible to all snapshots at or after `horizon`.
+    /// Returns whether some versions must be kept for older snapshots.
+    fn collect_garbage(data: &mut BTreeMap<Vec<u8>, Versions>, key: &[u8], horizon: u64) -> bool {
+        let versions = match data.get_mut(key) {
+            None => return false,
+            Some(vs) => vs,
+        };
+        let keep_from = versions
+            .iter()
+            .rposition(|(ts, _)| *ts <= horizon)
+            .unwrap_or(0);
+        versions.drain(..keep_from);
+        match versions.as_slice() {
+            [(_, Some(_))] => false,
+            [(ts, None)] if *ts <= horizon => {
+                data.remove(key);
+                false
+            }
+            _ => true,
+        }
+    }
 }
 
 impl<'s> Storage<'s> for MemStorage {
@@ -49,12 +325,12 @@ impl<'s> Storage<'s> for MemStorage {
     }
 
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
-        Ok(if write {
-            let wtr = self.store.write().unwrap();
-            MemTx::Writer(wtr, Default::default())
-        } else {
-            let rdr = self.store.read().unwrap();
-            MemTx::Reader(rdr)
+        Ok(MemTx {
+            store: &self.store,
+            snapshot: self.store.acquire_snapshot(),
+            write,
+            changes: Default::default(),
+            read_for_update: Default::default(),
         })
     }
 
@@ -66,44 +342,71 @@ impl<'s> Storage<'s> for MemStorage {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()> {
-        let mut store = self.store.write().unwrap();
+        let mut batch = BTreeMap::new();
         for pair in data {
             let (k, v) = pair?;
-            store.insert(k, v);
+            batch.insert(k, Some(v));
+            if batch.len() >= PUT_BATCH_SIZE {
+                self.store
+                    .commit(mem::take(&mut batch), None, &Default::default())?;
+            }
         }
-        Ok(())
+        self.store.commit(batch, None, &Default::default())
     }
 }
 
-pub enum MemTx<'s> {
-    Reader(ShardedLockReadGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>),
-    Writer(
-        ShardedLockWriteGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>,
-        BTreeMap<Vec<u8>, Option<Vec<u8>>>,
-    ),
+pub struct MemTx<'s> {
+    store: &'s MemStore,
+    snapshot: u64,
+    write: bool,
+    changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    read_for_update: Mutex<BTreeSet<Vec<u8>>>,
 }
 
-impl<'s> StoreTx<'s> for MemTx<'s> {
-    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
-        Ok(match self {
-            MemTx::Reader(rdr) => rdr.get(key).cloned(),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
-                Some(r) => r.clone(),
-                None => wtr.get(key).cloned(),
+impl Drop for MemTx<'_> {
+    fn drop(&mut self) {
+        self.store.release_snapshot(self.snapshot)
+    }
+}
+
+impl<'s> MemTx<'s> {
+    fn scan<'a>(&'a self, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>) -> CacheIter<'a> {
+        CacheIter {
+            change_iter: self
+                .changes
+                .range::<Vec<u8>, (Bound<Vec<u8>>, Bound<Vec<u8>>)>((lower.clone(), upper.clone()))
+                .fuse(),
+            db_iter: SnapshotIter {
+                store: self.store,
+                snapshot: self.snapshot,
+                lower,
+                upper,
+                buffer: vec![].into_iter(),
+                exhausted: false,
             },
-        })
+            change_cache: None,
+            db_cache: None,
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for MemTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        if let Some(r) = self.changes.get(key) {
+            return Ok(r.clone());
+        }
+        if for_update && self.write {
+            self.read_for_update.lock().unwrap().insert(key.to_vec());
+        }
+        Ok(self.store.get(key, self.snapshot))
     }
 
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
-        match self {
-            MemTx::Reader(_) => {
-                bail!("write in read transaction")
-            }
-            MemTx::Writer(_, cache) => {
-                cache.insert(key.to_vec(), Some(val.to_vec()));
-                Ok(())
-            }
+        if !self.write {
+            bail!("write in read transaction")
         }
+        self.changes.insert(key.to_vec(), Some(val.to_vec()));
+        Ok(())
     }
 
     fn supports_par_put(&self) -> bool {
@@ -115,65 +418,43 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     }
 
     fn del(&mut self, key: &[u8]) -> Result<()> {
-        match self {
-            MemTx::Reader(_) => {
-                bail!("write in read transaction")
-            }
-            MemTx::Writer(_, cache) => {
-                cache.insert(key.to_vec(), None);
-                Ok(())
-            }
+        if !self.write {
+            bail!("write in read transaction")
         }
+        self.changes.insert(key.to_vec(), None);
+        Ok(())
     }
 
     fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
-        match self {
-            MemTx::Reader(_) => {
-                bail!("write in read transaction")
-            }
-            MemTx::Writer(ref mut wtr, _) => {
-                let keys = wtr
-                    .range(lower.to_vec()..upper.to_vec())
-                    .map(|kv| kv.0.clone())
-                    .collect_vec();
-                for k in keys.iter() {
-                    wtr.remove(k);
-                }
-            }
+        if !self.write {
+            bail!("write in read transaction")
+        }
+        let persisted = SnapshotIter {
+            store: self.store,
+            snapshot: self.snapshot,
+            lower: Bound::Included(lower.to_vec()),
+            upper: Bound::Excluded(upper.to_vec()),
+            buffer: vec![].into_iter(),
+            exhausted: false,
+        };
+        for (k, _) in persisted {
+            self.changes.entry(k).or_insert(None);
         }
 
         Ok(())
     }
 
-    fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
-        Ok(match self {
-            MemTx::Reader(rdr) => rdr.contains_key(key),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
-                Some(r) => r.is_some(),
-                None => wtr.contains_key(key),
-            },
-        })
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        Ok(self.get(key, for_update)?.is_some())
     }
 
     fn commit(&mut self) -> Result<()> {
-        match self {
-            MemTx::Reader(_) => Ok(()),
-            MemTx::Writer(wtr, cached) => {
-                let mut cache = BTreeMap::default();
-                mem::swap(&mut cache, cached);
-                for (k, mv) in cache {
-                    match mv {
-                        None => {
-                            wtr.remove(&k);
-                        }
-                        Some(v) => {
-                            wtr.insert(k, v);
-                        }
-                    }
-                }
-                Ok(())
-            }
+        if !self.write {
+            return Ok(());
         }
+        let changes = mem::take(&mut self.changes);
+        let checked = mem::take(&mut *self.read_for_update.lock().unwrap());
+        self.store.commit(changes, Some(self.snapshot), &checked)
     }
 
     fn range_scan_tuple<'a>(
@@ -184,18 +465,13 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     where
         's: 'a,
     {
-        match self {
-            MemTx::Reader(rdr) => Box::new(
-                rdr.range(lower.to_vec()..upper.to_vec())
-                    .map(|(k, v)| Ok(decode_tuple_from_kv(k, v, None))),
-            ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIter {
-                change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
-                db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
-                change_cache: None,
-                db_cache: None,
-            }),
-        }
+        Box::new(
+            self.scan(
+                Bound::Included(lower.to_vec()),
+                Bound::Excluded(upper.to_vec()),
+            )
+            .map(|(k, v)| Ok(decode_tuple_from_kv(&k, &v, None))),
+        )
     }
 
     fn range_skip_scan_tuple<'a>(
@@ -204,28 +480,17 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
         upper: &[u8],
         valid_at: ValidityTs,
     ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
-        match self {
-            MemTx::Reader(stored) => Box::new(
-                SkipIterator {
-                    inner: stored,
-                    upper: upper.to_vec(),
-                    valid_at,
-                    next_bound: lower.to_vec(),
-                    size_hint: None,
-                }
-                .map(Ok),
-            ),
-            MemTx::Writer(stored, delta) => Box::new(
-                SkipDualIterator {
-                    stored,
-                    delta,
-                    upper: upper.to_vec(),
-                    valid_at,
-                    next_bound: lower.to_vec(),
-                }
-                .map(Ok),
-            ),
-        }
+        Box::new(
+            SkipDualIterator {
+                stored: self.store,
+                snapshot: self.snapshot,
+                delta: &self.changes,
+                upper: upper.to_vec(),
+                valid_at,
+                next_bound: lower.to_vec(),
+            }
+            .map(Ok),
+        )
     }
 
     fn range_scan<'a>(
@@ -236,147 +501,80 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     where
         's: 'a,
     {
-        match self {
-            MemTx::Reader(rdr) => Box::new(
-                rdr.range(lower.to_vec()..upper.to_vec())
-                    .map(|(k, v)| Ok((k.clone(), v.clone()))),
-            ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
-                change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
-                db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
-                change_cache: None,
-                db_cache: None,
-            }),
-        }
+        Box::new(
+            self.scan(
+                Bound::Included(lower.to_vec()),
+                Bound::Excluded(upper.to_vec()),
+            )
+            .map(Ok),
+        )
     }
 
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
         's: 'a,
     {
-        Ok(match self {
-            MemTx::Reader(rdr) => rdr.range(lower.to_vec()..upper.to_vec()).count(),
-            MemTx::Writer(wtr, cache) => (CacheIterRaw {
-                change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
-                db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
-                change_cache: None,
-                db_cache: None,
-            })
-            .count(),
-        })
+        Ok(self
+            .scan(
+                Bound::Included(lower.to_vec()),
+                Bound::Excluded(upper.to_vec()),
+            )
+            .count())
     }
 
     fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
     where
         's: 'a,
     {
-        match self {
-            MemTx::Reader(rdr) => Box::new(rdr.iter().map(|(k, v)| Ok((k.clone(), v.clone())))),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
-                change_iter: cache.iter().fuse(),
-                db_iter: wtr.iter().fuse(),
-                change_cache: None,
-                db_cache: None,
-            }),
-        }
+        Box::new(self.scan(Bound::Unbounded, Bound::Unbounded).map(Ok))
     }
 }
 
-struct CacheIterRaw<'a, C, T>
-where
-    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)> + 'a,
-    T: Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
-{
-    change_iter: C,
-    db_iter: T,
-    change_cache: Option<(&'a Vec<u8>, &'a Option<Vec<u8>>)>,
-    db_cache: Option<(&'a Vec<u8>, &'a Vec<u8>)>,
+/// Iterates over a snapshot, taking the read lock only while fetching each batch.
+struct SnapshotIter<'a> {
+    store: &'a MemStore,
+    snapshot: u64,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    buffer: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+    exhausted: bool,
 }
 
-impl<'a, C, T> CacheIterRaw<'a, C, T>
-where
-    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)> + 'a,
-    T: Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
-{
-    #[inline]
-    fn fill_cache(&mut self) -> Result<()> {
-        if self.change_cache.is_none() {
-            if let Some(kmv) = self.change_iter.next() {
-                self.change_cache = Some(kmv)
-            }
-        }
+impl Iterator for SnapshotIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
 
-        if self.db_cache.is_none() {
-            if let Some(kv) = self.db_iter.next() {
-                self.db_cache = Some(kv);
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(kv) = self.buffer.next() {
+            return Some(kv);
         }
-
-        Ok(())
-    }
-
-    #[inline]
-    fn next_inner(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
-        loop {
-            self.fill_cache()?;
-            match (&self.change_cache, &self.db_cache) {
-                (None, None) => return Ok(None),
-                (Some(_), None) => {
-                    let (k, cv) = self.change_cache.take().unwrap();
-                    match cv {
-                        None => continue,
-                        Some(v) => return Ok(Some((k.clone(), v.clone()))),
-                    }
-                }
-                (None, Some(_)) => {
-                    let (k, v) = self.db_cache.take().unwrap();
-                    return Ok(Some((k.clone(), v.clone())));
-                }
-                (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
-                    Ordering::Less => {
-                        let (k, sv) = self.change_cache.take().unwrap();
-                        match sv {
-                            None => continue,
-                            Some(v) => return Ok(Some((k.clone(), v.clone()))),
-                        }
-                    }
-                    Ordering::Greater => {
-                        let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some((k.clone(), v.clone())));
-                    }
-                    Ordering::Equal => {
-                        self.db_cache.take();
-                        continue;
-                    }
-                },
-            }
+        if self.exhausted {
+            return None;
         }
-    }
-}
-
-impl<'a, C, T> Iterator for CacheIterRaw<'a, C, T>
-where
-    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)> + 'a,
-    T: Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
-{
-    type Item = Result<(Vec<u8>, Vec<u8>)>;
-
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        swap_option_result(self.next_inner())
+        let batch = self.store.scan(
+            self.lower.as_ref(),
+            self.upper.as_ref(),
+            self.snapshot,
+            SCAN_BATCH_SIZE,
+        );
+        self.exhausted = batch.len() < SCAN_BATCH_SIZE;
+        if let Some((k, _)) = batch.last() {
+            self.lower = Bound::Excluded(k.clone());
+        }
+        self.buffer = batch.into_iter();
+        self.buffer.next()
     }
 }
 
 struct CacheIter<'a> {
     change_iter: Fuse<Range<'a, Vec<u8>, Option<Vec<u8>>>>,
-    db_iter: Fuse<Range<'a, Vec<u8>, Vec<u8>>>,
+    db_iter: SnapshotIter<'a>,
     change_cache: Option<(&'a Vec<u8>, &'a Option<Vec<u8>>)>,
-    db_cache: Option<(&'a Vec<u8>, &'a Vec<u8>)>,
+    db_cache: Option<(Vec<u8>, Vec<u8>)>,
 }
 
 impl CacheIter<'_> {
     #[inline]
-    fn fill_cache(&mut self) -> Result<()> {
+    fn fill_cache(&mut self) {
         if self.change_cache.is_none() {
             if let Some(kmv) = self.change_iter.next() {
                 self.change_cache = Some(kmv)
@@ -388,38 +586,38 @@ impl CacheIter<'_> {
                 self.db_cache = Some(kv);
             }
         }
-
-        Ok(())
     }
+}
+
+impl Iterator for CacheIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
 
     #[inline]
-    fn next_inner(&mut self) -> Result<Option<Tuple>> {
+    fn next(&mut self) -> Option<Self::Item> {
         loop {
-            self.fill_cache()?;
+            self.fill_cache();
             match (&self.change_cache, &self.db_cache) {
-                (None, None) => return Ok(None),
+                (None, None) => return None,
                 (Some(_), None) => {
                     let (k, cv) = self.change_cache.take().unwrap();
                     match cv {
                         None => continue,
-                        Some(v) => return Ok(Some(decode_tuple_from_kv(k, v, None))),
+                        Some(v) => return Some((k.clone(), v.clone())),
                     }
                 }
                 (None, Some(_)) => {
-                    let (k, v) = self.db_cache.take().unwrap();
-                    return Ok(Some(decode_tuple_from_kv(k, v, None)));
+                    return self.db_cache.take();
                 }
-                (Some((ck, _)), Some((dk, _))) => match ck.cmp(dk) {
+                (Some((ck, _)), Some((dk, _))) => match (*ck).cmp(dk) {
                     Ordering::Less => {
                         let (k, sv) = self.change_cache.take().unwrap();
                         match sv {
                             None => continue,
-                            Some(v) => return Ok(Some(decode_tuple_from_kv(k, v, None))),
+                            Some(v) => return Some((k.clone(), v.clone())),
                         }
                     }
                     Ordering::Greater => {
-                        let (k, v) = self.db_cache.take().unwrap();
-                        return Ok(Some(decode_tuple_from_kv(k, v, None)));
+                        return self.db_cache.take();
                     }
                     Ordering::Equal => {
                         self.db_cache.take();
@@ -431,15 +629,6 @@ impl CacheIter<'_> {
     }
 }
 
-impl Iterator for CacheIter<'_> {
-    type Item = Result<Tuple>;
-
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        swap_option_result(self.next_inner())
-    }
-}
-
 /// Keep an eye on https://github.com/rust-lang/rust/issues/49638
 pub(crate) struct SkipIterator<'a> {
     pub(crate) inner: &'a BTreeMap<Vec<u8>, Vec<u8>>,
@@ -478,7 +667,8 @@ impl<'a> Iterator for SkipIterator<'a> {
 }
 
 struct SkipDualIterator<'a> {
-    stored: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+    stored: &'a MemStore,
+    snapshot: u64,
     delta: &'a BTreeMap<Vec<u8>, Option<Vec<u8>>>,
     upper: Vec<u8>,
     valid_at: ValidityTs,
@@ -492,11 +682,13 @@ impl<'a> Iterator for SkipDualIterator<'a> {
         loop {
             let stored_nxt = self
                 .stored
-                .range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((
+                .scan(
                     Bound::Included(&self.next_bound),
                     Bound::Excluded(&self.upper),
-                ))
-                .next();
+                    self.snapshot,
+                    1,
+                )
+                .pop();
             let delta_nxt = self
                 .delta
                 .range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((
@@ -504,7 +696,7 @@ impl<'a> Iterator for SkipDualIterator<'a> {
                     Bound::Excluded(&self.upper),
                 ))
                 .next();
-            let (candidate_key, candidate_val) = match (stored_nxt, delta_nxt) {
+            let (candidate_key, candidate_val) = match (&stored_nxt, delta_nxt) {
                 (None, None) => return None,
                 (None, Some((delta_key, maybe_delta_val))) => match maybe_delta_val {
                     None => {
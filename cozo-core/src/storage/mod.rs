@@ -6,14 +6,22 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::path::Path;
+
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::decode_tuple_from_kv;
 
+pub(crate) mod encrypted;
 pub(crate) mod mem;
+pub(crate) mod options;
+pub(crate) mod read_only;
+#[cfg(feature = "storage-redb")]
+pub(crate) mod redb;
 #[cfg(feature = "storage-rocksdb")]
 pub(crate) mod rocks;
 #[cfg(feature = "storage-sled")]
@@ -23,10 +31,18 @@ pub(crate) mod sqlite;
 pub(crate) mod temp;
 #[cfg(feature = "storage-tikv")]
 pub(crate) mod tikv;
+pub(crate) mod wal;
+pub(crate) mod write_log;
 #[cfg(feature = "storage-new-rocksdb")]
 pub mod newrocks;
 // pub(crate) mod re;
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The storage engine does not support checkpoints")]
+#[diagnostic(code(storage::checkpoint_unsupported))]
+#[diagnostic(help("Check `supports_checkpoint` before asking for a checkpoint"))]
+pub(crate) struct CheckpointUnsupported;
+
 /// Swappable storage trait for Cozo's storage engine
 pub trait Storage<'s>: Send + Sync + Clone {
     /// The associated transaction type used by this engine
@@ -49,6 +65,30 @@ pub trait Storage<'s>: Send + Sync + Clone {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()>;
+
+    /// Whether the storage was opened read-only. Write transactions are then
+    /// not asked for, and writes made in transactions fail.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Make visible the changes made by the process writing to the database,
+    /// for storages opened as secondary instances. A no-op for the others.
+    fn catch_up_with_primary(&'s self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the storage can create checkpoints with [Storage::checkpoint].
+    fn supports_checkpoint(&self) -> bool {
+        false
+    }
+
+    /// Create a consistent copy of the database in the directory `dir`, which must not exist.
+    /// The copy can be opened as a database of the same engine.
+    /// Files that are never modified after creation may be hard links to the live files.
+    fn checkpoint(&'s self, _dir: &Path) -> Result<()> {
+        bail!(CheckpointUnsupported)
+    }
 }
 
 /// Trait for the associated transaction type of a storage engine.
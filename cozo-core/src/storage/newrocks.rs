@@ -3,14 +3,19 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, IntoDiagnostic, Result, WrapErr};
 
-use rocksdb::{OptimisticTransactionDB, Options, WriteBatchWithTransaction, DB};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{
+    BlockBasedOptions, Cache, DBCompressionType, OptimisticTransactionDB,
+    OptimisticTransactionOptions, Options, WriteBatchWithTransaction, WriteOptions, DB,
+};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::options::{InvalidEngineOptions, RocksDbCompression, RocksDbOptions};
 use crate::storage::{Storage, StoreTx};
 use crate::Db;
 
@@ -22,6 +27,21 @@ const CURRENT_STORAGE_VERSION: u64 = 3;
 /// sustain huge concurrency.
 /// Supports concurrent readers and writers.
 pub fn new_cozo_newrocksdb(path: impl AsRef<Path>) -> Result<Db<NewRocksDbStorage>> {
+    new_cozo_newrocksdb_with_options(path, Default::default())
+}
+
+/// Creates a RocksDB database object with the given options.
+pub fn new_cozo_newrocksdb_with_options(
+    path: impl AsRef<Path>,
+    rocks_options: RocksDbOptions,
+) -> Result<Db<NewRocksDbStorage>> {
+    rocks_options.validate()?;
+    if rocks_options.read_only || rocks_options.secondary_path.is_some() {
+        bail!(InvalidEngineOptions(
+            "newrocksdb",
+            "read_only and secondary_path are only supported by the rocksdb engine".to_string()
+        ))
+    }
     fs::create_dir_all(&path).map_err(|err| {
         BadDbInit(format!(
             "cannot create directory {}: {}",
@@ -65,26 +85,55 @@ pub fn new_cozo_newrocksdb(path: impl AsRef<Path>) -> Result<Db<NewRocksDbStorag
 
     let mut options = Options::default();
     options.create_if_missing(is_new);
-    // Add any necessary RocksDB options here
+    options.set_compression_type(compression_type(
+        rocks_options.compression.unwrap_or(RocksDbCompression::Lz4),
+    ));
+    options.set_bottommost_compression_type(compression_type(
+        rocks_options
+            .bottommost_compression
+            .unwrap_or(RocksDbCompression::Zstd),
+    ));
+    let mut table_options = BlockBasedOptions::default();
+    if rocks_options.block_cache_size > 0 {
+        table_options.set_block_cache(&Cache::new_lru_cache(rocks_options.block_cache_size));
+    }
+    if rocks_options.bloom_filter_bits_per_key > 0. {
+        table_options.set_bloom_filter(rocks_options.bloom_filter_bits_per_key, false);
+        table_options.set_whole_key_filtering(rocks_options.bloom_filter_whole_key);
+    }
+    options.set_block_based_table_factory(&table_options);
 
     let db = OptimisticTransactionDB::open(&options, store_path_str)
         .into_diagnostic()
         .wrap_err("Failed to open RocksDB")?;
 
-    let ret = Db::new(NewRocksDbStorage::new(db))?;
+    let ret = Db::new(NewRocksDbStorage::new(db, rocks_options.sync_wal))?;
     ret.initialize()?;
     Ok(ret)
 }
 
+fn compression_type(compression: RocksDbCompression) -> DBCompressionType {
+    match compression {
+        RocksDbCompression::None => DBCompressionType::None,
+        RocksDbCompression::Snappy => DBCompressionType::Snappy,
+        RocksDbCompression::Lz4 => DBCompressionType::Lz4,
+        RocksDbCompression::Zstd => DBCompressionType::Zstd,
+    }
+}
+
 /// RocksDB storage engine
 #[derive(Clone)]
 pub struct NewRocksDbStorage {
     db: Arc<OptimisticTransactionDB>,
+    sync_wal: bool,
 }
 
 impl NewRocksDbStorage {
-    pub(crate) fn new(db: OptimisticTransactionDB) -> Self {
-        Self { db: Arc::new(db) }
+    pub(crate) fn new(db: OptimisticTransactionDB, sync_wal: bool) -> Self {
+        Self {
+            db: Arc::new(db),
+            sync_wal,
+        }
     }
 }
 
@@ -96,8 +145,13 @@ impl<'s> Storage<'s> for NewRocksDbStorage {
     }
 
     fn transact(&'s self, _write: bool) -> Result<Self::Tx> {
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(self.sync_wal);
         Ok(NewRocksDbTx {
-            db_tx: Some(self.db.transaction()),
+            db_tx: Some(
+                self.db
+                    .transaction_opt(&write_options, &OptimisticTransactionOptions::default()),
+            ),
         })
     }
 
@@ -120,6 +174,28 @@ impl<'s> Storage<'s> for NewRocksDbStorage {
             .into_diagnostic()
             .wrap_err_with(|| "Batch put failed")
     }
+
+    fn supports_checkpoint(&self) -> bool {
+        true
+    }
+
+    fn checkpoint(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir).into_diagnostic()?;
+        fs::write(
+            dir.join("manifest"),
+            rmp_serde::to_vec_named(&DbManifest {
+                storage_version: CURRENT_STORAGE_VERSION,
+            })
+            .into_diagnostic()?,
+        )
+        .into_diagnostic()?;
+        let data_path = dir.join("data");
+        let data_path = data_path.to_str().ok_or_else(|| miette!("bad path name"))?;
+        Checkpoint::new(&*self.db)
+            .into_diagnostic()?
+            .create_checkpoint(data_path)
+            .into_diagnostic()
+    }
 }
 
 pub struct NewRocksDbTx<'a> {
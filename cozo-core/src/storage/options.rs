@@ -0,0 +1,457 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Options of the storage engines, given as JSON to [crate::DbInstance::new].
+
+use std::path::PathBuf;
+
+use miette::{bail, Diagnostic, Result};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid options for the {0} engine: {1}")]
+#[diagnostic(code(db::invalid_engine_options))]
+pub(crate) struct InvalidEngineOptions(pub(crate) &'static str, pub(crate) String);
+
+/// Parse the JSON `options` of `engine`, rejecting unknown or mistyped fields.
+pub(crate) fn parse_engine_options<T: DeserializeOwned>(
+    engine: &'static str,
+    options: &str,
+) -> Result<T> {
+    let options = if options.trim().is_empty() {
+        "{}"
+    } else {
+        options
+    };
+    serde_json::from_str(options)
+        .map_err(|err| InvalidEngineOptions(engine, err.to_string()).into())
+}
+
+/// Take the `sort_memory_budget` option, which applies to all engines, out of the JSON `options`.
+pub(crate) fn take_sort_memory_budget(options: &str) -> Result<(String, Option<usize>)> {
+    let mut fields: serde_json::Map<String, serde_json::Value> = match serde_json::from_str(options)
+    {
+        Ok(fields) => fields,
+        // the engine reports it
+        Err(_) => return Ok((options.to_string(), None)),
+    };
+    match fields.remove("sort_memory_budget") {
+        None => Ok((options.to_string(), None)),
+        Some(value) => match value.as_u64() {
+            Some(rows) if rows > 0 => Ok((
+                serde_json::Value::Object(fields).to_string(),
+                Some(rows as usize),
+            )),
+            _ => bail!("the `sort_memory_budget` option must be a positive integer"),
+        },
+    }
+}
+
+/// Compression algorithms for RocksDB data files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(missing_docs)]
+pub enum RocksDbCompression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+/// Options for the RocksDB engines, see [crate::new_cozo_rocksdb_with_options].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RocksDbOptions {
+    /// Size in bytes of the LRU cache for data blocks, `0` for the RocksDB default.
+    pub block_cache_size: usize,
+    /// Compression of all levels but the last, `None` for the default (LZ4).
+    pub compression: Option<RocksDbCompression>,
+    /// Compression of the last level, `None` for the default (Zstd).
+    pub bottommost_compression: Option<RocksDbCompression>,
+    /// Bits per key of the bloom filters, `0` to disable them.
+    pub bloom_filter_bits_per_key: f64,
+    /// Whether the bloom filters also hold whole keys, not only their prefixes.
+    pub bloom_filter_whole_key: bool,
+    /// Whether to `fsync` the write-ahead log on every commit.
+    /// Without it, committed data survives crashes of the process but not of the OS.
+    pub sync_wal: bool,
+    /// Whether to open an existing database read-only, which fails all writes.
+    /// Changes made by another process are not seen until the database is reopened.
+    pub read_only: bool,
+    /// Open an existing database as a read-only secondary instance, keeping its own
+    /// logs in this directory. Such an instance can be opened while another process
+    /// writes to the database, and sees the changes of that process
+    /// after [crate::Db::catch_up_with_primary].
+    pub secondary_path: Option<PathBuf>,
+}
+
+impl Default for RocksDbOptions {
+    fn default() -> Self {
+        Self {
+            block_cache_size: 0,
+            compression: None,
+            bottommost_compression: None,
+            bloom_filter_bits_per_key: 9.9,
+            bloom_filter_whole_key: true,
+            sync_wal: false,
+            read_only: false,
+            secondary_path: None,
+        }
+    }
+}
+
+impl RocksDbOptions {
+    /// Check the values of the options, which is also done when the database is opened.
+    pub fn validate(&self) -> Result<()> {
+        if !(0. ..=100.).contains(&self.bloom_filter_bits_per_key) {
+            bail!(InvalidEngineOptions(
+                "rocksdb",
+                format!(
+                    "bloom_filter_bits_per_key must be between 0 and 100, got {}",
+                    self.bloom_filter_bits_per_key
+                )
+            ))
+        }
+        Ok(())
+    }
+}
+
+/// SQLite journal modes, see <https://www.sqlite.org/pragma.html#pragma_journal_mode>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(missing_docs)]
+pub enum SqliteJournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+/// SQLite synchronous settings, see <https://www.sqlite.org/pragma.html#pragma_synchronous>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(missing_docs)]
+pub enum SqliteSynchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+/// Options for the SQLite engine, see [crate::new_cozo_sqlite_with_options].
+/// Options left as `None` keep the SQLite defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteOptions {
+    /// The journal mode, kept in the database file once set.
+    pub journal_mode: Option<SqliteJournalMode>,
+    /// How often SQLite waits for data to reach the disk.
+    pub synchronous: Option<SqliteSynchronous>,
+    /// The page cache size of each connection:
+    /// a number of pages if positive, a number of KiB if negative.
+    pub cache_size: Option<i64>,
+    /// Maximum number of bytes of the database file to memory-map, `0` to disable.
+    pub mmap_size: Option<u64>,
+    /// Whether to open an existing database read-only, which fails all writes.
+    /// Changes committed by other connections are seen by later transactions.
+    pub read_only: bool,
+}
+
+impl SqliteOptions {
+    /// Check the values of the options, which is also done when the database is opened.
+    pub fn validate(&self) -> Result<()> {
+        if self.read_only && self.journal_mode.is_some() {
+            bail!(InvalidEngineOptions(
+                "sqlite",
+                "journal_mode cannot be set when read_only".to_string()
+            ))
+        }
+        if self.cache_size == Some(0) {
+            bail!(InvalidEngineOptions(
+                "sqlite",
+                "cache_size must not be 0".to_string()
+            ))
+        }
+        if let Some(size) = self.mmap_size {
+            if size > i64::MAX as u64 {
+                bail!(InvalidEngineOptions(
+                    "sqlite",
+                    format!("mmap_size {size} is too large")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// The `PRAGMA` statements applying the options that hold for each connection.
+    pub(crate) fn connection_pragmas(&self) -> String {
+        let mut pragmas = String::new();
+        if let Some(synchronous) = self.synchronous {
+            let value = match synchronous {
+                SqliteSynchronous::Off => "off",
+                SqliteSynchronous::Normal => "normal",
+                SqliteSynchronous::Full => "full",
+                SqliteSynchronous::Extra => "extra",
+            };
+            pragmas.push_str(&format!("pragma synchronous = {value};"));
+        }
+        if let Some(size) = self.cache_size {
+            pragmas.push_str(&format!("pragma cache_size = {size};"));
+        }
+        if let Some(size) = self.mmap_size {
+            pragmas.push_str(&format!("pragma mmap_size = {size};"));
+        }
+        pragmas
+    }
+
+    pub(crate) fn journal_mode_pragma(&self) -> Option<String> {
+        let value = match self.journal_mode? {
+            SqliteJournalMode::Delete => "delete",
+            SqliteJournalMode::Truncate => "truncate",
+            SqliteJournalMode::Persist => "persist",
+            SqliteJournalMode::Memory => "memory",
+            SqliteJournalMode::Wal => "wal",
+            SqliteJournalMode::Off => "off",
+        };
+        Some(format!("pragma journal_mode = {value};"))
+    }
+}
+
+/// Options for the sled engine, see [crate::new_cozo_sled_with_options].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SledOptions {
+    /// Size in bytes of the page cache, `None` for the sled default (1 GiB).
+    pub cache_capacity: Option<u64>,
+}
+
+impl SledOptions {
+    /// Check the values of the options, which is also done when the database is opened.
+    pub fn validate(&self) -> Result<()> {
+        if self.cache_capacity == Some(0) {
+            bail!(InvalidEngineOptions(
+                "sled",
+                "cache_capacity must not be 0".to_string()
+            ))
+        }
+        Ok(())
+    }
+}
+
+/// Options for the redb engine, see [crate::new_cozo_redb_with_options].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedbOptions {
+    /// Size in bytes of the page cache, `None` for the redb default (1 GiB).
+    pub cache_size: Option<usize>,
+}
+
+impl RedbOptions {
+    /// Check the values of the options, which is also done when the database is opened.
+    pub fn validate(&self) -> Result<()> {
+        if self.cache_size == Some(0) {
+            bail!(InvalidEngineOptions(
+                "redb",
+                "cache_size must not be 0".to_string()
+            ))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_options() {
+        let opts: RocksDbOptions = parse_engine_options(
+            "rocksdb",
+            r#"{"block_cache_size": 1048576, "compression": "zstd", "sync_wal": true}"#,
+        )
+        .unwrap();
+        assert_eq!(opts.block_cache_size, 1 << 20);
+        assert_eq!(opts.compression, Some(RocksDbCompression::Zstd));
+        assert_eq!(opts.bloom_filter_bits_per_key, 9.9);
+        assert!(opts.sync_wal);
+        opts.validate().unwrap();
+
+        let err = parse_engine_options::<RocksDbOptions>("rocksdb", r#"{"compresion": "zstd"}"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown field `compresion`"));
+        assert!(
+            parse_engine_options::<RocksDbOptions>("rocksdb", r#"{"compression": "gzip"}"#)
+                .is_err()
+        );
+        let opts: RocksDbOptions =
+            parse_engine_options("rocksdb", r#"{"bloom_filter_bits_per_key": -1}"#).unwrap();
+        assert!(opts.validate().is_err());
+
+        let opts: SqliteOptions = parse_engine_options(
+            "sqlite",
+            r#"{"journal_mode": "wal", "synchronous": "normal", "cache_size": -2000}"#,
+        )
+        .unwrap();
+        opts.validate().unwrap();
+        assert_eq!(
+            opts.journal_mode_pragma().unwrap(),
+            "pragma journal_mode = wal;"
+        );
+        assert_eq!(
+            opts.connection_pragmas(),
+            "pragma synchronous = normal;pragma cache_size = -2000;"
+        );
+        assert_eq!(
+            parse_engine_options::<SqliteOptions>("sqlite", "").unwrap(),
+            SqliteOptions::default()
+        );
+
+        let opts: SledOptions = parse_engine_options("sled", r#"{"cache_capacity": 0}"#).unwrap();
+        assert!(opts.validate().is_err());
+
+        match crate::DbInstance::new("mem", "", r#"{"wall": true}"#) {
+            Err(err) => assert!(err
+                .to_string()
+                .contains("Invalid options for the mem engine")),
+            Ok(_) => panic!("unknown option accepted"),
+        }
+
+        let (rest, budget) =
+            take_sort_memory_budget(r#"{"wal": false, "sort_memory_budget": 16}"#).unwrap();
+        assert_eq!(budget, Some(16));
+        assert_eq!(rest, r#"{"wal":false}"#);
+        assert!(take_sort_memory_budget(r#"{"sort_memory_budget": 0}"#).is_err());
+        crate::DbInstance::new("mem", "", r#"{"sort_memory_budget": 16}"#).unwrap();
+    }
+
+    #[cfg(feature = "storage-sqlite")]
+    #[test]
+    fn sqlite_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cozo.db");
+        let db = crate::DbInstance::new(
+            "sqlite",
+            &path,
+            r#"{"journal_mode": "wal", "synchronous": "normal", "cache_size": -4096}"#,
+        )
+        .unwrap();
+        db.run_default("?[k] <- [[1], [2]] :create a {k}").unwrap();
+        assert_eq!(db.run_default("?[k] := *a[k]").unwrap().rows.len(), 2);
+        assert!(dir.path().join("cozo.db-wal").exists());
+    }
+
+    #[cfg(feature = "storage-sqlite")]
+    #[test]
+    fn sqlite_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cozo.db");
+        assert!(crate::DbInstance::new("sqlite", &path, r#"{"read_only": true}"#).is_err());
+
+        let db = crate::DbInstance::new("sqlite", &path, "").unwrap();
+        db.run_default("?[k] <- [[1], [2]] :create a {k}").unwrap();
+        let reader = crate::DbInstance::new("sqlite", &path, r#"{"read_only": true}"#).unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!(reader.run_default("?[k] := *a[k]").unwrap().rows.len(), 2);
+        assert_eq!(reader.run_default("::relations").unwrap().rows.len(), 1);
+        for script in [
+            "?[k] <- [[3]] :put a {k}",
+            "?[k] <- [[1]] :rm a {k}",
+            "?[k] <- [[1]] :create b {k}",
+            "::remove a",
+            "::compact",
+        ] {
+            let err = reader.run_default(script).unwrap_err();
+            assert!(
+                format!("{err:?}").contains("the database is opened read-only"),
+                "{script}: {err}"
+            );
+        }
+
+        db.run_default("?[k] <- [[3]] :put a {k}").unwrap();
+        assert_eq!(reader.run_default("?[k] := *a[k]").unwrap().rows.len(), 3);
+        assert!(parse_engine_options::<SqliteOptions>(
+            "sqlite",
+            r#"{"read_only": true, "journal_mode": "wal"}"#
+        )
+        .unwrap()
+        .validate()
+        .is_err());
+    }
+
+    #[cfg(feature = "storage-rocksdb")]
+    #[test]
+    fn rocksdb_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cozo");
+        assert!(crate::DbInstance::new("rocksdb", &path, r#"{"read_only": true}"#).is_err());
+
+        let db = crate::DbInstance::new("rocksdb", &path, "").unwrap();
+        db.run_default("?[k] <- [[1], [2]] :create a {k}").unwrap();
+        let reader = crate::DbInstance::new("rocksdb", &path, r#"{"read_only": true}"#).unwrap();
+        let secondary_options = serde_json::json!({
+            "secondary_path": dir.path().join("secondary").to_string_lossy()
+        })
+        .to_string();
+        let secondary = crate::DbInstance::new("rocksdb", &path, &secondary_options).unwrap();
+        for db in [&reader, &secondary] {
+            assert!(db.is_read_only());
+            assert_eq!(db.run_default("?[k] := *a[k]").unwrap().rows.len(), 2);
+            let err = db.run_default("?[k] <- [[3]] :put a {k}").unwrap_err();
+            assert!(
+                format!("{err:?}").contains("the database is opened read-only"),
+                "{err}"
+            );
+            // the queries of a transaction all read from the same snapshot
+            let tx = db.multi_transaction(false);
+            for _ in 0..2 {
+                let res = tx.run_script("?[count(k)] := *a[k]", Default::default());
+                assert_eq!(res.unwrap().rows, vec![vec![crate::DataValue::from(2)]]);
+            }
+            tx.commit().unwrap();
+        }
+
+        db.run_default("?[k] <- [[3]] :put a {k}").unwrap();
+        secondary.catch_up_with_primary().unwrap();
+        assert_eq!(
+            secondary.run_default("?[k] := *a[k]").unwrap().rows.len(),
+            3
+        );
+    }
+
+    #[cfg(feature = "storage-rocksdb")]
+    #[test]
+    fn rocksdb_checkpoint_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = dir.path().join("backups");
+        // the SST files of different databases may have the same names and sizes
+        for name in ["first", "second"] {
+            let db = crate::DbInstance::new("rocksdb", dir.path().join(name), "").unwrap();
+            db.run_default(&format!("?[k] <- [['{name}']] :create a {{k}}"))
+                .unwrap();
+            let manifest = db.backup_to_dir(&backups).unwrap();
+            assert_eq!(manifest.kind, crate::BackupKind::Checkpoint);
+            for file in &manifest.files {
+                if file.path.ends_with(".sst") {
+                    assert_eq!(file.path, format!("shared/{}.sst", file.sha256));
+                }
+            }
+        }
+        crate::verify_backup(&backups, None).unwrap();
+        let path = dir.path().join("restored");
+        crate::restore_checkpoint_backup(&backups, Some(1), &path).unwrap();
+        let restored = crate::DbInstance::new("rocksdb", &path, "").unwrap();
+        assert_eq!(
+            restored.run_default("?[k] := *a[k]").unwrap().into_json()["rows"],
+            serde_json::json!([["first"]])
+        );
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::storage::StoreTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot {0}: the database is opened read-only")]
+#[diagnostic(code(db::read_only))]
+#[diagnostic(help(
+    "Writes must go through an instance of the database opened without `read_only` or `secondary_path`"
+))]
+pub(crate) struct ReadOnlyDb(pub(crate) &'static str);
+
+/// Transaction given out for writing by storages opened read-only:
+/// reads are passed through, and writes fail with [ReadOnlyDb].
+pub(crate) struct ReadOnlyTx<T>(pub(crate) T);
+
+impl<'s, T: StoreTx<'s>> StoreTx<'s> for ReadOnlyTx<T> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.0.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.0.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, _key: &[u8], _val: &[u8]) -> Result<()> {
+        bail!(ReadOnlyDb("write"))
+    }
+
+    fn supports_par_put(&self) -> bool {
+        false
+    }
+
+    fn par_put(&self, _key: &[u8], _val: &[u8]) -> Result<()> {
+        bail!(ReadOnlyDb("write"))
+    }
+
+    fn del(&mut self, _key: &[u8]) -> Result<()> {
+        bail!(ReadOnlyDb("delete"))
+    }
+
+    fn par_del(&self, _key: &[u8]) -> Result<()> {
+        bail!(ReadOnlyDb("delete"))
+    }
+
+    fn del_range_from_persisted(&mut self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+        bail!(ReadOnlyDb("delete"))
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.0.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.0.commit()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.0.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.0.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.0.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.0.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.0.total_scan()
+    }
+}
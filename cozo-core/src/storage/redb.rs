@@ -0,0 +1,400 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::Arc;
+
+use miette::{bail, IntoDiagnostic, Result};
+use redb::{
+    Database, Range, ReadOnlyTable, ReadableTable, Table, TableDefinition, WriteTransaction,
+};
+
+use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::options::RedbOptions;
+use crate::storage::{Storage, StoreTx};
+use crate::utils::swap_option_result;
+
+const TABLE: TableDefinition<'_, &[u8], &[u8]> = TableDefinition::new("cozo");
+
+/// Creates a redb database object, stored in a single file.
+/// Supports concurrent readers but only a single writer.
+pub fn new_cozo_redb(path: impl AsRef<Path>) -> Result<crate::Db<RedbStorage>> {
+    new_cozo_redb_with_options(path, Default::default())
+}
+
+/// Creates a redb database object with the given options.
+pub fn new_cozo_redb_with_options(
+    path: impl AsRef<Path>,
+    options: RedbOptions,
+) -> Result<crate::Db<RedbStorage>> {
+    if path.as_ref().to_str() == Some("") {
+        bail!("empty path for redb storage")
+    }
+    options.validate()?;
+    let mut builder = Database::builder();
+    if let Some(size) = options.cache_size {
+        builder.set_cache_size(size);
+    }
+    let db = builder.create(path).into_diagnostic()?;
+    {
+        // read transactions cannot open a table that does not exist yet
+        let tx = db.begin_write().into_diagnostic()?;
+        tx.open_table(TABLE).into_diagnostic()?;
+        tx.commit().into_diagnostic()?;
+    }
+    let ret = crate::Db::new(RedbStorage { db: Arc::new(db) })?;
+
+    ret.initialize()?;
+    Ok(ret)
+}
+
+/// The redb storage engine
+#[derive(Clone)]
+pub struct RedbStorage {
+    db: Arc<Database>,
+}
+
+impl Storage<'_> for RedbStorage {
+    type Tx = RedbTx;
+
+    fn storage_kind(&self) -> &'static str {
+        "redb"
+    }
+
+    fn transact(&self, write: bool) -> Result<Self::Tx> {
+        Ok(if write {
+            let write_tx = Box::new(self.db.begin_write().into_diagnostic()?);
+            let table = write_tx.open_table(TABLE).into_diagnostic()?;
+            // SAFETY: only the lifetime of the borrow of `write_tx` is changed.
+            // The transaction is boxed, so its address is stable when the `RedbTx`
+            // holding both is moved, and the box is never replaced while the table lives.
+            // The table is never handed out with the `'static` lifetime: it is only
+            // reachable through `&self`/`&mut self` of the `RedbTx`, and it is dropped
+            // before the transaction, either in `commit` or by the field order of `RedbTx`.
+            let table: Table<'static, &'static [u8], &'static [u8]> =
+                unsafe { std::mem::transmute(table) };
+            RedbTx {
+                table: RedbTable::Write(Some(table)),
+                write_tx: Some(write_tx),
+            }
+        } else {
+            let read_tx = self.db.begin_read().into_diagnostic()?;
+            RedbTx {
+                table: RedbTable::Read(read_tx.open_table(TABLE).into_diagnostic()?),
+                write_tx: None,
+            }
+        })
+    }
+
+    /// A no-op: redb reuses the pages freed by deletions in place
+    /// and has no range compaction.
+    fn range_compact(&self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let tx = self.db.begin_write().into_diagnostic()?;
+        {
+            let mut table = tx.open_table(TABLE).into_diagnostic()?;
+            for result in data {
+                let (key, val) = result?;
+                table
+                    .insert(&key as &[u8], &val as &[u8])
+                    .into_diagnostic()?;
+            }
+        }
+        tx.commit().into_diagnostic()
+    }
+}
+
+enum RedbTable {
+    Read(ReadOnlyTable<&'static [u8], &'static [u8]>),
+    Write(Option<Table<'static, &'static [u8], &'static [u8]>>),
+}
+
+pub struct RedbTx {
+    // declared first so that it is dropped before the transaction it borrows from
+    table: RedbTable,
+    write_tx: Option<Box<WriteTransaction>>,
+}
+
+impl RedbTx {
+    fn range<'a, 'b>(
+        &'a self,
+        bounds: impl RangeBounds<&'b [u8]> + 'b,
+    ) -> Result<Range<'a, &'static [u8], &'static [u8]>> {
+        match &self.table {
+            RedbTable::Read(table) => table.range(bounds),
+            RedbTable::Write(Some(table)) => table.range(bounds),
+            RedbTable::Write(None) => bail!("transaction already committed"),
+        }
+        .into_diagnostic()
+    }
+
+    fn table_mut(&mut self) -> Result<&mut Table<'static, &'static [u8], &'static [u8]>> {
+        match &mut self.table {
+            RedbTable::Write(Some(table)) => Ok(table),
+            RedbTable::Write(None) => bail!("transaction already committed"),
+            RedbTable::Read(_) => bail!("write in a read transaction"),
+        }
+    }
+
+    fn raw_scan<'a, 'b>(
+        &'a self,
+        bounds: impl RangeBounds<&'b [u8]> + 'b,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a> {
+        match self.range(bounds) {
+            Ok(range) => Box::new(range.map(|item| {
+                let (k, v) = item.into_diagnostic()?;
+                Ok((k.value().to_vec(), v.value().to_vec()))
+            })),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for RedbTx {
+    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
+        let found = match &self.table {
+            RedbTable::Read(table) => table.get(key),
+            RedbTable::Write(Some(table)) => table.get(key),
+            RedbTable::Write(None) => bail!("transaction already committed"),
+        }
+        .into_diagnostic()?;
+        Ok(found.map(|v| v.value().to_vec()))
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.table_mut()?.insert(key, val).into_diagnostic()?;
+        Ok(())
+    }
+
+    fn supports_par_put(&self) -> bool {
+        false
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.table_mut()?.remove(key).into_diagnostic()?;
+        Ok(())
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.table_mut()?
+            .retain_in(lower..upper, |_, _| false)
+            .into_diagnostic()
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        Ok(self.get(key, for_update)?.is_some())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if let RedbTable::Write(table) = &mut self.table {
+            table.take();
+            match self.write_tx.take() {
+                Some(write_tx) => write_tx.commit().into_diagnostic()?,
+                None => bail!("multiple commits"),
+            }
+        }
+        Ok(())
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        match self.range(lower..upper) {
+            Ok(range) => Box::new(range.map(|item| {
+                let (k, v) = item.into_diagnostic()?;
+                Ok(decode_tuple_from_kv(k.value(), v.value(), None))
+            })),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        Box::new(SkipIter {
+            tx: self,
+            valid_at,
+            next_bound: lower.to_vec(),
+            upper_bound: upper.to_vec(),
+        })
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.raw_scan(lower..upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        let mut count = 0;
+        for item in self.range(lower..upper)? {
+            item.into_diagnostic()?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.raw_scan(..)
+    }
+}
+
+struct SkipIter<'a> {
+    tx: &'a RedbTx,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+    upper_bound: Vec<u8>,
+}
+
+impl SkipIter<'_> {
+    fn next_inner(&mut self) -> Result<Option<Tuple>> {
+        loop {
+            let found = self
+                .tx
+                .range(&self.next_bound as &[u8]..&self.upper_bound as &[u8])?
+                .next();
+            match found {
+                None => return Ok(None),
+                Some(item) => {
+                    let (k, v) = item.into_diagnostic()?;
+                    let (ret, nxt_bound) = check_key_for_validity(k.value(), self.valid_at, None);
+                    self.next_bound = nxt_bound;
+                    if let Some(mut tup) = ret {
+                        extend_tuple_from_v(&mut tup, v.value());
+                        return Ok(Some(tup));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for SkipIter<'_> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        swap_option_result(self.next_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::value::DataValue;
+    use crate::runtime::db::ScriptMutability;
+    use crate::Db;
+    use tempfile::TempDir;
+
+    fn setup_test_db() -> Result<(TempDir, Db<RedbStorage>)> {
+        let temp_dir = TempDir::new().into_diagnostic()?;
+        let db = new_cozo_redb(temp_dir.path().join("cozo.redb"))?;
+        db.run_script(
+            r#"
+            {:create plain {k: Int => v}}
+            {:create tt_test {k: Int, vld: Validity => v}}
+            "#,
+            Default::default(),
+            ScriptMutability::Mutable,
+        )?;
+        Ok((temp_dir, db))
+    }
+
+    fn run(db: &Db<RedbStorage>, script: &str) -> Result<Vec<Vec<DataValue>>> {
+        Ok(db
+            .run_script(script, Default::default(), ScriptMutability::Mutable)?
+            .rows)
+    }
+
+    #[test]
+    fn test_basic_operations() -> Result<()> {
+        let (_temp_dir, db) = setup_test_db()?;
+        run(
+            &db,
+            "?[k, v] := k in int_range(100), v = k * 2 :put plain {k => v}",
+        )?;
+        assert_eq!(
+            run(&db, "?[v] := *plain{k: 5, v}")?,
+            vec![vec![DataValue::from(10)]]
+        );
+        assert_eq!(run(&db, "?[k] := *plain{k}, k < 20")?.len(), 20);
+        run(&db, "?[k] := k in int_range(50) :rm plain {k}")?;
+        assert_eq!(run(&db, "?[k] := *plain{k}")?.len(), 50);
+        run(&db, "::remove plain")?;
+        assert!(run(&db, "?[k] := *plain{k}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_travel() -> Result<()> {
+        let (_temp_dir, db) = setup_test_db()?;
+        run(
+            &db,
+            r#"
+            ?[k, vld, v] <- [[1, [1, true], 'a'], [1, [5, true], 'b'], [1, [8, false], null],
+                             [2, [3, true], 'c']]
+            :put tt_test {k, vld => v}
+            "#,
+        )?;
+        assert_eq!(run(&db, "?[k, v] := *tt_test{k, v @ 0}")?.len(), 0);
+        assert_eq!(
+            run(&db, "?[k, v] := *tt_test{k, v @ 6}")?,
+            vec![
+                vec![DataValue::from(1), DataValue::from("b")],
+                vec![DataValue::from(2), DataValue::from("c")]
+            ]
+        );
+        assert_eq!(
+            run(&db, "?[k, v] := *tt_test{k, v @ 9}")?,
+            vec![vec![DataValue::from(2), DataValue::from("c")]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen() -> Result<()> {
+        let (temp_dir, db) = setup_test_db()?;
+        run(
+            &db,
+            "?[k, v] := k in int_range(10), v = k :put plain {k => v}",
+        )?;
+        drop(db);
+        let db = new_cozo_redb(temp_dir.path().join("cozo.redb"))?;
+        assert_eq!(run(&db, "?[k] := *plain{k}")?.len(), 10);
+        run(&db, "?[k] <- [[10]] :create other {k}")?;
+        assert_eq!(run(&db, "::relations")?.len(), 3);
+        Ok(())
+    }
+}
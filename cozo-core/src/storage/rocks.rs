@@ -10,14 +10,15 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, IntoDiagnostic, Result, WrapErr};
 
-use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+use cozorocks::{DbBuilder, DbIter, RocksDb, Tx, KEEP_COMPRESSION};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::options::{RocksDbCompression, RocksDbOptions};
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 use crate::Db;
@@ -30,14 +31,35 @@ const CURRENT_STORAGE_VERSION: u64 = 3;
 /// sustain huge concurrency.
 /// Supports concurrent readers and writers.
 pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
+    new_cozo_rocksdb_with_options(path, Default::default())
+}
+
+/// Creates a RocksDB database object with the given options.
+/// An `options` file in the database directory, if any, is loaded first,
+/// and the given options are applied over it.
+pub fn new_cozo_rocksdb_with_options(
+    path: impl AsRef<Path>,
+    options: RocksDbOptions,
+) -> Result<Db<RocksDbStorage>> {
+    options.validate()?;
+    let read_only = options.read_only || options.secondary_path.is_some();
     let builder = DbBuilder::default().path(path.as_ref());
-    fs::create_dir_all(path.as_ref()).map_err(|err| {
-        BadDbInit(format!(
-            "cannot create directory {}: {}",
-            path.as_ref().to_string_lossy(),
-            err
-        ))
-    })?;
+    if read_only {
+        if !path.as_ref().join("manifest").exists() {
+            bail!(BadDbInit(format!(
+                "no database to open read-only at {}",
+                path.as_ref().to_string_lossy()
+            )))
+        }
+    } else {
+        fs::create_dir_all(path.as_ref()).map_err(|err| {
+            BadDbInit(format!(
+                "cannot create directory {}: {}",
+                path.as_ref().to_string_lossy(),
+                err
+            ))
+        })?;
+    }
     let path_buf = PathBuf::from(path.as_ref());
 
     let is_new = {
@@ -96,30 +118,63 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
         ""
     };
 
-    let db_builder = builder
+    let mut db_builder = builder
         .create_if_missing(is_new)
         .use_capped_prefix_extractor(true, KEY_PREFIX_LEN)
-        .use_bloom_filter(true, 9.9, true)
+        .use_bloom_filter(
+            options.bloom_filter_bits_per_key > 0.,
+            options.bloom_filter_bits_per_key,
+            options.bloom_filter_whole_key,
+        )
+        .block_cache_size(options.block_cache_size)
+        .compression(
+            compression_type(options.compression),
+            compression_type(options.bottommost_compression),
+        )
         .path(store_path)
-        .options_path(options_path);
+        .options_path(options_path)
+        .read_only(read_only);
+    if let Some(secondary_path) = &options.secondary_path {
+        fs::create_dir_all(secondary_path).map_err(|err| {
+            BadDbInit(format!(
+                "cannot create directory {}: {}",
+                secondary_path.to_string_lossy(),
+                err
+            ))
+        })?;
+        db_builder = db_builder.secondary_path(secondary_path);
+    }
 
     let db = db_builder.build()?;
 
-    let ret = Db::new(RocksDbStorage::new(db))?;
+    let ret = Db::new(RocksDbStorage {
+        db,
+        sync_wal: options.sync_wal,
+        read_only,
+        secondary: options.secondary_path.is_some(),
+    })?;
     ret.initialize()?;
     Ok(ret)
 }
 
+fn compression_type(compression: Option<RocksDbCompression>) -> u8 {
+    // values of RocksDB's `CompressionType`
+    match compression {
+        None => KEEP_COMPRESSION,
+        Some(RocksDbCompression::None) => 0,
+        Some(RocksDbCompression::Snappy) => 1,
+        Some(RocksDbCompression::Lz4) => 4,
+        Some(RocksDbCompression::Zstd) => 7,
+    }
+}
+
 /// RocksDB storage engine
 #[derive(Clone)]
 pub struct RocksDbStorage {
     db: RocksDb,
-}
-
-impl RocksDbStorage {
-    pub(crate) fn new(db: RocksDb) -> Self {
-        Self { db }
-    }
+    sync_wal: bool,
+    read_only: bool,
+    secondary: bool,
 }
 
 impl Storage<'_> for RocksDbStorage {
@@ -130,7 +185,12 @@ impl Storage<'_> for RocksDbStorage {
     }
 
     fn transact(&self, _write: bool) -> Result<Self::Tx> {
-        let db_tx = self.db.transact().set_snapshot(true).start();
+        let db_tx = self
+            .db
+            .transact()
+            .set_snapshot(true)
+            .sync(self.sync_wal)
+            .start();
         Ok(RocksDbTx { db_tx })
     }
 
@@ -138,6 +198,17 @@ impl Storage<'_> for RocksDbStorage {
         self.db.range_compact(lower, upper).into_diagnostic()
     }
 
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn catch_up_with_primary(&self) -> Result<()> {
+        if self.secondary {
+            self.db.try_catch_up_with_primary()?;
+        }
+        Ok(())
+    }
+
     fn batch_put<'a>(
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
@@ -148,6 +219,25 @@ impl Storage<'_> for RocksDbStorage {
         }
         Ok(())
     }
+
+    fn supports_checkpoint(&self) -> bool {
+        true
+    }
+
+    fn checkpoint(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir).into_diagnostic()?;
+        fs::write(
+            dir.join("manifest"),
+            rmp_serde::to_vec_named(&DbManifest {
+                storage_version: CURRENT_STORAGE_VERSION,
+            })
+            .into_diagnostic()?,
+        )
+        .into_diagnostic()?;
+        let data_path = dir.join("data");
+        let data_path = data_path.to_str().ok_or_else(|| miette!("bad path name"))?;
+        self.db.create_checkpoint(data_path).into_diagnostic()
+    }
 }
 
 pub struct RocksDbTx {
@@ -18,6 +18,7 @@ use sled::{Batch, Config, Db, IVec, Iter, Mode};
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::decode_tuple_from_kv;
+use crate::storage::options::SledOptions;
 use crate::storage::{Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 
@@ -25,7 +26,20 @@ use crate::utils::{swap_option_result, TempCollector};
 /// You should use [`new_cozo_rocksdb`](crate::new_cozo_rocksdb) or
 /// [`new_cozo_sqlite`](crate::new_cozo_sqlite) instead.
 pub fn new_cozo_sled(path: impl AsRef<Path>) -> Result<crate::Db<SledStorage>> {
-    let db = sled::open(path).into_diagnostic()?;
+    new_cozo_sled_with_options(path, Default::default())
+}
+
+/// Creates a Sled database object with the given options.
+pub fn new_cozo_sled_with_options(
+    path: impl AsRef<Path>,
+    options: SledOptions,
+) -> Result<crate::Db<SledStorage>> {
+    options.validate()?;
+    let mut config = Config::new().path(path);
+    if let Some(capacity) = options.cache_capacity {
+        config = config.cache_capacity(capacity);
+    }
+    let db = config.open().into_diagnostic()?;
     let ret = crate::Db::new(SledStorage { db })?;
 
     ret.initialize()?;
@@ -9,7 +9,7 @@
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use ::sqlite::Connection;
+use ::sqlite::{Connection, OpenFlags};
 use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
 use either::{Either, Left, Right};
 use miette::{bail, miette, IntoDiagnostic, Result};
@@ -17,7 +17,9 @@ use sqlite::{ConnectionThreadSafe, State, Statement};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
+use crate::runtime::db::BadDbInit;
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
+use crate::storage::options::SqliteOptions;
 use crate::storage::{Storage, StoreTx};
 use crate::utils::swap_option_result;
 
@@ -27,6 +29,27 @@ pub struct SqliteStorage {
     lock: Arc<ShardedLock<()>>,
     name: PathBuf,
     pool: Arc<Mutex<Vec<ConnectionThreadSafe>>>,
+    pragmas: Arc<str>,
+    read_only: bool,
+}
+
+fn open_connection(path: &Path, read_only: bool) -> Result<ConnectionThreadSafe> {
+    if read_only {
+        Connection::open_thread_safe_with_flags(path, OpenFlags::new().with_read_only())
+            .into_diagnostic()
+    } else {
+        Connection::open_thread_safe(path).into_diagnostic()
+    }
+}
+
+impl SqliteStorage {
+    fn connect(&self) -> Result<ConnectionThreadSafe> {
+        let conn = open_connection(&self.name, self.read_only)?;
+        if !self.pragmas.is_empty() {
+            conn.execute(&*self.pragmas).into_diagnostic()?;
+        }
+        Ok(conn)
+    }
 }
 
 /// Create a sqlite backed database.
@@ -35,24 +58,56 @@ pub struct SqliteStorage {
 /// You must provide a disk-based path: `:memory:` is not OK.
 /// If you want a pure memory storage, use [`new_cozo_mem`](crate::new_cozo_mem).
 pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage>> {
+    new_cozo_sqlite_with_options(path, Default::default())
+}
+
+/// Create a sqlite backed database with the given options.
+pub fn new_cozo_sqlite_with_options(
+    path: impl AsRef<Path>,
+    options: SqliteOptions,
+) -> Result<crate::Db<SqliteStorage>> {
     if path.as_ref().to_str() == Some("") {
         bail!("empty path for sqlite storage")
     }
-    let conn = Connection::open_thread_safe(&path).into_diagnostic()?;
-    let query = r#"
+    options.validate()?;
+    if options.read_only {
+        let conn = open_connection(path.as_ref(), true).map_err(|err| {
+            BadDbInit(format!(
+                "cannot open {} read-only: {}",
+                path.as_ref().display(),
+                err
+            ))
+        })?;
+        let query = "select 1 from sqlite_master where type = 'table' and name = 'cozo';";
+        let mut statement = conn.prepare(query).into_diagnostic()?;
+        if statement.next().into_diagnostic()? != State::Row {
+            bail!(BadDbInit(format!(
+                "{} is not a Cozo database",
+                path.as_ref().display()
+            )))
+        }
+    } else {
+        let conn = Connection::open_thread_safe(&path).into_diagnostic()?;
+        if let Some(pragma) = options.journal_mode_pragma() {
+            conn.execute(pragma).into_diagnostic()?;
+        }
+        let query = r#"
         create table if not exists cozo
         (
             k BLOB primary key,
             v BLOB
         );
     "#;
-    let mut statement = conn.prepare(query).unwrap();
-    while statement.next().into_diagnostic()? != State::Done {}
+        let mut statement = conn.prepare(query).unwrap();
+        while statement.next().into_diagnostic()? != State::Done {}
+    }
 
     let ret = crate::Db::new(SqliteStorage {
         lock: Default::default(),
         name: PathBuf::from(path.as_ref()),
         pool: Default::default(),
+        pragmas: options.connection_pragmas().into(),
+        read_only: options.read_only,
     })?;
 
     ret.initialize()?;
@@ -65,7 +120,7 @@ impl<'s> Storage<'s> for SqliteStorage {
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
         let conn = {
             match self.pool.lock().unwrap().pop() {
-                None => Connection::open_thread_safe(&self.name).into_diagnostic()?,
+                None => self.connect()?,
                 Some(conn) => conn,
             }
         };
@@ -114,6 +169,10 @@ impl<'s> Storage<'s> for SqliteStorage {
     fn storage_kind(&self) -> &'static str {
         "sqlite"
     }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 }
 
 pub struct SqliteTx<'a> {
@@ -0,0 +1,419 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+use twox_hash::XxHash64;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"COZOSNP1";
+const WAL_FILE_PREFIX: &str = "wal.";
+const LOCK_FILE: &str = "LOCK";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+/// Options for the durable mode of the mem storage, see [crate::new_cozo_mem_durable].
+#[derive(Debug, Clone)]
+pub struct WalOptions {
+    /// Whether to `fsync` the log on every commit.
+    /// Without it, committed data survives crashes of the process but not of the OS.
+    pub sync: bool,
+    /// Size in bytes the log may grow to before a snapshot is written in the background,
+    /// after which the log up to the snapshot is removed.
+    pub checkpoint_size: u64,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self {
+            sync: false,
+            checkpoint_size: 64 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The snapshot file {0} is corrupt")]
+#[diagnostic(code(storage::corrupt_snapshot))]
+struct CorruptSnapshot(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The write-ahead log {0} is corrupt at offset {1}")]
+#[diagnostic(code(storage::corrupt_wal))]
+#[diagnostic(help("Only the last record of the log can be torn by a crash"))]
+struct CorruptWal(String, u64);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The directory {0} is in use by another database")]
+#[diagnostic(code(storage::wal_locked))]
+struct WalLocked(String);
+
+/// Write-ahead log of the committed write sets, together with the latest snapshot.
+///
+/// The log is split into numbered segments, and a new segment is started whenever a
+/// checkpoint is taken, so that the snapshot can be written while commits go on.
+/// Each log record is framed as `[len: u64][xxhash64 of payload: u64][payload]`,
+/// with the payload holding the commit sequence number and the write set.
+/// A record torn by a crash fails its check and is discarded on recovery,
+/// but only if it is the last one: any other record failing its check is an error.
+///
+/// The directory is locked for as long as the log is open.
+pub(crate) struct Wal {
+    dir: PathBuf,
+    _lock: File,
+    file: File,
+    segment: u64,
+    size: u64,
+    options: WalOptions,
+}
+
+impl Wal {
+    /// Open the log in `dir`, creating it if necessary.
+    /// Returns the log with the recovered data and the sequence number of the last commit.
+    pub(crate) fn open(
+        dir: &Path,
+        options: WalOptions,
+    ) -> Result<(Self, BTreeMap<Vec<u8>, Vec<u8>>, u64)> {
+        std::fs::create_dir_all(dir).into_diagnostic()?;
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(LOCK_FILE))
+            .into_diagnostic()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(err) = fs2::FileExt::try_lock_exclusive(&lock) {
+            if err.kind() == fs2::lock_contended_error().kind() {
+                bail!(WalLocked(dir.to_string_lossy().to_string()))
+            }
+            return Err(err).into_diagnostic();
+        }
+        let (mut data, mut seq) = read_snapshot(&dir.join(SNAPSHOT_FILE))?;
+        let segments = list_segments(dir)?;
+        let mut size = 0;
+        for (i, segment) in segments.iter().enumerate() {
+            let is_last = i == segments.len() - 1;
+            let path = segment_path(dir, *segment);
+            let file = File::open(&path).into_diagnostic()?;
+            let file_len = file.metadata().into_diagnostic()?.len();
+            let mut reader = BufReader::new(file);
+            let mut offset = 0;
+            loop {
+                match read_record(&mut reader)? {
+                    Record::End => break,
+                    Record::Valid(payload) => {
+                        let (record_seq, changes) = decode_write_set(&payload)?;
+                        // records already in the snapshot are left over from an interrupted checkpoint
+                        if record_seq > seq {
+                            for (k, v) in changes {
+                                match v {
+                                    None => data.remove(&k),
+                                    Some(v) => data.insert(k, v),
+                                };
+                            }
+                            seq = record_seq;
+                        }
+                        offset += payload.len() as u64 + 16;
+                    }
+                    Record::Invalid(len) => {
+                        if !is_last || offset.saturating_add(16).saturating_add(len) < file_len {
+                            bail!(CorruptWal(path.to_string_lossy().to_string(), offset))
+                        }
+                        break;
+                    }
+                }
+            }
+            if is_last {
+                size = offset;
+            }
+        }
+        let segment = segments.last().cloned().unwrap_or(1);
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(segment_path(dir, segment))
+            .into_diagnostic()?;
+        if file.metadata().into_diagnostic()?.len() != size {
+            // discard the torn tail
+            file.set_len(size).into_diagnostic()?;
+        }
+        Ok((
+            Self {
+                dir: dir.to_path_buf(),
+                _lock: lock,
+                file,
+                segment,
+                size,
+                options,
+            },
+            data,
+            seq,
+        ))
+    }
+
+    /// Durably record the write set of the commit numbered `seq`.
+    pub(crate) fn append(
+        &mut self,
+        seq: u64,
+        changes: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    ) -> Result<()> {
+        let mut payload = vec![];
+        payload.write_u64::<LE>(seq).into_diagnostic()?;
+        payload
+            .write_u64::<LE>(changes.len() as u64)
+            .into_diagnostic()?;
+        for (k, v) in changes {
+            write_bytes(&mut payload, k)?;
+            match v {
+                None => payload.write_u8(0).into_diagnostic()?,
+                Some(v) => {
+                    payload.write_u8(1).into_diagnostic()?;
+                    write_bytes(&mut payload, v)?;
+                }
+            }
+        }
+        let mut record = Vec::with_capacity(payload.len() + 16);
+        record
+            .write_u64::<LE>(payload.len() as u64)
+            .into_diagnostic()?;
+        record
+            .write_u64::<LE>(checksum(&payload))
+            .into_diagnostic()?;
+        record.extend_from_slice(&payload);
+
+        let written = self.file.write_all(&record).and_then(|_| {
+            if self.options.sync {
+                self.file.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(err) = written {
+            // do not leave a partial record that would hide later ones
+            let _ = self.file.set_len(self.size);
+            return Err(err).into_diagnostic();
+        }
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn needs_checkpoint(&self) -> bool {
+        self.size >= self.options.checkpoint_size
+    }
+
+    /// Start a new segment for the commits to come.
+    /// Returns the checkpoint for the commits up to now, to be written by [Checkpoint::write].
+    pub(crate) fn start_checkpoint(&mut self) -> Result<Checkpoint> {
+        let segment = self.segment + 1;
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(segment_path(&self.dir, segment))
+            .into_diagnostic()?;
+        sync_dir(&self.dir)?;
+        self.file = file;
+        self.segment = segment;
+        self.size = 0;
+        Ok(Checkpoint {
+            dir: self.dir.clone(),
+            segment,
+        })
+    }
+}
+
+/// A checkpoint covering the log segments before `segment`.
+pub(crate) struct Checkpoint {
+    dir: PathBuf,
+    segment: u64,
+}
+
+impl Checkpoint {
+    /// Replace the snapshot by `data`, the state after the commit numbered `seq`,
+    /// which must be the last commit before the checkpoint was started,
+    /// and remove the log segments it covers.
+    pub(crate) fn write(
+        self,
+        seq: u64,
+        data: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let file = File::create(&tmp_path).into_diagnostic()?;
+        let mut writer = BufWriter::new(file);
+        let mut hasher = XxHash64::with_seed(0);
+        writer.write_all(SNAPSHOT_MAGIC).into_diagnostic()?;
+        writer.write_u64::<LE>(seq).into_diagnostic()?;
+        let mut count = 0u64;
+        for (k, v) in data {
+            let mut entry = vec![];
+            write_bytes(&mut entry, &k)?;
+            write_bytes(&mut entry, &v)?;
+            hasher.write(&entry);
+            writer.write_all(&entry).into_diagnostic()?;
+            count += 1;
+        }
+        // the end marker is a key length that no key can have
+        writer.write_u32::<LE>(u32::MAX).into_diagnostic()?;
+        writer.write_u64::<LE>(count).into_diagnostic()?;
+        writer.write_u64::<LE>(hasher.finish()).into_diagnostic()?;
+        let file = writer.into_inner().into_diagnostic()?;
+        file.sync_all().into_diagnostic()?;
+        drop(file);
+        std::fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE)).into_diagnostic()?;
+        sync_dir(&self.dir)?;
+
+        for segment in list_segments(&self.dir)? {
+            if segment < self.segment {
+                std::fs::remove_file(segment_path(&self.dir, segment)).into_diagnostic()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{WAL_FILE_PREFIX}{segment:020}"))
+}
+
+/// The numbers of the log segments in `dir`, in order.
+fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut segments = vec![];
+    for entry in std::fs::read_dir(dir).into_diagnostic()? {
+        let name = entry.into_diagnostic()?.file_name();
+        if let Some(segment) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(WAL_FILE_PREFIX))
+            .and_then(|n| n.parse::<u64>().ok())
+        {
+            segments.push(segment);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+#[allow(unused_variables)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .into_diagnostic()?;
+    Ok(())
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(data);
+    hasher.finish()
+}
+
+fn write_bytes(buf: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+    buf.write_u32::<LE>(data.len() as u32).into_diagnostic()?;
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+fn read_bytes(reader: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let len = reader.read_u32::<LE>()?;
+    let mut data = vec![0; len as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+enum Record {
+    Valid(Vec<u8>),
+    /// A record that fails its check, with the payload length given by its header,
+    /// or 0 if the header is incomplete
+    Invalid(u64),
+    End,
+}
+
+fn read_record(reader: &mut impl Read) -> Result<Record> {
+    let mut header = [0u8; 16];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(Record::End),
+            Ok(0) => return Ok(Record::Invalid(0)),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err).into_diagnostic(),
+        }
+    }
+    let len = u64::from_le_bytes(header[..8].try_into().unwrap());
+    let expected = u64::from_le_bytes(header[8..].try_into().unwrap());
+    let mut payload = vec![];
+    let read = reader
+        .take(len)
+        .read_to_end(&mut payload)
+        .into_diagnostic()?;
+    if read as u64 != len || checksum(&payload) != expected {
+        return Ok(Record::Invalid(len));
+    }
+    Ok(Record::Valid(payload))
+}
+
+#[allow(clippy::type_complexity)]
+fn decode_write_set(mut payload: &[u8]) -> Result<(u64, Vec<(Vec<u8>, Option<Vec<u8>>)>)> {
+    let reader = &mut payload;
+    let seq = reader.read_u64::<LE>().into_diagnostic()?;
+    let n = reader.read_u64::<LE>().into_diagnostic()?;
+    let mut changes = vec![];
+    for _ in 0..n {
+        let k = read_bytes(reader).into_diagnostic()?;
+        let v = match reader.read_u8().into_diagnostic()? {
+            0 => None,
+            _ => Some(read_bytes(reader).into_diagnostic()?),
+        };
+        changes.push((k, v));
+    }
+    Ok((seq, changes))
+}
+
+fn read_snapshot(path: &Path) -> Result<(BTreeMap<Vec<u8>, Vec<u8>>, u64)> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok((BTreeMap::new(), 0)),
+        Err(err) => return Err(err).into_diagnostic(),
+    };
+    let corrupt = || CorruptSnapshot(path.to_string_lossy().to_string());
+    let mut reader = BufReader::new(file);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(|_| corrupt())?;
+    if &magic != SNAPSHOT_MAGIC {
+        bail!(corrupt())
+    }
+    let seq = reader.read_u64::<LE>().map_err(|_| corrupt())?;
+    let mut data = BTreeMap::new();
+    let mut hasher = XxHash64::with_seed(0);
+    loop {
+        let k_len = reader.read_u32::<LE>().map_err(|_| corrupt())?;
+        if k_len == u32::MAX {
+            break;
+        }
+        let mut k = vec![0; k_len as usize];
+        reader.read_exact(&mut k).map_err(|_| corrupt())?;
+        let v = read_bytes(&mut reader).map_err(|_| corrupt())?;
+        hasher.write(&k_len.to_le_bytes());
+        hasher.write(&k);
+        hasher.write(&(v.len() as u32).to_le_bytes());
+        hasher.write(&v);
+        data.insert(k, v);
+    }
+    let count = reader.read_u64::<LE>().map_err(|_| corrupt())?;
+    let expected = reader.read_u64::<LE>().map_err(|_| corrupt())?;
+    if count != data.len() as u64 || expected != hasher.finish() {
+        bail!(corrupt())
+    }
+    Ok((data, seq))
+}
@@ -0,0 +1,270 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+use miette::{IntoDiagnostic, Result};
+
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::runtime::relation::RelationId;
+use crate::storage::StoreTx;
+
+/// A single raw write of a committed transaction.
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum WriteOp {
+    /// Put the value at the key
+    Put(
+        #[serde(with = "serde_bytes")] Vec<u8>,
+        #[serde(with = "serde_bytes")] Vec<u8>,
+    ),
+    /// Delete the key
+    Del(#[serde(with = "serde_bytes")] Vec<u8>),
+    /// Delete all keys from the lower bound, inclusive, to the upper bound, exclusive
+    DelRange(
+        #[serde(with = "serde_bytes")] Vec<u8>,
+        #[serde(with = "serde_bytes")] Vec<u8>,
+    ),
+}
+
+/// The writes of a committed transaction, see [crate::Db::read_write_log].
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct WriteSet {
+    /// Sequence number, the first transaction committed after enabling the log has `1`
+    /// and each transaction increases it by one
+    pub seq: u64,
+    /// The writes, in the order they were made
+    pub ops: Vec<WriteOp>,
+}
+
+/// The entries of the write log are kept under a relation id that is never allocated,
+/// past the range copied by backups.
+const WRITE_LOG_ID: RelationId = RelationId(u64::MAX);
+
+/// Under this key is the sequence number the log starts after: `0` when it is enabled,
+/// the last truncated one after truncation. It is only written by these, and not by
+/// every transaction, so that writers do not conflict on it.
+pub(crate) fn write_log_seq_key() -> Vec<u8> {
+    vec![DataValue::Null, DataValue::from("WRITE_LOG_SEQ")].encode_as_key(RelationId::SYSTEM)
+}
+
+pub(crate) fn write_log_key(seq: u64) -> Vec<u8> {
+    vec![DataValue::from(seq as i64)].encode_as_key(WRITE_LOG_ID)
+}
+
+pub(crate) fn write_log_bounds() -> (Vec<u8>, Vec<u8>) {
+    (
+        Tuple::default().encode_as_key(WRITE_LOG_ID),
+        vec![DataValue::Bot].encode_as_key(WRITE_LOG_ID),
+    )
+}
+
+pub(crate) fn decode_log_key(key: &[u8]) -> u64 {
+    decode_tuple_from_key(key, 1)[0]
+        .get_int()
+        .unwrap_or_default() as u64
+}
+
+pub(crate) fn decode_seq(val: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&val[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+impl WriteSet {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).unwrap()
+    }
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        rmp_serde::from_slice(data).into_diagnostic()
+    }
+    /// Make the writes in `tx`, and record them in its write log.
+    pub(crate) fn apply<'s>(&self, tx: &mut impl StoreTx<'s>) -> Result<()> {
+        for op in &self.ops {
+            match op {
+                WriteOp::Put(key, val) => tx.put(key, val)?,
+                WriteOp::Del(key) => tx.del(key)?,
+                WriteOp::DelRange(lower, upper) => tx.del_range_from_persisted(lower, upper)?,
+            }
+        }
+        tx.put(&write_log_key(self.seq), &self.encode())
+    }
+}
+
+/// The sequence numbers of the write log, kept in memory: a number is taken by
+/// a transaction just before it commits, and the lock is not held during the commit.
+#[derive(Debug, Default)]
+pub(crate) struct WriteLogState {
+    /// The last sequence number taken
+    pub(crate) last: u64,
+    /// Sequence numbers taken by transactions still committing
+    pub(crate) in_flight: BTreeSet<u64>,
+}
+
+impl WriteLogState {
+    pub(crate) fn new(last: u64) -> Self {
+        Self {
+            last,
+            in_flight: Default::default(),
+        }
+    }
+    fn take(&mut self) -> u64 {
+        self.last += 1;
+        self.in_flight.insert(self.last);
+        self.last
+    }
+    fn release(&mut self, seq: u64) {
+        self.in_flight.remove(&seq);
+    }
+    /// The sequence number up to which every transaction is done committing,
+    /// either successfully or not. Those that failed leave holes in the log.
+    pub(crate) fn settled(&self) -> u64 {
+        match self.in_flight.first() {
+            None => self.last,
+            Some(seq) => seq - 1,
+        }
+    }
+}
+
+/// Transaction given out for writing when the write log is enabled:
+/// the writes are passed through and recorded, and on commit they are
+/// added to the log as a [WriteSet] in the same transaction.
+pub(crate) struct WriteLogTx<T> {
+    pub(crate) inner: T,
+    /// `None` if the log is disabled. Transactions writing the same keys cannot commit
+    /// concurrently, so their sequence numbers follow the order of their commits.
+    pub(crate) state: Arc<Mutex<Option<WriteLogState>>>,
+    pub(crate) ops: Mutex<Vec<WriteOp>>,
+}
+
+impl<T> WriteLogTx<T> {
+    pub(crate) fn new(inner: T, state: Arc<Mutex<Option<WriteLogState>>>) -> Self {
+        Self {
+            inner,
+            state,
+            ops: Default::default(),
+        }
+    }
+    fn record(&self, op: WriteOp) {
+        self.ops.lock().unwrap().push(op)
+    }
+}
+
+impl<'s, T: StoreTx<'s>> StoreTx<'s> for WriteLogTx<T> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.put(key, val)?;
+        self.record(WriteOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.par_put(key, val)?;
+        self.record(WriteOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)?;
+        self.record(WriteOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.inner.par_del(key)?;
+        self.record(WriteOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)?;
+        self.record(WriteOp::DelRange(lower.to_vec(), upper.to_vec()));
+        Ok(())
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let ops = std::mem::take(self.ops.get_mut().unwrap());
+        if ops.is_empty() {
+            return self.inner.commit();
+        }
+        let seq = match self.state.lock().unwrap().as_mut() {
+            None => return self.inner.commit(),
+            Some(state) => state.take(),
+        };
+        let write_set = WriteSet { seq, ops };
+        let res = self
+            .inner
+            .put(&write_log_key(seq), &write_set.encode())
+            .and_then(|_| self.inner.commit());
+        if let Some(state) = self.state.lock().unwrap().as_mut() {
+            state.release(seq);
+        }
+        res
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
@@ -22,6 +22,17 @@ pub(crate) struct TempCollector<T: serde::Serialize + for<'a> serde::Deserialize
 }
 
 impl<T: serde::Serialize + for<'a> serde::Deserialize<'a>> TempCollector<T> {
+    /// A collector that starts writing to disk right away, holding at most
+    /// `batch_size` elements in memory.
+    pub(crate) fn spilling(batch_size: usize) -> Self {
+        Self {
+            inner: swapvec::SwapVec::with_config(swapvec::SwapVecConfig {
+                swap_after: batch_size,
+                batch_size,
+                compression: None,
+            }),
+        }
+    }
     pub(crate) fn push(&mut self, val: T) {
         self.inner.push(val).unwrap();
     }
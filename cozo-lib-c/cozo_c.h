@@ -26,7 +26,8 @@ extern "C" {
  * `engine`:  which storage engine to use, can be "mem", "sqlite" or "rocksdb".
  * `path`:    should contain the UTF-8 encoded path name as a null-terminated C-string.
  * `db_id`:   will contain the ID of the database opened.
- * `options`: options for the DB constructor: engine dependent.
+ * `options`: options for the DB constructor as a JSON object: engine dependent,
+ *           see `DbInstance::new` of the Rust crate.
  *
  * When the function is successful, null pointer is returned,
  * otherwise a pointer to a C-string containing the error message will be returned.
@@ -63,6 +64,90 @@ char *cozo_run_query(int32_t db_id,
                      const char *params_raw,
                      bool immutable_query);
 
+/**
+ * Run a read-only query against a database, returning an iterator over the rows.
+ * The rows are computed lazily as they are fetched with `cozo_iter_next`,
+ * and the read transaction is held until the iterator is exhausted or closed.
+ *
+ * `db_id`:      the ID representing the database to run the query.
+ * `script_raw`: a UTF-8 encoded C-string for the CozoScript to execute.
+ * `params_raw`: a UTF-8 encoded C-string for the params of the query,
+ *               in JSON format. You must always pass in a valid JSON map,
+ *               even if you do not use params in your query
+ *               (pass "{}" in this case).
+ * `iter_id`:    will contain the ID of the iterator if the query is successful.
+ *
+ * Returns a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+ * The string contains the JSON headers of the query, together with the `cursor`
+ * of queries with `:after` that fill their `:limit`, or the error.
+ */
+char *cozo_run_query_iter(int32_t db_id,
+                          const char *script_raw,
+                          const char *params_raw,
+                          int32_t *iter_id);
+
+/**
+ * Fetch the next row from an iterator.
+ *
+ * `iter_id`: the ID representing the iterator.
+ *
+ * Returns a null pointer if the iterator is exhausted or does not exist,
+ * otherwise a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+ * The string contains the JSON row, or the error.
+ */
+char *cozo_iter_next(int32_t iter_id);
+
+/**
+ * Close an iterator, releasing the read transaction it holds.
+ *
+ * `iter_id`: the ID representing the iterator to close.
+ *
+ * Returns `true` if the iterator is closed,
+ * `false` if it has already been closed or exhausted, or does not exist.
+ */
+bool cozo_iter_close(int32_t iter_id);
+
+/**
+ * Prepare a query for repeated execution with `cozo_run_prepared`.
+ * The query is compiled once, and its plan is reused until the schema
+ * or the indices of the relations it uses change.
+ *
+ * `db_id`:       the ID representing the database.
+ * `script_raw`:  a UTF-8 encoded C-string for the CozoScript to prepare,
+ *                with its parameters left as `$name`.
+ * `prepared_id`: will contain the ID of the prepared query if successful.
+ *
+ * When the function is successful, null pointer is returned,
+ * otherwise a pointer to a C-string containing the JSON error will be returned.
+ * The returned C-string must be freed with `cozo_free_str`.
+ */
+char *cozo_prepare(int32_t db_id, const char *script_raw, int32_t *prepared_id);
+
+/**
+ * Run a query prepared with `cozo_prepare`.
+ *
+ * `prepared_id`:     the ID representing the prepared query.
+ * `params_raw`:      a UTF-8 encoded C-string for the params of the query,
+ *                    in JSON format. You must always pass in a valid JSON map,
+ *                    even if you do not use params in your query
+ *                    (pass "{}" in this case).
+ * `immutable_query`: whether the query is read-only.
+ *
+ * Returns a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+ * The string contains the JSON return value of the query.
+ */
+char *cozo_run_prepared(int32_t prepared_id, const char *params_raw, bool immutable_query);
+
+/**
+ * Discard a query prepared with `cozo_prepare`.
+ *
+ * `prepared_id`: the ID representing the prepared query.
+ *
+ * Returns `true` if the prepared query is discarded,
+ * `false` if it has already been discarded, or does not exist.
+ */
+bool cozo_close_prepared(int32_t prepared_id);
+
 /**
  * Import data into relations
  *
@@ -12,7 +12,7 @@ use std::collections::BTreeMap;
 use std::ffi::{c_char, CStr, CString};
 use std::ptr::null_mut;
 use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use lazy_static::lazy_static;
 
@@ -21,12 +21,18 @@ use cozo::*;
 struct Handles {
     current: AtomicI32,
     dbs: Mutex<BTreeMap<i32, DbInstance>>,
+    /// Each iterator is locked on its own while fetching, so that fetching does not
+    /// block other iterators. It is `None` once closed or exhausted.
+    iters: Mutex<BTreeMap<i32, Arc<Mutex<Option<RowIter>>>>>,
+    prepared: Mutex<BTreeMap<i32, Arc<(DbInstance, PreparedQuery)>>>,
 }
 
 lazy_static! {
     static ref HANDLES: Handles = Handles {
         current: Default::default(),
-        dbs: Mutex::new(Default::default())
+        dbs: Mutex::new(Default::default()),
+        iters: Mutex::new(Default::default()),
+        prepared: Mutex::new(Default::default())
     };
 }
 
@@ -35,7 +41,8 @@ lazy_static! {
 /// `engine`:  which storage engine to use, can be "mem", "sqlite" or "rocksdb".
 /// `path`:    should contain the UTF-8 encoded path name as a null-terminated C-string.
 /// `db_id`:   will contain the ID of the database opened.
-/// `options`: options for the DB constructor: engine dependent.
+/// `options`: options for the DB constructor as a JSON object: engine dependent,
+///           see `DbInstance::new` of the Rust crate.
 ///
 /// When the function is successful, null pointer is returned,
 /// otherwise a pointer to a C-string containing the error message will be returned.
@@ -145,6 +152,233 @@ pub unsafe extern "C" fn cozo_run_query(
     CString::new(result).unwrap().into_raw()
 }
 
+/// Run a read-only query against a database, returning an iterator over the rows.
+/// The rows are computed lazily as they are fetched with `cozo_iter_next`,
+/// and the read transaction is held until the iterator is exhausted or closed.
+///
+/// `db_id`:      the ID representing the database to run the query.
+/// `script_raw`: a UTF-8 encoded C-string for the CozoScript to execute.
+/// `params_raw`: a UTF-8 encoded C-string for the params of the query,
+///               in JSON format. You must always pass in a valid JSON map,
+///               even if you do not use params in your query
+///               (pass "{}" in this case).
+/// `iter_id`:    will contain the ID of the iterator if the query is successful.
+///
+/// Returns a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+/// The string contains the JSON headers of the query, together with the `cursor`
+/// of queries with `:after` that fill their `:limit`, or the error.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_run_query_iter(
+    db_id: i32,
+    script_raw: *const c_char,
+    params_raw: *const c_char,
+    iter_id: &mut i32,
+) -> *mut c_char {
+    let script = match CStr::from_ptr(script_raw).to_str() {
+        Ok(p) => p,
+        Err(_) => {
+            return CString::new(r##"{"ok":false,"message":"script is not UTF-8 encoded"}"##)
+                .unwrap()
+                .into_raw();
+        }
+    };
+    let db = {
+        let db_ref = {
+            let dbs = HANDLES.dbs.lock().unwrap();
+            dbs.get(&db_id).cloned()
+        };
+        match db_ref {
+            None => {
+                return CString::new(r##"{"ok":false,"message":"database closed"}"##)
+                    .unwrap()
+                    .into_raw();
+            }
+            Some(db) => db,
+        }
+    };
+    let params_str = match CStr::from_ptr(params_raw).to_str() {
+        Ok(p) => p,
+        Err(_) => {
+            return CString::new(
+                r##"{"ok":false,"message":"params argument is not UTF-8 encoded"}"##,
+            )
+            .unwrap()
+            .into_raw();
+        }
+    };
+
+    let (result, iter) = db.run_script_iter_str(script, params_str);
+    if let Some(iter) = iter {
+        let id = HANDLES.current.fetch_add(1, Ordering::AcqRel);
+        HANDLES
+            .iters
+            .lock()
+            .unwrap()
+            .insert(id, Arc::new(Mutex::new(Some(iter))));
+        *iter_id = id;
+    }
+    CString::new(result).unwrap().into_raw()
+}
+
+/// Fetch the next row from an iterator.
+///
+/// `iter_id`: the ID representing the iterator.
+///
+/// Returns a null pointer if the iterator is exhausted or does not exist,
+/// otherwise a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+/// The string contains the JSON row, or the error.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_iter_next(iter_id: i32) -> *mut c_char {
+    // the iterator stays in the map while fetching, so that closing it meanwhile
+    // waits for the fetch and then drops it
+    let entry = match HANDLES.iters.lock().unwrap().get(&iter_id) {
+        None => return null_mut(),
+        Some(entry) => entry.clone(),
+    };
+    let mut iter = entry.lock().unwrap();
+    match iter.as_mut().and_then(|iter| iter.next_str()) {
+        Some(s) => CString::new(s).unwrap().into_raw(),
+        None => {
+            *iter = None;
+            drop(iter);
+            HANDLES.iters.lock().unwrap().remove(&iter_id);
+            null_mut()
+        }
+    }
+}
+
+/// Close an iterator, releasing the read transaction it holds.
+///
+/// `iter_id`: the ID representing the iterator to close.
+///
+/// Returns `true` if the iterator is closed,
+/// `false` if it has already been closed or exhausted, or does not exist.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_iter_close(iter_id: i32) -> bool {
+    let entry = HANDLES.iters.lock().unwrap().remove(&iter_id);
+    match entry {
+        None => false,
+        Some(entry) => entry.lock().unwrap().take().is_some(),
+    }
+}
+
+/// Prepare a query for repeated execution with `cozo_run_prepared`.
+/// The query is compiled once, and its plan is reused until the schema
+/// or the indices of the relations it uses change.
+///
+/// `db_id`:       the ID representing the database.
+/// `script_raw`:  a UTF-8 encoded C-string for the CozoScript to prepare,
+///                with its parameters left as `$name`.
+/// `prepared_id`: will contain the ID of the prepared query if successful.
+///
+/// When the function is successful, null pointer is returned,
+/// otherwise a pointer to a C-string containing the JSON error will be returned.
+/// The returned C-string must be freed with `cozo_free_str`.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_prepare(
+    db_id: i32,
+    script_raw: *const c_char,
+    prepared_id: &mut i32,
+) -> *mut c_char {
+    let script = match CStr::from_ptr(script_raw).to_str() {
+        Ok(p) => p,
+        Err(_) => {
+            return CString::new(r##"{"ok":false,"message":"script is not UTF-8 encoded"}"##)
+                .unwrap()
+                .into_raw();
+        }
+    };
+    let db = {
+        let db_ref = {
+            let dbs = HANDLES.dbs.lock().unwrap();
+            dbs.get(&db_id).cloned()
+        };
+        match db_ref {
+            None => {
+                return CString::new(r##"{"ok":false,"message":"database closed"}"##)
+                    .unwrap()
+                    .into_raw();
+            }
+            Some(db) => db,
+        }
+    };
+    match db.prepare_str(script) {
+        Ok(query) => {
+            let id = HANDLES.current.fetch_add(1, Ordering::AcqRel);
+            HANDLES
+                .prepared
+                .lock()
+                .unwrap()
+                .insert(id, Arc::new((db, query)));
+            *prepared_id = id;
+            null_mut()
+        }
+        Err(err) => CString::new(err).unwrap().into_raw(),
+    }
+}
+
+/// Run a query prepared with `cozo_prepare`.
+///
+/// `prepared_id`:     the ID representing the prepared query.
+/// `params_raw`:      a UTF-8 encoded C-string for the params of the query,
+///                    in JSON format. You must always pass in a valid JSON map,
+///                    even if you do not use params in your query
+///                    (pass "{}" in this case).
+/// `immutable_query`: whether the query is read-only.
+///
+/// Returns a UTF-8-encoded C-string that **must** be freed with `cozo_free_str`.
+/// The string contains the JSON return value of the query.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_run_prepared(
+    prepared_id: i32,
+    params_raw: *const c_char,
+    immutable_query: bool,
+) -> *mut c_char {
+    let prepared = {
+        let prepared_ref = {
+            let prepared = HANDLES.prepared.lock().unwrap();
+            prepared.get(&prepared_id).cloned()
+        };
+        match prepared_ref {
+            None => {
+                return CString::new(r##"{"ok":false,"message":"prepared query closed"}"##)
+                    .unwrap()
+                    .into_raw();
+            }
+            Some(prepared) => prepared,
+        }
+    };
+    let params_str = match CStr::from_ptr(params_raw).to_str() {
+        Ok(p) => p,
+        Err(_) => {
+            return CString::new(
+                r##"{"ok":false,"message":"params argument is not UTF-8 encoded"}"##,
+            )
+            .unwrap()
+            .into_raw();
+        }
+    };
+
+    let (db, query) = prepared.as_ref();
+    let result = db.run_prepared_str(query, params_str, immutable_query);
+    CString::new(result).unwrap().into_raw()
+}
+
+/// Discard a query prepared with `cozo_prepare`.
+///
+/// `prepared_id`: the ID representing the prepared query.
+///
+/// Returns `true` if the prepared query is discarded,
+/// `false` if it has already been discarded, or does not exist.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_close_prepared(prepared_id: i32) -> bool {
+    let prepared = {
+        let mut prepared = HANDLES.prepared.lock().unwrap();
+        prepared.remove(&prepared_id)
+    };
+    prepared.is_some()
+}
+
 #[no_mangle]
 /// Import data into relations
 ///
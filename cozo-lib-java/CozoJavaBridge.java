@@ -9,4 +9,24 @@ public class CozoJavaBridge {
     private static native String backup(int id, String file);
     private static native String restore(int id, String file);
     private static native String importFromBackup(int id, String data);
+    /**
+     * Register a user-defined scalar function. A negative `maxArity` means no limit.
+     */
+    private static native String registerFunction(int id, String name, int minArity, int maxArity, CozoFunction callback);
+    private static native String unregisterFunction(int id, String name);
+    /**
+     * Prepare a query for repeated execution. Returns `{"ok":true,"id":...}` with the ID
+     * to pass to `runPrepared` and `closePrepared`.
+     */
+    private static native String prepare(int id, String script);
+    private static native String runPrepared(int preparedId, String params);
+    private static native boolean closePrepared(int preparedId);
+
+    /**
+     * A user-defined scalar function. The arguments are given as a JSON array,
+     * and the result must be returned as JSON.
+     */
+    public interface CozoFunction {
+        String call(String args);
+    }
 }
\ No newline at end of file
@@ -71,6 +71,46 @@ JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_restore
 JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_importFromBackup
   (JNIEnv *, jclass, jint, jstring);
 
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    registerFunction
+ * Signature: (ILjava/lang/String;IILorg/cozodb/CozoJavaBridge/CozoFunction;)Ljava/lang/String;
+ */
+JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_registerFunction
+  (JNIEnv *, jclass, jint, jstring, jint, jint, jobject);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    unregisterFunction
+ * Signature: (ILjava/lang/String;)Ljava/lang/String;
+ */
+JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_unregisterFunction
+  (JNIEnv *, jclass, jint, jstring);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    prepare
+ * Signature: (ILjava/lang/String;)Ljava/lang/String;
+ */
+JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_prepare
+  (JNIEnv *, jclass, jint, jstring);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    runPrepared
+ * Signature: (ILjava/lang/String;)Ljava/lang/String;
+ */
+JNIEXPORT jstring JNICALL Java_org_cozodb_CozoJavaBridge_runPrepared
+  (JNIEnv *, jclass, jint, jstring);
+
+/*
+ * Class:     org_cozodb_CozoJavaBridge
+ * Method:    closePrepared
+ * Signature: (I)Z
+ */
+JNIEXPORT jboolean JNICALL Java_org_cozodb_CozoJavaBridge_closePrepared
+  (JNIEnv *, jclass, jint);
+
 #ifdef __cplusplus
 }
 #endif
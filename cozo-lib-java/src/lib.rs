@@ -7,12 +7,13 @@
  */
 use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
-use jni::objects::{JClass, JString};
+use jni::objects::{JClass, JObject, JString, JValue};
 use jni::sys::{jboolean, jint, jstring};
 use jni::JNIEnv;
 use lazy_static::lazy_static;
+use serde_json::{json, Value};
 
 use cozo::*;
 
@@ -20,12 +21,14 @@ use cozo::*;
 struct Handles {
     current: AtomicI32,
     dbs: Mutex<BTreeMap<i32, DbInstance>>,
+    prepared: Mutex<BTreeMap<i32, Arc<(DbInstance, PreparedQuery)>>>,
 }
 
 lazy_static! {
     static ref HANDLES: Handles = Handles {
         current: Default::default(),
-        dbs: Mutex::new(Default::default())
+        dbs: Mutex::new(Default::default()),
+        prepared: Mutex::new(Default::default())
     };
 }
 
@@ -178,3 +181,147 @@ pub extern "system" fn Java_org_cozodb_CozoJavaBridge_importFromBackup(
         }
     }
 }
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_registerFunction(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    name: JString,
+    min_arity: jint,
+    max_arity: jint,
+    callback: JObject,
+) -> jstring {
+    let name: String = env.get_string(&name).unwrap().into();
+    let db = match get_db(id) {
+        None => return env.new_string(DB_NOT_FOUND).unwrap().into_raw(),
+        Some(db) => db,
+    };
+    let vm = env.get_java_vm().unwrap();
+    let callback = env.new_global_ref(callback).unwrap();
+    // arguments and results are passed to the Java callback as JSON strings
+    let func = move |args: &[DataValue]| -> Result<DataValue, Error> {
+        let mut env = vm
+            .attach_current_thread()
+            .map_err(|err| Error::msg(err.to_string()))?;
+        let args = Value::Array(args.iter().map(|v| Value::from(v.clone())).collect());
+        let args = env
+            .new_string(args.to_string())
+            .map_err(|err| Error::msg(err.to_string()))?;
+        let ret = env.call_method(
+            &callback,
+            "call",
+            "(Ljava/lang/String;)Ljava/lang/String;",
+            &[JValue::Object(&args)],
+        );
+        if env.exception_check().unwrap_or(false) {
+            let _ = env.exception_describe();
+            let _ = env.exception_clear();
+            return Err(Error::msg("Java function threw an exception"));
+        }
+        let ret = JString::from(
+            ret.and_then(|v| v.l())
+                .map_err(|err| Error::msg(err.to_string()))?,
+        );
+        let ret: String = env
+            .get_string(&ret)
+            .map_err(|err| Error::msg(err.to_string()))?
+            .into();
+        let ret: Value = serde_json::from_str(&ret).map_err(|err| Error::msg(err.to_string()))?;
+        Ok(DataValue::from(ret))
+    };
+    let min_arity = min_arity.max(0) as usize;
+    let res = if max_arity < 0 {
+        db.register_function(name, min_arity.., func)
+    } else {
+        db.register_function(name, min_arity..=max_arity as usize, func)
+    };
+    let res = match res {
+        Ok(()) => json!({"ok": true}),
+        Err(err) => json!({"ok": false, "message": err.to_string()}),
+    };
+    env.new_string(res.to_string()).unwrap().into_raw()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_unregisterFunction(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    name: JString,
+) -> jstring {
+    let name: String = env.get_string(&name).unwrap().into();
+    match get_db(id) {
+        None => env.new_string(DB_NOT_FOUND).unwrap().into_raw(),
+        Some(db) => {
+            let res = match db.unregister_function(&name) {
+                Ok(removed) => json!({"ok": true, "removed": removed}),
+                Err(err) => json!({"ok": false, "message": err.to_string()}),
+            };
+            env.new_string(res.to_string()).unwrap().into_raw()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_prepare(
+    mut env: JNIEnv,
+    _class: JClass,
+    id: jint,
+    script: JString,
+) -> jstring {
+    let script: String = env.get_string(&script).unwrap().into();
+    match get_db(id) {
+        None => env.new_string(DB_NOT_FOUND).unwrap().into_raw(),
+        Some(db) => {
+            let res = match db.prepare_str(&script) {
+                Ok(query) => {
+                    let prepared_id = HANDLES.current.fetch_add(1, Ordering::AcqRel);
+                    HANDLES
+                        .prepared
+                        .lock()
+                        .unwrap()
+                        .insert(prepared_id, Arc::new((db, query)));
+                    json!({"ok": true, "id": prepared_id}).to_string()
+                }
+                Err(err) => err,
+            };
+            env.new_string(res).unwrap().into_raw()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_runPrepared(
+    mut env: JNIEnv,
+    _class: JClass,
+    prepared_id: jint,
+    params_str: JString,
+) -> jstring {
+    let params_str: String = env.get_string(&params_str).unwrap().into();
+    let prepared = HANDLES.prepared.lock().unwrap().get(&prepared_id).cloned();
+    match prepared {
+        None => env
+            .new_string(r#"{"ok":false,"message":"prepared query not found"}"#)
+            .unwrap()
+            .into_raw(),
+        Some(prepared) => {
+            let (db, query) = prepared.as_ref();
+            let res = db.run_prepared_str(query, &params_str, false);
+            env.new_string(res).unwrap().into_raw()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cozodb_CozoJavaBridge_closePrepared(
+    _env: JNIEnv,
+    _class: JClass,
+    prepared_id: jint,
+) -> jboolean {
+    let prepared = {
+        let mut prepared = HANDLES.prepared.lock().unwrap();
+        prepared.remove(&prepared_id)
+    };
+    prepared.is_some().into()
+}
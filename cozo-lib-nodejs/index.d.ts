@@ -1,4 +1,38 @@
 declare module "cozo-node" {
+  export class CozoRowIter {
+    /**
+     * The headers of the result
+     */
+    headers: Array<string>;
+
+    /**
+     * Fetches the next row, resolving to `undefined` when the iterator is exhausted.
+     */
+    next(): Promise<Array<any> | undefined>;
+
+    /**
+     * Closes the iterator, releasing the read transaction it holds.
+     */
+    close(): boolean;
+
+    [Symbol.asyncIterator](): AsyncIterator<Array<any>>;
+  }
+
+  export class CozoPreparedQuery {
+    /**
+     * Runs the prepared query
+     *
+     * @param params: the parameters as key-value pairs, defaults to {}
+     * @param immutable: if true, the query is not allowed to mutate the database
+     */
+    run(params?: Record<string, any>, immutable?: boolean): Promise<any>;
+
+    /**
+     * Discards the prepared query, releasing its cached plan.
+     */
+    close(): boolean;
+  }
+
   export class CozoDb {
     /**
      * Constructor
@@ -8,7 +42,10 @@ declare module "cozo-node" {
      *                 depending on compile time flags.
      * @param path:    path to store the data on disk, defaults to 'data.db',
      *                 may not be applicable for some engines such as 'mem'
-     * @param options: defaults to {}, ignored by all the engines in the published NodeJS artefact
+     * @param options: defaults to {}, engine dependent, unknown options are errors:
+     *                 {"wal": true} makes 'mem' durable at `path`,
+     *                 {"journal_mode": "wal", "cache_size": -65536} tunes 'sqlite',
+     *                 {"block_cache_size": 1073741824, "compression": "zstd"} tunes 'rocksdb'
      */
     constructor(engine?: string, path?: string, options?: object);
 
@@ -27,6 +64,25 @@ declare module "cozo-node" {
      */
     run(script: string, params?: Record<string, any>): Promise<any>;
 
+    /**
+     * Prepares a query to be run many times with different parameters.
+     * The query is compiled once, and its plan is reused until the schema
+     * or the indices of the relations it uses change.
+     *
+     * @param script: the query, with parameters written as `$name`
+     */
+    prepare(script: string): CozoPreparedQuery;
+
+    /**
+     * Runs a read-only query, returning an iterator over the rows. Rows are
+     * computed as they are fetched, and the read transaction is held until the
+     * iterator is exhausted or closed.
+     *
+     * @param script: the query
+     * @param params: the parameters as key-value pairs, defaults to {}
+     */
+    runIter(script: string, params?: Record<string, any>): Promise<CozoRowIter>;
+
     /**
      * Export several relations
      *
@@ -69,5 +125,50 @@ declare module "cozo-node" {
      * @param rels: the relations to import.
      */
     importRelationsFromBackup(path: string, rels: Array<string>): Promise<any>;
+
+    /**
+     * Register a custom scalar function, usable in expressions like the built-in ones.
+     * The implementation is called with the evaluated arguments and returns the result.
+     *
+     * @param name: the name of the function
+     * @param minArity: the minimum number of arguments
+     * @param maxArity: the maximum number of arguments, `null` for no limit
+     * @param impl: the implementation
+     */
+    registerFunction(name: string, minArity: number, maxArity: number | null, impl: (...args: Array<any>) => any): void;
+
+    /**
+     * Unregister a custom scalar function.
+     *
+     * @param name: the name of the function
+     */
+    unregisterFunction(name: string): boolean;
+
+    /**
+     * Register a custom aggregation, usable in rule heads like the built-in ones.
+     * The state of the aggregation is passed by value: `init` receives the arguments
+     * given to the aggregation and returns the initial state, `update` and `merge`
+     * return the new state, and `finalize` returns the result.
+     *
+     * For a meet aggregation (`isMeet: true`), usable in recursive rules, only `init`
+     * and `merge` are called.
+     *
+     * @param name: the name of the aggregation
+     * @param impl: the implementation
+     */
+    registerAggregation(name: string, impl: {
+      init: (args: Array<any>) => any,
+      update?: (state: any, value: any) => any,
+      merge: (state: any, other: any) => any,
+      finalize?: (state: any) => any,
+      isMeet?: boolean,
+    }): void;
+
+    /**
+     * Unregister a custom aggregation.
+     *
+     * @param name: the name of the aggregation
+     */
+    unregisterAggregation(name: string): boolean;
   }
 }
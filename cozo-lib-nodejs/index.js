@@ -39,6 +39,66 @@ class CozoTx {
     }
 }
 
+class CozoRowIter {
+    constructor(id, headers) {
+        this.iter_id = id;
+        this.headers = headers;
+    }
+
+    next() {
+        return new Promise((resolve, reject) => {
+            native.iter_next(this.iter_id, (err, row) => {
+                if (err) {
+                    reject(JSON.parse(err))
+                } else {
+                    resolve(row)
+                }
+            })
+        })
+    }
+
+    close() {
+        return native.iter_close(this.iter_id)
+    }
+
+    async* [Symbol.asyncIterator]() {
+        try {
+            while (true) {
+                const row = await this.next();
+                if (row === undefined) {
+                    return
+                }
+                yield row
+            }
+        } finally {
+            this.close()
+        }
+    }
+}
+
+class CozoPreparedQuery {
+    constructor(id) {
+        this.prepared_id = id;
+    }
+
+    run(params, immutable) {
+        return new Promise((resolve, reject) => {
+            params = params || {};
+            native.query_prepared(this.prepared_id, params, (err, result) => {
+                if (err) {
+                    reject(JSON.parse(err))
+                } else {
+                    resolve(result)
+                }
+            }, !!immutable)
+        })
+    }
+
+    close() {
+        return native.close_prepared(this.prepared_id)
+    }
+}
+
 class CozoDb {
     constructor(engine, path, options) {
         this.db_id = native.open_db(engine || 'mem', path || 'data.db', JSON.stringify(options || {}))
@@ -65,6 +125,27 @@ class CozoDb {
         })
     }
 
+    prepare(script) {
+        try {
+            return new CozoPreparedQuery(native.prepare(this.db_id, script))
+        } catch (err) {
+            throw JSON.parse(err)
+        }
+    }
+
+    runIter(script, params) {
+        return new Promise((resolve, reject) => {
+            params = params || {};
+            native.query_iter(this.db_id, script, params, (err, result) => {
+                if (err) {
+                    reject(JSON.parse(err))
+                } else {
+                    resolve(new CozoRowIter(result.iter_id, result.headers))
+                }
+            })
+        })
+    }
+
     exportRelations(relations, as_objects) {
         return new Promise((resolve, reject) => {
             native.export_relations(this.db_id, relations, (err, data) => {
@@ -154,6 +235,51 @@ class CozoDb {
     unregisterNamedRule(name) {
         return native.unregister_named_rule(this.db_id, name)
     }
+
+    registerFunction(name, minArity, maxArity, impl) {
+        return native.register_function(this.db_id, name, minArity, maxArity, async (ret_id, args) => {
+            let ret = undefined;
+            try {
+                ret = await impl(...args);
+            } catch (e) {
+                console.error(e);
+                native.respond_to_function_invocation(ret_id, null, '' + e);
+                return;
+            }
+            try {
+                native.respond_to_function_invocation(ret_id, ret === undefined ? null : ret);
+            } catch (e) {
+                console.error(e);
+            }
+        })
+    }
+
+    unregisterFunction(name) {
+        return native.unregister_function(this.db_id, name)
+    }
+
+    registerAggregation(name, {init, update, merge, finalize, isMeet = false}) {
+        const ops = {update, merge, finalize};
+        return native.register_aggregation(this.db_id, name, isMeet, async (ret_id, op, args) => {
+            let ret = undefined;
+            try {
+                ret = await (op === 'init' ? init(args) : ops[op](...args));
+            } catch (e) {
+                console.error(e);
+                native.respond_to_aggregation_invocation(ret_id, null, '' + e);
+                return;
+            }
+            try {
+                native.respond_to_aggregation_invocation(ret_id, ret === undefined ? null : ret);
+            } catch (e) {
+                console.error(e);
+            }
+        })
+    }
+
+    unregisterAggregation(name) {
+        return native.unregister_aggregation(this.db_id, name)
+    }
 }
 
 module.exports = {CozoDb: CozoDb}
@@ -9,7 +9,7 @@ use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
-use crossbeam::channel::Sender;
+use crossbeam::channel::{bounded, unbounded, Sender};
 use lazy_static::lazy_static;
 use miette::{miette, Result};
 use neon::prelude::*;
@@ -138,6 +138,7 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
         DataValue::Num(n) => match n {
             Num::Int(i) => cx.number(*i as f64).as_value(cx),
             Num::Float(f) => cx.number(*f).as_value(cx),
+            Num::Decimal(d) => cx.string(d.to_string()).as_value(cx),
         },
         DataValue::Str(s) => cx.string(s).as_value(cx),
         DataValue::Bytes(b) => {
@@ -190,6 +191,10 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
             target_l.as_value(cx)
         }
         DataValue::Json(JsonData(j)) => json2js(cx, j)?,
+        DataValue::Date(d) => cx.string(d.to_string()).as_value(cx),
+        DataValue::Time(t) => cx.string(t.to_string()).as_value(cx),
+        DataValue::Timestamp(ts) => cx.string(ts.to_string()).as_value(cx),
+        DataValue::Duration(d) => cx.string(d.to_string()).as_value(cx),
     })
 }
 
@@ -271,8 +276,14 @@ struct Handles {
     dbs: Mutex<BTreeMap<u32, DbInstance>>,
     cb_idx: AtomicU32,
     current_cbs: Mutex<BTreeMap<u32, Sender<Result<NamedRows>>>>,
+    current_aggr_cbs: Mutex<BTreeMap<u32, Sender<Result<DataValue>>>>,
+    current_fn_cbs: Mutex<BTreeMap<u32, Sender<Result<DataValue>>>>,
     nxt_tx_id: AtomicU32,
     txs: Mutex<BTreeMap<u32, Arc<MultiTransaction>>>,
+    nxt_iter_id: AtomicU32,
+    iters: Mutex<BTreeMap<u32, Arc<Mutex<RowIter>>>>,
+    nxt_prepared_id: AtomicU32,
+    prepared: Mutex<BTreeMap<u32, Arc<(DbInstance, PreparedQuery)>>>,
 }
 
 lazy_static! {
@@ -410,7 +421,7 @@ fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let channel = cx.channel();
 
     rayon::spawn(move || {
-        let result = db.run_script(
+        let result = db.run_script_page(
             &query,
             params,
             if immutable {
@@ -419,6 +430,206 @@ fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
                 ScriptMutability::Mutable
             },
         );
+        channel.send(move |mut cx| {
+            let callback = callback.into_inner(&mut cx);
+            let this = cx.undefined();
+            match result {
+                Ok((nr, cursor)) => {
+                    let js_vals = named_rows2js(&mut cx, &nr)?;
+                    if let Some(cursor) = cursor {
+                        let converted = cx.string(cursor);
+                        js_vals.set(&mut cx, "cursor", converted)?;
+                    }
+                    let js_vals = js_vals.as_value(&mut cx);
+                    let err = cx.undefined().as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err, js_vals])?;
+                }
+                Err(err) => {
+                    let reports = format_error_as_json(err, Some(&query)).to_string();
+                    let err = cx.string(&reports).as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err])?;
+                }
+            }
+            Ok(())
+        });
+    });
+
+    Ok(cx.undefined())
+}
+
+fn query_iter(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let db = get_db!(cx);
+    let query = cx.argument::<JsString>(1)?.value(&mut cx);
+    let params_js = cx.argument::<JsObject>(2)?;
+    let mut params = BTreeMap::new();
+    js2params(&mut cx, params_js, &mut params)?;
+
+    let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+
+    let channel = cx.channel();
+
+    rayon::spawn(move || {
+        let result = db.run_script_iter(&query, params).map(|iter| {
+            let headers = iter.headers.clone();
+            let id = HANDLES.nxt_iter_id.fetch_add(1, Ordering::AcqRel);
+            HANDLES
+                .iters
+                .lock()
+                .unwrap()
+                .insert(id, Arc::new(Mutex::new(iter)));
+            (id, headers)
+        });
+        channel.send(move |mut cx| {
+            let callback = callback.into_inner(&mut cx);
+            let this = cx.undefined();
+            match result {
+                Ok((id, headers)) => {
+                    let ret = cx.empty_object();
+                    let id = cx.number(id);
+                    ret.set(&mut cx, "iter_id", id)?;
+                    let js_headers = cx.empty_array();
+                    for (i, header) in headers.iter().enumerate() {
+                        let converted = cx.string(header);
+                        js_headers.set(&mut cx, i as u32, converted)?;
+                    }
+                    ret.set(&mut cx, "headers", js_headers)?;
+                    let ret = ret.as_value(&mut cx);
+                    let err = cx.undefined().as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err, ret])?;
+                }
+                Err(err) => {
+                    let reports = format_error_as_json(err, Some(&query)).to_string();
+                    let err = cx.string(&reports).as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err])?;
+                }
+            }
+            Ok(())
+        });
+    });
+
+    Ok(cx.undefined())
+}
+
+fn iter_next(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let iter = {
+        let iter_ref = {
+            let iters = HANDLES.iters.lock().unwrap();
+            iters.get(&id).cloned()
+        };
+        match iter_ref {
+            None => {
+                let s = cx.string("iterator closed");
+                cx.throw(s)?
+            }
+            Some(iter) => iter,
+        }
+    };
+    let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
+
+    let channel = cx.channel();
+
+    rayon::spawn(move || {
+        let result = iter.lock().unwrap().next();
+        if !matches!(result, Some(Ok(_))) {
+            HANDLES.iters.lock().unwrap().remove(&id);
+        }
+        channel.send(move |mut cx| {
+            let callback = callback.into_inner(&mut cx);
+            let this = cx.undefined();
+            match result {
+                Some(Ok(row)) => {
+                    let js_row = cx.empty_array();
+                    for (i, el) in row.iter().enumerate() {
+                        let el = value2js(&mut cx, el)?;
+                        js_row.set(&mut cx, i as u32, el)?;
+                    }
+                    let js_row = js_row.as_value(&mut cx);
+                    let err = cx.undefined().as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err, js_row])?;
+                }
+                Some(Err(err)) => {
+                    let reports = format_error_as_json(err, None).to_string();
+                    let err = cx.string(&reports).as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err])?;
+                }
+                None => {
+                    let err = cx.undefined().as_value(&mut cx);
+                    let done = cx.undefined().as_value(&mut cx);
+                    callback.call(&mut cx, this, vec![err, done])?;
+                }
+            }
+            Ok(())
+        });
+    });
+
+    Ok(cx.undefined())
+}
+
+fn iter_close(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let iter = {
+        let mut iters = HANDLES.iters.lock().unwrap();
+        iters.remove(&id)
+    };
+    Ok(cx.boolean(iter.is_some()))
+}
+
+fn prepare(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let db = get_db!(cx);
+    let query = cx.argument::<JsString>(1)?.value(&mut cx);
+    match db.prepare_str(&query) {
+        Ok(prepared) => {
+            let id = HANDLES.nxt_prepared_id.fetch_add(1, Ordering::AcqRel);
+            HANDLES
+                .prepared
+                .lock()
+                .unwrap()
+                .insert(id, Arc::new((db, prepared)));
+            Ok(cx.number(id))
+        }
+        Err(reports) => {
+            let err = cx.string(reports);
+            cx.throw(err)
+        }
+    }
+}
+
+fn query_prepared(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let prepared = {
+        let prepared_ref = {
+            let prepared = HANDLES.prepared.lock().unwrap();
+            prepared.get(&id).cloned()
+        };
+        match prepared_ref {
+            None => {
+                let s = cx.string("prepared query closed");
+                cx.throw(s)?
+            }
+            Some(prepared) => prepared,
+        }
+    };
+    let params_js = cx.argument::<JsObject>(1)?;
+    let mut params = BTreeMap::new();
+    js2params(&mut cx, params_js, &mut params)?;
+
+    let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+    let immutable = cx.argument::<JsBoolean>(3)?.value(&mut cx);
+
+    let channel = cx.channel();
+
+    rayon::spawn(move || {
+        let (db, query) = prepared.as_ref();
+        let result = db.run_prepared(
+            query,
+            params,
+            if immutable {
+                ScriptMutability::Immutable
+            } else {
+                ScriptMutability::Mutable
+            },
+        );
         channel.send(move |mut cx| {
             let callback = callback.into_inner(&mut cx);
             let this = cx.undefined();
@@ -429,7 +640,7 @@ fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
                     callback.call(&mut cx, this, vec![err, js_vals])?;
                 }
                 Err(err) => {
-                    let reports = format_error_as_json(err, Some(&query)).to_string();
+                    let reports = format_error_as_json(err, Some(prepared.1.script())).to_string();
                     let err = cx.string(&reports).as_value(&mut cx);
                     callback.call(&mut cx, this, vec![err])?;
                 }
@@ -441,6 +652,15 @@ fn query_db(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     Ok(cx.undefined())
 }
 
+fn close_prepared(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let prepared = {
+        let mut prepared = HANDLES.prepared.lock().unwrap();
+        prepared.remove(&id)
+    };
+    Ok(cx.boolean(prepared.is_some()))
+}
+
 fn query_tx(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let tx = get_tx!(cx);
     let query = cx.argument::<JsString>(1)?.value(&mut cx);
@@ -776,6 +996,201 @@ fn unregister_named_rule(mut cx: FunctionContext) -> JsResult<JsBoolean> {
     Ok(cx.boolean(removed))
 }
 
+fn register_function(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let db = get_db!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let min_arity = cx.argument::<JsNumber>(2)?.value(&mut cx) as usize;
+    let max_arity = match cx.argument_opt(3) {
+        Some(v) => match v.downcast::<JsNumber, _>(&mut cx) {
+            Ok(n) => Some(n.value(&mut cx) as usize),
+            Err(_) => None,
+        },
+        None => None,
+    };
+    let callback = Arc::new(cx.argument::<JsFunction>(4)?.root(&mut cx));
+    let channel = cx.channel();
+    let (call_sender, recv) = unbounded::<(Vec<DataValue>, Sender<Result<DataValue>>)>();
+    let func = move |args: &[DataValue]| -> Result<DataValue> {
+        let (sender, receiver) = bounded(1);
+        call_sender
+            .send((args.to_vec(), sender))
+            .map_err(|_| miette!("Javascript function is no longer available"))?;
+        receiver
+            .recv()
+            .map_err(|_| miette!("Javascript function failed"))?
+    };
+    let res = match max_arity {
+        Some(max_arity) => db.register_function(name, min_arity..=max_arity, func),
+        None => db.register_function(name, min_arity.., func),
+    };
+    if let Err(err) = res {
+        let msg = cx.string(err.to_string());
+        return cx.throw(msg);
+    }
+    rayon::spawn(move || {
+        for (args, sender) in recv {
+            let id = HANDLES.cb_idx.fetch_add(1, Ordering::AcqRel);
+            {
+                HANDLES.current_fn_cbs.lock().unwrap().insert(id, sender);
+            }
+            let cb = callback.clone();
+            channel.send(move |mut cx| {
+                let callback = cb.to_inner(&mut cx);
+                let args_js = cx.empty_array();
+                for (i, arg) in args.iter().enumerate() {
+                    let arg_js = value2js(&mut cx, arg)?;
+                    args_js.set(&mut cx, i as u32, arg_js)?;
+                }
+                let args_js = args_js.as_value(&mut cx);
+                let this = cx.undefined();
+                let ret_id = cx.number(id).as_value(&mut cx);
+                callback.call(&mut cx, this, vec![ret_id, args_js])?;
+
+                Ok(())
+            });
+        }
+    });
+
+    Ok(cx.undefined())
+}
+
+fn respond_to_function_invocation(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let ret_id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let sender = {
+        match HANDLES.current_fn_cbs.lock().unwrap().remove(&ret_id) {
+            None => {
+                let msg = cx.string("function invocation sender should only be used once");
+                return cx.throw(msg);
+            }
+            Some(s) => s,
+        }
+    };
+
+    if let Some(err) = cx.argument_opt(2) {
+        if let Ok(msg) = err.downcast::<JsString, _>(&mut cx) {
+            let _ = sender.send(Err(miette!(msg.value(&mut cx))));
+            return Ok(cx.undefined());
+        }
+    }
+
+    let payload = cx.argument::<JsValue>(1)?;
+    let mut value = DataValue::Null;
+    if let Err(err) = js2value(&mut cx, payload, &mut value) {
+        let _ = sender.send(Err(miette!("Javascript function failed")));
+        return Err(err);
+    }
+    if let Err(err) = sender.send(Ok(value)) {
+        let msg = err.to_string();
+        let msg = cx.string(msg);
+        return cx.throw(msg);
+    }
+    Ok(cx.undefined())
+}
+
+fn unregister_function(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let db = get_db!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let removed = match db.unregister_function(&name) {
+        Ok(b) => b,
+        Err(msg) => {
+            let msg = cx.string(msg.to_string());
+            return cx.throw(msg);
+        }
+    };
+    Ok(cx.boolean(removed))
+}
+
+fn register_aggregation(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let db = get_db!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let is_meet = cx.argument::<JsBoolean>(2)?.value(&mut cx);
+    let callback = Arc::new(cx.argument::<JsFunction>(3)?.root(&mut cx));
+    let channel = cx.channel();
+    let (aggr_impl, recv) = SimpleAggregation::aggregation_with_channel(is_meet);
+    if let Err(err) = db.register_aggregation(name, aggr_impl) {
+        let msg = cx.string(err.to_string());
+        return cx.throw(msg);
+    }
+    rayon::spawn(move || {
+        for (call, sender) in recv {
+            let id = HANDLES.cb_idx.fetch_add(1, Ordering::AcqRel);
+            {
+                HANDLES.current_aggr_cbs.lock().unwrap().insert(id, sender);
+            }
+            let cb = callback.clone();
+            channel.send(move |mut cx| {
+                let callback = cb.to_inner(&mut cx);
+                let (op, args) = match call {
+                    AggregationCall::Init(args) => ("init", args),
+                    AggregationCall::Update(state, value) => ("update", vec![state, value]),
+                    AggregationCall::Merge(state, other) => ("merge", vec![state, other]),
+                    AggregationCall::Finalize(state) => ("finalize", vec![state]),
+                };
+                let op_js = cx.string(op).as_value(&mut cx);
+                let args_js = cx.empty_array();
+                for (i, arg) in args.iter().enumerate() {
+                    let arg_js = value2js(&mut cx, arg)?;
+                    args_js.set(&mut cx, i as u32, arg_js)?;
+                }
+                let args_js = args_js.as_value(&mut cx);
+                let this = cx.undefined();
+                let ret_id = cx.number(id).as_value(&mut cx);
+                callback.call(&mut cx, this, vec![ret_id, op_js, args_js])?;
+
+                Ok(())
+            });
+        }
+    });
+
+    Ok(cx.undefined())
+}
+
+fn respond_to_aggregation_invocation(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let ret_id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let sender = {
+        match HANDLES.current_aggr_cbs.lock().unwrap().remove(&ret_id) {
+            None => {
+                let msg = cx.string("aggregation invocation sender should only be used once");
+                return cx.throw(msg);
+            }
+            Some(s) => s,
+        }
+    };
+
+    if let Some(err) = cx.argument_opt(2) {
+        if let Ok(msg) = err.downcast::<JsString, _>(&mut cx) {
+            let _ = sender.send(Err(miette!(msg.value(&mut cx))));
+            return Ok(cx.undefined());
+        }
+    }
+
+    let payload = cx.argument::<JsValue>(1)?;
+    let mut value = DataValue::Null;
+    if let Err(err) = js2value(&mut cx, payload, &mut value) {
+        let _ = sender.send(Err(miette!("Javascript aggregation failed")));
+        return Err(err);
+    }
+    if let Err(err) = sender.send(Ok(value)) {
+        let msg = err.to_string();
+        let msg = cx.string(msg);
+        return cx.throw(msg);
+    }
+    Ok(cx.undefined())
+}
+
+fn unregister_aggregation(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let db = get_db!(cx);
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let removed = match db.unregister_aggregation(&name) {
+        Ok(b) => b,
+        Err(msg) => {
+            let msg = cx.string(msg.to_string());
+            return cx.throw(msg);
+        }
+    };
+    Ok(cx.boolean(removed))
+}
+
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("open_db", open_db)?;
@@ -794,9 +1209,27 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
         respond_to_named_rule_invocation,
     )?;
     cx.export_function("unregister_named_rule", unregister_named_rule)?;
+    cx.export_function("register_function", register_function)?;
+    cx.export_function(
+        "respond_to_function_invocation",
+        respond_to_function_invocation,
+    )?;
+    cx.export_function("unregister_function", unregister_function)?;
+    cx.export_function("register_aggregation", register_aggregation)?;
+    cx.export_function(
+        "respond_to_aggregation_invocation",
+        respond_to_aggregation_invocation,
+    )?;
+    cx.export_function("unregister_aggregation", unregister_aggregation)?;
     cx.export_function("abort_tx", abort_tx)?;
     cx.export_function("commit_tx", commit_tx)?;
     cx.export_function("multi_transact", multi_transact)?;
     cx.export_function("query_tx", query_tx)?;
+    cx.export_function("query_iter", query_iter)?;
+    cx.export_function("iter_next", iter_next)?;
+    cx.export_function("iter_close", iter_close)?;
+    cx.export_function("prepare", prepare)?;
+    cx.export_function("query_prepared", query_prepared)?;
+    cx.export_function("close_prepared", close_prepared)?;
     Ok(())
 }
@@ -145,6 +145,7 @@ fn value_to_py(val: DataValue, py: Python<'_>) -> PyObject {
         DataValue::Num(num) => match num {
             Num::Int(i) => i.into_py(py),
             Num::Float(f) => f.into_py(py),
+            Num::Decimal(d) => d.to_string().into_py(py),
         },
         DataValue::Str(s) => s.as_str().into_py(py),
         DataValue::Bytes(b) => PyBytes::new(py, &b).into(),
@@ -173,6 +174,10 @@ fn value_to_py(val: DataValue, py: Python<'_>) -> PyObject {
             }
         },
         DataValue::Json(JsonData(j)) => json_to_py(j, py),
+        DataValue::Date(d) => d.to_string().into_py(py),
+        DataValue::Time(t) => t.to_string().into_py(py),
+        DataValue::Timestamp(ts) => ts.to_string().into_py(py),
+        DataValue::Duration(d) => d.to_string().into_py(py),
     }
 }
 
@@ -208,6 +213,21 @@ struct CozoDbMulTx {
     tx: MultiTransaction,
 }
 
+#[pyclass]
+struct CozoPreparedQuery {
+    db: DbInstance,
+    query: PreparedQuery,
+}
+
+#[pyclass]
+struct CozoRowIter {
+    #[pyo3(get)]
+    headers: Vec<String>,
+    #[pyo3(get)]
+    cursor: Option<String>,
+    iter: Option<RowIter>,
+}
+
 const DB_CLOSED_MSG: &str = r##"{"ok":false,"message":"database closed"}"##;
 
 #[pymethods]
@@ -229,7 +249,7 @@ impl CozoDbPy {
         if let Some(db) = &self.db {
             let params = convert_params(params)?;
             match py.allow_threads(|| {
-                db.run_script(
+                db.run_script_page(
                     query,
                     params,
                     if immutable {
@@ -239,7 +259,41 @@ impl CozoDbPy {
                     },
                 )
             }) {
-                Ok(rows) => Ok(named_rows_to_py(rows, py)),
+                Ok((rows, cursor)) => {
+                    let ret = named_rows_to_py(rows, py);
+                    if let Some(cursor) = cursor {
+                        ret.downcast_bound::<PyDict>(py)?
+                            .set_item("cursor", cursor)?;
+                    }
+                    Ok(ret)
+                }
+                Err(err) => {
+                    let reports = format_error_as_json(err, Some(query)).to_string();
+                    let json_mod = py.import("json")?;
+                    let loads_fn = json_mod.getattr("loads")?;
+                    let args = PyTuple::new(py, [PyString::new(py, &reports)]);
+                    let msg = loads_fn.call1(args)?;
+                    Err(PyException::new_err(PyObject::from(msg)))
+                }
+            }
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
+    pub fn run_script_iter(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        params: &PyDict,
+    ) -> PyResult<CozoRowIter> {
+        if let Some(db) = &self.db {
+            let params = convert_params(params)?;
+            match py.allow_threads(|| db.run_script_iter(query, params)) {
+                Ok(iter) => Ok(CozoRowIter {
+                    headers: iter.headers.clone(),
+                    cursor: iter.cursor.clone(),
+                    iter: Some(iter),
+                }),
                 Err(err) => {
                     let reports = format_error_as_json(err, Some(query)).to_string();
                     let json_mod = py.import("json")?;
@@ -301,6 +355,86 @@ impl CozoDbPy {
             Err(PyException::new_err(DB_CLOSED_MSG))
         }
     }
+    pub fn register_function(
+        &self,
+        name: String,
+        callback: &PyAny,
+        min_arity: usize,
+        max_arity: Option<usize>,
+    ) -> PyResult<()> {
+        if let Some(db) = &self.db {
+            let callback: Py<PyAny> = callback.into();
+            let func = move |args: &[DataValue]| -> Result<DataValue> {
+                Python::with_gil(|py| -> Result<DataValue> {
+                    let py_args = PyTuple::new(py, args.iter().map(|v| value_to_py(v.clone(), py)));
+                    let res = callback.as_ref(py).call1(py_args).into_diagnostic()?;
+                    py_to_value(res).into_diagnostic()
+                })
+            };
+            let res = match max_arity {
+                Some(max_arity) => db.register_function(name, min_arity..=max_arity, func),
+                None => db.register_function(name, min_arity.., func),
+            };
+            res.map_err(report2py)
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
+    pub fn unregister_function(&self, name: &str) -> PyResult<bool> {
+        if let Some(db) = &self.db {
+            db.unregister_function(name).map_err(report2py)
+        } else {
+            Ok(false)
+        }
+    }
+    pub fn register_aggregation(
+        &self,
+        name: String,
+        is_meet: bool,
+        init: &PyAny,
+        update: &PyAny,
+        merge: &PyAny,
+        finalize: &PyAny,
+    ) -> PyResult<()> {
+        if let Some(db) = &self.db {
+            let init: Py<PyAny> = init.into();
+            let update: Py<PyAny> = update.into();
+            let merge: Py<PyAny> = merge.into();
+            let finalize: Py<PyAny> = finalize.into();
+            let aggr_impl = SimpleAggregation::new(is_meet, move |call| -> Result<DataValue> {
+                Python::with_gil(|py| -> Result<DataValue> {
+                    let res = match call {
+                        AggregationCall::Init(args) => {
+                            let py_args =
+                                PyList::new(py, args.into_iter().map(|v| value_to_py(v, py)));
+                            init.as_ref(py).call1((py_args,))
+                        }
+                        AggregationCall::Update(state, value) => update
+                            .as_ref(py)
+                            .call1((value_to_py(state, py), value_to_py(value, py))),
+                        AggregationCall::Merge(state, other) => merge
+                            .as_ref(py)
+                            .call1((value_to_py(state, py), value_to_py(other, py))),
+                        AggregationCall::Finalize(state) => {
+                            finalize.as_ref(py).call1((value_to_py(state, py),))
+                        }
+                    }
+                    .into_diagnostic()?;
+                    py_to_value(res).into_diagnostic()
+                })
+            });
+            db.register_aggregation(name, aggr_impl).map_err(report2py)
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
+    pub fn unregister_aggregation(&self, name: &str) -> PyResult<bool> {
+        if let Some(db) = &self.db {
+            db.unregister_aggregation(name).map_err(report2py)
+        } else {
+            Ok(false)
+        }
+    }
     pub fn unregister_callback(&self, id: u32) -> bool {
         if let Some(db) = &self.db {
             db.unregister_callback(id)
@@ -378,6 +512,26 @@ impl CozoDbPy {
     pub fn close(&mut self) -> bool {
         self.db.take().is_some()
     }
+    pub fn prepare(&self, py: Python<'_>, query: &str) -> PyResult<CozoPreparedQuery> {
+        if let Some(db) = &self.db {
+            match py.allow_threads(|| db.prepare(query)) {
+                Ok(prepared) => Ok(CozoPreparedQuery {
+                    db: db.clone(),
+                    query: prepared,
+                }),
+                Err(err) => {
+                    let reports = format_error_as_json(err, Some(query)).to_string();
+                    let json_mod = py.import("json")?;
+                    let loads_fn = json_mod.getattr("loads")?;
+                    let args = PyTuple::new(py, [PyString::new(py, &reports)]);
+                    let msg = loads_fn.call1(args)?;
+                    Err(PyException::new_err(PyObject::from(msg)))
+                }
+            }
+        } else {
+            Err(PyException::new_err(DB_CLOSED_MSG))
+        }
+    }
     pub fn multi_transact(&self, write: bool) -> PyResult<CozoDbMulTx> {
         if let Some(db) = &self.db {
             Ok(CozoDbMulTx {
@@ -417,6 +571,72 @@ impl CozoDbMulTx {
     }
 }
 
+#[pymethods]
+impl CozoPreparedQuery {
+    pub fn run(&self, py: Python<'_>, params: &PyDict, immutable: bool) -> PyResult<PyObject> {
+        let params = convert_params(params)?;
+        match py.allow_threads(|| {
+            self.db.run_prepared(
+                &self.query,
+                params,
+                if immutable {
+                    ScriptMutability::Immutable
+                } else {
+                    ScriptMutability::Mutable
+                },
+            )
+        }) {
+            Ok(rows) => Ok(named_rows_to_py(rows, py)),
+            Err(err) => {
+                let reports = format_error_as_json(err, Some(self.query.script())).to_string();
+                let json_mod = py.import("json")?;
+                let loads_fn = json_mod.getattr("loads")?;
+                let args = PyTuple::new(py, [PyString::new(py, &reports)]);
+                let msg = loads_fn.call1(args)?;
+                Err(PyException::new_err(PyObject::from(msg)))
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl CozoRowIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let iter = match &mut self.iter {
+            None => return Ok(None),
+            Some(iter) => iter,
+        };
+        match py.allow_threads(|| iter.next()) {
+            None => {
+                self.iter = None;
+                Ok(None)
+            }
+            Some(Ok(row)) => Ok(Some(
+                row.into_iter()
+                    .map(|v| value_to_py(v, py))
+                    .collect::<Vec<_>>()
+                    .into_py(py),
+            )),
+            Some(Err(err)) => {
+                self.iter = None;
+                let reports = format_error_as_json(err, None).to_string();
+                let json_mod = py.import("json")?;
+                let loads_fn = json_mod.getattr("loads")?;
+                let args = PyTuple::new(py, [PyString::new(py, &reports)]);
+                let msg = loads_fn.call1(args)?;
+                Err(PyException::new_err(PyObject::from(msg)))
+            }
+        }
+    }
+    /// Releases the read transaction held by the iterator.
+    pub fn close(&mut self) -> bool {
+        self.iter.take().is_some()
+    }
+}
+
 #[pyfunction]
 fn eval_expressions(
     py: Python<'_>,
@@ -459,6 +679,8 @@ fn variables(py: Python<'_>, query: &str, params: &PyDict) -> PyResult<BTreeSet<
 fn cozo_embedded(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<CozoDbPy>()?;
     m.add_class::<CozoDbMulTx>()?;
+    m.add_class::<CozoPreparedQuery>()?;
+    m.add_class::<CozoRowIter>()?;
     m.add_function(wrap_pyfunction!(eval_expressions, m)?)?;
     m.add_function(wrap_pyfunction!(variables, m)?)?;
     Ok(())
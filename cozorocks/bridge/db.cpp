@@ -4,6 +4,7 @@
 // If a copy of the MPL was not distributed with this file,
 // You can obtain one at https://mozilla.org/MPL/2.0/.
 
+#include <cstdint>
 #include <iostream>
 #include <memory>
 #include "db.h"
@@ -53,13 +54,16 @@ ColumnFamilyOptions default_cf_options() {
     return options;
 }
 
+// must agree with `KEEP_COMPRESSION` on the Rust side
+static const uint8_t KEEP_COMPRESSION = UINT8_MAX;
+
 shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
     auto options = default_db_options();
 
     shared_ptr<Cache> cache = nullptr;
 
     if (opts.block_cache_size > 0) {
-        cache = NewLRUCache(1 * 1024 * 1024 * 1024);
+        cache = NewLRUCache(opts.block_cache_size);
     }
 
     if (!opts.options_path.empty()) {
@@ -74,15 +78,6 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
             return nullptr;
         }
 
-        if (cache != nullptr) {
-            for (size_t i = 0; i < loaded_cf_descs.size(); ++i) {
-                auto* loaded_bbt_opt =
-                        loaded_cf_descs[0]
-                                .options.table_factory->GetOptions<BlockBasedTableOptions>();
-                loaded_bbt_opt->block_cache = cache;
-            }
-        }
-
         options = Options(loaded_db_opt, loaded_cf_descs[0].options);
     }
 
@@ -106,10 +101,22 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 
         options.enable_blob_garbage_collection = opts.enable_blob_garbage_collection;
     }
-    if (opts.use_bloom_filter) {
-        BlockBasedTableOptions table_options;
-        table_options.filter_policy.reset(NewBloomFilterPolicy(opts.bloom_filter_bits_per_key, false));
-        table_options.whole_key_filtering = opts.bloom_filter_whole_key_filtering;
+    if (opts.compression != KEEP_COMPRESSION) {
+        options.compression = static_cast<CompressionType>(opts.compression);
+    }
+    if (opts.bottommost_compression != KEEP_COMPRESSION) {
+        options.bottommost_compression = static_cast<CompressionType>(opts.bottommost_compression);
+    }
+    if (opts.use_bloom_filter || cache != nullptr) {
+        auto *current = options.table_factory->GetOptions<BlockBasedTableOptions>();
+        BlockBasedTableOptions table_options = current != nullptr ? *current : BlockBasedTableOptions();
+        if (opts.use_bloom_filter) {
+            table_options.filter_policy.reset(NewBloomFilterPolicy(opts.bloom_filter_bits_per_key, false));
+            table_options.whole_key_filtering = opts.bloom_filter_whole_key_filtering;
+        }
+        if (cache != nullptr) {
+            table_options.block_cache = cache;
+        }
         options.table_factory.reset(NewBlockBasedTableFactory(table_options));
     }
     if (opts.use_capped_prefix_extractor) {
@@ -124,11 +131,24 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 
     db->db_path = convert_vec_to_string(opts.db_path);
 
-    TransactionDB *txn_db = nullptr;
-    write_status(
-            TransactionDB::Open(options, TransactionDBOptions(), db->db_path, &txn_db),
-            status);
-    db->db.reset(txn_db);
+    if (opts.read_only) {
+        DB *ro_db = nullptr;
+        if (opts.secondary_path.empty()) {
+            write_status(DB::OpenForReadOnly(options, db->db_path, &ro_db), status);
+        } else {
+            // required by secondary instances
+            options.max_open_files = -1;
+            string secondary_path = convert_vec_to_string(opts.secondary_path);
+            write_status(DB::OpenAsSecondary(options, db->db_path, secondary_path, &ro_db), status);
+        }
+        db->ro_db.reset(ro_db);
+    } else {
+        TransactionDB *txn_db = nullptr;
+        write_status(
+                TransactionDB::Open(options, TransactionDBOptions(), db->db_path, &txn_db),
+                status);
+        db->db.reset(txn_db);
+    }
     db->destroy_on_exit = opts.destroy_on_exit;
 
 
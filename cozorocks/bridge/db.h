@@ -46,13 +46,15 @@ static WriteOptions DEFAULT_WRITE_OPTIONS = WriteOptions();
 
 struct RocksDbBridge {
     unique_ptr<TransactionDB> db;
+    // set instead of `db` when opened read-only or as a secondary instance
+    unique_ptr<DB> ro_db;
 
     bool destroy_on_exit;
     string db_path;
 
     inline unique_ptr<SstFileWriterBridge> get_sst_writer(rust::Str path, RocksDbStatus &status) const {
         DB *db_ = get_base_db();
-        auto cf = db->DefaultColumnFamily();
+        auto cf = db_->DefaultColumnFamily();
         Options options_ = db_->GetOptions(cf);
         auto sst_file_writer = std::make_unique<SstFileWriterBridge>(EnvOptions(), options_);
         string path_(path);
@@ -65,7 +67,7 @@ struct RocksDbBridge {
         IngestExternalFileOptions ifo;
         DB *db_ = get_base_db();
         string path_(path);
-        auto cf = db->DefaultColumnFamily();
+        auto cf = db_->DefaultColumnFamily();
         write_status(db_->IngestExternalFile(cf, {std::move(path_)}, ifo), status);
     }
 
@@ -75,11 +77,18 @@ struct RocksDbBridge {
 
 
     [[nodiscard]] inline unique_ptr<TxBridge> transact() const {
+        if (ro_db != nullptr) {
+            return make_unique<TxBridge>(&*this->ro_db, ro_db->DefaultColumnFamily());
+        }
         auto ret = make_unique<TxBridge>(&*this->db, db->DefaultColumnFamily());
         return ret;
     }
 
     inline void del_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
+        if (db == nullptr) {
+            write_status(Status::NotSupported("the database is opened read-only"), status);
+            return;
+        }
         WriteBatch batch;
         auto cf = db->DefaultColumnFamily();
         auto s = batch.DeleteRange(cf, convert_slice(start), convert_slice(end));
@@ -103,14 +112,38 @@ struct RocksDbBridge {
 
     void compact_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
         CompactRangeOptions options;
-        auto cf = db->DefaultColumnFamily();
+        DB *db_ = get_base_db();
+        auto cf = db_->DefaultColumnFamily();
         auto start_s = convert_slice(start);
         auto end_s = convert_slice(end);
-        auto s = db->CompactRange(options, cf, &start_s, &end_s);
+        auto s = db_->CompactRange(options, cf, &start_s, &end_s);
         write_status(s, status);
     }
 
+    void create_checkpoint(rust::Str path, RocksDbStatus &status) const {
+        Checkpoint *checkpoint_ptr;
+        auto s = Checkpoint::Create(get_base_db(), &checkpoint_ptr);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        unique_ptr<Checkpoint> checkpoint(checkpoint_ptr);
+        string path_(path);
+        write_status(checkpoint->CreateCheckpoint(path_), status);
+    }
+
+    void try_catch_up_with_primary(RocksDbStatus &status) const {
+        if (ro_db == nullptr) {
+            write_status(Status::NotSupported("the database is not a secondary instance"), status);
+            return;
+        }
+        write_status(ro_db->TryCatchUpWithPrimary(), status);
+    }
+
     DB *get_base_db() const {
+        if (db == nullptr) {
+            return &*ro_db;
+        }
         return db->GetBaseDB();
     }
 
@@ -15,5 +15,5 @@ void TxBridge::start() {
         Transaction *txn = tdb->BeginTransaction(*w_opts, *p_tx_opts);
         tx.reset(txn);
     }
-    assert(tx);
+    assert(tx || rdb);
 }
\ No newline at end of file
@@ -15,6 +15,10 @@
 struct TxBridge {
     OptimisticTransactionDB *odb;
     TransactionDB *tdb;
+    // a database opened read-only, read without transactions
+    DB *rdb;
+    // the snapshot reads of `rdb` are made from, as it has no transaction to hold one
+    const Snapshot *r_snapshot;
     unique_ptr<Transaction> tx;
     unique_ptr<WriteOptions> w_opts;
     unique_ptr<ReadOptions> r_opts;
@@ -25,6 +29,8 @@ struct TxBridge {
     explicit TxBridge(TransactionDB *tdb_, ColumnFamilyHandle * cf_handle_) :
             odb(nullptr),
             tdb(tdb_),
+            rdb(nullptr),
+            r_snapshot(nullptr),
             tx(),
             w_opts(new WriteOptions),
             r_opts(new ReadOptions),
@@ -34,6 +40,32 @@ struct TxBridge {
         r_opts->ignore_range_deletions = true;
     }
 
+    explicit TxBridge(DB *rdb_, ColumnFamilyHandle * cf_handle_) :
+            odb(nullptr),
+            tdb(nullptr),
+            rdb(rdb_),
+            r_snapshot(nullptr),
+            tx(),
+            w_opts(new WriteOptions),
+            r_opts(new ReadOptions),
+            o_tx_opts(nullptr),
+            p_tx_opts(nullptr),
+            cf_handle(cf_handle_) {
+        r_opts->ignore_range_deletions = true;
+    }
+
+    ~TxBridge() {
+        release_read_snapshot();
+    }
+
+    inline void release_read_snapshot() {
+        if (r_snapshot != nullptr) {
+            r_opts->snapshot = nullptr;
+            rdb->ReleaseSnapshot(r_snapshot);
+            r_snapshot = nullptr;
+        }
+    }
+
     inline WriteOptions &get_w_opts() {
         return *w_opts;
     }
@@ -51,6 +83,11 @@ struct TxBridge {
     }
 
     inline unique_ptr<IterBridge> iterator() const {
+        if (tx == nullptr) {
+            auto ret = make_unique<IterBridge>(rdb);
+            ret->set_snapshot(r_snapshot);
+            return ret;
+        }
         return make_unique<IterBridge>(&*tx);
     };
 
@@ -63,18 +100,31 @@ struct TxBridge {
             o_tx_opts->set_snapshot = val;
         } else if (p_tx_opts != nullptr) {
             p_tx_opts->set_snapshot = val;
+        } else if (rdb != nullptr) {
+            if (val && r_snapshot == nullptr) {
+                r_snapshot = rdb->GetSnapshot();
+                r_opts->snapshot = r_snapshot;
+            } else if (!val) {
+                release_read_snapshot();
+            }
         }
     }
 
     inline void clear_snapshot() {
-        tx->ClearSnapshot();
+        if (tx != nullptr) {
+            tx->ClearSnapshot();
+        } else {
+            release_read_snapshot();
+        }
     }
 
     [[nodiscard]] inline DB *get_db() const {
         if (tdb != nullptr) {
             return tdb;
-        } else {
+        } else if (odb != nullptr) {
             return odb;
+        } else {
+            return rdb;
         }
     }
 
@@ -83,7 +133,10 @@ struct TxBridge {
     inline unique_ptr<PinnableSlice> get(RustBytes key, bool for_update, RocksDbStatus &status) const {
         Slice key_ = convert_slice(key);
         auto ret = make_unique<PinnableSlice>();
-        if (for_update) {
+        if (tx == nullptr) {
+            auto s = rdb->Get(*r_opts, cf_handle, key_, &*ret);
+            write_status(s, status);
+        } else if (for_update) {
             auto s = tx->GetForUpdate(*r_opts, cf_handle, key_, &*ret);
             write_status(s, status);
         } else {
@@ -96,7 +149,10 @@ struct TxBridge {
     inline void exists(RustBytes key, bool for_update, RocksDbStatus &status) const {
         Slice key_ = convert_slice(key);
         auto ret = PinnableSlice();
-        if (for_update) {
+        if (tx == nullptr) {
+            auto s = rdb->Get(*r_opts, cf_handle, key_, &ret);
+            write_status(s, status);
+        } else if (for_update) {
             auto s = tx->GetForUpdate(*r_opts, cf_handle, key_, &ret);
             write_status(s, status);
         } else {
@@ -106,31 +162,52 @@ struct TxBridge {
     }
 
     inline void put(RustBytes key, RustBytes val, RocksDbStatus &status) const {
+        if (tx == nullptr) {
+            write_status(Status::NotSupported("the database is opened read-only"), status);
+            return;
+        }
         write_status(tx->Put(convert_slice(key), convert_slice(val)), status);
     }
 
     inline void del(RustBytes key, RocksDbStatus &status) const {
+        if (tx == nullptr) {
+            write_status(Status::NotSupported("the database is opened read-only"), status);
+            return;
+        }
         write_status(tx->Delete(convert_slice(key)), status);
     }
 
     inline void commit(RocksDbStatus &status) {
-        write_status(tx->Commit(), status);
+        // nothing to commit for a read-only database
+        if (tx != nullptr) {
+            write_status(tx->Commit(), status);
+        }
     }
 
     inline void rollback(RocksDbStatus &status) {
-        write_status(tx->Rollback(), status);
+        if (tx != nullptr) {
+            write_status(tx->Rollback(), status);
+        }
     }
 
+    // nothing is ever written to a read-only database, so savepoints are no-ops for it
+
     inline void rollback_to_savepoint(RocksDbStatus &status) {
-        write_status(tx->RollbackToSavePoint(), status);
+        if (tx != nullptr) {
+            write_status(tx->RollbackToSavePoint(), status);
+        }
     }
 
     inline void pop_savepoint(RocksDbStatus &status) {
-        write_status(tx->PopSavePoint(), status);
+        if (tx != nullptr) {
+            write_status(tx->PopSavePoint(), status);
+        }
     }
 
     inline void set_savepoint() {
-        tx->SetSavePoint();
+        if (tx != nullptr) {
+            tx->SetSavePoint();
+        }
     }
 };
 
@@ -31,6 +31,9 @@ fn path2buf(path: impl AsRef<Path>) -> Vec<u8> {
     }
 }
 
+/// Compression type meaning that the one of the default or loaded options is kept
+pub const KEEP_COMPRESSION: u8 = u8::MAX;
+
 impl Default for DbOpts {
     fn default() -> Self {
         Self {
@@ -54,6 +57,10 @@ impl Default for DbOpts {
             fixed_prefix_extractor_len: 0,
             destroy_on_exit: false,
             block_cache_size: 0,
+            compression: KEEP_COMPRESSION,
+            bottommost_compression: KEEP_COMPRESSION,
+            read_only: false,
+            secondary_path: vec![],
         }
     }
 }
@@ -121,6 +128,27 @@ impl DbBuilder {
         self.opts.fixed_prefix_extractor_len = len;
         self
     }
+    pub fn block_cache_size(mut self, size: usize) -> Self {
+        self.opts.block_cache_size = size;
+        self
+    }
+    /// The arguments are values of RocksDB's `CompressionType`, or [KEEP_COMPRESSION].
+    pub fn compression(mut self, compression: u8, bottommost_compression: u8) -> Self {
+        self.opts.compression = compression;
+        self.opts.bottommost_compression = bottommost_compression;
+        self
+    }
+    pub fn read_only(mut self, val: bool) -> Self {
+        self.opts.read_only = val;
+        self
+    }
+    /// Open the database as a secondary instance keeping its own logs in `path`,
+    /// which implies [DbBuilder::read_only].
+    pub fn secondary_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.opts.read_only = true;
+        self.opts.secondary_path = path2buf(path);
+        self
+    }
     pub fn build(self) -> Result<RocksDb, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
 
@@ -177,6 +205,25 @@ impl RocksDb {
             Err(status)
         }
     }
+    pub fn create_checkpoint(&self, path: &str) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.create_checkpoint(path, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// Make the latest writes of the primary instance visible to a secondary instance
+    pub fn try_catch_up_with_primary(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.try_catch_up_with_primary(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
     pub fn get_sst_writer(&self, path: &str) -> Result<SstWriter, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
         let ret = self.inner.get_sst_writer(path, &mut status);
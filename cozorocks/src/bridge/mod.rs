@@ -41,6 +41,10 @@ pub(crate) mod ffi {
         pub fixed_prefix_extractor_len: usize,
         pub destroy_on_exit: bool,
         pub block_cache_size: usize,
+        pub compression: u8,
+        pub bottommost_compression: u8,
+        pub read_only: bool,
+        pub secondary_path: Vec<u8>,
     }
 
     #[derive(Clone, Debug, Eq, PartialEq)]
@@ -134,6 +138,8 @@ pub(crate) mod ffi {
             upper: &[u8],
             status: &mut RocksDbStatus,
         );
+        fn create_checkpoint(self: &RocksDbBridge, path: &str, status: &mut RocksDbStatus);
+        fn try_catch_up_with_primary(self: &RocksDbBridge, status: &mut RocksDbStatus);
         fn get_sst_writer(
             self: &RocksDbBridge,
             path: &str,